@@ -44,6 +44,7 @@ function connectVault() {
         if (line.trim() && vaultResponseResolve) {
           try {
             const resp = JSON.parse(line);
+            if (!("status" in resp)) continue; // Connection greeting
             vaultResponseResolve(resp);
             vaultResponseResolve = null;
           } catch (e) {}
@@ -68,7 +68,9 @@ function connectVault() {
       vaultBuffer = lines.pop();
       for (const line of lines) {
         if (line.trim() && vaultResponseResolve) {
-          vaultResponseResolve(JSON.parse(line));
+          const resp = JSON.parse(line);
+          if (!("status" in resp)) continue; // Connection greeting
+          vaultResponseResolve(resp);
           vaultResponseResolve = null;
         }
       }
@@ -193,7 +195,7 @@ async function executeSteps(page, steps, values) {
 
 async function rotatePassword(browser, entry, siteConfig, dryRun) {
   const fullEntry = await vaultGet(entry.id);
-  const oldPassword = Buffer.from(fullEntry.value, "base64").toString();
+  const oldPassword = Buffer.from(fullEntry.value_b64, "base64").toString();
   const newPassword = generatePassword(20);
   
   console.log(`\n🔄 ${entry.name}`);
@@ -38,7 +38,9 @@ function connectVault() {
       vaultBuffer = lines.pop();
       for (const line of lines) {
         if (line.trim() && vaultResponseResolve) {
-          vaultResponseResolve(JSON.parse(line));
+          const resp = JSON.parse(line);
+          if (!("status" in resp)) continue; // Connection greeting
+          vaultResponseResolve(resp);
           vaultResponseResolve = null;
         }
       }
@@ -190,7 +192,7 @@ async function login(siteName, passphrase = "test") {
   
   // Get full entry with password
   const fullEntry = await vaultGet(entry.id);
-  const password = Buffer.from(fullEntry.value, "base64").toString();
+  const password = Buffer.from(fullEntry.value_b64, "base64").toString();
   
   // Launch browser
   if (!existsSync(BROWSER_PROFILE)) {
@@ -91,7 +91,9 @@ function connectVault() {
       for (const line of lines) {
         if (line.trim() && vaultResponseResolve) {
           try {
-            vaultResponseResolve(JSON.parse(line));
+            const resp = JSON.parse(line);
+            if (!("status" in resp)) continue; // Connection greeting
+            vaultResponseResolve(resp);
             vaultResponseResolve = null;
           } catch (e) {}
         }
@@ -288,7 +290,7 @@ async function handleLocalCommand(text) {
     
     if (entry) {
       const full = await vaultGet(entry.id);
-      const password = Buffer.from(full.value, "base64").toString();
+      const password = Buffer.from(full.value_b64, "base64").toString();
       return { 
         her: `Found it. The password for ${entry.name} is on your screen.`,
         claude: `[vault] get ${entry.id}\n🔑 ${entry.name}\n   User: ${entry.username}\n   Pass: ${password}`
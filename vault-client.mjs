@@ -10,6 +10,7 @@ import { promisify } from "util";
 import { EventEmitter } from "events";
 
 const SOCKET_PATH = "/run/prosperity/vault.sock";
+const PROTOCOL_VERSION = 2;
 
 class VaultClient extends EventEmitter {
   constructor(socketPath = SOCKET_PATH) {
@@ -18,6 +19,7 @@ class VaultClient extends EventEmitter {
     this.socket = null;
     this.connected = false;
     this.buffer = "";
+    this.greeting = null;
   }
 
   /**
@@ -42,6 +44,12 @@ class VaultClient extends EventEmitter {
           if (line.trim()) {
             try {
               const response = JSON.parse(line);
+              // The daemon's greeting isn't a reply to any command
+              if (!this.greeting && !("status" in response) && "protocol_version" in response) {
+                this.greeting = response;
+                this.emit("greeting", response);
+                continue;
+              }
               this.emit("response", response);
             } catch (e) {
               console.error("Invalid JSON from vault:", line);
@@ -104,6 +112,17 @@ class VaultClient extends EventEmitter {
     });
   }
 
+  /**
+   * Tell the daemon which protocol version this client speaks
+   */
+  async hello() {
+    const resp = await this.send({ cmd: "hello", client_version: PROTOCOL_VERSION });
+    if (resp.status === "ok") {
+      return resp.data;
+    }
+    throw new Error(resp.message || "Protocol handshake failed");
+  }
+
   /**
    * Check vault status
    */
@@ -0,0 +1,66 @@
+//! The paths every request goes through: audit appends, category saves,
+//! and the libsodium initialization the crypto under both of them does
+//!
+//!     cargo bench --offline --bench hot_paths
+//!
+//! Goes through the library's public API, as the daemon binary does.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use tempfile::TempDir;
+use uuid::Uuid;
+
+use prosperity_vault::audit::AuditLog;
+use prosperity_vault::crypto::{self, SecureKey};
+use prosperity_vault::vault::{Category, EntryType, Vault, VaultEntry};
+
+/// Entries in the category being saved, about a busy personal vault's
+const CATEGORY_ENTRIES: usize = 100;
+
+fn crypto_init(c: &mut Criterion) {
+    crypto::init().unwrap();
+    c.bench_function("crypto_init", |b| b.iter(|| crypto::init().unwrap()));
+}
+
+fn encrypt_decrypt(c: &mut Criterion) {
+    crypto::init().unwrap();
+    let key = SecureKey::generate();
+    let plaintext = [7u8; 256];
+    c.bench_function("encrypt_decrypt_256b", |b| b.iter(|| {
+        let sealed = crypto::encrypt(&plaintext, &key, b"bench").unwrap();
+        crypto::decrypt(&sealed, &key, b"bench").unwrap()
+    }));
+}
+
+fn audit_append(c: &mut Criterion) {
+    crypto::init().unwrap();
+    let tmp = TempDir::new().unwrap();
+    let mut log = AuditLog::open(tmp.path().join("audit.log"), SecureKey::generate()).unwrap();
+    let id = Uuid::new_v4();
+    c.bench_function("audit_append", |b| b.iter(|| {
+        log.log_access(id, "GitHub", Category::Authentication, Some("agent"), Some("bench"), None).unwrap()
+    }));
+}
+
+fn category_save(c: &mut Criterion) {
+    crypto::init().unwrap();
+    let tmp = TempDir::new().unwrap();
+    let mut vault = Vault::create(tmp.path().join("vault"), "bench passphrase").unwrap();
+    let entry = |n: usize| VaultEntry::new(Category::Personal, EntryType::Password, format!("Entry {}", n), vec![b'x'; 32]);
+    for n in 1..CATEGORY_ENTRIES {
+        vault.add_entry(entry(n)).unwrap();
+    }
+    let id = vault.add_entry(entry(0)).unwrap();
+    let mut saved = vault.get_entry(&id).unwrap().unwrap().clone();
+    c.bench_function("category_save", |b| b.iter(|| {
+        saved.value[0] ^= 1;
+        vault.update_entry(saved.clone()).unwrap()
+    }));
+}
+
+criterion_group! {
+    name = benches;
+    // Both writes fsync; fewer, longer samples keep the run short
+    config = Criterion::default().sample_size(20);
+    targets = crypto_init, encrypt_decrypt, audit_append, category_save
+}
+criterion_main!(benches);
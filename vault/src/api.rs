@@ -5,56 +5,773 @@
 //! - Entry CRUD
 //! - Credential use (without exposing values)
 
-use anyhow::{anyhow, Result};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::{UnixListener, UnixStream};
-use tokio::sync::Mutex;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::RwLock;
+use tracing::Instrument;
 use uuid::Uuid;
+use zeroize::{Zeroize, Zeroizing};
 
-use std::path::Path;
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard, PoisonError};
+use std::time::{Duration as StdDuration, Instant};
 
-use crate::vault::{Category, EntryMetadata, EntryType, Vault, VaultEntry};
-use crate::audit::AuditLog;
-use crate::crypto::{SecureKey, derive_subkey};
+use crate::vault::{
+    AccessMode, Category, CategoryLocked, CustomField, EntryMetadata, EntryType, GcReport, SearchQuery, Vault,
+    VaultEntry, UnsupportedVersion, VaultInUse, VaultReadOnly, WrongPassphrase,
+};
+use crate::audit::{
+    AnomalyThresholds, AuditEntry, AuditEventType, AuditFilter, AuditLog, ChainVerification, RotationPolicy,
+};
+use crate::auth::{self, DomainPolicy};
+use crate::strength::estimate_strength;
+use crate::totp;
+use crate::generate::{generate_password, PasswordSpec};
+use crate::validate;
+use crate::hwkey::HardwareKeyUnavailable;
+use crate::metrics::Metrics;
+use crate::policy::AccessPolicy;
+use crate::crypto::{
+    derive_master_key, derive_subkey, read_pepper, DecryptionFailed, KdfParams, PepperUnavailable, SecureKey,
+};
+
+/// A string in a request that must never reach a log: `Debug` prints
+/// `***`, and it's wiped from memory when dropped
+#[derive(Default)]
+pub struct Secret(Zeroizing<String>);
+
+impl std::ops::Deref for Secret {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(Zeroizing::new(value.to_string()))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|s| Self(Zeroizing::new(s)))
+    }
+}
+
+impl JsonSchema for Secret {
+    fn schema_name() -> String {
+        String::schema_name()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(gen)
+    }
+
+    fn is_referenceable() -> bool {
+        false
+    }
+}
 
 /// API request types
-#[derive(Debug, Deserialize)]
+///
+/// `vault` names which of the daemon's vaults a request targets; when
+/// absent, the default vault is used.
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(tag = "cmd", rename_all = "snake_case")]
 pub enum Request {
     // Vault operations
-    Unlock { passphrase: String, categories: Option<Vec<Category>> },
-    Lock,
+    Unlock {
+        vault: Option<String>,
+        /// Registers `vault` at this path if the name is new
+        path: Option<PathBuf>,
+        passphrase: Secret,
+        categories: Option<Vec<Category>>,
+        #[serde(default)]
+        mode: AccessMode,
+        /// Create the vault even if the passphrase is weak
+        #[serde(default)]
+        force: bool,
+    },
+    Lock { vault: Option<String> },
+    /// Lock every vault at once on a suspected compromise, auditing it
+    /// first, and end audit subscriptions; answers the vaults locked
+    Emergency { agent_id: Option<String> },
+    RotateDek { vault: Option<String> },
+    /// Delete attachment files and keys left behind by crashes; needs a
+    /// full unlock. Answers what was dropped and the bytes reclaimed.
+    Gc { vault: Option<String> },
+    /// Encrypt `vault.meta`'s timestamps, flags and counters, or stop;
+    /// the salt and KDF parameters the unlock needs stay readable
+    SetMetaEncryption { vault: Option<String>, enabled: bool },
+    /// Needs the current passphrase as well as an unlocked vault
+    UpdatePassphrase {
+        vault: Option<String>,
+        old_passphrase: Secret,
+        new_passphrase: Secret,
+    },
+    /// Re-derive the master key under stronger Argon2 parameters; needs
+    /// the passphrase, and turns recovery off as a passphrase change does
+    UpgradeKdf {
+        vault: Option<String>,
+        passphrase: Secret,
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    /// Register the attached FIDO2 security key and require it for every
+    /// unlock from then on; needs a touch, and libfido2 installed
+    EnrollHardwareKey { vault: Option<String> },
+    /// Check the passphrase of an unlocked vault without re-unlocking it,
+    /// e.g. to step up before showing a sensitive entry; wrong guesses
+    /// count towards the unlock backoff
+    Verify { vault: Option<String>, passphrase: Secret },
+    /// `None` removes the policy, allowing every agent again
+    SetPolicy { vault: Option<String>, policy: Option<AccessPolicy> },
+    GetPolicy { vault: Option<String> },
+    /// Every entry and attachment, encrypted under `passphrase`
+    Export {
+        vault: Option<String>,
+        passphrase: Secret,
+        agent_id: Option<String>,
+    },
+    /// Decrypt and parse every vault file, reporting each one's status
+    VerifyIntegrity { vault: Option<String> },
+    /// Whether auditing is running and how the hash chain last verified;
+    /// `verify` checks the chain again first
+    AuditStatus {
+        vault: Option<String>,
+        #[serde(default)]
+        verify: bool,
+    },
+    /// Check the audit hash chain: valid, or where it first breaks
+    VerifyAudit { vault: Option<String> },
+    /// Usage from the audit log over the last `window_hours`: uses per
+    /// entry and category, activity per agent, denials, events by hour
+    Stats { vault: Option<String>, window_hours: i64 },
+    /// Audit entries matching every criterion given, oldest first; at most
+    /// `limit` (default 1000) of them, the most recent
+    AuditQuery {
+        vault: Option<String>,
+        /// Only the last this many hours [default: the whole log]
+        since_hours: Option<i64>,
+        /// Any of these event types [default: all]
+        #[serde(default)]
+        event_types: Vec<AuditEventType>,
+        agent_id: Option<String>,
+        category: Option<Category>,
+        limit: Option<usize>,
+    },
     Status,
+    /// Announce the client's protocol version (after reading the greeting);
+    /// an incompatible version gets an error and the connection is closed
+    Hello { client_version: u32 },
+    /// Liveness check; never needs a passphrase and doesn't count as activity
+    Ping { vault: Option<String> },
+    Subscribe { vault: Option<String> },  // Stream audit events; takes over the connection
+    Batch { requests: Vec<Request> },  // Run in order, one response each
     
     // Entry operations
-    List { category: Category },
-    Get { id: Uuid, agent_id: Option<String>, purpose: Option<String> },
-    Create { entry: NewEntryRequest },
-    Delete { id: Uuid },
+    /// Per category: entry count, whether it's unlocked and loaded, and how
+    /// many entries expire within `within_hours` (default a week)
+    Overview { vault: Option<String>, within_hours: Option<i64> },
+    /// Decrypt `categories` (default: all unlocked ones) into the cache
+    /// now, so the first reads after an unlock are fast; stops at the
+    /// cache budget. Answers which categories were warmed and skipped.
+    Warm { vault: Option<String>, categories: Option<Vec<Category>> },
+    /// Paged when `offset` or `limit` is given, otherwise the whole category
+    List {
+        vault: Option<String>,
+        category: Category,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    },
+    Search { vault: Option<String>, query: SearchQuery },
+    ExpiringSoon { vault: Option<String>, within_hours: i64 },
+    FindDuplicates { vault: Option<String> },
+    /// Answers the entry, its value as `value_b64` (see `entry_response`)
+    Get {
+        vault: Option<String>,
+        id: Uuid,
+        agent_id: Option<String>,
+        purpose: Option<String>,
+        /// Agents the request was delegated through, outermost first
+        origin_chain: Option<Vec<String>>,
+        /// Look without counting towards the entry's `access_count`
+        #[serde(default)]
+        peek: bool,
+        /// Opens a protected entry's value (see `Create`)
+        extra_passphrase: Option<Secret>,
+    },
+    /// Everything about an entry except its value (custom fields by name
+    /// and sensitivity only, attachments, history length), for showing
+    /// details before deciding to `get` it
+    Describe { vault: Option<String>, id: Uuid, agent_id: Option<String> },
+    /// An entry's earlier versions, oldest first, each as `version` (its
+    /// index), `recorded` and the entry's metadata; never values. A deleted
+    /// entry's history outlives it.
+    History { vault: Option<String>, id: Uuid, agent_id: Option<String> },
+    /// Bring back an entry as it was at `version` (an index from `history`);
+    /// its current state becomes a version in turn, and a deleted entry is
+    /// re-created
+    RestoreVersion {
+        vault: Option<String>,
+        id: Uuid,
+        version: usize,
+        agent_id: Option<String>,
+    },
+    /// Add a TOTP secret from an authenticator QR code's
+    /// `otpauth://totp/` URI, keeping its digits, period and algorithm;
+    /// `name` defaults to "Issuer (account)". Answers the new entry's id.
+    CreateTotpFromUri {
+        vault: Option<String>,
+        uri: Secret,
+        name: Option<String>,
+        agent_id: Option<String>,
+    },
+    Create {
+        vault: Option<String>,
+        entry: NewEntryRequest,
+        agent_id: Option<String>,
+        origin_chain: Option<Vec<String>>,
+        /// Also seal the value under this second passphrase, which `get`
+        /// then needs; the entry's metadata stays readable without it
+        extra_passphrase: Option<Secret>,
+        /// Makes a retry safe: a repeat within `IDEMPOTENCY_TTL` from the
+        /// same agent answers the first create's id instead of adding another
+        idempotency_key: Option<String>,
+    },
+    /// Create many entries at once (e.g. from another password manager)
+    ImportEntries {
+        vault: Option<String>,
+        entries: Vec<NewEntryRequest>,
+        agent_id: Option<String>,
+    },
+    /// Replace an entry's fields; tags, notes and attachments are kept
+    Update {
+        vault: Option<String>,
+        id: Uuid,
+        entry: NewEntryRequest,
+        agent_id: Option<String>,
+    },
+    /// With `dry_run`, report what would be removed and change nothing
+    Delete {
+        vault: Option<String>,
+        id: Uuid,
+        agent_id: Option<String>,
+        #[serde(default)]
+        dry_run: bool,
+    },
+    /// Delete several entries at once, all or none; answers which ids
+    /// were deleted and which no entry has
+    BulkDelete {
+        vault: Option<String>,
+        ids: Vec<Uuid>,
+        agent_id: Option<String>,
+    },
+    Move { vault: Option<String>, id: Uuid, to: Category },
+    AddAttachment {
+        vault: Option<String>,
+        entry_id: Uuid,
+        filename: String,
+        mime_type: String,
+        /// The file's bytes, base64-encoded (also accepted as `data_b64`)
+        #[serde(alias = "data_b64")]
+        data: Secret,
+    },
+    /// Answers the attachment's metadata, and its bytes base64-encoded
+    /// as `data_b64`
+    GetAttachment {
+        vault: Option<String>,
+        entry_id: Uuid,
+        attachment_id: Uuid,
+        agent_id: Option<String>,
+        purpose: Option<String>,
+    },
+    RemoveAttachment { vault: Option<String>, entry_id: Uuid, attachment_id: Uuid },
+    /// Tags compare case-insensitively; the response has the entry's tags
+    AddTags { vault: Option<String>, id: Uuid, tags: Vec<String> },
+    RemoveTags { vault: Option<String>, id: Uuid, tags: Vec<String> },
+    /// Every tag in use, with how many entries carry it
+    ListTags { vault: Option<String> },
+    /// Add an entry to or remove it from the quick-access list
+    SetFavorite { vault: Option<String>, id: Uuid, favorite: bool },
+    /// Favorite entries' metadata across categories, by name
+    ListFavorites { vault: Option<String> },
+    /// File an entry under a slash-separated folder path, e.g.
+    /// "work/aws/prod"; null or "" moves it to the top level
+    SetFolder { vault: Option<String>, id: Uuid, folder: Option<String> },
+    /// Metadata of entries in `category` under `prefix` and its
+    /// subfolders; an empty prefix lists the whole category
+    ListFolder {
+        vault: Option<String>,
+        category: Category,
+        #[serde(default)]
+        prefix: String,
+    },
+    /// The folder paths in use in `category`
+    ListFolders { vault: Option<String>, category: Category },
+    /// Change an entry's name, leaving everything else (and the value,
+    /// which isn't sent either way) as it is
+    Rename {
+        vault: Option<String>,
+        id: Uuid,
+        name: String,
+        agent_id: Option<String>,
+    },
+    /// Replace a password or API key entry's value with a new random
+    /// password, keeping the old one in history, and answer the new one
+    /// (as `value_b64`, like `Get`) so it can be set upstream
+    Rotate {
+        vault: Option<String>,
+        id: Uuid,
+        #[serde(default)]
+        spec: PasswordSpec,
+        agent_id: Option<String>,
+    },
+    /// Copy an entry (e.g. to keep the old credential while rotating it)
+    /// as "<name> (copy)"; answers the copy's id
+    Clone {
+        vault: Option<String>,
+        id: Uuid,
+        agent_id: Option<String>,
+    },
+    /// Replace an entry's markdown notes (at most 16 KiB; NUL bytes are
+    /// dropped) without touching or returning its value; `None` clears them
+    UpdateNotes {
+        vault: Option<String>,
+        id: Uuid,
+        notes: Option<Secret>,
+        agent_id: Option<String>,
+    },
     
     // Auth operations (credential used without returning value)
-    UseForAuth { id: Uuid, target_url: String, agent_id: String, purpose: String },
+    UseForAuth {
+        vault: Option<String>,
+        id: Uuid,
+        target_url: String,
+        agent_id: String,
+        purpose: String,
+        origin_chain: Option<Vec<String>>,
+    },
+    /// Count a use of an entry without reading it (e.g. the credential was
+    /// checked out of band): bumps `accessed` and `access_count` like a
+    /// `get`, saved with the other recorded accesses rather than at once
+    Touch { vault: Option<String>, id: Uuid, agent_id: Option<String> },
+    /// Renew an `OAuthToken` entry's access token with the refresh grant
+    /// in its custom fields (see `auth::build_refresh`); neither token is
+    /// returned
+    #[serde(rename = "refresh_oauth")]
+    RefreshOAuth { vault: Option<String>, id: Uuid, agent_id: Option<String> },
+}
+
+impl Request {
+    /// The vault this request targets, if it names one
+    fn vault(&self) -> Option<&str> {
+        match self {
+            Request::Unlock { vault, .. }
+            | Request::Lock { vault }
+            | Request::RotateDek { vault }
+            | Request::Gc { vault }
+            | Request::SetMetaEncryption { vault, .. }
+            | Request::UpdatePassphrase { vault, .. }
+            | Request::UpgradeKdf { vault, .. }
+            | Request::EnrollHardwareKey { vault }
+            | Request::Verify { vault, .. }
+            | Request::SetPolicy { vault, .. }
+            | Request::GetPolicy { vault }
+            | Request::Export { vault, .. }
+            | Request::VerifyIntegrity { vault }
+            | Request::AuditStatus { vault, .. }
+            | Request::VerifyAudit { vault }
+            | Request::Stats { vault, .. }
+            | Request::AuditQuery { vault, .. }
+            | Request::Subscribe { vault }
+            | Request::Ping { vault }
+            | Request::Overview { vault, .. }
+            | Request::Warm { vault, .. }
+            | Request::List { vault, .. }
+            | Request::Search { vault, .. }
+            | Request::ExpiringSoon { vault, .. }
+            | Request::FindDuplicates { vault }
+            | Request::Get { vault, .. }
+            | Request::Create { vault, .. }
+            | Request::CreateTotpFromUri { vault, .. }
+            | Request::ImportEntries { vault, .. }
+            | Request::Update { vault, .. }
+            | Request::Delete { vault, .. }
+            | Request::BulkDelete { vault, .. }
+            | Request::Move { vault, .. }
+            | Request::AddAttachment { vault, .. }
+            | Request::GetAttachment { vault, .. }
+            | Request::RemoveAttachment { vault, .. }
+            | Request::AddTags { vault, .. }
+            | Request::RemoveTags { vault, .. }
+            | Request::ListTags { vault }
+            | Request::SetFavorite { vault, .. }
+            | Request::Rename { vault, .. }
+            | Request::Clone { vault, .. }
+            | Request::Rotate { vault, .. }
+            | Request::UpdateNotes { vault, .. }
+            | Request::ListFavorites { vault }
+            | Request::SetFolder { vault, .. }
+            | Request::ListFolder { vault, .. }
+            | Request::ListFolders { vault, .. }
+            | Request::UseForAuth { vault, .. }
+            | Request::Touch { vault, .. }
+            | Request::Describe { vault, .. }
+            | Request::History { vault, .. }
+            | Request::RestoreVersion { vault, .. }
+            | Request::RefreshOAuth { vault, .. } => vault.as_deref(),
+            Request::Status | Request::Hello { .. } | Request::Batch { .. } | Request::Emergency { .. } => None,
+        }
+    }
+
+    /// The agent the request is made for, if it names one
+    fn agent_id(&self) -> Option<&str> {
+        match self {
+            Request::Emergency { agent_id }
+            | Request::Export { agent_id, .. }
+            | Request::Get { agent_id, .. }
+            | Request::CreateTotpFromUri { agent_id, .. }
+            | Request::Create { agent_id, .. }
+            | Request::ImportEntries { agent_id, .. }
+            | Request::Update { agent_id, .. }
+            | Request::Delete { agent_id, .. }
+            | Request::BulkDelete { agent_id, .. }
+            | Request::GetAttachment { agent_id, .. }
+            | Request::Rename { agent_id, .. }
+            | Request::Clone { agent_id, .. }
+            | Request::Rotate { agent_id, .. }
+            | Request::UpdateNotes { agent_id, .. }
+            | Request::Touch { agent_id, .. }
+            | Request::Describe { agent_id, .. }
+            | Request::History { agent_id, .. }
+            | Request::RestoreVersion { agent_id, .. }
+            | Request::RefreshOAuth { agent_id, .. } => agent_id.as_deref(),
+            Request::UseForAuth { agent_id, .. } => Some(agent_id),
+            _ => None,
+        }
+    }
+
+    /// Whether the request leaves vaults and daemon state untouched (apart
+    /// from the audit log and access counts), so it can run alongside
+    /// other reads
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Request::Status
+                | Request::Hello { .. }
+                | Request::Ping { .. }
+                | Request::GetPolicy { .. }
+                | Request::Export { .. }
+                | Request::VerifyIntegrity { .. }
+                | Request::AuditStatus { .. }
+                | Request::VerifyAudit { .. }
+                | Request::Stats { .. }
+                | Request::AuditQuery { .. }
+                | Request::Overview { .. }
+                | Request::List { .. }
+                | Request::Search { .. }
+                | Request::ExpiringSoon { .. }
+                | Request::FindDuplicates { .. }
+                | Request::ListTags { .. }
+                | Request::ListFavorites { .. }
+                | Request::ListFolder { .. }
+                | Request::ListFolders { .. }
+                | Request::Get { .. }
+                | Request::GetAttachment { .. }
+                | Request::UseForAuth { .. }
+                | Request::Touch { .. }
+                | Request::Describe { .. }
+                | Request::History { .. }
+        )
+    }
+
+    /// How long the daemon works on the request before giving up, given
+    /// the configured `default`
+    ///
+    /// Requests deriving keys with Argon2, or rewriting every category,
+    /// get at least `SLOW_REQUEST_TIMEOUT`; so does unlock, which also
+    /// sits out the failed-unlock backoff that a timeout mustn't cut short.
+    pub fn timeout(&self, default: StdDuration) -> StdDuration {
+        match self {
+            Request::Unlock { .. }
+            | Request::UpdatePassphrase { .. }
+            | Request::UpgradeKdf { .. }
+            | Request::EnrollHardwareKey { .. }
+            | Request::Verify { .. }
+            | Request::RotateDek { .. }
+            | Request::Gc { .. }
+            | Request::Export { .. } => default.max(SLOW_REQUEST_TIMEOUT),
+            Request::Batch { requests } => requests.iter()
+                .map(|r| r.timeout(default))
+                .fold(StdDuration::ZERO, StdDuration::saturating_add),
+            _ => default,
+        }
+    }
+
+    /// The request's `cmd` tag, for labelling metrics without reading
+    /// anything the request carries
+    pub fn command(&self) -> &'static str {
+        match self {
+            Request::Unlock { .. } => "unlock",
+            Request::Lock { .. } => "lock",
+            Request::Emergency { .. } => "emergency",
+            Request::RotateDek { .. } => "rotate_dek",
+            Request::Gc { .. } => "gc",
+            Request::SetMetaEncryption { .. } => "set_meta_encryption",
+            Request::UpdatePassphrase { .. } => "update_passphrase",
+            Request::UpgradeKdf { .. } => "upgrade_kdf",
+            Request::EnrollHardwareKey { .. } => "enroll_hardware_key",
+            Request::Verify { .. } => "verify",
+            Request::SetPolicy { .. } => "set_policy",
+            Request::GetPolicy { .. } => "get_policy",
+            Request::Export { .. } => "export",
+            Request::VerifyIntegrity { .. } => "verify_integrity",
+            Request::AuditStatus { .. } => "audit_status",
+            Request::VerifyAudit { .. } => "verify_audit",
+            Request::Stats { .. } => "stats",
+            Request::AuditQuery { .. } => "audit_query",
+            Request::Status => "status",
+            Request::Hello { .. } => "hello",
+            Request::Ping { .. } => "ping",
+            Request::Subscribe { .. } => "subscribe",
+            Request::Batch { .. } => "batch",
+            Request::Overview { .. } => "overview",
+            Request::Warm { .. } => "warm",
+            Request::List { .. } => "list",
+            Request::Search { .. } => "search",
+            Request::ExpiringSoon { .. } => "expiring_soon",
+            Request::FindDuplicates { .. } => "find_duplicates",
+            Request::Get { .. } => "get",
+            Request::CreateTotpFromUri { .. } => "create_totp_from_uri",
+            Request::Create { .. } => "create",
+            Request::ImportEntries { .. } => "import_entries",
+            Request::Update { .. } => "update",
+            Request::Delete { .. } => "delete",
+            Request::BulkDelete { .. } => "bulk_delete",
+            Request::Move { .. } => "move",
+            Request::AddAttachment { .. } => "add_attachment",
+            Request::GetAttachment { .. } => "get_attachment",
+            Request::RemoveAttachment { .. } => "remove_attachment",
+            Request::AddTags { .. } => "add_tags",
+            Request::RemoveTags { .. } => "remove_tags",
+            Request::ListTags { .. } => "list_tags",
+            Request::SetFavorite { .. } => "set_favorite",
+            Request::ListFavorites { .. } => "list_favorites",
+            Request::SetFolder { .. } => "set_folder",
+            Request::ListFolder { .. } => "list_folder",
+            Request::ListFolders { .. } => "list_folders",
+            Request::Rename { .. } => "rename",
+            Request::Clone { .. } => "clone",
+            Request::Rotate { .. } => "rotate",
+            Request::UpdateNotes { .. } => "update_notes",
+            Request::UseForAuth { .. } => "use_for_auth",
+            Request::Touch { .. } => "touch",
+            Request::Describe { .. } => "describe",
+            Request::History { .. } => "history",
+            Request::RestoreVersion { .. } => "restore_version",
+            Request::RefreshOAuth { .. } => "refresh_oauth",
+        }
+    }
 }
 
 /// Request to create a new entry
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct NewEntryRequest {
     pub category: Category,
     pub entry_type: EntryType,
     pub name: String,
-    pub value: String,  // Base64 encoded
+    /// The raw secret bytes, base64-encoded (also accepted as `value_b64`)
+    #[serde(alias = "value_b64")]
+    pub value: Secret,
     pub username: Option<String>,
     pub url: Option<String>,
+    pub pinned_cert_sha256: Option<String>,
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub custom_fields: Vec<CustomField>,
+    /// Reads allowed per minute; omitted uses the category default
+    #[serde(default)]
+    pub max_access_per_minute: Option<u32>,
+    /// Delete the entry once its value is read; always on for `one_time`
+    #[serde(default)]
+    pub consume_on_read: bool,
+    /// Markdown, as `UpdateNotes` sets it
+    #[serde(default)]
+    pub notes: Option<Secret>,
+    /// Folder path, as `SetFolder` sets it; `update` keeps the old one
+    #[serde(default)]
+    pub folder: Option<String>,
+}
+
+impl NewEntryRequest {
+    /// Check the fields that need no decoding, naming every one that fails
+    fn validate(&self) -> Result<(), Response> {
+        let mut problems = Vec::new();
+        if let Err(reason) = validate::check_name(&self.name) {
+            problems.push(format!("name: {}", reason));
+        }
+        if let Some(Err(reason)) = self.url.as_deref().map(validate::check_url) {
+            problems.push(format!("url: {}", reason));
+        }
+        if let Some(Err(reason)) = self.folder.as_deref().map(validate::clean_folder) {
+            problems.push(format!("folder: {}", reason));
+        }
+        if self.value.is_empty() {
+            problems.push("value: Value is empty".to_string());
+        }
+        if let Err(reason) = validate::check_category(self.entry_type, self.category) {
+            problems.push(format!("entry_type: {}", reason));
+        }
+        if let Some(Err(e)) = self.pinned_cert_sha256.as_deref().map(auth::parse_pin) {
+            problems.push(format!("pinned_cert_sha256: Invalid certificate pin: {}", e));
+        }
+        if self.max_access_per_minute == Some(0) {
+            problems.push("max_access_per_minute: must be at least 1".to_string());
+        }
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(Response::error(ErrorCode::InvalidRequest, problems.join("; "))),
+        }
+    }
+
+    /// Decode and validate into an entry, or the error to send back
+    fn into_entry(self) -> Result<VaultEntry, Response> {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        self.validate()?;
+        // Refused before decoding allocates for it
+        if self.value.len() > validate::MAX_VALUE_BYTES.div_ceil(3) * 4 {
+            return Err(Response::error(ErrorCode::InvalidRequest, format!(
+                "Value is over the {}-byte limit",
+                validate::MAX_VALUE_BYTES
+            )));
+        }
+        let value = STANDARD.decode(self.value.as_bytes())
+            .map_err(|e| Response::error(ErrorCode::InvalidRequest, format!("Invalid base64 value: {}", e)))?;
+        validate::check_value(self.entry_type, &value)
+            .map_err(|reason| Response::error(ErrorCode::InvalidRequest, reason))?;
+
+        let mut entry = VaultEntry::new(self.category, self.entry_type, self.name, value);
+        if let Some(username) = self.username {
+            entry = entry.with_username(username);
+        }
+        if let Some(url) = self.url {
+            entry = entry.with_url(url);
+        }
+        if let Some(pin) = self.pinned_cert_sha256 {
+            entry = entry.with_pinned_cert(pin);
+        }
+        if let Some(expires) = self.expires {
+            entry = entry.with_expires(expires);
+        }
+        if let Some(limit) = self.max_access_per_minute {
+            entry = entry.with_max_access_per_minute(limit);
+        }
+        if let Some(notes) = self.notes {
+            let notes = validate::clean_notes(&notes)
+                .map_err(|reason| Response::error(ErrorCode::InvalidRequest, format!("notes: {}", reason)))?;
+            entry.notes = Some(notes);
+        }
+        entry.folder = self.folder.as_deref().and_then(|f| validate::clean_folder(f).ok().flatten());
+        entry.custom_fields = self.custom_fields;
+        entry.consume_on_read |= self.consume_on_read;
+        Ok(entry)
+    }
+
+    /// `into_entry` for each request, all or nothing: the first bad one
+    /// is the error, numbered by its position
+    pub fn into_entries(requests: Vec<Self>) -> Result<Vec<VaultEntry>, Response> {
+        let mut entries = Vec::with_capacity(requests.len());
+        for (index, req) in requests.into_iter().enumerate() {
+            match req.into_entry() {
+                Ok(e) => entries.push(e),
+                Err(Response::Error { code, message }) => {
+                    return Err(Response::error(code, format!("Entry {}: {}", index, message)));
+                }
+                Err(response) => return Err(response),
+            }
+        }
+        Ok(entries)
+    }
 }
 
 /// API response types
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, JsonSchema)]
 #[serde(tag = "status", rename_all = "snake_case")]
 pub enum Response {
     Ok { data: Option<serde_json::Value> },
-    Error { message: String },
+    Error { code: ErrorCode, message: String },
+}
+
+/// What kind of failure an error response reports
+///
+/// Serialized as a stable snake_case string for clients to match on;
+/// `message` is human-readable detail and may change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    VaultLocked,
+    NotFound,
+    BadPassphrase,
+    InvalidRequest,
+    DecryptFailed,
+    PermissionDenied,
+    HardwareKeyRequired,
+    IncompatibleProtocol,
+    RateLimited,
+    WeakPassphrase,
+    AuditUnavailable,
+    CategoryLocked,
+    PepperRequired,
+    RequestTooLarge,
+    RequestTimeout,
+    UnsupportedVersion,
+    VaultInUse,
+    Internal,
+}
+
+impl ErrorCode {
+    /// Best code for an error from the vault layer
+    fn of(e: &anyhow::Error) -> Self {
+        if e.downcast_ref::<HardwareKeyUnavailable>().is_some() {
+            ErrorCode::HardwareKeyRequired
+        } else if e.downcast_ref::<DecryptionFailed>().is_some() {
+            ErrorCode::DecryptFailed
+        } else if e.downcast_ref::<VaultReadOnly>().is_some() {
+            ErrorCode::PermissionDenied
+        } else if e.downcast_ref::<WrongPassphrase>().is_some() {
+            ErrorCode::BadPassphrase
+        } else if e.downcast_ref::<CategoryLocked>().is_some() {
+            ErrorCode::CategoryLocked
+        } else if e.downcast_ref::<PepperUnavailable>().is_some() {
+            ErrorCode::PepperRequired
+        } else if e.downcast_ref::<UnsupportedVersion>().is_some() {
+            ErrorCode::UnsupportedVersion
+        } else if e.downcast_ref::<VaultInUse>().is_some() {
+            ErrorCode::VaultInUse
+        } else {
+            ErrorCode::Internal
+        }
+    }
 }
 
 impl Response {
@@ -68,315 +785,4626 @@ impl Response {
         }
     }
 
-    pub fn error(msg: impl Into<String>) -> Self {
-        Self::Error { message: msg.into() }
+    pub fn error(code: ErrorCode, msg: impl Into<String>) -> Self {
+        Self::Error { code, message: msg.into() }
+    }
+
+    /// `what` failed because of `e`, coded by the kind of error
+    pub fn failed(what: &str, e: &anyhow::Error) -> Self {
+        Self::error(ErrorCode::of(e), format!("{}: {}", what, e))
     }
 }
 
-/// Vault daemon state
-pub struct VaultDaemon {
-    vault: Option<Vault>,
-    audit: Option<AuditLog>,
-    vault_path: std::path::PathBuf,
+/// Which local users may talk to the daemon
+///
+/// Checked against the peer credentials of each connection before any
+/// request is read. An empty allowlist means only the daemon's own uid.
+#[derive(Debug, Clone)]
+pub struct PeerPolicy {
+    allowed_uids: Vec<u32>,
 }
 
-impl VaultDaemon {
-    pub fn new(vault_path: impl AsRef<Path>) -> Self {
-        Self {
-            vault: None,
-            audit: None,
-            vault_path: vault_path.as_ref().to_path_buf(),
-        }
+impl PeerPolicy {
+    pub fn new(allowed_uids: Vec<u32>) -> Self {
+        let allowed_uids = if allowed_uids.is_empty() {
+            // SAFETY: getuid has no preconditions and cannot fail
+            vec![unsafe { libc::getuid() }]
+        } else {
+            allowed_uids
+        };
+        Self { allowed_uids }
     }
 
-    /// Handle a request
-    pub async fn handle(&mut self, req: Request) -> Response {
-        match req {
-            Request::Unlock { passphrase, categories } => {
-                self.handle_unlock(&passphrase, categories).await
-            }
-            Request::Lock => self.handle_lock().await,
-            Request::Status => self.handle_status(),
-            Request::List { category } => self.handle_list(category).await,
-            Request::Get { id, agent_id, purpose } => {
-                self.handle_get(id, agent_id, purpose).await
-            }
-            Request::Create { entry } => self.handle_create(entry).await,
-            Request::Delete { id } => self.handle_delete(id).await,
-            Request::UseForAuth { id, target_url, agent_id, purpose } => {
-                self.handle_use_for_auth(id, target_url, agent_id, purpose).await
-            }
-        }
+    pub fn allows(&self, uid: u32) -> bool {
+        self.allowed_uids.contains(&uid)
     }
+}
 
-    async fn handle_unlock(&mut self, passphrase: &str, categories: Option<Vec<Category>>) -> Response {
-        // Try to open existing vault or create new one
-        let vault_result = if self.vault_path.exists() {
-            let mut vault = match Vault::open(&self.vault_path) {
-                Ok(v) => v,
-                Err(e) => return Response::error(format!("Failed to open vault: {}", e)),
-            };
-            
-            if let Some(cats) = categories {
-                match vault.unlock_categories(passphrase, &cats) {
-                    Ok(()) => Ok(vault),
-                    Err(e) => Err(e),
-                }
-            } else {
-                match vault.unlock(passphrase) {
-                    Ok(()) => Ok(vault),
-                    Err(e) => Err(e),
-                }
-            }
-        } else {
-            Vault::create(&self.vault_path, passphrase)
-        };
-
-        match vault_result {
-            Ok(vault) => {
-                // Initialize audit log
-                let master_key = crate::crypto::derive_master_key(
-                    passphrase, 
-                    &[0u8; 32] // Would get from vault meta
-                ).ok();
-                
-                if let Some(mk) = master_key {
-                    let audit_key = derive_subkey(&mk, "audit");
-                    let audit_path = self.vault_path.join("audit.enc");
-                    self.audit = AuditLog::open(&audit_path, audit_key).ok();
-                    
-                    if let Some(ref mut audit) = self.audit {
-                        let _ = audit.log_unlock();
-                    }
-                }
-                
-                self.vault = Some(vault);
-                Response::ok()
-            }
-            Err(e) => Response::error(format!("Unlock failed: {}", e)),
+/// An entry as clients see it
+///
+/// Values are arbitrary bytes (NULs, invalid UTF-8), so they always go
+/// out as standard, padded base64 under `value_b64`; the name says what
+/// to decode rather than leaving clients to guess from the content. Every
+/// other field is as `VaultEntry` serializes it.
+fn entry_response(entry: &VaultEntry) -> Response {
+    let mut json = match serde_json::to_value(entry) {
+        Ok(json) => json,
+        Err(e) => return Response::failed("Get failed", &e.into()),
+    };
+    if let Some(fields) = json.as_object_mut() {
+        if let Some(value) = fields.remove("value") {
+            fields.insert("value_b64".into(), value);
         }
     }
+    Response::Ok { data: Some(json) }
+}
 
-    async fn handle_lock(&mut self) -> Response {
-        if let Some(ref mut vault) = self.vault {
-            if let Some(ref mut audit) = self.audit {
-                let _ = audit.log_lock();
-            }
-            vault.lock();
-            self.vault = None;
-            self.audit = None;
-            Response::ok()
-        } else {
-            Response::error("Vault not unlocked")
-        }
+/// Version of the JSON request/response protocol, reported by `ping`
+///
+/// Bumped when a change would break existing clients; added commands and
+/// optional fields don't count. v2 moved secret values in responses to
+/// base64 `value_b64` / `data_b64` fields.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest client protocol version the daemon still serves
+const MIN_PROTOCOL_VERSION: u32 = 2;
+
+/// Optional capabilities advertised in the greeting
+const FEATURES: &[&str] = &[
+    "batch",
+    "subscribe",
+    "named_vaults",
+    "paging",
+    "attachments",
+    "error_codes",
+];
+
+/// First line the daemon sends on every accepted connection
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Greeting {
+    pub protocol_version: u32,
+    pub features: &'static [&'static str],
+}
+
+impl Greeting {
+    pub fn current() -> Self {
+        Self { protocol_version: PROTOCOL_VERSION, features: FEATURES }
     }
+}
 
-    fn handle_status(&self) -> Response {
-        #[derive(Serialize)]
-        struct Status {
-            unlocked: bool,
-            vault_exists: bool,
-        }
-        
-        Response::ok_with(Status {
-            unlocked: self.vault.as_ref().map(|v| v.is_unlocked()).unwrap_or(false),
-            vault_exists: self.vault_path.exists(),
-        })
+/// Accept or refuse a client's `hello`
+fn handle_hello(client_version: u32) -> Response {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&client_version) {
+        return Response::error(ErrorCode::IncompatibleProtocol, format!(
+            "Client protocol v{} is not supported; this daemon speaks v{} to v{}",
+            client_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ));
     }
+    Response::ok_with(Greeting::current())
+}
 
-    async fn handle_list(&mut self, category: Category) -> Response {
-        let vault = match self.vault.as_mut() {
-            Some(v) if v.is_unlocked() => v,
-            _ => return Response::error("Vault not unlocked"),
-        };
+/// Buffered audit events per subscriber before it is considered lagging
+const AUDIT_CHANNEL_CAPACITY: usize = 256;
 
-        match vault.list_entries(category) {
-            Ok(entries) => Response::ok_with(entries),
-            Err(e) => Response::error(format!("List failed: {}", e)),
-        }
-    }
+/// Page size for a `list` that gives an offset but no limit
+const DEFAULT_PAGE_SIZE: usize = 100;
 
-    async fn handle_get(
-        &mut self,
-        id: Uuid,
-        agent_id: Option<String>,
-        purpose: Option<String>,
-    ) -> Response {
-        let vault = match self.vault.as_mut() {
-            Some(v) if v.is_unlocked() => v,
-            _ => return Response::error("Vault not unlocked"),
-        };
+/// Largest page a single `list` returns
+const MAX_PAGE_SIZE: usize = 1000;
 
-        match vault.get_entry(&id) {
-            Ok(Some(entry)) => {
-                // Log access
-                if let Some(ref mut audit) = self.audit {
-                    let _ = audit.log_access(
-                        id,
-                        &entry.name,
-                        entry.category,
-                        agent_id.as_deref(),
-                        purpose.as_deref(),
-                    );
-                }
-                
-                // Return handle (not raw value in production)
-                // For now, return full entry
-                Response::ok_with(entry)
-            }
-            Ok(None) => Response::error("Entry not found"),
-            Err(e) => Response::error(format!("Get failed: {}", e)),
-        }
+/// Entries an `audit_query` without a `limit` returns
+const DEFAULT_AUDIT_QUERY_LIMIT: usize = 1000;
+
+/// Daemon runtime options (from the command line)
+#[derive(Debug, Clone)]
+pub struct DaemonConfig {
+    pub peer_policy: PeerPolicy,
+    /// Lock the vault after this long without a request
+    pub lock_timeout: Option<StdDuration>,
+    /// Also compact unlocked vaults' audit logs this often, besides on lock
+    pub audit_compact_interval: Option<StdDuration>,
+    /// Vaults besides the default, selectable by name
+    pub named_vaults: Vec<(String, PathBuf)>,
+    /// Unlock the default vault with this before accepting connections
+    pub unlock_passphrase: Option<Zeroizing<String>>,
+    /// Most bytes of decrypted categories each vault keeps cached
+    pub cache_budget: Option<u64>,
+    /// Strength score (0-4) a new vault's passphrase needs
+    pub min_passphrase_score: u8,
+    /// Refuse to unlock a vault whose audit log can't be opened
+    pub require_audit: bool,
+    /// Purge audit entries older than this whenever a vault is unlocked
+    pub audit_retention: Option<StdDuration>,
+    /// When each vault's active audit log is sealed into an archive
+    pub audit_rotation: RotationPolicy,
+    /// How `UseForAuth` targets must match entry URLs
+    pub domain_policy: DomainPolicy,
+    /// Secret mixed into the KEK of vaults created from now on, and needed
+    /// by those created with one
+    pub pepper_file: Option<PathBuf>,
+    /// Longest request line (or WebSocket message) accepted, in bytes
+    pub max_request_bytes: usize,
+    /// Give up on a request after this long (see `Request::timeout`)
+    pub request_timeout: StdDuration,
+    /// Remove the socket on an emergency lock (`Request::Emergency` or
+    /// SIGUSR1)
+    pub emergency_remove_socket: bool,
+    /// Serve Prometheus metrics on `GET /metrics` at this address
+    pub metrics_listen: Option<SocketAddr>,
+    /// Optional WebSocket listener alongside the Unix socket
+    #[cfg(feature = "websocket")]
+    pub websocket: Option<crate::ws::WsConfig>,
+}
+
+/// Parse `--metrics-listen` or `--ws-listen`: a bare port binds to
+/// localhost, otherwise `IP:PORT`
+pub fn parse_listen_addr(arg: &str) -> Result<SocketAddr, String> {
+    if let Ok(port) = arg.parse::<u16>() {
+        return Ok(SocketAddr::from((Ipv4Addr::LOCALHOST, port)));
     }
+    arg.parse()
+        .map_err(|_| format!("expected PORT or IP:PORT, got '{}'", arg))
+}
 
-    async fn handle_create(&mut self, req: NewEntryRequest) -> Response {
-        let vault = match self.vault.as_mut() {
-            Some(v) if v.is_unlocked() => v,
-            _ => return Response::error("Vault not unlocked"),
-        };
+/// Default for `DaemonConfig::max_request_bytes`: room for the largest
+/// attachment, which base64 makes a third bigger
+pub const DEFAULT_MAX_REQUEST_BYTES: usize = 16 * 1024 * 1024;
 
-        // Decode value
-        use base64::{Engine as _, engine::general_purpose::STANDARD};
-        let value = match STANDARD.decode(&req.value) {
-            Ok(v) => v,
-            Err(e) => return Response::error(format!("Invalid base64 value: {}", e)),
-        };
+/// A vault's active audit log, in its directory
+pub const AUDIT_LOG_FILE: &str = "audit.enc";
 
-        let mut entry = VaultEntry::new(req.category, req.entry_type, req.name, value);
-        if let Some(username) = req.username {
-            entry = entry.with_username(username);
+/// Default for `DaemonConfig::request_timeout`
+pub const DEFAULT_REQUEST_TIMEOUT: StdDuration = StdDuration::from_secs(30);
+
+/// Least time `Request::timeout` gives requests that are slow by design
+const SLOW_REQUEST_TIMEOUT: StdDuration = StdDuration::from_secs(120);
+
+/// How often the idle-lock task checks for inactivity
+const IDLE_CHECK_INTERVAL: StdDuration = StdDuration::from_secs(5);
+
+/// How often uses recorded in `access_count` are written to disk
+const ACCESS_FLUSH_INTERVAL: StdDuration = StdDuration::from_secs(60);
+
+/// Longest window a request may look back or ahead, a century; past it
+/// the date arithmetic would run off the calendar
+const MAX_WINDOW_HOURS: i64 = 100 * 366 * 24;
+
+/// `hours` of a request's `field` as a window, or the refusal to answer
+/// if it isn't a positive number of hours up to `MAX_WINDOW_HOURS`
+fn hours_window(field: &str, hours: i64) -> Result<Duration, Response> {
+    Duration::try_hours(hours)
+        .filter(|_| hours > 0 && hours <= MAX_WINDOW_HOURS)
+        .ok_or_else(|| Response::error(
+            ErrorCode::InvalidRequest,
+            format!("{} must be a positive number of hours, at most {}", field, MAX_WINDOW_HOURS),
+        ))
+}
+
+/// Default expiry window of `Request::Overview`
+const OVERVIEW_EXPIRY_HOURS: i64 = 7 * 24;
+
+/// Delay after the first failed unlock; doubles with each further failure
+const UNLOCK_BACKOFF_BASE: StdDuration = StdDuration::from_millis(500);
+
+/// Ceiling for the failed-unlock delay
+const UNLOCK_BACKOFF_MAX: StdDuration = StdDuration::from_secs(30);
+
+/// Delay imposed after the `failures`-th consecutive failed unlock
+fn unlock_backoff(failures: u32) -> StdDuration {
+    let doublings = failures.saturating_sub(1).min(16);
+    UNLOCK_BACKOFF_BASE
+        .saturating_mul(1 << doublings)
+        .min(UNLOCK_BACKOFF_MAX)
+}
+
+/// Window `max_access_per_minute` is counted over
+const ACCESS_WINDOW: StdDuration = StdDuration::from_secs(60);
+
+/// Sliding-window count of recent reads per entry
+#[derive(Debug, Default)]
+struct AccessLimiter {
+    recent: HashMap<Uuid, VecDeque<Instant>>,
+}
+
+impl AccessLimiter {
+    /// Count a read of `id` at `now`, unless `limit` reads already
+    /// happened in the window before it
+    ///
+    /// Refused reads aren't counted, so a caller that backs off gets
+    /// through again once the window slides past its earlier reads.
+    fn try_access(&mut self, id: Uuid, limit: u32, now: Instant) -> bool {
+        let times = self.recent.entry(id).or_default();
+        while times.front().is_some_and(|t| now.duration_since(*t) >= ACCESS_WINDOW) {
+            times.pop_front();
         }
-        if let Some(url) = req.url {
-            entry = entry.with_url(url);
+        if times.len() >= limit as usize {
+            return false;
         }
+        times.push_back(now);
+        true
+    }
+}
 
-        match vault.add_entry(entry) {
-            Ok(id) => Response::ok_with(serde_json::json!({ "id": id })),
-            Err(e) => Response::error(format!("Create failed: {}", e)),
+/// How long a create's `idempotency_key` is remembered
+const IDEMPOTENCY_TTL: StdDuration = StdDuration::from_secs(5 * 60);
+
+/// Longest `idempotency_key` accepted
+const MAX_IDEMPOTENCY_KEY_CHARS: usize = 128;
+
+/// Most keys remembered at once; the oldest go first past this
+const MAX_IDEMPOTENCY_KEYS: usize = 10_000;
+
+/// Entries recently created with an `idempotency_key`, by agent and key
+#[derive(Debug, Default)]
+struct IdempotencyKeys {
+    created: HashMap<(Option<String>, String), (Uuid, Instant)>,
+}
+
+impl IdempotencyKeys {
+    /// The entry created under `key` less than `IDEMPOTENCY_TTL` before `now`
+    fn get(&self, agent_id: Option<&str>, key: &str, now: Instant) -> Option<Uuid> {
+        let (id, at) = self.created.get(&(agent_id.map(str::to_string), key.to_string()))?;
+        (now.duration_since(*at) < IDEMPOTENCY_TTL).then_some(*id)
+    }
+
+    fn insert(&mut self, agent_id: Option<String>, key: String, id: Uuid, now: Instant) {
+        self.created.retain(|_, (_, at)| now.duration_since(*at) < IDEMPOTENCY_TTL);
+        if self.created.len() >= MAX_IDEMPOTENCY_KEYS {
+            let oldest = self.created.iter().min_by_key(|(_, (_, at))| *at).map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                self.created.remove(&oldest);
+            }
         }
+        self.created.insert((agent_id, key), (id, now));
     }
+}
 
-    async fn handle_delete(&mut self, id: Uuid) -> Response {
-        let vault = match self.vault.as_mut() {
-            Some(v) if v.is_unlocked() => v,
-            _ => return Response::error("Vault not unlocked"),
-        };
+/// How a vault's audit log is doing, for `Request::AuditStatus`
+#[derive(Debug, Clone, Default, Serialize)]
+struct AuditHealth {
+    /// Why the log isn't running, or why its chain couldn't be checked
+    error: Option<String>,
+    /// Outcome of the last chain verification
+    chain: Option<ChainVerification>,
+    verified_at: Option<DateTime<Utc>>,
+}
 
-        match vault.delete_entry(&id) {
-            Ok(true) => Response::ok(),
-            Ok(false) => Response::error("Entry not found"),
-            Err(e) => Response::error(format!("Delete failed: {}", e)),
+impl AuditHealth {
+    /// Keep the outcome of a chain verification, warning if it failed
+    fn record_chain(&mut self, result: Result<ChainVerification>, vault_path: &Path) {
+        self.verified_at = Some(Utc::now());
+        match result {
+            Ok(chain) => {
+                match chain {
+                    ChainVerification::Valid => {}
+                    ChainVerification::Broken { index, .. } => {
+                        tracing::warn!("Audit chain of {:?} is broken at entry {}", vault_path, index);
+                    }
+                    ChainVerification::Truncated { checkpoint, entries } => tracing::warn!(
+                        "Audit chain of {:?} has {} entries but was checkpointed at {}",
+                        vault_path, entries, checkpoint,
+                    ),
+                    ChainVerification::BadCheckpoint { count } => {
+                        tracing::warn!("Audit checkpoint of {:?} at entry {} doesn't verify", vault_path, count);
+                    }
+                    ChainVerification::BadAnchor => {
+                        tracing::warn!("Audit purge record of {:?} doesn't verify", vault_path);
+                    }
+                }
+                self.chain = Some(chain);
+                self.error = None;
+            }
+            Err(e) => {
+                tracing::warn!("Audit chain of {:?} could not be verified: {:#}", vault_path, e);
+                self.chain = None;
+                self.error = Some(format!("Chain verification failed: {:#}", e));
+            }
         }
     }
+}
 
-    async fn handle_use_for_auth(
-        &mut self,
-        id: Uuid,
-        target_url: String,
-        agent_id: String,
-        purpose: String,
-    ) -> Response {
-        // In production, this would:
-        // 1. Verify target_url matches entry's associated URL
-        // 2. Check certificate pinning
-        // 3. Make the HTTP request directly from daemon
-        // 4. Return only success/failure (not the credential)
-        
-        // For now, just validate and return placeholder
-        let vault = match self.vault.as_mut() {
-            Some(v) if v.is_unlocked() => v,
-            _ => return Response::error("Vault not unlocked"),
+/// Name of the vault used when a request doesn't pick one
+pub const DEFAULT_VAULT: &str = "default";
+
+/// One vault known to the daemon, locked or unlocked
+pub struct VaultSession {
+    vault: Option<Vault>,
+    audit: StdMutex<Option<AuditLog>>,
+    vault_path: PathBuf,
+    audit_events: broadcast::Sender<AuditEntry>,
+    anomaly_thresholds: AnomalyThresholds,
+    cache_budget: Option<u64>,
+    min_passphrase_score: u8,
+    require_audit: bool,
+    audit_retention: Option<StdDuration>,
+    audit_rotation: RotationPolicy,
+    domain_policy: DomainPolicy,
+    pepper_file: Option<PathBuf>,
+    /// Why the audit log is off, and how its chain last verified
+    audit_health: StdMutex<AuditHealth>,
+    /// Consecutive failed unlocks, for backoff
+    failed_unlocks: u32,
+    /// Failed unlocks not yet in the audit log (it needs the vault unlocked)
+    unaudited_unlock_failures: u32,
+    /// Recent reads of rate-limited entries
+    access_limiter: StdMutex<AccessLimiter>,
+    /// Recent creates that named an `idempotency_key`
+    idempotency_keys: IdempotencyKeys,
+}
+
+/// Vault daemon state
+pub struct VaultDaemon {
+    sessions: HashMap<String, VaultSession>,
+    anomaly_thresholds: AnomalyThresholds,
+    cache_budget: Option<u64>,
+    min_passphrase_score: u8,
+    require_audit: bool,
+    audit_retention: Option<StdDuration>,
+    audit_rotation: RotationPolicy,
+    domain_policy: DomainPolicy,
+    pepper_file: Option<PathBuf>,
+    /// Behind a lock since read-only requests only borrow the daemon
+    last_activity: StdMutex<Instant>,
+    started: Instant,
+    metrics: Arc<Metrics>,
+    /// Socket to remove on an emergency lock, if so configured
+    emergency_socket: Option<PathBuf>,
+}
+
+impl VaultDaemon {
+    pub fn new(vault_path: impl AsRef<Path>) -> Self {
+        let mut daemon = Self {
+            sessions: HashMap::new(),
+            anomaly_thresholds: AnomalyThresholds::default(),
+            cache_budget: None,
+            min_passphrase_score: 0,
+            require_audit: false,
+            audit_retention: None,
+            audit_rotation: RotationPolicy::default(),
+            domain_policy: DomainPolicy::default(),
+            pepper_file: None,
+            last_activity: StdMutex::new(Instant::now()),
+            started: Instant::now(),
+            metrics: Arc::default(),
+            emergency_socket: None,
         };
+        daemon.register(DEFAULT_VAULT, vault_path.as_ref());
+        daemon
+    }
 
-        match vault.get_entry(&id) {
-            Ok(Some(entry)) => {
-                // Log the auth use
-                if let Some(ref mut audit) = self.audit {
-                    let _ = audit.log_access(
-                        id,
-                        &entry.name,
-                        entry.category,
-                        Some(&agent_id),
-                        Some(&purpose),
-                    );
+    /// Make a vault available under `name` (locked until unlocked)
+    pub fn register(&mut self, name: &str, path: impl AsRef<Path>) {
+        let mut session = VaultSession::new(path, self.anomaly_thresholds.clone(), self.cache_budget);
+        session.min_passphrase_score = self.min_passphrase_score;
+        session.require_audit = self.require_audit;
+        session.audit_retention = self.audit_retention;
+        session.audit_rotation = self.audit_rotation.clone();
+        session.domain_policy = self.domain_policy;
+        session.pepper_file = self.pepper_file.clone();
+        self.sessions.insert(name.to_string(), session);
+    }
+
+    /// Refuse unlocks that would leave a vault without a working audit log
+    pub fn with_require_audit(mut self, require: bool) -> Self {
+        self.require_audit = require;
+        for session in self.sessions.values_mut() {
+            session.require_audit = require;
+        }
+        self
+    }
+
+    /// Purge audit entries older than `retention` each time a vault is
+    /// unlocked (`AuditLog::purge_older_than`); `None` keeps them all
+    pub fn with_audit_retention(mut self, retention: Option<StdDuration>) -> Self {
+        self.audit_retention = retention;
+        for session in self.sessions.values_mut() {
+            session.audit_retention = retention;
+        }
+        self
+    }
+
+    /// Seal each vault's active audit log into an archive as `rotation`
+    /// says (`AuditLog::rotate`)
+    ///
+    /// Applies to audit logs opened from now on.
+    pub fn with_audit_rotation(mut self, rotation: RotationPolicy) -> Self {
+        for session in self.sessions.values_mut() {
+            session.audit_rotation = rotation.clone();
+        }
+        self.audit_rotation = rotation;
+        self
+    }
+
+    /// Match `UseForAuth` targets to entry URLs under `policy`
+    pub fn with_domain_policy(mut self, policy: DomainPolicy) -> Self {
+        self.domain_policy = policy;
+        for session in self.sessions.values_mut() {
+            session.domain_policy = policy;
+        }
+        self
+    }
+
+    /// Read the pepper from `path` when a vault is created or unlocked
+    ///
+    /// New vaults require it from then on; existing vaults without one
+    /// ignore it.
+    pub fn with_pepper_file(mut self, path: Option<PathBuf>) -> Self {
+        for session in self.sessions.values_mut() {
+            session.pepper_file = path.clone();
+        }
+        self.pepper_file = path;
+        self
+    }
+
+    /// Remove `socket` on an emergency lock, so no new connections can be
+    /// made until the daemon restarts
+    pub fn with_emergency_socket(mut self, socket: Option<PathBuf>) -> Self {
+        self.emergency_socket = socket;
+        self
+    }
+
+    /// Refuse to create vaults whose passphrase scores below `score` (0-4)
+    /// unless the unlock sets `force`; 0 (the default) accepts anything
+    pub fn with_min_passphrase_score(mut self, score: u8) -> Self {
+        self.min_passphrase_score = score;
+        for session in self.sessions.values_mut() {
+            session.min_passphrase_score = score;
+        }
+        self
+    }
+
+    /// Cap the decrypted categories each vault keeps in memory (`None`: no cap)
+    ///
+    /// Applies to vaults unlocked from now on.
+    pub fn with_cache_budget(mut self, budget: Option<u64>) -> Self {
+        self.cache_budget = budget;
+        for session in self.sessions.values_mut() {
+            session.cache_budget = budget;
+        }
+        self
+    }
+
+    /// The session for `name` (or the default vault)
+    fn session(&self, name: Option<&str>) -> Result<&VaultSession, Response> {
+        let name = name.unwrap_or(DEFAULT_VAULT);
+        self.sessions.get(name)
+            .ok_or_else(|| Response::error(ErrorCode::NotFound, format!("Unknown vault '{}'", name)))
+    }
+
+    /// The session for `name` (or the default vault), for changing it
+    fn session_mut(&mut self, name: Option<&str>) -> Result<&mut VaultSession, Response> {
+        let name = name.unwrap_or(DEFAULT_VAULT);
+        self.sessions.get_mut(name)
+            .ok_or_else(|| Response::error(ErrorCode::NotFound, format!("Unknown vault '{}'", name)))
+    }
+
+    /// Unlock the default vault read-write before any client connects
+    pub async fn unlock_at_startup(&mut self, passphrase: &str) -> Result<()> {
+        let session = self.session_mut(None).map_err(|_| anyhow::anyhow!("No default vault"))?;
+        match session.handle_unlock(passphrase, None, AccessMode::ReadWrite, false).await {
+            Response::Error { message, .. } => Err(anyhow::anyhow!("Startup unlock failed: {}", message)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Subscribe to live audit events (vault must be unlocked)
+    pub fn subscribe(&self, name: Option<&str>) -> Result<broadcast::Receiver<AuditEntry>, Response> {
+        let session = self.session(name)?;
+        match session.vault {
+            Some(ref v) if v.is_unlocked() => Ok(session.audit_events.subscribe()),
+            _ => Err(Response::error(ErrorCode::VaultLocked, "Vault not unlocked")),
+        }
+    }
+
+    /// Record a rejected connection in every unlocked vault's audit log
+    fn log_peer_denial(&self, uid: u32, pid: Option<i32>) {
+        let reason = match pid {
+            Some(pid) => format!("peer uid {} (pid {}) not allowed", uid, pid),
+            None => format!("peer uid {} not allowed", uid),
+        };
+        for session in self.sessions.values() {
+            if let Some(audit) = audit_log(&session.audit).as_mut() {
+                let _ = audit.log_denial(&reason, None, None);
+            }
+        }
+    }
+
+    /// Lock every vault if no request arrived within `timeout`
+    ///
+    /// Returns true if any vault was locked by this call.
+    pub async fn lock_if_idle(&mut self, timeout: StdDuration) -> bool {
+        if self.last_activity.lock().unwrap_or_else(PoisonError::into_inner).elapsed() < timeout {
+            return false;
+        }
+
+        let mut locked = false;
+        for (name, session) in self.sessions.iter_mut() {
+            if session.is_unlocked() {
+                tracing::info!("Locking vault '{}' after {:?} idle", name, timeout);
+                if matches!(session.handle_lock().await, Response::Ok { .. }) {
+                    self.metrics.record_lock();
+                    locked = true;
                 }
-                
-                // TODO: Actually perform auth
-                // For now, indicate credential would be used
-                Response::ok_with(serde_json::json!({
-                    "auth_performed": false,
-                    "message": "Auth execution not yet implemented",
-                    "target": target_url,
-                }))
             }
-            Ok(None) => Response::error("Entry not found"),
-            Err(e) => Response::error(format!("Auth failed: {}", e)),
         }
+        locked
     }
-}
 
-/// Run the vault daemon on a Unix socket
-pub async fn run_daemon(socket_path: impl AsRef<Path>, vault_path: impl AsRef<Path>) -> Result<()> {
-    let socket_path = socket_path.as_ref();
-    
-    // Remove existing socket
-    if socket_path.exists() {
-        std::fs::remove_file(socket_path)?;
+    /// Lock every vault at once on a suspected compromise
+    ///
+    /// Each vault's audit log records it (naming `trigger`) before its
+    /// keys and decrypted categories are dropped. Returns the vaults that
+    /// were unlocked, by name.
+    pub fn emergency(&mut self, trigger: &str, agent_id: Option<&str>) -> Vec<String> {
+        tracing::warn!("Emergency lock ({})", trigger);
+        let mut locked: Vec<String> = self.sessions.iter_mut()
+            .filter_map(|(name, session)| session.emergency_lock(trigger, agent_id).then(|| name.clone()))
+            .collect();
+        locked.sort();
+        for _ in &locked {
+            self.metrics.record_lock();
+        }
+
+        if let Some(socket) = &self.emergency_socket {
+            match std::fs::remove_file(socket) {
+                Ok(()) => tracing::warn!("Removed {:?}; restart the daemon to accept connections again", socket),
+                Err(e) => tracing::error!("Could not remove {:?}: {}", socket, e),
+            }
+        }
+        locked
     }
-    
-    // Create parent directory
-    if let Some(parent) = socket_path.parent() {
-        std::fs::create_dir_all(parent)?;
+
+    /// Counters shared with the connections and the metrics listener
+    pub fn metrics(&self) -> Arc<Metrics> {
+        Arc::clone(&self.metrics)
     }
-    
-    let listener = UnixListener::bind(socket_path)?;
-    
-    // Set socket permissions (owner only)
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600))?;
+
+    /// Each registered vault's name and whether it's unlocked, by name
+    pub fn lock_states(&self) -> Vec<(String, bool)> {
+        let mut states: Vec<(String, bool)> = self.sessions.iter()
+            .map(|(name, session)| (name.clone(), session.is_unlocked()))
+            .collect();
+        states.sort();
+        states
     }
-    
-    tracing::info!("Vault daemon listening on {:?}", socket_path);
-    
-    let daemon = Arc::new(Mutex::new(VaultDaemon::new(vault_path)));
-    
-    loop {
-        let (stream, _) = listener.accept().await?;
-        let daemon = Arc::clone(&daemon);
-        
-        tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, daemon).await {
-                tracing::error!("Connection error: {}", e);
+
+    /// Whether any vault has entry uses not yet written to disk
+    pub fn has_pending_access(&self) -> bool {
+        self.sessions.values()
+            .filter_map(|s| s.vault.as_ref())
+            .any(|v| v.has_pending_access())
+    }
+
+    /// Write every vault's recorded entry uses to disk
+    pub fn flush_access(&mut self) {
+        for (name, session) in self.sessions.iter_mut() {
+            if let Some(vault) = session.vault.as_mut().filter(|v| v.is_unlocked()) {
+                if let Err(e) = vault.flush_access() {
+                    tracing::warn!("Could not save access counts for vault '{}': {}", name, e);
+                }
+                vault.trim_cache();
             }
-        });
+        }
     }
-}
 
-async fn handle_connection(stream: UnixStream, daemon: Arc<Mutex<VaultDaemon>>) -> Result<()> {
-    let (reader, mut writer) = stream.into_split();
-    let mut reader = BufReader::new(reader);
-    let mut line = String::new();
-    
-    loop {
-        line.clear();
-        let n = reader.read_line(&mut line).await?;
-        if n == 0 {
-            break; // Connection closed
+    /// Compact every unlocked vault's audit log
+    pub fn compact_audit(&mut self) {
+        for session in self.sessions.values() {
+            // Only unlocked vaults have their audit log open
+            if let Some(audit) = audit_log(&session.audit).as_mut() {
+                compact_audit(audit);
+            }
         }
-        
-        let response = match serde_json::from_str::<Request>(&line) {
-            Ok(req) => {
-                let mut daemon = daemon.lock().await;
-                daemon.handle(req).await
+    }
+
+    /// Handle a request
+    pub async fn handle(&mut self, req: Request) -> Response {
+        self.touch(&req);
+        match req {
+            Request::Batch { requests } => self.handle_batch(requests).await,
+            req => self.dispatch(req).await,
+        }
+    }
+
+    /// Handle a read-only request with the daemon only borrowed shared
+    pub async fn handle_read(&self, req: Request) -> Response {
+        if !req.is_read_only() || self.consumes_on_read(&req) {
+            return Response::error(ErrorCode::InvalidRequest, "Not a read-only request");
+        }
+        self.touch(&req);
+        self.dispatch_read(req).await
+    }
+
+    /// Note activity for the idle lock
+    fn touch(&self, req: &Request) {
+        // A supervisor polling for liveness mustn't hold the vault open
+        if !matches!(req, Request::Ping { .. }) {
+            *self.last_activity.lock().unwrap_or_else(PoisonError::into_inner) = Instant::now();
+        }
+    }
+
+    /// Whether `req` would find its vault's category cache cold or stale
+    pub fn is_cache_cold(&self, req: &Request) -> bool {
+        if matches!(req, Request::Status | Request::Hello { .. } | Request::Ping { .. }) {
+            return false;
+        }
+        self.session(req.vault()).ok()
+            .and_then(|s| s.vault.as_ref())
+            .is_some_and(|v| v.is_unlocked() && !v.is_cache_warm())
+    }
+
+    /// Whether `req` reads an entry that its read then deletes, so it needs
+    /// the daemon exclusively
+    pub fn consumes_on_read(&self, req: &Request) -> bool {
+        let Request::Get { id, .. } = req else {
+            return false;
+        };
+        self.session(req.vault()).is_ok_and(|s| s.consumes_on_read(id))
+    }
+
+    /// Load the categories of `req`'s vault into its cache
+    ///
+    /// Failures are left for the request itself to report.
+    pub fn warm_cache(&mut self, req: &Request) {
+        if let Ok(session) = self.session_mut(req.vault()) {
+            if let Some(vault) = session.vault.as_mut().filter(|v| v.is_unlocked()) {
+                let _ = vault.warm_cache();
+            }
+        }
+    }
+
+    /// Run sub-requests in order; the caller holds the daemon lock throughout
+    async fn handle_batch(&mut self, requests: Vec<Request>) -> Response {
+        let mut responses = Vec::with_capacity(requests.len());
+        for req in requests {
+            responses.push(self.dispatch(req).await);
+        }
+        Response::ok_with(responses)
+    }
+
+    async fn dispatch(&mut self, req: Request) -> Response {
+        match req {
+            Request::Unlock { vault, path, passphrase, categories, mode, force } => {
+                let name = vault.unwrap_or_else(|| DEFAULT_VAULT.to_string());
+                let session = match self.session_for_unlock(&name, path) {
+                    Ok(s) => s,
+                    Err(response) => return response,
+                };
+                let response = session.handle_unlock(&passphrase, categories, mode, force).await;
+                if matches!(response, Response::Ok { .. }) {
+                    self.metrics.record_unlock();
+                }
+                response
+            }
+            Request::Emergency { agent_id } => {
+                let locked = self.emergency("emergency request", agent_id.as_deref());
+                Response::ok_with(serde_json::json!({ "locked": locked }))
+            }
+            Request::Subscribe { .. } => {
+                Response::error(ErrorCode::InvalidRequest, "Subscribe must be the only request on its connection")
+            }
+            Request::Batch { .. } => Response::error(ErrorCode::InvalidRequest, "Batch requests cannot be nested"),
+            req if req.is_read_only() && !self.consumes_on_read(&req) => self.dispatch_read(req).await,
+            req => {
+                let locking = matches!(req, Request::Lock { .. });
+                let session = match self.session_mut(req.vault()) {
+                    Ok(s) => s,
+                    Err(response) => return response,
+                };
+                let response = session.dispatch(req).await;
+                // Trim between requests, when nothing still uses the cache
+                if let Some(vault) = session.vault.as_mut() {
+                    vault.trim_cache();
+                }
+                if locking && matches!(response, Response::Ok { .. }) {
+                    self.metrics.record_lock();
+                }
+                response
+            }
+        }
+    }
+
+    async fn dispatch_read(&self, req: Request) -> Response {
+        match req {
+            Request::Status => self.handle_status(),
+            Request::Hello { client_version } => handle_hello(client_version),
+            Request::Ping { vault } => self.handle_ping(vault.as_deref()),
+            req => {
+                let session = match self.session(req.vault()) {
+                    Ok(s) => s,
+                    Err(response) => return response,
+                };
+                session.dispatch_read(req).await
+            }
+        }
+    }
+
+    /// Find or register the session an unlock targets
+    ///
+    /// A registered name is used as-is; an explicit path registers a new
+    /// name, but never silently re-points an existing one.
+    fn session_for_unlock(
+        &mut self,
+        name: &str,
+        path: Option<PathBuf>,
+    ) -> Result<&mut VaultSession, Response> {
+        if name.is_empty() {
+            return Err(Response::error(ErrorCode::InvalidRequest, "Vault name must not be empty"));
+        }
+
+        match (self.sessions.get(name), path) {
+            (Some(existing), Some(path)) if existing.vault_path != path => {
+                Err(Response::error(ErrorCode::InvalidRequest, format!(
+                    "Vault '{}' is already registered at {:?}",
+                    name, existing.vault_path
+                )))
             }
-            Err(e) => Response::error(format!("Invalid request: {}", e)),
+            (None, Some(path)) => {
+                self.register(name, path);
+                self.session_mut(Some(name))
+            }
+            _ => self.session_mut(Some(name)),
+        }
+    }
+
+    fn handle_ping(&self, name: Option<&str>) -> Response {
+        #[derive(Serialize)]
+        struct Pong {
+            protocol_version: u32,
+            daemon_version: &'static str,
+            uptime_secs: u64,
+            vault_exists: bool,
+            unlocked: bool,
+        }
+
+        let uptime_secs = self.started.elapsed().as_secs();
+        let session = match self.session(name) {
+            Ok(s) => s,
+            Err(response) => return response,
         };
-        
-        let response_json = serde_json::to_string(&response)? + "\n";
-        writer.write_all(response_json.as_bytes()).await?;
+        Response::ok_with(Pong {
+            protocol_version: PROTOCOL_VERSION,
+            daemon_version: env!("CARGO_PKG_VERSION"),
+            uptime_secs,
+            vault_exists: session.vault_path.exists(),
+            unlocked: session.is_unlocked(),
+        })
+    }
+
+    fn handle_status(&self) -> Response {
+        #[derive(Serialize)]
+        struct VaultStatus<'a> {
+            name: &'a str,
+            path: &'a Path,
+            unlocked: bool,
+            exists: bool,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            mode: Option<AccessMode>,
+        }
+
+        #[derive(Serialize)]
+        struct Status<'a> {
+            // The default vault, as reported before named vaults existed
+            unlocked: bool,
+            vault_exists: bool,
+            vaults: Vec<VaultStatus<'a>>,
+        }
+
+        let mut vaults: Vec<VaultStatus> = self.sessions.iter()
+            .map(|(name, s)| VaultStatus {
+                name,
+                path: &s.vault_path,
+                unlocked: s.is_unlocked(),
+                exists: s.vault_path.exists(),
+                mode: s.vault.as_ref()
+                    .filter(|v| v.is_unlocked())
+                    .map(|v| v.access_mode()),
+            })
+            .collect();
+        vaults.sort_by_key(|v| v.name);
+
+        let default = self.sessions.get(DEFAULT_VAULT);
+        Response::ok_with(Status {
+            unlocked: default.map(|s| s.is_unlocked()).unwrap_or(false),
+            vault_exists: default.map(|s| s.vault_path.exists()).unwrap_or(false),
+            vaults,
+        })
+    }
+}
+
+impl VaultSession {
+    fn new(
+        vault_path: impl AsRef<Path>,
+        anomaly_thresholds: AnomalyThresholds,
+        cache_budget: Option<u64>,
+    ) -> Self {
+        let (audit_events, _) = broadcast::channel(AUDIT_CHANNEL_CAPACITY);
+        Self {
+            vault: None,
+            audit: StdMutex::new(None),
+            vault_path: vault_path.as_ref().to_path_buf(),
+            audit_events,
+            anomaly_thresholds,
+            cache_budget,
+            min_passphrase_score: 0,
+            require_audit: false,
+            audit_retention: None,
+            audit_rotation: RotationPolicy::default(),
+            domain_policy: DomainPolicy::default(),
+            pepper_file: None,
+            audit_health: StdMutex::default(),
+            failed_unlocks: 0,
+            unaudited_unlock_failures: 0,
+            access_limiter: StdMutex::default(),
+            idempotency_keys: IdempotencyKeys::default(),
+        }
+    }
+
+    fn is_unlocked(&self) -> bool {
+        self.vault.as_ref().map(|v| v.is_unlocked()).unwrap_or(false)
+    }
+
+    /// Whether the entry with this id is deleted once read
+    fn consumes_on_read(&self, id: &Uuid) -> bool {
+        self.vault.as_ref()
+            .filter(|v| v.is_unlocked())
+            .and_then(|v| v.get_entry(id).ok().flatten())
+            .is_some_and(|e| e.consume_on_read)
+    }
+
+    /// Requests scoped to a single vault
+    async fn dispatch(&mut self, req: Request) -> Response {
+        match req {
+            Request::Lock { .. } => self.handle_lock().await,
+            Request::RotateDek { .. } => self.handle_rotate_dek().await,
+            Request::Gc { .. } => self.handle_gc().await,
+            Request::SetMetaEncryption { enabled, .. } => self.handle_set_meta_encryption(enabled).await,
+            Request::UpdatePassphrase { old_passphrase, new_passphrase, .. } => {
+                self.handle_update_passphrase(&old_passphrase, &new_passphrase).await
+            }
+            Request::UpgradeKdf { passphrase, memory_kib, iterations, parallelism, .. } => {
+                self.handle_upgrade_kdf(&passphrase, KdfParams { memory_kib, iterations, parallelism }).await
+            }
+            Request::EnrollHardwareKey { .. } => self.handle_enroll_hardware_key().await,
+            Request::Verify { passphrase, .. } => self.handle_verify(&passphrase).await,
+            Request::SetPolicy { policy, .. } => self.handle_set_policy(policy).await,
+            Request::CreateTotpFromUri { uri, name, agent_id, .. } => {
+                self.handle_create_totp_from_uri(&uri, name, agent_id).await
+            }
+            Request::Create { entry, agent_id, origin_chain, extra_passphrase, idempotency_key, .. } => {
+                self.handle_create(entry, agent_id, origin_chain, extra_passphrase, idempotency_key).await
+            }
+            Request::ImportEntries { entries, agent_id, .. } => {
+                self.handle_import_entries(entries, agent_id).await
+            }
+            Request::Update { id, entry, agent_id, .. } => {
+                self.handle_update(id, entry, agent_id).await
+            }
+            Request::Delete { id, agent_id, dry_run, .. } => {
+                self.handle_delete(id, agent_id, dry_run).await
+            }
+            Request::BulkDelete { ids, agent_id, .. } => self.handle_bulk_delete(ids, agent_id).await,
+            Request::Move { id, to, .. } => self.handle_move(id, to).await,
+            Request::AddAttachment { entry_id, filename, mime_type, data, .. } => {
+                self.handle_add_attachment(entry_id, filename, mime_type, data).await
+            }
+            Request::RemoveAttachment { entry_id, attachment_id, .. } => {
+                self.handle_remove_attachment(entry_id, attachment_id).await
+            }
+            Request::AddTags { id, tags, .. } => self.handle_change_tags(id, tags, true).await,
+            Request::RemoveTags { id, tags, .. } => self.handle_change_tags(id, tags, false).await,
+            Request::SetFavorite { id, favorite, .. } => self.handle_set_favorite(id, favorite).await,
+            Request::SetFolder { id, folder, .. } => self.handle_set_folder(id, folder).await,
+            Request::Rename { id, name, agent_id, .. } => self.handle_rename(id, name, agent_id).await,
+            Request::Clone { id, agent_id, .. } => self.handle_clone(id, agent_id).await,
+            Request::Warm { categories, .. } => self.handle_warm(categories).await,
+            Request::Rotate { id, spec, agent_id, .. } => self.handle_rotate(id, spec, agent_id).await,
+            Request::UpdateNotes { id, notes, agent_id, .. } => {
+                self.handle_update_notes(id, notes, agent_id).await
+            }
+            Request::RefreshOAuth { id, agent_id, .. } => self.handle_refresh_oauth(id, agent_id).await,
+            Request::RestoreVersion { id, version, agent_id, .. } => {
+                self.handle_restore_version(id, version, agent_id).await
+            }
+            Request::Get { id, agent_id, purpose, origin_chain, extra_passphrase, .. }
+                if self.consumes_on_read(&id) =>
+            {
+                self.handle_get_once(id, agent_id, purpose, origin_chain, extra_passphrase).await
+            }
+            req => self.dispatch_read(req).await,
+        }
+    }
+
+    /// Per-vault requests that only read (`Request::is_read_only`)
+    async fn dispatch_read(&self, req: Request) -> Response {
+        match req {
+            Request::GetPolicy { .. } => self.handle_get_policy().await,
+            Request::Export { passphrase, agent_id, .. } => {
+                self.handle_export(&passphrase, agent_id).await
+            }
+            Request::VerifyIntegrity { .. } => self.handle_verify_integrity().await,
+            Request::AuditStatus { verify, .. } => self.handle_audit_status(verify).await,
+            Request::VerifyAudit { .. } => self.handle_verify_audit().await,
+            Request::Stats { window_hours, .. } => self.handle_stats(window_hours).await,
+            Request::AuditQuery { since_hours, event_types, agent_id, category, limit, .. } => {
+                self.handle_audit_query(since_hours, event_types, agent_id, category, limit).await
+            }
+            Request::Overview { within_hours, .. } => self.handle_overview(within_hours).await,
+            Request::List { category, offset, limit, .. } => {
+                self.handle_list(category, offset, limit).await
+            }
+            Request::Search { query, .. } => self.handle_search(query).await,
+            Request::ExpiringSoon { within_hours, .. } => {
+                self.handle_expiring_soon(within_hours).await
+            }
+            Request::FindDuplicates { .. } => self.handle_find_duplicates().await,
+            Request::ListTags { .. } => self.handle_list_tags().await,
+            Request::ListFavorites { .. } => self.handle_list_favorites().await,
+            Request::ListFolder { category, prefix, .. } => self.handle_list_folder(category, &prefix).await,
+            Request::ListFolders { category, .. } => self.handle_list_folders(category).await,
+            Request::Get { id, agent_id, purpose, origin_chain, peek, extra_passphrase, .. } => {
+                self.handle_get(id, agent_id, purpose, origin_chain, peek, extra_passphrase).await
+            }
+            Request::GetAttachment { entry_id, attachment_id, agent_id, purpose, .. } => {
+                self.handle_get_attachment(entry_id, attachment_id, agent_id, purpose).await
+            }
+            Request::UseForAuth { id, target_url, agent_id, purpose, origin_chain, .. } => {
+                self.handle_use_for_auth(id, target_url, agent_id, purpose, origin_chain).await
+            }
+            Request::Touch { id, agent_id, .. } => self.handle_touch(id, agent_id).await,
+            Request::Describe { id, agent_id, .. } => self.handle_describe(id, agent_id).await,
+            Request::History { id, agent_id, .. } => self.handle_history(id, agent_id).await,
+            _ => Response::error(ErrorCode::InvalidRequest, "Not a per-vault request"),
+        }
+    }
+
+    async fn handle_unlock(
+        &mut self,
+        passphrase: &str,
+        categories: Option<Vec<Category>>,
+        mode: AccessMode,
+        force: bool,
+    ) -> Response {
+        // Older formats are brought up to date by a full read-write unlock
+        let migrate = categories.is_none() && mode == AccessMode::ReadWrite;
+        // Try to open existing vault or create new one
+        let vault_result = if self.vault_path.exists() {
+            let mut vault = match Vault::open(&self.vault_path) {
+                Ok(v) => v.with_cache_budget(self.cache_budget),
+                Err(e) => return Response::failed("Failed to open vault", &e),
+            };
+            if vault.requires_pepper() {
+                match self.read_pepper() {
+                    Ok(pepper) => vault = vault.with_pepper(pepper),
+                    Err(e) => return Response::error(ErrorCode::PepperRequired, e.to_string()),
+                }
+            }
+            
+            if let Some(cats) = categories {
+                match vault.unlock_categories(passphrase, &cats, mode) {
+                    Ok(()) => Ok(vault),
+                    Err(e) => Err(e),
+                }
+            } else {
+                match vault.unlock(passphrase, mode) {
+                    Ok(()) => Ok(vault),
+                    Err(e) => Err(e),
+                }
+            }
+        } else if mode == AccessMode::ReadOnly {
+            // Creating is a write
+            return Response::error(ErrorCode::NotFound, "Vault does not exist");
+        } else {
+            let strength = estimate_strength(passphrase);
+            if strength.score < self.min_passphrase_score && !force {
+                let advice: String = strength.feedback.iter().map(|f| format!("{}. ", f)).collect();
+                return Response::error(ErrorCode::WeakPassphrase, format!(
+                    "Passphrase too weak for a new vault (strength {} of 4, {} required). \
+                     {}Set \"force\" to create it anyway.",
+                    strength.score,
+                    self.min_passphrase_score,
+                    advice,
+                ));
+            }
+            let pepper = match self.read_pepper() {
+                Ok(pepper) => pepper,
+                Err(e) => return Response::error(ErrorCode::PepperRequired, e.to_string()),
+            };
+            Vault::create_with_pepper(&self.vault_path, passphrase, pepper)
+                .map(|v| v.with_cache_budget(self.cache_budget))
+        };
+
+        match vault_result {
+            Ok(mut vault) => {
+                self.failed_unlocks = 0;
+                if migrate && vault.needs_migration() {
+                    // The old format still reads fine, so a failed rewrite
+                    // needn't keep the vault locked; the next unlock retries
+                    if let Err(e) = vault.migrate() {
+                        tracing::warn!("Could not migrate {:?}: {:#}", self.vault_path, e);
+                    }
+                }
+                let mut health = AuditHealth::default();
+                let log = match self.open_audit_log(passphrase, &mut vault) {
+                    Ok(log) => {
+                        health.record_chain(log.verify_chain_detailed(), &self.vault_path);
+                        Some(log)
+                    }
+                    Err(e) => {
+                        tracing::error!("Auditing is off for {:?}: {:#}", self.vault_path, e);
+                        if self.require_audit {
+                            return Response::error(
+                                ErrorCode::AuditUnavailable,
+                                format!("Audit log unavailable, so the vault stays locked: {:#}", e),
+                            );
+                        }
+                        health.error = Some(format!("{:#}", e));
+                        None
+                    }
+                };
+                let audit_available = log.is_some();
+                *audit_log(&self.audit) = log;
+                *self.audit_health.lock().unwrap_or_else(PoisonError::into_inner) = health;
+
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    if self.unaudited_unlock_failures > 0 {
+                        let _ = audit.log_denial(
+                            &format!("failed unlock attempts: {}", self.unaudited_unlock_failures),
+                            None,
+                            None,
+                        );
+                    }
+                    let _ = audit.log_unlock(mode);
+                    if let Some(retention) = self.audit_retention {
+                        purge_audit(audit, retention);
+                    }
+                }
+
+                self.unaudited_unlock_failures = 0;
+                self.vault = Some(vault);
+                Response::ok_with(serde_json::json!({ "audit_available": audit_available }))
+            }
+            // Fails before the passphrase is checked, so it says nothing
+            // about it and isn't counted as a guess
+            Err(e) if e.downcast_ref::<HardwareKeyUnavailable>().is_some() => {
+                Response::error(ErrorCode::HardwareKeyRequired, e.to_string())
+            }
+            Err(e) if e.downcast_ref::<PepperUnavailable>().is_some() => {
+                Response::error(ErrorCode::PepperRequired, e.to_string())
+            }
+            Err(e) => {
+                self.failed_unlocks = self.failed_unlocks.saturating_add(1);
+                tracing::warn!("Unlock failed ({} in a row): {}", self.failed_unlocks, e);
+
+                match audit_log(&self.audit).as_mut() {
+                    Some(audit) => {
+                        let _ = audit.log_denial("failed unlock attempt", None, None);
+                    }
+                    None => self.unaudited_unlock_failures += 1,
+                }
+
+                // Runs under the daemon lock, so this throttles every caller,
+                // not just this connection
+                tokio::time::sleep(unlock_backoff(self.failed_unlocks)).await;
+
+                // Same message however the unlock broke
+                Response::error(ErrorCode::BadPassphrase, "Unlock failed")
+            }
+        }
+    }
+
+    /// The pepper from `--pepper-file`, read on each unlock so the file
+    /// can be attached after the daemon starts
+    fn read_pepper(&self) -> Result<Option<SecureKey>> {
+        self.pepper_file.as_deref().map(read_pepper).transpose()
+    }
+
+    /// Open the vault's audit log, picking its key on first use
+    ///
+    /// Vaults from before signed checkpoints get a signing key on their
+    /// first full read-write unlock; until then nothing is checkpointed.
+    fn open_audit_log(&self, passphrase: &str, vault: &mut Vault) -> Result<AuditLog> {
+        let key = match vault.audit_key().context("Audit key unreadable")? {
+            Some(key) => key,
+            None => self.first_audit_key(passphrase, vault)?,
+        };
+        let signer = match vault.audit_signing_key().context("Audit signing key unreadable")? {
+            Some(signer) => Some(signer),
+            None if vault.is_fully_unlocked() && vault.access_mode() == AccessMode::ReadWrite => Some(
+                vault.create_audit_signing_key().context("Could not create audit signing key")?,
+            ),
+            None => None,
+        };
+        let log = AuditLog::open(self.vault_path.join(AUDIT_LOG_FILE), key)
+            .context("Audit log unreadable (corrupt, or under another key)")?;
+        Ok(log.with_notifier(self.audit_events.clone())
+            .with_thresholds(self.anomaly_thresholds.clone())
+            .with_rotation(self.audit_rotation.clone())
+            .with_nonces(vault.nonce_sequence())
+            .with_checkpoints(signer, vault.audit_verify_key()))
+    }
+
+    /// Pick and store the audit key for a vault that has none yet
+    ///
+    /// Logs written before keys were stored used a key derived from the
+    /// passphrase with a fixed salt; it is kept so they stay readable.
+    fn first_audit_key(&self, passphrase: &str, vault: &Vault) -> Result<SecureKey> {
+        let has_history = std::fs::read_dir(&self.vault_path)
+            .map(|dir| dir.flatten().any(|f| {
+                let name = f.file_name().to_string_lossy().into_owned();
+                name.starts_with("audit") && name.ends_with(".enc")
+            }))
+            .unwrap_or(false);
+        let key = if has_history {
+            derive_subkey(&derive_master_key(passphrase, &[0u8; 32], &KdfParams::BASELINE)?, "audit")
+        } else {
+            SecureKey::generate()
+        };
+
+        vault.store_audit_key(&key).context("Could not store audit key")?;
+        Ok(key)
+    }
+
+    async fn handle_lock(&mut self) -> Response {
+        if let Some(ref mut vault) = self.vault {
+            if let Some(audit) = audit_log(&self.audit).as_mut() {
+                let _ = audit.log_lock();
+                compact_audit(audit);
+            }
+            vault.lock();
+            self.vault = None;
+            *audit_log(&self.audit) = None;
+            Response::ok()
+        } else {
+            Response::error(ErrorCode::VaultLocked, "Vault not unlocked")
+        }
+    }
+
+    /// Lock at once for `VaultDaemon::emergency`, ending audit
+    /// subscriptions and forgetting rate-limited reads
+    ///
+    /// The failed-unlock backoff is kept; it only slows guessing. Returns
+    /// whether the vault was unlocked.
+    fn emergency_lock(&mut self, trigger: &str, agent_id: Option<&str>) -> bool {
+        // Written while the keys still exist
+        if let Some(audit) = audit_log(&self.audit).as_mut() {
+            if let Err(e) = audit.log_emergency(trigger, agent_id) {
+                tracing::error!("Emergency lock of {:?} not audited: {}", self.vault_path, e);
+            }
+        }
+        let unlocked = match self.vault.take() {
+            Some(mut vault) => {
+                vault.lock();
+                true
+            }
+            None => false,
+        };
+        *audit_log(&self.audit) = None;
+        // Dropping the sender ends every subscriber's stream
+        self.audit_events = broadcast::channel(AUDIT_CHANNEL_CAPACITY).0;
+        *self.access_limiter.lock().unwrap_or_else(PoisonError::into_inner) = AccessLimiter::default();
+        self.idempotency_keys = IdempotencyKeys::default();
+        unlocked
+    }
+
+    async fn handle_rotate_dek(&mut self) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.rotate_dek() {
+            Ok(()) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_key_rotation();
+                }
+                Response::ok()
+            }
+            Err(e) => Response::failed("Key rotation failed", &e),
+        }
+    }
+
+    async fn handle_update_passphrase(&mut self, old_passphrase: &str, new_passphrase: &str) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+        if new_passphrase.is_empty() {
+            return Response::error(ErrorCode::InvalidRequest, "New passphrase must not be empty");
+        }
+
+        match vault.change_passphrase(old_passphrase, new_passphrase) {
+            Ok(recovery_disabled) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_passphrase_change();
+                }
+                Response::ok_with(serde_json::json!({ "recovery_disabled": recovery_disabled }))
+            }
+            Err(e) => Response::failed("Passphrase change failed", &e),
+        }
+    }
+
+    async fn handle_upgrade_kdf(&mut self, passphrase: &str, kdf: KdfParams) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.upgrade_kdf(passphrase, kdf) {
+            Ok(recovery_disabled) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_kdf_upgrade(&kdf);
+                }
+                Response::ok_with(serde_json::json!({ "recovery_disabled": recovery_disabled }))
+            }
+            Err(e) => Response::failed("KDF upgrade failed", &e),
+        }
+    }
+
+    async fn handle_enroll_hardware_key(&mut self) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.enroll_hardware_key() {
+            Ok(()) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_hardware_key_enroll();
+                }
+                Response::ok()
+            }
+            Err(e) => Response::failed("Hardware key enrollment failed", &e),
+        }
+    }
+
+    async fn handle_verify(&mut self, passphrase: &str) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.verify_passphrase(passphrase) {
+            Ok(true) => {
+                self.failed_unlocks = 0;
+                Response::ok_with(serde_json::json!({ "verified": true }))
+            }
+            Ok(false) => {
+                // A guess like any failed unlock, throttled the same way
+                self.failed_unlocks = self.failed_unlocks.saturating_add(1);
+                tracing::warn!("Passphrase verification failed ({} in a row)", self.failed_unlocks);
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_denial("failed passphrase verification", None, None);
+                }
+                tokio::time::sleep(unlock_backoff(self.failed_unlocks)).await;
+                Response::ok_with(serde_json::json!({ "verified": false }))
+            }
+            Err(e) if e.downcast_ref::<HardwareKeyUnavailable>().is_some() => {
+                Response::error(ErrorCode::HardwareKeyRequired, e.to_string())
+            }
+            Err(e) => Response::failed("Verification failed", &e),
+        }
+    }
+
+    async fn handle_list(
+        &self,
+        category: Category,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        // A bare list keeps returning a plain array for existing clients
+        if offset.is_none() && limit.is_none() {
+            return match vault.list_entries(category) {
+                Ok(entries) => Response::ok_with(entries),
+                Err(e) => Response::failed("List failed", &e),
+            };
+        }
+
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+        match vault.list_entries_paged(category, offset.unwrap_or(0), limit) {
+            Ok(page) => Response::ok_with(page),
+            Err(e) => Response::failed("List failed", &e),
+        }
+    }
+
+    async fn handle_search(&self, query: SearchQuery) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.search(&query) {
+            Ok(entries) => Response::ok_with(entries),
+            Err(e) => Response::failed("Search failed", &e),
+        }
+    }
+
+    async fn handle_overview(&self, within_hours: Option<i64>) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let within = match hours_window("within_hours", within_hours.unwrap_or(OVERVIEW_EXPIRY_HOURS)) {
+            Ok(within) => within,
+            Err(refusal) => return refusal,
+        };
+        match vault.overview(within) {
+            Ok(categories) => Response::ok_with(categories),
+            Err(e) => Response::failed("Overview failed", &e),
+        }
+    }
+
+    async fn handle_expiring_soon(&self, within_hours: i64) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let within = match hours_window("within_hours", within_hours) {
+            Ok(within) => within,
+            Err(refusal) => return refusal,
+        };
+        match vault.expiring_soon(within) {
+            Ok(entries) => Response::ok_with(entries),
+            Err(e) => Response::failed("Expiry scan failed", &e),
+        }
+    }
+
+    async fn handle_export(&self, passphrase: &str, agent_id: Option<String>) -> Response {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+        if !vault.is_fully_unlocked() {
+            return Response::error(ErrorCode::VaultLocked, "Export needs the whole vault unlocked, not selected categories");
+        }
+
+        // Everything leaves at once, so the agent must be allowed everywhere
+        let policy = vault.policy().cloned();
+        for cat in Category::all() {
+            if let Some(denied) = policy_denial(&self.audit, policy.as_ref(), agent_id.as_deref(), *cat) {
+                return denied;
+            }
+        }
+
+        match vault.export(passphrase) {
+            Ok(blob) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_export(agent_id.as_deref());
+                }
+                Response::ok_with(serde_json::json!({ "export": STANDARD.encode(blob) }))
+            }
+            Err(e) => Response::failed("Export failed", &e),
+        }
+    }
+
+    async fn handle_audit_status(&self, verify: bool) -> Response {
+        #[derive(Serialize)]
+        struct AuditStatus {
+            active: bool,
+            #[serde(flatten)]
+            health: AuditHealth,
+        }
+
+        if !self.is_unlocked() {
+            return Response::error(ErrorCode::VaultLocked, "Vault not unlocked");
+        }
+
+        let audit = audit_log(&self.audit);
+        let mut health = self.audit_health.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(log) = audit.as_ref().filter(|_| verify) {
+            health.record_chain(log.verify_chain_detailed(), &self.vault_path);
+        }
+        Response::ok_with(AuditStatus { active: audit.is_some(), health: health.clone() })
+    }
+
+    async fn handle_verify_audit(&self) -> Response {
+        if !self.is_unlocked() {
+            return Response::error(ErrorCode::VaultLocked, "Vault not unlocked");
+        }
+        let audit = audit_log(&self.audit);
+        let Some(log) = audit.as_ref() else {
+            return Response::error(ErrorCode::AuditUnavailable, "Auditing is not running for this vault");
+        };
+
+        let mut health = self.audit_health.lock().unwrap_or_else(PoisonError::into_inner);
+        health.record_chain(log.verify_chain_detailed(), &self.vault_path);
+        match &health.chain {
+            Some(chain) => Response::ok_with(chain),
+            None => Response::error(
+                ErrorCode::Internal,
+                health.error.clone().unwrap_or_else(|| "Chain verification failed".to_string()),
+            ),
+        }
+    }
+
+    async fn handle_stats(&self, window_hours: i64) -> Response {
+        if !self.is_unlocked() {
+            return Response::error(ErrorCode::VaultLocked, "Vault not unlocked");
+        }
+        let window = match hours_window("window_hours", window_hours) {
+            Ok(window) => window,
+            Err(refusal) => return refusal,
+        };
+        let audit = audit_log(&self.audit);
+        let Some(log) = audit.as_ref() else {
+            return Response::error(ErrorCode::AuditUnavailable, "Auditing is not running for this vault");
+        };
+
+        match log.stats(window) {
+            Ok(stats) => Response::ok_with(stats),
+            Err(e) => Response::failed("Audit stats failed", &e),
+        }
+    }
+
+    async fn handle_audit_query(
+        &self,
+        since_hours: Option<i64>,
+        event_types: Vec<AuditEventType>,
+        agent_id: Option<String>,
+        category: Option<Category>,
+        limit: Option<usize>,
+    ) -> Response {
+        if !self.is_unlocked() {
+            return Response::error(ErrorCode::VaultLocked, "Vault not unlocked");
+        }
+        let since = match since_hours.map(|hours| hours_window("since_hours", hours)).transpose() {
+            Ok(window) => window.map(|w| Utc::now().checked_sub_signed(w).unwrap_or(DateTime::<Utc>::MIN_UTC)),
+            Err(refusal) => return refusal,
+        };
+        let audit = audit_log(&self.audit);
+        let Some(log) = audit.as_ref() else {
+            return Response::error(ErrorCode::AuditUnavailable, "Auditing is not running for this vault");
+        };
+
+        let filter = AuditFilter {
+            since,
+            event_types,
+            agent_id,
+            category,
+            limit: limit.unwrap_or(DEFAULT_AUDIT_QUERY_LIMIT),
+        };
+        match log.query(&filter) {
+            Ok((entries, matched)) => Response::ok_with(serde_json::json!({
+                "entries": entries,
+                "matched": matched,
+            })),
+            Err(e) => Response::failed("Audit query failed", &e),
+        }
+    }
+
+    async fn handle_verify_integrity(&self) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.verify_integrity() {
+            Ok(report) => Response::ok_with(report),
+            Err(e) => Response::failed("Integrity check failed", &e),
+        }
+    }
+
+    async fn handle_find_duplicates(&self) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.find_duplicates() {
+            Ok(groups) => Response::ok_with(groups),
+            Err(e) => Response::failed("Duplicate scan failed", &e),
+        }
+    }
+
+    async fn handle_list_favorites(&self) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.list_favorites() {
+            Ok(favorites) => Response::ok_with(favorites),
+            Err(e) => Response::failed("Listing favorites failed", &e),
+        }
+    }
+
+    async fn handle_list_folder(&self, category: Category, prefix: &str) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.list_folder(category, prefix) {
+            Ok(entries) => Response::ok_with(entries),
+            Err(e) => Response::failed("List failed", &e),
+        }
+    }
+
+    async fn handle_list_folders(&self, category: Category) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.folders(category) {
+            Ok(folders) => Response::ok_with(folders),
+            Err(e) => Response::failed("Listing folders failed", &e),
+        }
+    }
+
+    async fn handle_list_tags(&self) -> Response {
+        #[derive(Serialize)]
+        struct TagCount {
+            tag: String,
+            count: usize,
+        }
+
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.all_tags() {
+            Ok(tags) => Response::ok_with(
+                tags.into_iter().map(|(tag, count)| TagCount { tag, count }).collect::<Vec<_>>(),
+            ),
+            Err(e) => Response::failed("Listing tags failed", &e),
+        }
+    }
+
+    async fn handle_get(
+        &self,
+        id: Uuid,
+        agent_id: Option<String>,
+        purpose: Option<String>,
+        origin_chain: Option<Vec<String>>,
+        peek: bool,
+        extra_passphrase: Option<Secret>,
+    ) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let policy = vault.policy().cloned();
+        match vault.get_entry(&id) {
+            Ok(Some(mut entry)) => {
+                if let Some(denied) = policy_denial(
+                    &self.audit,
+                    policy.as_ref(),
+                    agent_id.as_deref(),
+                    entry.category,
+                ) {
+                    return denied;
+                }
+                if let Some(denied) = rate_limit_denial(
+                    &self.audit,
+                    &self.access_limiter,
+                    &entry,
+                    agent_id.as_deref(),
+                ) {
+                    return denied;
+                }
+
+                if entry.protected {
+                    let Some(extra) = extra_passphrase else {
+                        return Response::error(
+                            ErrorCode::PermissionDenied,
+                            "Entry is protected; send its extra_passphrase",
+                        );
+                    };
+                    match vault.get_protected_entry(&id, &extra) {
+                        Ok(Some(opened)) => entry = opened,
+                        Ok(None) => return Response::error(ErrorCode::NotFound, "Entry not found"),
+                        Err(e) => {
+                            if let Some(audit) = audit_log(&self.audit).as_mut() {
+                                let _ = audit.log_denial(
+                                    "wrong extra passphrase",
+                                    agent_id.as_deref(),
+                                    Some(entry.category),
+                                );
+                            }
+                            return Response::failed("Get failed", &e);
+                        }
+                    }
+                }
+
+                // Log access
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_access(
+                        id,
+                        &entry.name,
+                        entry.category,
+                        agent_id.as_deref(),
+                        purpose.as_deref(),
+                        origin_chain,
+                    );
+
+                    if let Ok(anomalies) = audit.record_anomalies() {
+                        for anomaly in anomalies {
+                            tracing::warn!("Anomaly detected: {}", anomaly.description);
+                        }
+                    }
+                }
+                
+                if !peek {
+                    vault.record_access(&entry);
+                }
+
+                // Return handle (not raw value in production)
+                // For now, return full entry
+                entry_response(&entry)
+            }
+            Ok(None) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Get failed", &e),
+        }
+    }
+
+    /// `handle_get` for a `consume_on_read` entry, deleting it once read
+    ///
+    /// The value is only sent back once the entry is gone, so a failed
+    /// delete can't let it be read twice.
+    async fn handle_get_once(
+        &mut self,
+        id: Uuid,
+        agent_id: Option<String>,
+        purpose: Option<String>,
+        origin_chain: Option<Vec<String>>,
+        extra_passphrase: Option<Secret>,
+    ) -> Response {
+        if self.vault.as_ref().is_some_and(|v| v.access_mode() == AccessMode::ReadOnly) {
+            return Response::failed("Get failed", &VaultReadOnly.into());
+        }
+        let response = self.handle_get(id, agent_id.clone(), purpose, origin_chain, true, extra_passphrase).await;
+        if !matches!(response, Response::Ok { .. }) {
+            return response;
+        }
+
+        let Some(vault) = self.vault.as_mut() else {
+            return Response::error(ErrorCode::VaultLocked, "Vault not unlocked");
+        };
+        match vault.consume_entry(&id) {
+            Ok(Some(metadata)) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_consume(id, &metadata.name, metadata.category, agent_id.as_deref());
+                }
+                response
+            }
+            Ok(None) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Get failed", &e),
+        }
+    }
+
+    async fn handle_create_totp_from_uri(
+        &mut self,
+        uri: &str,
+        name: Option<String>,
+        agent_id: Option<String>,
+    ) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let params = match totp::parse_otpauth_uri(uri) {
+            Ok(params) => params,
+            Err(e) => return Response::error(ErrorCode::InvalidRequest, e.to_string()),
+        };
+        let name = name.unwrap_or_else(|| params.display_name());
+        if let Err(reason) = validate::check_name(&name) {
+            return Response::error(ErrorCode::InvalidRequest, format!("name: {}", reason));
+        }
+        let entry = params.into_entry(name);
+        if let Err(reason) = validate::check_value(entry.entry_type, &entry.value) {
+            return Response::error(ErrorCode::InvalidRequest, reason);
+        }
+
+        let (name, category) = (entry.name.clone(), entry.category);
+        match vault.add_entry(entry) {
+            Ok(id) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_create(id, &name, category, agent_id.as_deref(), None);
+                }
+                Response::ok_with(serde_json::json!({ "id": id }))
+            }
+            Err(e) => Response::failed("Create failed", &e),
+        }
+    }
+
+    async fn handle_create(
+        &mut self,
+        req: NewEntryRequest,
+        agent_id: Option<String>,
+        origin_chain: Option<Vec<String>>,
+        extra_passphrase: Option<Secret>,
+        idempotency_key: Option<String>,
+    ) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        if let Some(key) = &idempotency_key {
+            if key.is_empty() || key.chars().count() > MAX_IDEMPOTENCY_KEY_CHARS {
+                return Response::error(
+                    ErrorCode::InvalidRequest,
+                    format!("idempotency_key must be 1 to {} characters", MAX_IDEMPOTENCY_KEY_CHARS),
+                );
+            }
+            // Already done: answer as the first time, without writing or auditing
+            if let Some(id) = self.idempotency_keys.get(agent_id.as_deref(), key, Instant::now()) {
+                return Response::ok_with(serde_json::json!({ "id": id }));
+            }
+        }
+
+        let entry = match req.into_entry() {
+            Ok(e) => e,
+            Err(response) => return response,
+        };
+        let (name, category) = (entry.name.clone(), entry.category);
+
+        let added = match extra_passphrase {
+            Some(extra) if extra.is_empty() => {
+                return Response::error(ErrorCode::InvalidRequest, "extra_passphrase is empty");
+            }
+            Some(extra) => vault.add_protected_entry(entry, &extra),
+            None => vault.add_entry(entry),
+        };
+        match added {
+            Ok(id) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_create(id, &name, category, agent_id.as_deref(), origin_chain);
+                }
+                if let Some(key) = idempotency_key {
+                    self.idempotency_keys.insert(agent_id, key, id, Instant::now());
+                }
+                Response::ok_with(serde_json::json!({ "id": id }))
+            }
+            Err(e) => Response::failed("Create failed", &e),
+        }
+    }
+
+    async fn handle_import_entries(
+        &mut self,
+        requests: Vec<NewEntryRequest>,
+        agent_id: Option<String>,
+    ) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        // All or nothing: one bad entry rejects the batch before any write
+        let entries = match NewEntryRequest::into_entries(requests) {
+            Ok(entries) => entries,
+            Err(response) => return response,
+        };
+        let summaries: Vec<(String, Category)> = entries.iter()
+            .map(|e| (e.name.clone(), e.category))
+            .collect();
+
+        match vault.import_entries(entries) {
+            Ok(ids) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    for (id, (name, category)) in ids.iter().zip(&summaries) {
+                        let _ = audit.log_create(*id, name, *category, agent_id.as_deref(), None);
+                    }
+                }
+                Response::ok_with(serde_json::json!({ "ids": ids }))
+            }
+            Err(e) => Response::failed("Import failed", &e),
+        }
+    }
+
+    async fn handle_update(
+        &mut self,
+        id: Uuid,
+        req: NewEntryRequest,
+        agent_id: Option<String>,
+    ) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let fields = match req.into_entry() {
+            Ok(e) => e,
+            Err(response) => return response,
+        };
+        let mut entry = match vault.get_entry(&id) {
+            // The new value would land outside the seal, leaving it unreadable
+            Ok(Some(existing)) if existing.protected => {
+                return Response::error(ErrorCode::InvalidRequest, "Protected entries can't be updated");
+            }
+            Ok(Some(existing)) => existing.clone(),
+            Ok(None) => return Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => return Response::failed("Update failed", &e),
+        };
+        entry.category = fields.category;
+        entry.entry_type = fields.entry_type;
+        entry.name = fields.name;
+        entry.value = fields.value;
+        entry.username = fields.username;
+        entry.url = fields.url;
+        entry.pinned_cert_sha256 = fields.pinned_cert_sha256;
+        entry.expires = fields.expires;
+        entry.custom_fields = fields.custom_fields;
+        entry.max_access_per_minute = fields.max_access_per_minute;
+        let (name, category) = (entry.name.clone(), entry.category);
+
+        match vault.update_entry(entry) {
+            Ok(true) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_update(id, &name, category, agent_id.as_deref());
+                }
+                Response::ok()
+            }
+            Ok(false) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Update failed", &e),
+        }
+    }
+
+    async fn handle_delete(&mut self, id: Uuid, agent_id: Option<String>, dry_run: bool) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        // Gone after the delete, so capture what the audit entry needs now
+        let (name, category) = match vault.get_entry(&id) {
+            Ok(Some(entry)) => (entry.name.clone(), entry.category),
+            Ok(None) => return Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => return Response::failed("Delete failed", &e),
+        };
+
+        // Nothing touches disk or the audit log on a preview
+        if dry_run {
+            return Response::ok_with(serde_json::json!({
+                "dry_run": true,
+                "would_delete": { "id": id, "name": name, "category": category },
+            }));
+        }
+
+        match vault.delete_entry(&id) {
+            Ok(true) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_delete(id, &name, category, agent_id.as_deref());
+                }
+                Response::ok()
+            }
+            Ok(false) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Delete failed", &e),
+        }
+    }
+
+    async fn handle_bulk_delete(&mut self, ids: Vec<Uuid>, agent_id: Option<String>) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        // Gone after the delete, so capture what the audit entries need now
+        let mut described = HashMap::new();
+        for id in &ids {
+            match vault.get_entry(id) {
+                Ok(Some(entry)) => {
+                    described.insert(*id, (entry.name.clone(), entry.category));
+                }
+                Ok(None) => {}
+                Err(e) => return Response::failed("Bulk delete failed", &e),
+            }
+        }
+
+        match vault.delete_entries(&ids) {
+            Ok(result) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    for id in &result.deleted {
+                        if let Some((name, category)) = described.get(id) {
+                            let _ = audit.log_delete(*id, name, *category, agent_id.as_deref());
+                        }
+                    }
+                }
+                Response::ok_with(result)
+            }
+            Err(e) => Response::failed("Bulk delete failed", &e),
+        }
+    }
+
+    async fn handle_move(&mut self, id: Uuid, to: Category) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.move_entry(&id, to) {
+            Ok(true) => Response::ok(),
+            Ok(false) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Move failed", &e),
+        }
+    }
+
+    async fn handle_change_tags(&mut self, id: Uuid, tags: Vec<String>, add: bool) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let result = match add {
+            true => vault.add_tags(&id, &tags),
+            false => vault.remove_tags(&id, &tags),
+        };
+        match result {
+            Ok(Some(tags)) => Response::ok_with(serde_json::json!({ "tags": tags })),
+            Ok(None) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Changing tags failed", &e),
+        }
+    }
+
+    async fn handle_set_favorite(&mut self, id: Uuid, favorite: bool) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.set_favorite(&id, favorite) {
+            Ok(true) => Response::ok(),
+            Ok(false) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Setting favorite failed", &e),
+        }
+    }
+
+    async fn handle_set_folder(&mut self, id: Uuid, folder: Option<String>) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+        let folder = match folder.as_deref().map(validate::clean_folder).transpose() {
+            Ok(folder) => folder.flatten(),
+            Err(reason) => return Response::error(ErrorCode::InvalidRequest, reason),
+        };
+
+        match vault.set_folder(&id, folder) {
+            Ok(true) => Response::ok(),
+            Ok(false) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Setting folder failed", &e),
+        }
+    }
+
+    async fn handle_rename(&mut self, id: Uuid, name: String, agent_id: Option<String>) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+        if let Err(reason) = validate::check_name(&name) {
+            return Response::error(ErrorCode::InvalidRequest, reason);
+        }
+
+        match vault.rename_entry(&id, name) {
+            Ok(Some(metadata)) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_update(id, &metadata.name, metadata.category, agent_id.as_deref());
+                }
+                Response::ok()
+            }
+            Ok(None) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Rename failed", &e),
+        }
+    }
+
+    async fn handle_clone(&mut self, id: Uuid, agent_id: Option<String>) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let (name, category) = match vault.get_entry(&id) {
+            Ok(Some(entry)) if entry.protected => {
+                return Response::error(ErrorCode::InvalidRequest, "Protected entries can't be cloned");
+            }
+            Ok(Some(entry)) => (format!("{} (copy)", entry.name), entry.category),
+            Ok(None) => return Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => return Response::failed("Clone failed", &e),
+        };
+        if let Err(reason) = validate::check_name(&name) {
+            return Response::error(ErrorCode::InvalidRequest, reason);
+        }
+
+        match vault.clone_entry(&id) {
+            Ok(copy) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_create(copy, &name, category, agent_id.as_deref(), None);
+                }
+                Response::ok_with(serde_json::json!({ "id": copy }))
+            }
+            Err(e) => Response::failed("Clone failed", &e),
+        }
+    }
+
+    async fn handle_gc(&mut self) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.gc() {
+            Ok(report) => {
+                if report != GcReport::default() {
+                    tracing::info!(
+                        "Collected {} attachment keys and {} files ({} bytes)",
+                        report.dropped_keys, report.removed_files, report.bytes_reclaimed,
+                    );
+                }
+                Response::ok_with(report)
+            }
+            Err(e) => Response::failed("Garbage collection failed", &e),
+        }
+    }
+
+    async fn handle_set_meta_encryption(&mut self, enabled: bool) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.set_meta_encryption(enabled) {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::failed("Setting metadata encryption failed", &e),
+        }
+    }
+
+    async fn handle_warm(&mut self, categories: Option<Vec<Category>>) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.warm_categories(categories.as_deref()) {
+            Ok(report) => Response::ok_with(report),
+            Err(e) => Response::failed("Warming failed", &e),
+        }
+    }
+
+    async fn handle_rotate(&mut self, id: Uuid, spec: PasswordSpec, agent_id: Option<String>) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let mut entry = match vault.get_entry(&id) {
+            Ok(Some(entry)) => entry.clone(),
+            Ok(None) => return Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => return Response::failed("Rotation failed", &e),
+        };
+        // The new value is answered, so it's read as much as written
+        if let Some(denied) = policy_denial(&self.audit, vault.policy(), agent_id.as_deref(), entry.category) {
+            return denied;
+        }
+        if !matches!(entry.entry_type, EntryType::Password | EntryType::ApiKey) {
+            return Response::error(
+                ErrorCode::InvalidRequest,
+                format!("Only password and API key entries can be rotated, not {:?}", entry.entry_type),
+            );
+        }
+        if entry.protected {
+            return Response::error(ErrorCode::InvalidRequest, "Protected entries can't be rotated");
+        }
+
+        let password = match generate_password(&spec) {
+            Ok(password) => password,
+            Err(e) => return Response::error(ErrorCode::InvalidRequest, e.to_string()),
+        };
+        entry.value = Zeroizing::new(password.as_bytes().to_vec());
+        let (name, category) = (entry.name.clone(), entry.category);
+
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        match vault.update_entry(entry) {
+            Ok(true) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_rotation(id, &name, category, agent_id.as_deref());
+                }
+                Response::ok_with(serde_json::json!({ "id": id, "value_b64": STANDARD.encode(password.as_bytes()) }))
+            }
+            Ok(false) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Rotation failed", &e),
+        }
+    }
+
+    async fn handle_update_notes(
+        &mut self,
+        id: Uuid,
+        notes: Option<Secret>,
+        agent_id: Option<String>,
+    ) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+        let notes = match notes.as_deref().map(validate::clean_notes).transpose() {
+            Ok(notes) => notes,
+            Err(reason) => return Response::error(ErrorCode::InvalidRequest, reason),
+        };
+
+        match vault.set_notes(&id, notes) {
+            Ok(Some(metadata)) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_update(id, &metadata.name, metadata.category, agent_id.as_deref());
+                }
+                Response::ok()
+            }
+            Ok(None) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Updating notes failed", &e),
+        }
+    }
+
+    async fn handle_add_attachment(
+        &mut self,
+        entry_id: Uuid,
+        filename: String,
+        mime_type: String,
+        data: Secret,
+    ) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        let data = match STANDARD.decode(data.as_bytes()) {
+            Ok(d) => d,
+            Err(e) => return Response::error(ErrorCode::InvalidRequest, format!("Invalid base64 data: {}", e)),
+        };
+
+        match vault.add_attachment(&entry_id, filename, mime_type, &data) {
+            Ok(id) => Response::ok_with(serde_json::json!({ "id": id })),
+            Err(e) => Response::failed("Add attachment failed", &e),
+        }
+    }
+
+    async fn handle_get_attachment(
+        &self,
+        entry_id: Uuid,
+        attachment_id: Uuid,
+        agent_id: Option<String>,
+        purpose: Option<String>,
+    ) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let entry = match vault.get_entry(&entry_id) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => return Response::failed("Get attachment failed", &e),
+        };
+        let (name, category) = (entry.name.clone(), entry.category);
+        if let Some(denied) = policy_denial(
+            &self.audit,
+            vault.policy(),
+            agent_id.as_deref(),
+            category,
+        ) {
+            return denied;
+        }
+        if let Some(denied) = rate_limit_denial(
+            &self.audit,
+            &self.access_limiter,
+            &entry,
+            agent_id.as_deref(),
+        ) {
+            return denied;
+        }
+
+        match vault.get_attachment(&entry_id, &attachment_id) {
+            Ok(Some((attachment, data))) => {
+                // Reading an attachment is an access to its entry
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_access(
+                        entry_id,
+                        &name,
+                        category,
+                        agent_id.as_deref(),
+                        purpose.as_deref(),
+                        None,
+                    );
+                }
+
+                use base64::{Engine as _, engine::general_purpose::STANDARD};
+                Response::ok_with(serde_json::json!({
+                    "attachment": attachment,
+                    "data_b64": STANDARD.encode(&data),
+                }))
+            }
+            Ok(None) => Response::error(ErrorCode::NotFound, "Attachment not found"),
+            Err(e) => Response::failed("Get attachment failed", &e),
+        }
+    }
+
+    async fn handle_remove_attachment(&mut self, entry_id: Uuid, attachment_id: Uuid) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.remove_attachment(&entry_id, &attachment_id) {
+            Ok(true) => Response::ok(),
+            Ok(false) => Response::error(ErrorCode::NotFound, "Attachment not found"),
+            Err(e) => Response::failed("Remove attachment failed", &e),
+        }
+    }
+
+    async fn handle_set_policy(&mut self, policy: Option<AccessPolicy>) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.set_policy(policy) {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::failed("Set policy failed", &e),
+        }
+    }
+
+    async fn handle_get_policy(&self) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        Response::ok_with(vault.policy())
+    }
+
+    /// Not audited as an access: nothing secret leaves the vault
+    async fn handle_describe(&self, id: Uuid, agent_id: Option<String>) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.describe_entry(&id) {
+            Ok(Some(detail)) => {
+                let category = detail.metadata.category;
+                if let Some(denied) = policy_denial(&self.audit, vault.policy(), agent_id.as_deref(), category) {
+                    return denied;
+                }
+                Response::ok_with(detail)
+            }
+            Ok(None) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Describe failed", &e),
+        }
+    }
+
+    /// Metadata only, so not audited as an access either
+    async fn handle_history(&self, id: Uuid, agent_id: Option<String>) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let versions = match vault.entry_history(&id) {
+            Ok(versions) if versions.is_empty() => {
+                return Response::error(ErrorCode::NotFound, "No history for this entry");
+            }
+            Ok(versions) => versions,
+            Err(e) => return Response::failed("History failed", &e),
+        };
+        // Versions from before a move name the category they were in then
+        let current = match vault.get_entry(&id) {
+            Ok(current) => current.map(|e| e.category),
+            Err(e) => return Response::failed("History failed", &e),
+        };
+        for category in versions.iter().map(|v| v.entry.category).chain(current) {
+            if let Some(denied) = policy_denial(&self.audit, vault.policy(), agent_id.as_deref(), category) {
+                return denied;
+            }
+        }
+        let versions: Vec<_> = versions.iter().enumerate()
+            .map(|(index, v)| serde_json::json!({
+                "version": index,
+                "recorded": v.recorded,
+                "entry": EntryMetadata::from(&v.entry),
+            }))
+            .collect();
+        Response::ok_with(versions)
+    }
+
+    async fn handle_restore_version(&mut self, id: Uuid, version: usize, agent_id: Option<String>) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        match vault.entry_history(&id) {
+            Ok(versions) if versions.is_empty() => {
+                return Response::error(ErrorCode::NotFound, "No history for this entry");
+            }
+            Ok(versions) if version >= versions.len() => {
+                return Response::error(
+                    ErrorCode::InvalidRequest,
+                    format!("No version {}; the entry has {}", version, versions.len()),
+                );
+            }
+            Ok(_) => {}
+            Err(e) => return Response::failed("Restore failed", &e),
+        }
+
+        let restored = vault.restore_version(&id, version).and_then(|()| vault.get_entry(&id));
+        match restored {
+            Ok(Some(entry)) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_update(id, &entry.name, entry.category, agent_id.as_deref());
+                }
+                Response::ok()
+            }
+            Ok(None) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Restore failed", &e),
+        }
+    }
+
+    /// Only records the access, so it runs alongside other readers; the
+    /// count reaches disk with the next `flush_access`
+    async fn handle_touch(&self, id: Uuid, agent_id: Option<String>) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+        // A read-only unlock records no uses, so a touch would be lost
+        if vault.access_mode() == AccessMode::ReadOnly {
+            return Response::failed("Touch failed", &VaultReadOnly.into());
+        }
+
+        match vault.get_entry(&id) {
+            Ok(Some(entry)) => {
+                if let Some(denied) = policy_denial(&self.audit, vault.policy(), agent_id.as_deref(), entry.category) {
+                    return denied;
+                }
+                vault.record_access(&entry);
+                Response::ok()
+            }
+            Ok(None) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Touch failed", &e),
+        }
+    }
+
+    async fn handle_use_for_auth(
+        &self,
+        id: Uuid,
+        target_url: String,
+        agent_id: String,
+        purpose: String,
+        origin_chain: Option<Vec<String>>,
+    ) -> Response {
+        let vault = match self.vault.as_ref() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let target = match auth::parse_url(&target_url) {
+            Ok(t) => t,
+            Err(e) => return Response::error(ErrorCode::InvalidRequest, format!("Invalid target URL: {}", e)),
+        };
+        let target_domain = target.host_str().unwrap_or_default().to_string();
+
+        let policy = vault.policy().cloned();
+        match vault.get_entry(&id) {
+            Ok(Some(entry)) => {
+                if let Some(denied) = policy_denial(
+                    &self.audit,
+                    policy.as_ref(),
+                    Some(&agent_id),
+                    entry.category,
+                ) {
+                    return denied;
+                }
+                if let Some(denied) = rate_limit_denial(
+                    &self.audit,
+                    &self.access_limiter,
+                    &entry,
+                    Some(&agent_id),
+                ) {
+                    return denied;
+                }
+
+                // Everything that can refuse the request happens before
+                // the credential is attached to anything outbound
+                let request = auth::check_target(&entry, &target, &self.domain_policy)
+                    .and_then(|()| auth::build_request(&entry, &target));
+
+                let request = match request {
+                    Ok(r) => r,
+                    Err(e) => {
+                        let reason = e.to_string();
+                        if let Some(audit) = audit_log(&self.audit).as_mut() {
+                            let _ = audit.log_auth_use(
+                                &entry,
+                                &agent_id,
+                                &purpose,
+                                &target_domain,
+                                Err(&reason),
+                                origin_chain,
+                            );
+                        }
+                        return Response::error(ErrorCode::PermissionDenied, format!("Auth denied: {}", reason));
+                    }
+                };
+
+                let result = auth::execute(request, &target).await;
+
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = match result {
+                        Err(ref e) if e.is::<auth::PinMismatch>() => audit.log_denial(
+                            "certificate pin mismatch",
+                            Some(&agent_id),
+                            Some(entry.category),
+                        ),
+                        // Any other failure may have happened after the
+                        // credential was sent, so it still counts as a use
+                        _ => {
+                            let outcome = match &result {
+                                Ok(outcome) => format!("HTTP {}", outcome.status),
+                                Err(e) => format!("failed: {:#}", e),
+                            };
+                            audit.log_auth_use(
+                                &entry,
+                                &agent_id,
+                                &purpose,
+                                &target_domain,
+                                Ok(&outcome),
+                                origin_chain,
+                            )
+                        }
+                    };
+                }
+                if !matches!(result, Err(ref e) if e.is::<auth::PinMismatch>()) {
+                    vault.record_access(&entry);
+                }
+
+                match result {
+                    Ok(outcome) => Response::ok_with(outcome),
+                    Err(e) if e.is::<auth::PinMismatch>() => {
+                        Response::error(ErrorCode::PermissionDenied, "Auth denied: certificate pin mismatch")
+                    }
+                    Err(e) => Response::failed("Auth request failed", &e),
+                }
+            }
+            Ok(None) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Auth failed", &e),
+        }
+    }
+
+    async fn handle_refresh_oauth(&mut self, id: Uuid, agent_id: Option<String>) -> Response {
+        let vault = match self.vault.as_mut() {
+            Some(v) if v.is_unlocked() => v,
+            _ => return Response::error(ErrorCode::VaultLocked, "Vault not unlocked"),
+        };
+
+        let mut entry = match vault.get_entry(&id) {
+            Ok(Some(entry)) => entry.clone(),
+            Ok(None) => return Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => return Response::failed("Token refresh failed", &e),
+        };
+        let policy = vault.policy().cloned();
+        if let Some(denied) = policy_denial(&self.audit, policy.as_ref(), agent_id.as_deref(), entry.category) {
+            return denied;
+        }
+        if let Some(denied) = rate_limit_denial(&self.audit, &self.access_limiter, &entry, agent_id.as_deref()) {
+            return denied;
+        }
+        let (request, endpoint) = match auth::build_refresh(&entry) {
+            Ok(built) => built,
+            Err(e) => return Response::error(ErrorCode::InvalidRequest, format!("Cannot refresh: {}", e)),
+        };
+
+        // The refresh token has gone out whatever the answer
+        let result = auth::refresh(request).await;
+        if let Some(audit) = audit_log(&self.audit).as_mut() {
+            let outcome = match &result {
+                Ok(_) => "refreshed".to_string(),
+                Err(e) => format!("failed: {:#}", e),
+            };
+            let _ = audit.log_auth_use(
+                &entry,
+                agent_id.as_deref().unwrap_or_default(),
+                "oauth token refresh",
+                endpoint.host_str().unwrap_or_default(),
+                Ok(&outcome),
+                None,
+            );
+        }
+        let refreshed = match result {
+            Ok(refreshed) => refreshed,
+            Err(e) => return Response::failed("Token refresh failed", &e),
+        };
+
+        entry.value = Zeroizing::new(refreshed.access_token.as_bytes().to_vec());
+        entry.expires = refreshed.expires_in
+            .and_then(chrono::Duration::try_seconds)
+            .map(|valid| Utc::now() + valid);
+        // Servers that rotate refresh tokens revoke the one just used
+        let stored = entry.custom_fields.iter_mut().find(|f| f.name == auth::REFRESH_TOKEN_FIELD);
+        if let (Some(rotated), Some(field)) = (refreshed.refresh_token, stored) {
+            field.value = rotated;
+        }
+        let (name, category, expires) = (entry.name.clone(), entry.category, entry.expires);
+
+        match vault.update_entry(entry) {
+            Ok(true) => {
+                if let Some(audit) = audit_log(&self.audit).as_mut() {
+                    let _ = audit.log_update(id, &name, category, agent_id.as_deref());
+                }
+                Response::ok_with(serde_json::json!({ "refreshed": true, "expires": expires }))
+            }
+            Ok(false) => Response::error(ErrorCode::NotFound, "Entry not found"),
+            Err(e) => Response::failed("Storing the refreshed token failed", &e),
+        }
+    }
+}
+
+/// Refuse (and audit) a read the vault's access policy doesn't allow
+fn policy_denial(
+    audit: &StdMutex<Option<AuditLog>>,
+    policy: Option<&AccessPolicy>,
+    agent_id: Option<&str>,
+    category: Category,
+) -> Option<Response> {
+    let reason = policy?.check(agent_id, category).err()?;
+    if let Some(audit) = audit_log(audit).as_mut() {
+        let _ = audit.log_denial(&reason, agent_id, Some(category));
+    }
+    Some(Response::error(ErrorCode::PermissionDenied, format!("Access denied: {}", reason)))
+}
+
+/// Refuse (and audit) a read past the entry's per-minute access limit
+fn rate_limit_denial(
+    audit: &StdMutex<Option<AuditLog>>,
+    limiter: &StdMutex<AccessLimiter>,
+    entry: &VaultEntry,
+    agent_id: Option<&str>,
+) -> Option<Response> {
+    let limit = entry.access_limit()?;
+    let allowed = limiter.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .try_access(entry.id, limit, Instant::now());
+    if allowed {
+        return None;
+    }
+
+    if let Some(audit) = audit_log(audit).as_mut() {
+        let _ = audit.log_denial("rate limit", agent_id, Some(entry.category));
+    }
+    Some(Response::error(
+        ErrorCode::RateLimited,
+        format!("Access denied: rate limit of {} reads per minute", limit),
+    ))
+}
+
+/// The session's audit log, open while the vault is unlocked
+///
+/// Behind its own lock because reads are audited too and run with the
+/// daemon only borrowed shared. A panic mid-append leaves at worst a
+/// torn line, which `verify_chain` reports, so poisoning is ignored.
+fn audit_log(audit: &StdMutex<Option<AuditLog>>) -> MutexGuard<'_, Option<AuditLog>> {
+    audit.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Purge entries past `retention`, where failing only means they stay
+fn purge_audit(audit: &mut AuditLog, retention: StdDuration) {
+    let Some(cutoff) = Duration::from_std(retention).ok().and_then(|d| Utc::now().checked_sub_signed(d)) else {
+        return;
+    };
+    match audit.purge_older_than(cutoff) {
+        Ok(0) => {}
+        Ok(purged) => tracing::info!("Purged {} audit entries older than {}", purged, cutoff),
+        Err(e) => tracing::warn!("Audit purge failed: {:#}", e),
+    }
+}
+
+/// `AuditLog::compact`, where failing only means it stays as it was
+fn compact_audit(audit: &mut AuditLog) {
+    match audit.compact() {
+        Ok(report) => tracing::debug!(
+            "Compacted audit archives: {} entries compressed, {} bytes reclaimed",
+            report.entries_retained, report.bytes_reclaimed
+        ),
+        Err(e) => tracing::warn!("Audit log compaction failed: {:#}", e),
+    }
+}
+
+/// Handle `req` on a daemon shared between connections
+///
+/// Read-only requests (`Request::is_read_only`) share the lock with each
+/// other; everything else waits for exclusive access.
+pub async fn handle_shared(daemon: &RwLock<VaultDaemon>, req: Request) -> Response {
+    if !req.is_read_only() {
+        return daemon.write().await.handle(req).await;
+    }
+
+    // Filling the category cache needs exclusive access; do it first so
+    // concurrent reads don't each decrypt categories from disk
+    if daemon.read().await.is_cache_cold(&req) {
+        daemon.write().await.warm_cache(&req);
+    }
+    // Reading a one-time entry deletes it
+    if daemon.read().await.consumes_on_read(&req) {
+        return daemon.write().await.handle(req).await;
+    }
+    daemon.read().await.handle_read(req).await
+}
+
+/// `handle_shared`, answering `request_timeout` if `req` takes longer than
+/// `req.timeout(default)`
+///
+/// The abandoned request releases the daemon lock, so one stuck request
+/// can't hold up every client. Only waiting is cut short: work that never
+/// yields, like Argon2, finishes first.
+///
+/// Each request runs in a `request` span naming its command, vault and
+/// agent; its outcome is logged at debug level and the request itself,
+/// secrets redacted (see `Secret`), at trace level.
+pub async fn handle_timed(daemon: &RwLock<VaultDaemon>, req: Request, default: StdDuration) -> Response {
+    let timeout = req.timeout(default);
+    let span = tracing::info_span!("request", cmd = req.command(), vault = req.vault(), agent_id = req.agent_id());
+    async move {
+        tracing::trace!(request = ?req, "Handling request");
+        let started = Instant::now();
+        let response = match tokio::time::timeout(timeout, handle_shared(daemon, req)).await {
+            Ok(response) => response,
+            Err(_) => {
+                tracing::warn!("Abandoned a request after {:?}", timeout);
+                Response::error(
+                    ErrorCode::RequestTimeout,
+                    format!("Request took longer than {} seconds", timeout.as_secs()),
+                )
+            }
+        };
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        match &response {
+            // Messages can repeat parts of the request; the code is enough
+            Response::Error { code, .. } => tracing::debug!(error = ?code, elapsed_ms, "Request failed"),
+            _ => tracing::debug!(elapsed_ms, "Request succeeded"),
+        }
+        response
+    }
+    .instrument(span)
+    .await
+}
+
+/// Run the vault daemon on a Unix socket
+pub async fn run_daemon(
+    socket_path: impl AsRef<Path>,
+    vault_path: impl AsRef<Path>,
+    config: DaemonConfig,
+) -> Result<()> {
+    let socket_path = socket_path.as_ref();
+    
+    // Remove existing socket
+    if socket_path.exists() {
+        std::fs::remove_file(socket_path)?;
+    }
+    
+    // Create parent directory
+    if let Some(parent) = socket_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    
+    let listener = UnixListener::bind(socket_path)?;
+    
+    // Set socket permissions (owner only)
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    
+    tracing::info!("Vault daemon listening on {:?}", socket_path);
+    
+    let mut daemon = VaultDaemon::new(vault_path)
+        .with_cache_budget(config.cache_budget)
+        .with_min_passphrase_score(config.min_passphrase_score)
+        .with_require_audit(config.require_audit)
+        .with_audit_retention(config.audit_retention)
+        .with_audit_rotation(config.audit_rotation.clone())
+        .with_domain_policy(config.domain_policy)
+        .with_pepper_file(config.pepper_file.clone())
+        .with_emergency_socket(config.emergency_remove_socket.then(|| socket_path.to_path_buf()));
+    for (name, path) in &config.named_vaults {
+        daemon.register(name, path);
+    }
+    if let Some(passphrase) = config.unlock_passphrase {
+        daemon.unlock_at_startup(&passphrase).await?;
+        drop(passphrase);
+        tracing::info!("Default vault unlocked at startup");
+    }
+    let daemon = Arc::new(RwLock::new(daemon));
+
+    if let Some(addr) = config.metrics_listen {
+        let listener = crate::metrics::bind(addr).await?;
+        let daemon = Arc::clone(&daemon);
+        tokio::spawn(async move {
+            if let Err(e) = crate::metrics::serve(listener, daemon).await {
+                tracing::error!("Metrics listener stopped: {}", e);
+            }
+        });
+    }
+
+    #[cfg(feature = "websocket")]
+    if let Some(ws) = config.websocket {
+        let listener = crate::ws::bind(&ws).await?;
+        let daemon = Arc::clone(&daemon);
+        tokio::spawn(async move {
+            let serving = crate::ws::serve(listener, ws.token, daemon, config.max_request_bytes, config.request_timeout);
+            if let Err(e) = serving.await {
+                tracing::error!("WebSocket listener stopped: {}", e);
+            }
+        });
+    }
+    let peer_policy = Arc::new(config.peer_policy);
+    let max_request = config.max_request_bytes;
+    let request_timeout = config.request_timeout;
+
+    {
+        let daemon = Arc::clone(&daemon);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(ACCESS_FLUSH_INTERVAL);
+            loop {
+                ticker.tick().await;
+                // Readers only wait on the write lock when there's a write
+                if daemon.read().await.has_pending_access() {
+                    daemon.write().await.flush_access();
+                }
+            }
+        });
+    }
+
+    {
+        let daemon = Arc::clone(&daemon);
+        let mut usr1 = signal(SignalKind::user_defined1())?;
+        tokio::spawn(async move {
+            while usr1.recv().await.is_some() {
+                daemon.write().await.emergency("SIGUSR1", None);
+            }
+        });
+    }
+
+    if let Some(interval) = config.audit_compact_interval {
+        let daemon = Arc::clone(&daemon);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick is immediate; nothing has been logged yet
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                daemon.write().await.compact_audit();
+            }
+        });
+    }
+
+    if let Some(timeout) = config.lock_timeout {
+        let daemon = Arc::clone(&daemon);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(IDLE_CHECK_INTERVAL.min(timeout));
+            loop {
+                ticker.tick().await;
+                daemon.write().await.lock_if_idle(timeout).await;
+            }
+        });
+    }
+    
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let daemon = Arc::clone(&daemon);
+        let peer_policy = Arc::clone(&peer_policy);
+        
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, daemon, peer_policy, max_request, request_timeout).await {
+                tracing::error!("Connection error: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    daemon: Arc<RwLock<VaultDaemon>>,
+    peer_policy: Arc<PeerPolicy>,
+    max_request: usize,
+    request_timeout: StdDuration,
+) -> Result<()> {
+    let metrics = daemon.read().await.metrics();
+    // SO_PEERCRED on Linux, getpeereid on other Unixes
+    let cred = stream.peer_cred()?;
+    metrics.record_connection(peer_policy.allows(cred.uid()));
+    if !peer_policy.allows(cred.uid()) {
+        tracing::warn!("Rejected connection from uid {} (pid {:?})", cred.uid(), cred.pid());
+        daemon.read().await.log_peer_denial(cred.uid(), cred.pid());
+
+        let mut stream = stream;
+        let response_json = serde_json::to_string(&Response::error(ErrorCode::PermissionDenied, "Permission denied"))? + "\n";
+        stream.write_all(response_json.as_bytes()).await?;
+        return Ok(());
+    }
+
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let greeting = serde_json::to_string(&Greeting::current())? + "\n";
+    writer.write_all(greeting.as_bytes()).await?;
+
+    // Requests and responses can carry secret values
+    let mut line = Zeroizing::new(String::new());
+    
+    loop {
+        line.zeroize();
+        // Capped, so a client can't grow the line until the daemon runs out of memory
+        let n = (&mut reader).take(max_request as u64 + 1).read_line(&mut line).await?;
+        if n == 0 {
+            break; // Connection closed
+        }
+        if n > max_request {
+            // The rest of the line is still unread; there's no resyncing
+            let response = Response::error(
+                ErrorCode::RequestTooLarge,
+                format!("Request exceeds {} bytes", max_request),
+            );
+            writer.write_all((serde_json::to_string(&response)? + "\n").as_bytes()).await?;
+            break;
+        }
+        
+        let mut refused_hello = false;
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Subscribe { vault }) => {
+                let events = daemon.read().await.subscribe(vault.as_deref());
+                match events {
+                    Ok(events) => return stream_audit_events(reader, writer, events).await,
+                    Err(response) => response,
+                }
+            }
+            Ok(req) => {
+                let hello = matches!(req, Request::Hello { .. });
+                let command = req.command();
+                let started = Instant::now();
+                let response = handle_timed(&daemon, req, request_timeout).await;
+                metrics.record_request(command, &response, started.elapsed());
+                refused_hello = hello && matches!(response, Response::Error { .. });
+                response
+            }
+            Err(e) => {
+                metrics.record_invalid_request();
+                Response::error(ErrorCode::InvalidRequest, format!("Invalid request: {}", e))
+            }
+        };
+        
+        let response_json = Zeroizing::new(serde_json::to_string(&response)? + "\n");
+        writer.write_all(response_json.as_bytes()).await?;
+        if refused_hello {
+            break;
+        }
+    }
+    
+    Ok(())
+}
+
+/// Stream audit events as newline-delimited JSON until the subscriber
+/// disconnects or falls behind the broadcast buffer
+async fn stream_audit_events(
+    mut reader: BufReader<OwnedReadHalf>,
+    mut writer: OwnedWriteHalf,
+    mut events: broadcast::Receiver<AuditEntry>,
+) -> Result<()> {
+    let ack = serde_json::to_string(&Response::ok())? + "\n";
+    writer.write_all(ack.as_bytes()).await?;
+
+    let mut discard = [0u8; 1024];
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(entry) => {
+                    let line = serde_json::to_string(&entry)? + "\n";
+                    writer.write_all(line.as_bytes()).await?;
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Dropping audit subscriber that missed {} events", missed);
+                    let response = Response::error(ErrorCode::Internal, format!("Subscriber lagged by {} events", missed));
+                    let line = serde_json::to_string(&response)? + "\n";
+                    writer.write_all(line.as_bytes()).await?;
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // Anything the subscriber sends is ignored; EOF ends the stream
+            read = reader.read(&mut discard) => match read {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            },
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditEventType;
+    use tempfile::TempDir;
+
+    /// A request as a client would send it
+    fn call(json: serde_json::Value) -> Request {
+        serde_json::from_value(json).unwrap()
+    }
+
+    /// A response as a client would receive it
+    fn json(response: Response) -> serde_json::Value {
+        serde_json::to_value(response).unwrap()
+    }
+
+    #[test]
+    fn test_peer_policy_defaults_to_own_uid() {
+        let own = unsafe { libc::getuid() };
+        let policy = PeerPolicy::new(Vec::new());
+        assert!(policy.allows(own));
+        assert!(!policy.allows(own.wrapping_add(1)));
+
+        let policy = PeerPolicy::new(vec![1000, 1001]);
+        assert!(policy.allows(1001));
+        assert!(!policy.allows(0));
+    }
+
+    #[test]
+    fn test_parse_listen_addr() {
+        assert_eq!(parse_listen_addr("8765").unwrap(), "127.0.0.1:8765".parse().unwrap());
+        assert_eq!(parse_listen_addr("[::1]:9000").unwrap(), "[::1]:9000".parse().unwrap());
+        assert!(parse_listen_addr("localhost").is_err());
+    }
+
+    #[test]
+    fn test_request_debug_redacts_secrets() {
+        let unlock = format!("{:?}", call(serde_json::json!({"cmd": "unlock", "passphrase": "hunter2"})));
+        assert!(!unlock.contains("hunter2"), "{}", unlock);
+        assert!(unlock.contains("passphrase: ***"), "{}", unlock);
+
+        let create = format!("{:?}", call(serde_json::json!({
+            "cmd": "create",
+            "entry": {
+                "category": "personal", "entry_type": "password", "name": "Forum", "value": "c2VjcmV0",
+                "custom_fields": [{"name": "PIN", "value": "4321"}],
+            },
+            "agent_id": "agent-7",
+            "extra_passphrase": "open sesame",
+        })));
+        for secret in ["c2VjcmV0", "4321", "open sesame"] {
+            assert!(!create.contains(secret), "{}", create);
+        }
+        assert!(create.contains("Forum") && create.contains("PIN") && create.contains("agent-7"), "{}", create);
+    }
+
+    #[test]
+    fn test_request_labels() {
+        let refresh = call(serde_json::json!({"cmd": "refresh_oauth", "id": Uuid::nil(), "agent_id": "a"}));
+        assert_eq!((refresh.command(), refresh.agent_id()), ("refresh_oauth", Some("a")));
+        let create = call(serde_json::json!({"cmd": "create_totp_from_uri", "vault": "work", "uri": "otpauth://totp/x"}));
+        assert_eq!((create.command(), create.vault(), create.agent_id()), ("create_totp_from_uri", Some("work"), None));
+        assert_eq!(Request::Status.command(), "status");
+    }
+
+    #[tokio::test]
+    async fn test_audit_retention_on_unlock() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"))
+            .with_audit_retention(Some(StdDuration::from_millis(200)));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        tokio::time::sleep(StdDuration::from_millis(300)).await;
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+
+        let audit = audit_log(&daemon.sessions[DEFAULT_VAULT].audit);
+        let audit = audit.as_ref().unwrap();
+        let events: Vec<AuditEventType> = audit.read_all().unwrap().iter().map(|e| e.event_type).collect();
+        assert_eq!(events, [AuditEventType::VaultUnlock, AuditEventType::AuditPurge]);
+        assert_eq!(audit.anchor().unwrap().unwrap().purged, 2);
+        assert!(audit.verify_chain().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_audit_rotation() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"))
+            .with_audit_rotation(RotationPolicy { max_bytes: Some(1), max_age: None });
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+
+        let audit = audit_log(&daemon.sessions[DEFAULT_VAULT].audit);
+        let audit = audit.as_ref().unwrap();
+        // Every append after the first finds the log over a byte
+        assert_eq!(audit.read_all().unwrap().len(), 1);
+        assert!(!audit.archives().unwrap().is_empty());
+        assert!(audit.verify_chain().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_emergency() {
+        let tmp = TempDir::new().unwrap();
+        let socket = tmp.path().join("vault.sock");
+        std::fs::write(&socket, b"").unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"))
+            .with_emergency_socket(Some(socket.clone()));
+        daemon.register("work", tmp.path().join("work"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let mut events = daemon.subscribe(None).unwrap();
+
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "emergency", "agent_id": "ops"}))).await);
+        assert_eq!(r["data"]["locked"], serde_json::json!(["default"]));
+        assert!(!daemon.sessions.values().any(VaultSession::is_unlocked));
+        assert!(!socket.exists());
+
+        // Subscribers get the event, then their stream ends
+        let event = events.recv().await.unwrap();
+        assert_eq!((event.event_type, event.agent_id.as_deref()), (AuditEventType::EmergencyLock, Some("ops")));
+        assert!(matches!(events.recv().await, Err(RecvError::Closed)));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let emergency = entries.iter().find(|e| e.event_type == AuditEventType::EmergencyLock).unwrap();
+        assert_eq!(emergency.purpose.as_deref(), Some("emergency request"));
+        assert!(audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().verify_chain().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_idle_lock() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        // Nothing to lock yet
+        assert!(!daemon.lock_if_idle(StdDuration::ZERO).await);
+
+        let unlock = Request::Unlock {
+            vault: None,
+            path: None,
+            passphrase: "pass".into(),
+            categories: None,
+            mode: AccessMode::ReadWrite,
+            force: false,
+        };
+        assert!(matches!(daemon.handle(unlock).await, Response::Ok { .. }));
+
+        assert!(!daemon.lock_if_idle(StdDuration::from_secs(3600)).await);
+        assert!(daemon.lock_if_idle(StdDuration::ZERO).await);
+        assert!(!daemon.sessions[DEFAULT_VAULT].is_unlocked());
+    }
+
+    #[test]
+    fn test_unlock_backoff() {
+        assert_eq!(unlock_backoff(1), UNLOCK_BACKOFF_BASE);
+        assert_eq!(unlock_backoff(3), UNLOCK_BACKOFF_BASE * 4);
+        assert_eq!(unlock_backoff(20), UNLOCK_BACKOFF_MAX);
+        assert_eq!(unlock_backoff(u32::MAX), UNLOCK_BACKOFF_MAX);
+    }
+
+    #[tokio::test]
+    async fn test_failed_unlocks_are_audited() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        let unlock = |p: &str| Request::Unlock {
+            vault: None,
+            path: None,
+            passphrase: p.into(),
+            categories: None,
+            mode: AccessMode::ReadWrite,
+            force: false,
+        };
+        daemon.handle(unlock("pass")).await;
+        daemon.handle(Request::Lock { vault: None }).await;
+
+        match daemon.handle(unlock("wrong")).await {
+            Response::Error { code, message } => {
+                assert_eq!(code, ErrorCode::BadPassphrase);
+                assert_eq!(message, "Unlock failed");
+            }
+            other => panic!("expected error, got {:?}", other),
+        }
+        assert_eq!(daemon.sessions[DEFAULT_VAULT].failed_unlocks, 1);
+
+        assert!(matches!(daemon.handle(unlock("pass")).await, Response::Ok { .. }));
+        let session = &daemon.sessions[DEFAULT_VAULT];
+        assert_eq!(session.failed_unlocks, 0);
+
+        let entries = audit_log(&session.audit).as_ref().unwrap().read_all().unwrap();
+        let denial = entries.iter()
+            .find(|e| e.event_type == AuditEventType::AccessDenied)
+            .expect("failed unlock was audited");
+        assert_eq!(denial.denial_reason.as_deref(), Some("failed unlock attempts: 1"));
+        let unlock_event = entries.iter()
+            .find(|e| e.event_type == AuditEventType::VaultUnlock)
+            .unwrap();
+        assert_eq!(unlock_event.access_mode, Some(AccessMode::ReadWrite));
+    }
+
+    #[tokio::test]
+    async fn test_unlock_migrates_old_formats() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("vault");
+        let mut daemon = VaultDaemon::new(path.clone());
+        let unlock = || call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}));
+
+        daemon.handle(unlock()).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "financial", "entry_type": "password", "name": "Bank", "value": "eA=="},
+        }))).await);
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        crate::vault::tests::downgrade(&path, "pass", 2);
+        let needs_migration = |daemon: &VaultDaemon| {
+            daemon.sessions[DEFAULT_VAULT].vault.as_ref().unwrap().needs_migration()
+        };
+
+        // Neither a partial nor a read-only unlock may rewrite the vault
+        for request in [
+            serde_json::json!({"cmd": "unlock", "passphrase": "pass", "categories": ["financial"]}),
+            serde_json::json!({"cmd": "unlock", "passphrase": "pass", "mode": "read_only"}),
+        ] {
+            assert!(matches!(daemon.handle(call(request)).await, Response::Ok { .. }));
+            assert!(needs_migration(&daemon));
+            daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        }
+
+        assert!(matches!(daemon.handle(unlock()).await, Response::Ok { .. }));
+        assert!(!needs_migration(&daemon));
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": created["data"]["id"]}))).await);
+        assert_eq!(r["data"]["name"], "Bank");
+    }
+
+    #[tokio::test]
+    async fn test_named_vaults() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("personal"));
+        let work_path = tmp.path().join("work");
+
+        let ok = |r: &Response| matches!(r, Response::Ok { .. });
+
+        // An unknown name needs a path to register it
+        let r = daemon.handle(call(serde_json::json!({
+            "cmd": "unlock", "vault": "work", "passphrase": "w"
+        }))).await;
+        assert!(!ok(&r));
+
+        let r = daemon.handle(call(serde_json::json!({
+            "cmd": "unlock", "vault": "work", "path": work_path, "passphrase": "w"
+        }))).await;
+        assert!(ok(&r));
+
+        // Requests without a name still hit the (locked) default vault
+        let r = daemon.handle(call(serde_json::json!({"cmd": "list", "category": "financial"}))).await;
+        assert!(!ok(&r));
+        let r = daemon.handle(call(serde_json::json!({
+            "cmd": "list", "vault": "work", "category": "financial"
+        }))).await;
+        assert!(ok(&r));
+
+        // A registered name can't be re-pointed
+        let r = daemon.handle(call(serde_json::json!({
+            "cmd": "unlock", "vault": "work", "path": tmp.path().join("elsewhere"), "passphrase": "w"
+        }))).await;
+        assert!(!ok(&r));
+
+        let status = json(daemon.handle(Request::Status).await);
+        assert_eq!(status["data"]["unlocked"], false);
+        let vaults = status["data"]["vaults"].as_array().unwrap();
+        assert_eq!(vaults.len(), 2);
+        assert_eq!(vaults[0]["name"], DEFAULT_VAULT);
+        assert_eq!(vaults[1]["name"], "work");
+        assert_eq!(vaults[1]["unlocked"], true);
+    }
+
+    #[tokio::test]
+    async fn test_batch_preserves_order() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        let req: Request = serde_json::from_str(r#"{"cmd": "batch", "requests": [
+            {"cmd": "status"},
+            {"cmd": "unlock", "passphrase": "pass", "categories": null},
+            {"cmd": "list", "category": "financial"},
+            {"cmd": "batch", "requests": []}
+        ]}"#).unwrap();
+
+        let response = json(daemon.handle(req).await);
+        let results = response["data"].as_array().unwrap();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[0]["data"]["unlocked"], false);
+        assert_eq!(results[1]["status"], "ok");
+        assert_eq!(results[2]["data"], serde_json::json!([]));
+        assert_eq!(results[3]["status"], "error");
+    }
+
+    #[tokio::test]
+    async fn test_mutations_are_audited() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let entry = |name: &str| serde_json::json!({
+            "category": "financial",
+            "entry_type": "api_key",
+            "name": name,
+            "value": "c2VjcmV0",
+        });
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create", "entry": entry("Stripe"), "agent_id": "billing-agent",
+            "origin_chain": ["user", "planner"],
+        }))).await);
+        let id = created["data"]["id"].as_str().unwrap().to_string();
+
+        let r = daemon.handle(call(serde_json::json!({
+            "cmd": "update", "id": id, "entry": entry("Stripe live"), "agent_id": "billing-agent",
+        }))).await;
+        assert!(matches!(r, Response::Ok { .. }));
+        // A dry run reports the entry but neither removes nor audits it
+        let preview = json(daemon.handle(call(serde_json::json!({
+            "cmd": "delete", "id": id, "dry_run": true,
+        }))).await);
+        assert_eq!(preview["data"]["would_delete"]["name"], "Stripe live");
+        assert_eq!(preview["data"]["would_delete"]["category"], "financial");
+        let r = daemon.handle(call(serde_json::json!({"cmd": "get", "id": id}))).await;
+        assert!(matches!(r, Response::Ok { .. }));
+
+        let r = daemon.handle(call(serde_json::json!({"cmd": "delete", "id": id}))).await;
+        assert!(matches!(r, Response::Ok { .. }));
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let mutations: Vec<_> = entries.iter()
+            .filter(|e| matches!(e.event_type,
+                AuditEventType::EntryCreate | AuditEventType::EntryUpdate | AuditEventType::EntryDelete))
+            .map(|e| (e.event_type, e.entry_name.as_deref(), e.agent_id.as_deref()))
+            .collect();
+        assert_eq!(mutations, vec![
+            (AuditEventType::EntryCreate, Some("Stripe"), Some("billing-agent")),
+            (AuditEventType::EntryUpdate, Some("Stripe live"), Some("billing-agent")),
+            (AuditEventType::EntryDelete, Some("Stripe live"), None),
+        ]);
+        assert!(entries.iter().all(|e| e.category.is_none() || e.category == Some(Category::Financial)));
+        let create = entries.iter().find(|e| e.event_type == AuditEventType::EntryCreate).unwrap();
+        assert_eq!(create.origin_chain, Some(vec!["user".to_string(), "planner".to_string()]));
+
+        // The same trail, filtered, through the protocol
+        let query = json(daemon.handle(call(serde_json::json!({
+            "cmd": "audit_query", "since_hours": 1, "agent_id": "billing-agent",
+            "event_types": ["entry_create", "entry_update", "entry_delete"],
+        }))).await);
+        assert_eq!(query["data"]["matched"], 2);
+        assert_eq!(query["data"]["entries"][1]["entry_name"], "Stripe live");
+        let query = json(daemon.handle(call(serde_json::json!({
+            "cmd": "audit_query", "category": "financial", "limit": 1,
+        }))).await);
+        assert_eq!(query["data"]["entries"][0]["event_type"], "entry_delete");
+        let bad = json(daemon.handle(call(serde_json::json!({
+            "cmd": "audit_query", "since_hours": 0,
+        }))).await);
+        assert_eq!(bad["code"], "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn test_expiring_soon() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        let expiring = |hours: i64| call(serde_json::json!({"cmd": "expiring_soon", "within_hours": hours}));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {
+                "category": "authentication", "entry_type": "api_key", "name": "Deploy key", "value": "eA==",
+                "expires": Utc::now() + Duration::days(2),
+            },
+        }))).await;
+
+        assert_eq!(json(daemon.handle(expiring(72)).await)["data"][0]["name"], "Deploy key");
+        assert_eq!(json(daemon.handle(expiring(24)).await)["data"], serde_json::json!([]));
+        // Out of range, or enough to overflow the deadline, is refused
+        // rather than panicking with the daemon locked
+        for hours in [i64::MAX, MAX_WINDOW_HOURS + 1, 0, -1] {
+            assert_eq!(json(daemon.handle(expiring(hours)).await)["code"], "invalid_request");
+            let stats = call(serde_json::json!({"cmd": "stats", "window_hours": hours}));
+            assert_eq!(json(daemon.handle(stats).await)["code"], "invalid_request");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_overview_window() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "overview"}))).await);
+        assert_eq!(r["data"].as_array().unwrap().len(), Category::all().len());
+        for hours in [i64::MAX, 0, -1] {
+            let r = json(daemon.handle(call(serde_json::json!({"cmd": "overview", "within_hours": hours}))).await);
+            assert_eq!(r["code"], "invalid_request");
+            let r = json(daemon.handle(call(serde_json::json!({"cmd": "audit_query", "since_hours": hours}))).await);
+            assert_eq!(r["code"], "invalid_request");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        let verify = |passphrase: &str| call(serde_json::json!({"cmd": "verify", "passphrase": passphrase}));
+        let verified = |r: Response| json(r)["data"]["verified"].clone();
+
+        let r = json(daemon.handle(verify("pass")).await);
+        assert_eq!(r["code"], "vault_locked");
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        assert_eq!(verified(daemon.handle(verify("wrong")).await), false);
+        assert_eq!(daemon.sessions[DEFAULT_VAULT].failed_unlocks, 1);
+        assert_eq!(verified(daemon.handle(verify("pass")).await), true);
+        let session = &daemon.sessions[DEFAULT_VAULT];
+        assert_eq!(session.failed_unlocks, 0);
+        assert!(session.is_unlocked());
+
+        let entries = audit_log(&session.audit).as_ref().unwrap().read_all().unwrap();
+        let denial = entries.iter().find(|e| e.event_type == AuditEventType::AccessDenied).unwrap();
+        assert_eq!(denial.denial_reason.as_deref(), Some("failed passphrase verification"));
+    }
+
+    #[tokio::test]
+    async fn test_passphrase_change_keeps_audit_log() {
+        let tmp = TempDir::new().unwrap();
+        let vault_path = tmp.path().join("vault");
+        let code = |r: &Response| serde_json::to_value(r).unwrap()["code"].clone();
+
+        // A log written before audit keys were stored in the vault
+        Vault::create(&vault_path, "old pass").unwrap();
+        let legacy = derive_subkey(&derive_master_key("old pass", &[0u8; 32], &KdfParams::BASELINE).unwrap(), "audit");
+        AuditLog::open(vault_path.join("audit.enc"), legacy).unwrap().log_lock().unwrap();
+
+        let mut daemon = VaultDaemon::new(&vault_path);
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "old pass"}))).await;
+        daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": "Forum", "value": "eA=="},
+        }))).await;
+
+        let r = daemon.handle(call(serde_json::json!({
+            "cmd": "update_passphrase", "old_passphrase": "guess", "new_passphrase": "new pass",
+        }))).await;
+        assert_eq!(code(&r), "bad_passphrase");
+        let r = daemon.handle(call(serde_json::json!({
+            "cmd": "update_passphrase", "old_passphrase": "old pass", "new_passphrase": "new pass",
+        }))).await;
+        assert!(matches!(r, Response::Ok { .. }));
+
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        let r = daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "old pass"}))).await;
+        assert_eq!(code(&r), "bad_passphrase");
+        let r = daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "new pass"}))).await;
+        assert!(matches!(r, Response::Ok { .. }));
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let events: Vec<AuditEventType> = entries.iter().map(|e| e.event_type).collect();
+        assert_eq!(events.first(), Some(&AuditEventType::VaultLock));
+        assert!(events.contains(&AuditEventType::EntryCreate));
+        assert!(events.contains(&AuditEventType::PassphraseChange));
+        assert_eq!(events.last(), Some(&AuditEventType::VaultUnlock));
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_kdf() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        let upgrade = |passphrase: &str| call(serde_json::json!({
+            "cmd": "upgrade_kdf", "passphrase": passphrase,
+            "memory_kib": 16 * 1024, "iterations": 2, "parallelism": 1,
+        }));
+
+        assert_eq!(json(daemon.handle(upgrade("pass")).await)["code"], "vault_locked");
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        assert_eq!(json(daemon.handle(upgrade("guess")).await)["code"], "bad_passphrase");
+        let r = json(daemon.handle(upgrade("pass")).await);
+        assert_eq!(r["data"], serde_json::json!({"recovery_disabled": false}));
+
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        let r = daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        assert!(matches!(r, Response::Ok { .. }));
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let upgrade = entries.iter().find(|e| e.event_type == AuditEventType::KdfUpgrade).unwrap();
+        assert_eq!(upgrade.purpose.as_deref(), Some("argon2id m=16384 KiB t=2 p=1"));
+    }
+
+    #[tokio::test]
+    async fn test_enroll_hardware_key() {
+        use crate::hwkey::tests::SoftKey;
+        use std::sync::atomic::Ordering;
+
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        let enroll = || call(serde_json::json!({"cmd": "enroll_hardware_key"}));
+
+        assert_eq!(json(daemon.handle(enroll()).await)["code"], "vault_locked");
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let key = SoftKey::new();
+        let session = daemon.sessions.get_mut(DEFAULT_VAULT).unwrap();
+        session.vault = session.vault.take().map(|v| v.with_hardware_device(Box::new(key.clone())));
+
+        key.present.store(false, Ordering::SeqCst);
+        assert_eq!(json(daemon.handle(enroll()).await)["code"], "hardware_key_required");
+        key.present.store(true, Ordering::SeqCst);
+        assert!(matches!(daemon.handle(enroll()).await, Response::Ok { .. }));
+        assert!(matches!(daemon.handle(enroll()).await, Response::Error { .. }));
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let enrolled = entries.iter().filter(|e| e.event_type == AuditEventType::HardwareKeyEnroll).count();
+        assert_eq!(enrolled, 1);
+    }
+
+    #[tokio::test]
+    async fn test_export_over_api() {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": "Forum", "value": "eA=="},
+        }))).await;
+
+        let exported = json(daemon.handle(call(serde_json::json!({
+            "cmd": "export", "passphrase": "backup pass", "agent_id": "backup-agent",
+        }))).await);
+        let blob = STANDARD.decode(exported["data"]["export"].as_str().unwrap()).unwrap();
+        let restored = Vault::import(tmp.path().join("restored"), &blob, "backup pass").unwrap();
+        assert_eq!(restored.list_entries(Category::Personal).unwrap()[0].name, "Forum");
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let export = entries.iter().find(|e| e.event_type == AuditEventType::VaultExport).unwrap();
+        assert_eq!(export.agent_id.as_deref(), Some("backup-agent"));
+
+        // A partial unlock isn't enough
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        daemon.handle(call(serde_json::json!({
+            "cmd": "unlock", "passphrase": "pass", "categories": ["personal"],
+        }))).await;
+        let r = daemon.handle(call(serde_json::json!({"cmd": "export", "passphrase": "backup pass"}))).await;
+        assert!(matches!(r, Response::Error { .. }));
+        // Nor can it tell a missing entry from one in a locked category
+        let r = daemon.handle(call(serde_json::json!({"cmd": "get", "id": Uuid::new_v4()}))).await;
+        assert_eq!(json(r)["code"], "category_locked");
+    }
+
+    #[tokio::test]
+    async fn test_weak_passphrase_refused_for_new_vault() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault")).with_min_passphrase_score(3);
+        daemon.register("strong", tmp.path().join("strong"));
+
+        let r = json(daemon.handle(call(serde_json::json!({
+            "cmd": "unlock", "passphrase": "password1",
+        }))).await);
+        assert_eq!(r["code"], "weak_passphrase");
+        assert!(r["message"].as_str().unwrap().contains("common"));
+        assert!(!tmp.path().join("vault").exists());
+
+        let r = daemon.handle(call(serde_json::json!({
+            "cmd": "unlock", "vault": "strong", "passphrase": "correct horse battery staple",
+        }))).await;
+        assert!(matches!(r, Response::Ok { .. }));
+
+        // Forced, and existing vaults aren't judged again
+        let r = daemon.handle(call(serde_json::json!({
+            "cmd": "unlock", "passphrase": "password1", "force": true,
+        }))).await;
+        assert!(matches!(r, Response::Ok { .. }));
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        let r = daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "password1"}))).await;
+        assert!(matches!(r, Response::Ok { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_newer_vault_format_is_reported() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("vault");
+        let mut daemon = VaultDaemon::new(path.clone());
+        let unlock = || call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}));
+
+        assert!(matches!(daemon.handle(unlock()).await, Response::Ok { .. }));
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+
+        let meta_path = path.join("vault.meta");
+        let mut meta: serde_json::Value = serde_json::from_slice(&std::fs::read(&meta_path).unwrap()).unwrap();
+        meta["version"] = serde_json::json!(crate::vault::VAULT_VERSION + 1);
+        std::fs::write(&meta_path, serde_json::to_vec(&meta).unwrap()).unwrap();
+
+        let r = json(daemon.handle(unlock()).await);
+        assert_eq!(r["code"], "unsupported_version");
+    }
+
+    #[tokio::test]
+    async fn test_missing_pepper_file_is_reported() {
+        let tmp = TempDir::new().unwrap();
+        let pepper_file = tmp.path().join("pepper");
+        std::fs::write(&pepper_file, [42u8; 32]).unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"))
+            .with_pepper_file(Some(pepper_file.clone()));
+        let unlock = || call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}));
+
+        assert!(matches!(daemon.handle(unlock()).await, Response::Ok { .. }));
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+
+        std::fs::remove_file(&pepper_file).unwrap();
+        let r = json(daemon.handle(unlock()).await);
+        assert_eq!(r["code"], "pepper_required");
+        assert!(r["message"].as_str().unwrap().contains("pepper"));
+
+        std::fs::write(&pepper_file, b"short").unwrap();
+        let r = json(daemon.handle(unlock()).await);
+        assert_eq!(r["code"], "pepper_required");
+
+        // Without --pepper-file at all
+        let mut bare = VaultDaemon::new(tmp.path().join("vault"));
+        let r = json(bare.handle(unlock()).await);
+        assert_eq!(r["code"], "pepper_required");
+
+        std::fs::write(&pepper_file, [42u8; 32]).unwrap();
+        assert!(matches!(daemon.handle(unlock()).await, Response::Ok { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_tag_requests() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        let data = |r: Response| json(r)["data"].clone();
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = data(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": "Forum", "value": "eA=="},
+        }))).await);
+        let id = created["id"].clone();
+
+        let r = daemon.handle(call(serde_json::json!({"cmd": "add_tags", "id": id, "tags": ["fun", "social"]}))).await;
+        assert_eq!(data(r)["tags"], serde_json::json!(["fun", "social"]));
+        let r = daemon.handle(call(serde_json::json!({"cmd": "remove_tags", "id": id, "tags": ["fun"]}))).await;
+        assert_eq!(data(r)["tags"], serde_json::json!(["social"]));
+
+        let list = call(serde_json::json!({"cmd": "list_tags"}));
+        assert!(list.is_read_only());
+        assert_eq!(data(daemon.handle(list).await), serde_json::json!([{"tag": "social", "count": 1}]));
+
+        let r = daemon.handle(call(serde_json::json!({"cmd": "add_tags", "id": Uuid::new_v4(), "tags": ["x"]}))).await;
+        assert_eq!(json(r)["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_folder_requests() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": "AWS", "value": "eA==", "folder": "/work/aws/"},
+        }))).await);
+        let id = created["data"]["id"].clone();
+        let r = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": "Bad", "value": "eA==", "folder": "a//b"},
+        }))).await);
+        assert_eq!(r["code"], "invalid_request");
+
+        let folders = call(serde_json::json!({"cmd": "list_folders", "category": "personal"}));
+        assert!(folders.is_read_only());
+        assert_eq!(json(daemon.handle(folders).await)["data"], serde_json::json!(["work/aws"]));
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "list_folder", "category": "personal", "prefix": "work"}))).await);
+        assert_eq!((r["data"][0]["name"].clone(), r["data"][0]["folder"].clone()), ("AWS".into(), "work/aws".into()));
+
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "set_folder", "id": id, "folder": "home"}))).await);
+        assert_eq!(r["status"], "ok");
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "list_folder", "category": "personal", "prefix": "work"}))).await);
+        assert_eq!(r["data"], serde_json::json!([]));
+        daemon.handle(call(serde_json::json!({"cmd": "set_folder", "id": id, "folder": null}))).await;
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "list_folders", "category": "personal"}))).await);
+        assert_eq!(r["data"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_audit_log_is_reported() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("vault");
+        let mut daemon = VaultDaemon::new(&path);
+        let unlock = || call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}));
+        let status = || call(serde_json::json!({"cmd": "audit_status", "verify": true}));
+
+        assert_eq!(json(daemon.handle(unlock()).await)["data"]["audit_available"], true);
+        let r = json(daemon.handle(status()).await);
+        assert_eq!(r["data"]["active"], true);
+        assert_eq!(r["data"]["chain"]["result"], "valid");
+        assert!(r["data"]["verified_at"].is_string());
+
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        std::fs::write(path.join("audit.enc"), b"not a log").unwrap();
+
+        // Unlocks, but says auditing is off
+        assert_eq!(json(daemon.handle(unlock()).await)["data"]["audit_available"], false);
+        let r = json(daemon.handle(status()).await);
+        assert_eq!(r["data"]["active"], false);
+        assert!(r["data"]["error"].as_str().unwrap().contains("Audit log unreadable"));
+
+        // Strict mode keeps the vault locked instead
+        let mut strict = VaultDaemon::new(&path).with_require_audit(true);
+        let r = json(strict.handle(unlock()).await);
+        assert_eq!(r["code"], "audit_unavailable");
+        assert!(!strict.sessions[DEFAULT_VAULT].is_unlocked());
+    }
+
+    #[tokio::test]
+    async fn test_verify_audit_finds_tampering() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("vault");
+        let mut daemon = VaultDaemon::new(&path);
+        let verify = || call(serde_json::json!({"cmd": "verify_audit"}));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        assert!(verify().is_read_only());
+        assert_eq!(json(daemon.handle(verify()).await)["data"], serde_json::json!({"result": "valid"}));
+
+        // Someone rewrites history with the key
+        let session = &daemon.sessions[DEFAULT_VAULT];
+        let key = session.vault.as_ref().unwrap().audit_key().unwrap().unwrap();
+        let original = audit_log(&session.audit).as_ref().unwrap().read_all().unwrap();
+        let rewrite = |entries: &[AuditEntry]| {
+            let content: String = entries.iter().map(|e| serde_json::to_string(e).unwrap() + "\n").collect();
+            std::fs::write(path.join("audit.enc"), crate::crypto::encrypt(content.as_bytes(), &key, b"").unwrap())
+                .unwrap();
+        };
+        let mut entries = original.clone();
+        entries[1].granted = !entries[1].granted;
+        rewrite(&entries);
+
+        let r = json(daemon.handle(verify()).await);
+        assert_eq!(r["data"]["result"], "broken");
+        assert_eq!(r["data"]["index"], 1);
+        assert_eq!(r["data"]["reason"], "entry_hash_mismatch");
+        // Remembered as the last verification
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "audit_status"}))).await);
+        assert_eq!(r["data"]["chain"]["result"], "broken");
+
+        // Or drops everything since the lock, which was checkpointed
+        rewrite(&original[..1]);
+        assert_eq!(
+            json(daemon.handle(verify()).await)["data"],
+            serde_json::json!({"result": "truncated", "checkpoint": 2, "entries": 1}),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stuck_requests_time_out() {
+        let tmp = TempDir::new().unwrap();
+        let daemon = RwLock::new(VaultDaemon::new(tmp.path().join("vault")));
+        let status = || call(serde_json::json!({"cmd": "status"}));
+        let timeout = StdDuration::from_millis(100);
+
+        // Something else holds the daemon far too long
+        let held = daemon.write().await;
+        let r = json(handle_timed(&daemon, status(), timeout).await);
+        assert_eq!(r["code"], "request_timeout");
+        drop(held);
+        assert!(matches!(handle_timed(&daemon, status(), timeout).await, Response::Ok { .. }));
+
+        // Slow requests get more time, batches the sum of theirs
+        let unlock = || call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}));
+        assert_eq!(status().timeout(timeout), timeout);
+        assert_eq!(unlock().timeout(timeout), SLOW_REQUEST_TIMEOUT);
+        assert_eq!(unlock().timeout(StdDuration::from_secs(600)), StdDuration::from_secs(600));
+        let batch = call(serde_json::json!({"cmd": "batch", "requests": [
+            {"cmd": "unlock", "passphrase": "pass"},
+            {"cmd": "status"},
+        ]}));
+        assert_eq!(batch.timeout(timeout), SLOW_REQUEST_TIMEOUT + timeout);
+    }
+
+    #[tokio::test]
+    async fn test_reads_share_the_daemon() {
+        let tmp = TempDir::new().unwrap();
+        let daemon = RwLock::new(VaultDaemon::new(tmp.path().join("vault")));
+
+        handle_shared(&daemon, call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(handle_shared(&daemon, call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": "Forum", "value": "eA=="},
+        }))).await);
+        let get = serde_json::json!({"cmd": "get", "id": created["data"]["id"]});
+
+        // The first read fills the cache, so later ones never need the write lock
+        assert!(daemon.read().await.is_cache_cold(&call(get.clone())));
+        handle_shared(&daemon, call(get.clone())).await;
+        assert!(!daemon.read().await.is_cache_cold(&call(get.clone())));
+
+        let held = daemon.read().await;
+        let timeout = StdDuration::from_millis(200);
+        let read = tokio::time::timeout(timeout, handle_shared(&daemon, call(get))).await
+            .expect("a read waited on another read");
+        assert!(matches!(read, Response::Ok { .. }));
+
+        let write = handle_shared(&daemon, call(serde_json::json!({"cmd": "lock"})));
+        assert!(tokio::time::timeout(timeout, write).await.is_err());
+        drop(held);
+    }
+
+    #[tokio::test]
+    async fn test_gets_count_towards_access_count() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": "Forum", "value": "eA=="},
+        }))).await);
+        let id = created["data"]["id"].clone();
+
+        daemon.handle(call(serde_json::json!({"cmd": "get", "id": id}))).await;
+        daemon.handle(call(serde_json::json!({"cmd": "get", "id": id}))).await;
+        daemon.handle(call(serde_json::json!({"cmd": "get", "id": id, "peek": true}))).await;
+        assert!(daemon.has_pending_access());
+        daemon.flush_access();
+        assert!(!daemon.has_pending_access());
+
+        let r = daemon.handle(call(serde_json::json!({"cmd": "get", "id": id, "peek": true}))).await;
+        assert_eq!(json(r)["data"]["access_count"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_describe() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {
+                "category": "financial", "entry_type": "bank_account", "name": "Checking", "value": "MTIzNDU2Nzg=",
+                "notes": "Joint account",
+                "custom_fields": [
+                    {"name": "Routing", "value": "routing-021000021"},
+                    {"name": "PIN", "value": "pin-4321", "sensitive": true},
+                ],
+            },
+        }))).await);
+        let id = created["data"]["id"].clone();
+
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "describe", "id": id}))).await);
+        let detail = &r["data"];
+        assert_eq!((&detail["name"], &detail["notes"], &detail["history_length"]), (&"Checking".into(), &"Joint account".into(), &0.into()));
+        assert_eq!(detail["custom_fields"], serde_json::json!([
+            {"name": "Routing", "sensitive": false},
+            {"name": "PIN", "sensitive": true},
+        ]));
+        assert!(detail.get("value").is_none() && detail.get("value_b64").is_none());
+        for secret in ["MTIzNDU2Nzg=", "routing-", "pin-"] {
+            assert!(!r.to_string().contains(secret), "{} in {}", secret, r);
+        }
+
+        // Describing isn't a read
+        let got = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": id, "peek": true}))).await);
+        assert_eq!(got["data"]["access_count"], 0);
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "describe", "id": Uuid::new_v4()}))).await);
+        assert_eq!(r["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_history_and_restore_version() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let entry = |name: &str, value: &str| serde_json::json!({
+            "category": "authentication", "entry_type": "password", "name": name, "value": value,
+        });
+        let created = json(daemon.handle(call(serde_json::json!({"cmd": "create", "entry": entry("Forum", "djE=")}))).await);
+        let id = created["data"]["id"].clone();
+        let history = || call(serde_json::json!({"cmd": "history", "id": id, "agent_id": "email-agent"}));
+        assert!(history().is_read_only());
+        assert_eq!(json(daemon.handle(history()).await)["code"], "not_found");
+
+        daemon.handle(call(serde_json::json!({"cmd": "update", "id": id, "entry": entry("Forum (new)", "djI=")}))).await;
+        let r = json(daemon.handle(history()).await);
+        assert_eq!(r["data"][0]["version"], 0);
+        assert_eq!(r["data"][0]["entry"]["name"], "Forum");
+        assert!(!r.to_string().contains("djE="), "{}", r);
+
+        let restore = |version: usize| call(serde_json::json!({
+            "cmd": "restore_version", "id": id, "version": version, "agent_id": "restorer",
+        }));
+        assert!(!restore(0).is_read_only());
+        assert_eq!(json(daemon.handle(restore(1)).await)["code"], "invalid_request");
+        assert!(matches!(daemon.handle(restore(0)).await, Response::Ok { .. }));
+        let got = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": id}))).await);
+        assert_eq!((&got["data"]["name"], &got["data"]["value_b64"]), (&"Forum".into(), &"djE=".into()));
+        // The replaced state can be restored in turn
+        assert_eq!(json(daemon.handle(history()).await)["data"][1]["entry"]["name"], "Forum (new)");
+
+        // Deleted entries come back from their history
+        daemon.handle(call(serde_json::json!({"cmd": "delete", "id": id}))).await;
+        assert!(matches!(daemon.handle(restore(0)).await, Response::Ok { .. }));
+        let got = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": id}))).await);
+        assert_eq!(got["data"]["value_b64"], "djE=");
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        assert!(entries.iter().any(|e| e.event_type == AuditEventType::EntryUpdate
+            && e.agent_id.as_deref() == Some("restorer")));
+
+        daemon.handle(call(serde_json::json!({
+            "cmd": "set_policy", "policy": {"agents": {"email-agent": ["personal"]}},
+        }))).await;
+        assert_eq!(json(daemon.handle(history()).await)["code"], "permission_denied");
+    }
+
+    #[tokio::test]
+    async fn test_touch() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "touch", "id": Uuid::new_v4()}))).await);
+        assert_eq!(r["code"], "vault_locked");
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": "Forum", "value": "eA=="},
+        }))).await);
+        let id = created["data"]["id"].clone();
+        let before = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": id, "peek": true}))).await);
+
+        // Only success comes back, and nothing is written yet
+        let touch = || call(serde_json::json!({"cmd": "touch", "id": id, "agent_id": "checker"}));
+        assert_eq!(json(daemon.handle(touch()).await), serde_json::json!({"status": "ok", "data": null}));
+        assert_eq!(json(daemon.handle_read(touch()).await)["status"], "ok");
+        assert!(daemon.has_pending_access());
+        daemon.flush_access();
+
+        let after = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": id, "peek": true}))).await);
+        assert_eq!(after["data"]["access_count"], 2);
+        assert_ne!(after["data"]["accessed"], before["data"]["accessed"]);
+
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "touch", "id": Uuid::new_v4()}))).await);
+        assert_eq!(r["code"], "not_found");
+    }
+
+    #[test]
+    fn test_access_limiter_window_slides() {
+        let mut limiter = AccessLimiter::default();
+        let (id, start) = (Uuid::new_v4(), Instant::now());
+
+        assert!(limiter.try_access(id, 2, start));
+        assert!(limiter.try_access(id, 2, start + StdDuration::from_secs(30)));
+        assert!(!limiter.try_access(id, 2, start + StdDuration::from_secs(59)));
+        // Other entries have their own count
+        assert!(limiter.try_access(Uuid::new_v4(), 2, start + StdDuration::from_secs(59)));
+        // The first read has left the window
+        assert!(limiter.try_access(id, 2, start + StdDuration::from_secs(60)));
+        assert!(!limiter.try_access(id, 2, start + StdDuration::from_secs(61)));
+    }
+
+    #[test]
+    fn test_idempotency_keys_expire() {
+        let mut keys = IdempotencyKeys::default();
+        let (id, start) = (Uuid::new_v4(), Instant::now());
+
+        keys.insert(Some("agent".into()), "k1".into(), id, start);
+        assert_eq!(keys.get(Some("agent"), "k1", start + StdDuration::from_secs(60)), Some(id));
+        // Scoped to the agent that sent it
+        assert_eq!(keys.get(None, "k1", start), None);
+        assert_eq!(keys.get(Some("agent"), "k1", start + IDEMPOTENCY_TTL), None);
+
+        // Expired keys are pruned as new ones arrive
+        keys.insert(None, "k2".into(), Uuid::new_v4(), start + IDEMPOTENCY_TTL);
+        assert_eq!(keys.created.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_create_idempotency_key() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        let create = |name: &str, key: &str| call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": name, "value": "eA=="},
+            "idempotency_key": key,
+        }));
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+
+        let first = json(daemon.handle(create("Retried", "abc")).await);
+        let retry = json(daemon.handle(create("Retried", "abc")).await);
+        assert_eq!(first["data"]["id"], retry["data"]["id"]);
+        let other = json(daemon.handle(create("Other", "def")).await);
+        assert_ne!(first["data"]["id"], other["data"]["id"]);
+
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "list", "category": "personal"}))).await);
+        assert_eq!(r["data"].as_array().unwrap().len(), 2);
+        let r = json(daemon.handle(create("Bad", "")).await);
+        assert_eq!(r["code"], "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn test_reads_are_rate_limited() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        let code = |r: &Response| serde_json::to_value(r).unwrap()["code"].clone();
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let mut ids = Vec::new();
+        for entry in [
+            serde_json::json!({"category": "financial", "entry_type": "password", "name": "Bank", "value": "eA=="}),
+            serde_json::json!({"category": "personal", "entry_type": "password", "name": "Forum", "value": "eA==",
+                               "max_access_per_minute": 2}),
+            serde_json::json!({"category": "personal", "entry_type": "password", "name": "Notes", "value": "eA=="}),
+        ] {
+            let r = daemon.handle(call(serde_json::json!({"cmd": "create", "entry": entry}))).await;
+            ids.push(json(r)["data"]["id"].clone());
+        }
+        let [card, forum, notes] = <[serde_json::Value; 3]>::try_from(ids).unwrap();
+
+        // Financial entries get the low default
+        for _ in 0..10 {
+            let r = daemon.handle(call(serde_json::json!({"cmd": "get", "id": card}))).await;
+            assert!(matches!(r, Response::Ok { .. }));
+        }
+        let r = daemon.handle(call(serde_json::json!({"cmd": "get", "id": card, "agent_id": "looper"}))).await;
+        assert_eq!(code(&r), "rate_limited");
+
+        for _ in 0..2 {
+            daemon.handle(call(serde_json::json!({"cmd": "get", "id": forum}))).await;
+        }
+        let r = daemon.handle(call(serde_json::json!({"cmd": "get", "id": forum}))).await;
+        assert_eq!(code(&r), "rate_limited");
+
+        // Unlimited categories stay unlimited
+        for _ in 0..20 {
+            let r = daemon.handle(call(serde_json::json!({"cmd": "get", "id": notes}))).await;
+            assert!(matches!(r, Response::Ok { .. }));
+        }
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let denial = entries.iter()
+            .find(|e| e.event_type == AuditEventType::AccessDenied)
+            .unwrap();
+        assert_eq!(denial.agent_id.as_deref(), Some("looper"));
+        assert_eq!(denial.category, Some(Category::Financial));
+
+        let r = daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": "X", "value": "eA==",
+                      "max_access_per_minute": 0},
+        }))).await;
+        assert_eq!(code(&r), "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn test_policy_denies_other_categories() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "financial", "entry_type": "password", "name": "Bank", "value": "eA=="},
+        }))).await);
+        let id = created["data"]["id"].as_str().unwrap().to_string();
+
+        // No policy yet: anyone may read
+        let get = |agent: &str| call(serde_json::json!({"cmd": "get", "id": id, "agent_id": agent}));
+        assert!(matches!(daemon.handle(get("email-agent")).await, Response::Ok { .. }));
+
+        let r = daemon.handle(call(serde_json::json!({
+            "cmd": "set_policy",
+            "policy": {"agents": {"email-agent": ["authentication"], "finance-agent": ["financial"]}},
+        }))).await;
+        assert!(matches!(r, Response::Ok { .. }));
+
+        match daemon.handle(get("email-agent")).await {
+            Response::Error { code, message } => {
+                assert_eq!(code, ErrorCode::PermissionDenied);
+                assert!(message.starts_with("Access denied"));
+            }
+            other => panic!("expected denial, got {:?}", other),
+        }
+        assert!(matches!(daemon.handle(get("finance-agent")).await, Response::Ok { .. }));
+
+        let policy = json(daemon.handle(call(serde_json::json!({"cmd": "get_policy"}))).await);
+        assert_eq!(policy["data"]["agents"]["email-agent"], serde_json::json!(["authentication"]));
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let denial = entries.iter().find(|e| e.event_type == AuditEventType::AccessDenied).unwrap();
+        assert_eq!(denial.agent_id.as_deref(), Some("email-agent"));
+        assert_eq!(denial.category, Some(Category::Financial));
+    }
+
+    #[tokio::test]
+    async fn test_ping_without_unlock() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        let ping: Request = serde_json::from_str(r#"{"cmd": "ping"}"#).unwrap();
+
+        let pong = json(daemon.handle(ping).await);
+        assert_eq!(pong["status"], "ok");
+        assert_eq!(pong["data"]["protocol_version"], PROTOCOL_VERSION);
+        assert_eq!(pong["data"]["vault_exists"], false);
+        assert_eq!(pong["data"]["unlocked"], false);
+
+        let unknown: Request = serde_json::from_str(r#"{"cmd": "ping", "vault": "nope"}"#).unwrap();
+        assert!(matches!(daemon.handle(unknown).await, Response::Error { .. }));
+
+        // Pings don't keep an unlocked vault from idling out
+        let unlock: Request = serde_json::from_str(r#"{"cmd": "unlock", "passphrase": "pass"}"#).unwrap();
+        daemon.handle(unlock).await;
+        *daemon.last_activity.get_mut().unwrap() -= StdDuration::from_secs(60);
+        daemon.handle(serde_json::from_str(r#"{"cmd": "ping"}"#).unwrap()).await;
+        assert!(daemon.lock_if_idle(StdDuration::from_secs(30)).await);
+    }
+
+    #[tokio::test]
+    async fn test_errors_carry_codes() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        let code = |r: Response| json(r)["code"].clone();
+
+        let locked = daemon.handle(call(serde_json::json!({"cmd": "list", "category": "personal"}))).await;
+        assert_eq!(code(locked), "vault_locked");
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let missing = daemon.handle(call(serde_json::json!({"cmd": "get", "id": Uuid::new_v4()}))).await;
+        assert_eq!(code(missing), "not_found");
+        let bad = daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": "x", "value": "!!"},
+        }))).await;
+        assert_eq!(code(bad), "invalid_request");
+
+        // Every bad field is named at once
+        let junk = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "card", "name": " ", "value": "", "url": "example.com"},
+        }))).await);
+        assert_eq!(junk["code"], "invalid_request");
+        let message = junk["message"].as_str().unwrap();
+        for field in ["name:", "url:", "value:", "entry_type:"] {
+            assert!(message.contains(field), "{} not in {}", field, message);
+        }
+
+        // Values past the limit are refused whether or not they decode
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        for value in [STANDARD.encode(vec![0u8; validate::MAX_VALUE_BYTES + 1]), "A".repeat(2 << 20)] {
+            let huge = daemon.handle(call(serde_json::json!({
+                "cmd": "create",
+                "entry": {"category": "personal", "entry_type": "secure_note", "name": "x", "value": value},
+            }))).await;
+            assert_eq!(code(huge), "invalid_request");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_notes() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {
+                "category": "personal", "entry_type": "password", "name": "Router", "value": "aHVudGVyMg==",
+                "notes": "Label\u{0} on the back",
+            },
+        }))).await);
+        let id = created["data"]["id"].clone();
+        let got = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": id}))).await);
+        assert_eq!(got["data"]["notes"], "Label on the back");
+        let notes = |notes: serde_json::Value| call(serde_json::json!({
+            "cmd": "update_notes", "id": id, "notes": notes, "agent_id": "notes-agent",
+        }));
+
+        let r = json(daemon.handle(notes(serde_json::json!("## Admin\u{0}\nOn the *sticker*"))).await);
+        assert_eq!(r, serde_json::json!({"status": "ok", "data": null}));
+        let got = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": id}))).await);
+        assert_eq!(got["data"]["notes"], "## Admin\nOn the *sticker*");
+        assert_eq!(got["data"]["value_b64"], "aHVudGVyMg==");
+
+        let r = json(daemon.handle(notes(serde_json::json!("x".repeat(validate::MAX_NOTES_BYTES + 1)))).await);
+        assert_eq!(r["code"], "invalid_request");
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "update_notes", "id": Uuid::new_v4(), "notes": null})))
+            .await);
+        assert_eq!(r["code"], "not_found");
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let update = entries.iter().find(|e| e.event_type == AuditEventType::EntryUpdate).unwrap();
+        assert_eq!((update.entry_name.as_deref(), update.agent_id.as_deref()), (Some("Router"), Some("notes-agent")));
+
+        // Renaming is just as light
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "rename", "id": id, "name": "Home router"}))).await);
+        assert_eq!(r["status"], "ok");
+        let got = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": id}))).await);
+        assert_eq!(got["data"]["name"], "Home router");
+        assert_eq!(got["data"]["notes"], "## Admin\nOn the *sticker*");
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "rename", "id": id, "name": ""}))).await);
+        assert_eq!(r["code"], "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn test_bulk_delete() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let mut ids = Vec::new();
+        for (category, name) in [("authentication", "Old token"), ("personal", "Old note")] {
+            let created = json(daemon.handle(call(serde_json::json!({
+                "cmd": "create",
+                "entry": {"category": category, "entry_type": "password", "name": name, "value": "eA=="},
+            }))).await);
+            ids.push(created["data"]["id"].clone());
+        }
+        let missing = Uuid::new_v4();
+        ids.push(serde_json::json!(missing));
+
+        let bulk = call(serde_json::json!({"cmd": "bulk_delete", "ids": ids, "agent_id": "janitor"}));
+        assert!(!bulk.is_read_only());
+        let r = json(daemon.handle(bulk).await);
+        assert_eq!(r["data"], serde_json::json!({"deleted": [ids[0], ids[1]], "not_found": [missing]}));
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": ids[0]}))).await);
+        assert_eq!(r["code"], "not_found");
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let deletes: Vec<_> = entries.iter()
+            .filter(|e| e.event_type == AuditEventType::EntryDelete)
+            .map(|e| (e.entry_name.as_deref(), e.agent_id.as_deref()))
+            .collect();
+        assert_eq!(deletes, [(Some("Old token"), Some("janitor")), (Some("Old note"), Some("janitor"))]);
+    }
+
+    #[tokio::test]
+    async fn test_create_totp_from_uri() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create_totp_from_uri",
+            "uri": "otpauth://totp/Example:alice%40example.com?secret=JBSWY3DPEHPK3PXP&issuer=Example&digits=8",
+            "agent_id": "enroller",
+        }))).await);
+        let got = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": created["data"]["id"]}))).await);
+        assert_eq!(got["data"]["name"], "Example (alice@example.com)");
+        assert_eq!((&got["data"]["category"], &got["data"]["entry_type"]), (&"authentication".into(), &"totp_seed".into()));
+        assert_eq!(got["data"]["custom_fields"][0], serde_json::json!({"name": "digits", "value": "8", "sensitive": false}));
+
+        // Too short a secret for validation, and not a URI at all
+        for uri in ["otpauth://totp/bob?secret=JBSWY3DP", "JBSWY3DPEHPK3PXP"] {
+            let r = json(daemon.handle(call(serde_json::json!({"cmd": "create_totp_from_uri", "uri": uri}))).await);
+            assert_eq!(r["code"], "invalid_request");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_clone() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "authentication", "entry_type": "api_key", "name": "Stripe", "value": "c2tfb2xk"},
+        }))).await);
+        let id = created["data"]["id"].clone();
+        let clone = |id: serde_json::Value| call(serde_json::json!({"cmd": "clone", "id": id, "agent_id": "rotator"}));
+        assert!(!clone(id.clone()).is_read_only());
+
+        let r = json(daemon.handle(clone(id.clone())).await);
+        let copy = r["data"]["id"].clone();
+        assert_ne!(copy, id);
+        let got = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": copy}))).await);
+        assert_eq!((&got["data"]["name"], &got["data"]["value_b64"]), (&"Stripe (copy)".into(), &"c2tfb2xk".into()));
+        assert_eq!(got["data"]["entry_type"], "api_key");
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let logged = entries.iter().rfind(|e| e.event_type == AuditEventType::EntryCreate).unwrap();
+        assert_eq!((logged.entry_name.as_deref(), logged.agent_id.as_deref()), (Some("Stripe (copy)"), Some("rotator")));
+
+        let r = json(daemon.handle(clone(serde_json::json!(Uuid::new_v4()))).await);
+        assert_eq!(r["code"], "not_found");
+        let protected = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "secure_note", "name": "Seed", "value": "eA=="},
+            "extra_passphrase": "second",
+        }))).await);
+        let r = json(daemon.handle(clone(protected["data"]["id"].clone())).await);
+        assert_eq!(r["code"], "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn test_warm() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "warm"}))).await);
+        assert_eq!(r["code"], "vault_locked");
+
+        // Unlock and warm in one round trip
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "batch", "requests": [
+            {"cmd": "unlock", "passphrase": "pass"},
+            {"cmd": "warm", "categories": ["authentication", "personal", "authentication"]},
+        ]}))).await);
+        assert_eq!(r["data"][1]["data"], serde_json::json!({"warmed": ["authentication", "personal"], "skipped": []}));
+        assert!(!daemon.sessions[DEFAULT_VAULT].vault.as_ref().unwrap().is_cache_warm());
+
+        daemon.handle(call(serde_json::json!({"cmd": "warm"}))).await;
+        assert!(daemon.sessions[DEFAULT_VAULT].vault.as_ref().unwrap().is_cache_warm());
+    }
+
+    #[tokio::test]
+    async fn test_gc() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "gc"}))).await);
+        assert_eq!(r["code"], "vault_locked");
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let attachments = tmp.path().join("vault").join("attachments");
+        std::fs::create_dir_all(&attachments).unwrap();
+        std::fs::write(attachments.join(format!("{}.enc", Uuid::new_v4())), b"orphan").unwrap();
+
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "gc"}))).await);
+        assert_eq!(r["data"], serde_json::json!({"dropped_keys": 0, "removed_files": 1, "bytes_reclaimed": 6}));
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "gc"}))).await);
+        assert_eq!(r["data"]["removed_files"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_set_meta_encryption() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        let encrypt = || call(serde_json::json!({"cmd": "set_meta_encryption", "enabled": true}));
+        let unlock = || call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}));
+
+        assert_eq!(json(daemon.handle(encrypt()).await)["code"], "vault_locked");
+        daemon.handle(unlock()).await;
+        assert_eq!(json(daemon.handle(encrypt()).await)["status"], "ok");
+
+        let meta = std::fs::read_to_string(tmp.path().join("vault").join("vault.meta")).unwrap();
+        assert!(meta.contains("\"sealed\"") && !meta.contains("\"created\""));
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        assert!(matches!(daemon.handle(unlock()).await, Response::Ok { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_binary_values_round_trip() {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+
+        // NULs, a lone continuation byte, and invalid UTF-8 throughout
+        let bytes: Vec<u8> = vec![0, 0xff, 0x80, 0, b'\n', 0xc3, 0x28, 0xfe, 0];
+        let encoded = STANDARD.encode(&bytes);
+        for field in ["value", "value_b64"] {
+            let created = json(daemon.handle(call(serde_json::json!({
+                "cmd": "create",
+                "entry": {"category": "personal", "entry_type": "secure_note", "name": field, field: encoded},
+            }))).await);
+            let id = created["data"]["id"].clone();
+
+            let got = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": id}))).await);
+            assert!(got["data"].get("value").is_none());
+            let value = STANDARD.decode(got["data"]["value_b64"].as_str().unwrap()).unwrap();
+            assert_eq!(value, bytes);
+
+            let added = json(daemon.handle(call(serde_json::json!({
+                "cmd": "add_attachment", "entry_id": id, "filename": "blob.bin",
+                "mime_type": "application/octet-stream", "data": encoded,
+            }))).await);
+            let got = json(daemon.handle(call(serde_json::json!({
+                "cmd": "get_attachment", "entry_id": id, "attachment_id": added["data"]["id"],
+            }))).await);
+            assert_eq!(STANDARD.decode(got["data"]["data_b64"].as_str().unwrap()).unwrap(), bytes);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotate() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "password", "name": "Forum", "value": "aHVudGVyMg=="},
+        }))).await);
+        let id = created["data"]["id"].clone();
+
+        let r = json(daemon.handle(call(serde_json::json!({
+            "cmd": "rotate", "id": id, "spec": {"length": 32, "symbols": false}, "agent_id": "rotator",
+        }))).await);
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        let password = STANDARD.decode(r["data"]["value_b64"].as_str().unwrap()).unwrap();
+        let password = String::from_utf8(password).unwrap();
+        assert!(password.len() == 32 && password.chars().all(char::is_alphanumeric), "{}", password);
+
+        let got = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": id}))).await);
+        assert_eq!(got["data"]["value_b64"], r["data"]["value_b64"]);
+        let uuid: Uuid = serde_json::from_value(id.clone()).unwrap();
+        let vault = daemon.sessions.get_mut(DEFAULT_VAULT).unwrap().vault.as_mut().unwrap();
+        assert_eq!(*vault.entry_history(&uuid).unwrap()[0].entry.value, b"hunter2");
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let logged = entries.iter().find(|e| e.event_type == AuditEventType::EntryUpdate).unwrap();
+        assert_eq!((logged.purpose.as_deref(), logged.agent_id.as_deref()), (Some("rotation"), Some("rotator")));
+
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "rotate", "id": id, "spec": {"length": 4}}))).await);
+        assert_eq!(r["code"], "invalid_request");
+        let totp = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create_totp_from_uri", "uri": "otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP",
+        }))).await);
+        let r = json(daemon.handle(call(serde_json::json!({"cmd": "rotate", "id": totp["data"]["id"]}))).await);
+        assert_eq!(r["code"], "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn test_one_time_entries() {
+        let tmp = TempDir::new().unwrap();
+        let daemon = RwLock::new(VaultDaemon::new(tmp.path().join("vault")));
+
+        handle_shared(&daemon, call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(handle_shared(&daemon, call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "one_time", "name": "Wifi guest", "value": "Z3Vlc3Q="},
+        }))).await);
+        let id = created["data"]["id"].clone();
+        let get = || call(serde_json::json!({"cmd": "get", "id": id, "agent_id": "courier"}));
+        assert!(daemon.read().await.consumes_on_read(&get()));
+        let listed = json(handle_shared(&daemon, call(serde_json::json!({"cmd": "list", "category": "personal"}))).await);
+        assert_eq!(listed["data"][0]["consume_on_read"], true);
+
+        // Shown once, then gone
+        let r = json(handle_shared(&daemon, get()).await);
+        assert_eq!(r["data"]["value_b64"], "Z3Vlc3Q=");
+        let r = json(handle_shared(&daemon, get()).await);
+        assert_eq!(r["code"], "not_found");
+
+        let daemon = daemon.into_inner();
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let consumed = entries.iter().find(|e| e.event_type == AuditEventType::EntryDelete).unwrap();
+        assert_eq!(consumed.purpose.as_deref(), Some("consumed"));
+        assert_eq!(consumed.agent_id.as_deref(), Some("courier"));
+
+        // Any type can opt in, but never on a read-only unlock
+        let mut daemon = daemon;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {
+                "category": "authentication", "entry_type": "password", "name": "Temp",
+                "value": "eA==", "consume_on_read": true,
+            },
+        }))).await);
+        let id = created["data"]["id"].clone();
+        let get = || call(serde_json::json!({"cmd": "get", "id": id}));
+        daemon.handle(call(serde_json::json!({"cmd": "lock"}))).await;
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass", "mode": "read_only"}))).await;
+        let r = json(daemon.handle(get()).await);
+        assert_eq!(r["code"], "permission_denied");
+        assert!(matches!(daemon.handle_read(get()).await, Response::Error { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_protected_entry_needs_extra_passphrase() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {"category": "personal", "entry_type": "secure_note", "name": "Recovery", "value": "UkMtMTIzNA=="},
+            "extra_passphrase": "second secret",
+        }))).await);
+        let id = created["data"]["id"].clone();
+        let get = |extra: Option<&str>| call(serde_json::json!({"cmd": "get", "id": id, "extra_passphrase": extra}));
+
+        let r = json(daemon.handle(get(None)).await);
+        assert_eq!(r["code"], "permission_denied");
+        let r = json(daemon.handle(get(Some("pass"))).await);
+        assert_eq!(r["code"], "decrypt_failed");
+        let r = json(daemon.handle(get(Some("second secret"))).await);
+        assert_eq!(r["data"]["value_b64"], "UkMtMTIzNA==");
+        assert_eq!(r["data"]["protected"], true);
+
+        let listed = json(daemon.handle(call(serde_json::json!({"cmd": "list", "category": "personal"}))).await);
+        assert_eq!(listed["data"][0]["protected"], true);
+
+        let r = json(daemon.handle(call(serde_json::json!({
+            "cmd": "update",
+            "id": id,
+            "entry": {"category": "personal", "entry_type": "secure_note", "name": "Recovery", "value": "bmV3"},
+        }))).await);
+        assert_eq!(r["code"], "invalid_request");
+        let r = json(daemon.handle(get(Some("second secret"))).await);
+        assert_eq!(r["data"]["value_b64"], "UkMtMTIzNA==");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_oauth_needs_a_grant() {
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {
+                "category": "authentication",
+                "entry_type": "o_auth_token",
+                "name": "Google",
+                "value": "eWEyOS5vbGQ=",
+                "custom_fields": [
+                    {"name": "refresh_token", "value": "1//refresh", "sensitive": true},
+                    {"name": "client_id", "value": "client-1"},
+                    {"name": "token_endpoint", "value": "http://oauth2.example.com/token"},
+                ],
+            },
+        }))).await);
+        let id = created["data"]["id"].clone();
+        let refresh = |id: serde_json::Value| call(serde_json::json!({"cmd": "refresh_oauth", "id": id}));
+        assert!(!refresh(id.clone()).is_read_only());
+
+        // Refused before anything is sent, without echoing the grant
+        let r = json(daemon.handle(refresh(id.clone())).await);
+        assert_eq!(r["code"], "invalid_request");
+        assert_eq!(r["message"], "Cannot refresh: Token endpoint must be https");
+        let got = json(daemon.handle(call(serde_json::json!({"cmd": "get", "id": id}))).await);
+        assert_eq!(got["data"]["value_b64"], "eWEyOS5vbGQ=");
+
+        let r = json(daemon.handle(refresh(serde_json::json!(Uuid::new_v4()))).await);
+        assert_eq!(r["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_use_for_auth_records_outcome() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let server = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut conn, _) = server.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = conn.read(&mut buf).await;
+            let _ = conn.write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n").await;
+        });
+
+        let tmp = TempDir::new().unwrap();
+        let mut daemon = VaultDaemon::new(tmp.path().join("vault"));
+        daemon.handle(call(serde_json::json!({"cmd": "unlock", "passphrase": "pass"}))).await;
+        let created = json(daemon.handle(call(serde_json::json!({
+            "cmd": "create",
+            "entry": {
+                "category": "authentication",
+                "entry_type": "api_key",
+                "name": "Local",
+                "value": "c2VjcmV0",
+                "url": format!("http://127.0.0.1:{}", port),
+            },
+        }))).await);
+        let id = created["data"]["id"].clone();
+        let use_for_auth = |target: String| call(serde_json::json!({
+            "cmd": "use_for_auth",
+            "id": id,
+            "target_url": target,
+            "agent_id": "agent-1",
+            "purpose": "health check",
+        }));
+
+        let r = json(daemon.handle(use_for_auth(format!("http://127.0.0.1:{}/ping", port))).await);
+        assert_eq!(r["data"]["status"], 204);
+
+        // The listener is gone, so the second use fails after sending
+        let r = json(daemon.handle(use_for_auth(format!("http://127.0.0.1:{}/ping", port))).await);
+        assert_eq!(r["code"], "internal");
+
+        // Refused before sending: no outcome, only the reason
+        let r = json(daemon.handle(use_for_auth("http://example.com/ping".into())).await);
+        assert_eq!(r["code"], "permission_denied");
+
+        let entries = audit_log(&daemon.sessions[DEFAULT_VAULT].audit).as_ref().unwrap().read_all().unwrap();
+        let uses: Vec<_> = entries.iter().filter(|e| e.event_type == AuditEventType::AuthUse).collect();
+        assert_eq!(uses.len(), 3);
+        assert_eq!(uses[0].outcome.as_deref(), Some("HTTP 204"));
+        assert!(uses[0].granted);
+        assert!(uses[1].outcome.as_deref().unwrap().starts_with("failed: "));
+        assert!(uses[1].granted);
+        assert_eq!(uses[2].outcome, None);
+        assert!(!uses[2].granted);
+        assert!(entries.iter().all(|e| e.verify_hash()));
+    }
+
+    #[tokio::test]
+    async fn test_greeting_and_hello() {
+        let tmp = TempDir::new().unwrap();
+        let daemon = Arc::new(RwLock::new(VaultDaemon::new(tmp.path().join("vault"))));
+        let policy = Arc::new(PeerPolicy::new(vec![unsafe { libc::getuid() }]));
+
+        let (server, client) = UnixStream::pair().unwrap();
+        let (reader, mut writer) = client.into_split();
+        writer.write_all(b"{\"cmd\":\"hello\",\"client_version\":2}\n").await.unwrap();
+        writer.write_all(b"{\"cmd\":\"hello\",\"client_version\":99}\n").await.unwrap();
+        writer.write_all(b"{\"cmd\":\"status\"}\n").await.unwrap();
+
+        handle_connection(server, daemon, policy, DEFAULT_MAX_REQUEST_BYTES, DEFAULT_REQUEST_TIMEOUT).await.unwrap();
+
+        let mut lines = BufReader::new(reader).lines();
+        let mut next = async || -> serde_json::Value {
+            serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap()
+        };
+        let greeting = next().await;
+        assert_eq!(greeting["protocol_version"], PROTOCOL_VERSION);
+        assert!(greeting["features"].as_array().unwrap().contains(&"batch".into()));
+
+        assert_eq!(next().await["status"], "ok");
+        let refused = next().await;
+        assert_eq!(refused["code"], "incompatible_protocol");
+
+        // The connection closed before the status request was answered
+        assert!(!matches!(lines.next_line().await, Ok(Some(_))));
+    }
+
+    #[tokio::test]
+    async fn test_oversized_request_closes_connection() {
+        let tmp = TempDir::new().unwrap();
+        let daemon = Arc::new(RwLock::new(VaultDaemon::new(tmp.path().join("vault"))));
+        let policy = Arc::new(PeerPolicy::new(vec![unsafe { libc::getuid() }]));
+
+        let (server, client) = UnixStream::pair().unwrap();
+        let (reader, mut writer) = client.into_split();
+        writer.write_all(b"{\"cmd\":\"status\"}\n").await.unwrap();
+        writer.write_all(&[b' '; 200]).await.unwrap();
+        writer.write_all(b"{\"cmd\":\"status\"}\n").await.unwrap();
+
+        handle_connection(server, daemon, policy, 100, DEFAULT_REQUEST_TIMEOUT).await.unwrap();
+
+        let mut lines = BufReader::new(reader).lines();
+        lines.next_line().await.unwrap(); // greeting
+        assert!(lines.next_line().await.unwrap().unwrap().contains("\"ok\""));
+        let refused: serde_json::Value = serde_json::from_str(&lines.next_line().await.unwrap().unwrap()).unwrap();
+        assert_eq!(refused["code"], "request_too_large");
+        assert!(!matches!(lines.next_line().await, Ok(Some(_))));
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_peer_is_rejected_before_reading() {
+        let tmp = TempDir::new().unwrap();
+        let daemon = Arc::new(RwLock::new(VaultDaemon::new(tmp.path().join("vault"))));
+
+        let own = unsafe { libc::getuid() };
+        let policy = Arc::new(PeerPolicy::new(vec![own.wrapping_add(1)]));
+
+        let (server, client) = UnixStream::pair().unwrap();
+        let (reader, mut writer) = client.into_split();
+        writer.write_all(b"{\"cmd\":\"status\"}\n").await.unwrap();
+
+        handle_connection(server, daemon, policy, DEFAULT_MAX_REQUEST_BYTES, DEFAULT_REQUEST_TIMEOUT).await.unwrap();
+
+        let mut lines = BufReader::new(reader).lines();
+        let first = lines.next_line().await.unwrap().unwrap();
+        assert!(first.contains("Permission denied"));
+
+        // The unread request is dropped with the connection (EOF or reset)
+        assert!(!matches!(lines.next_line().await, Ok(Some(_))));
     }
-    
-    Ok(())
 }
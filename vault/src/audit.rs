@@ -7,26 +7,60 @@
 //! - Why (purpose string)
 //! - Outcome (granted/denied)
 //! 
-//! Hash chaining ensures tamper detection.
+//! Hash chaining ensures tamper detection; signed checkpoints make
+//! cutting entries off the end detectable too.
 
 use anyhow::{anyhow, Result};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Timelike, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
 use uuid::Uuid;
 
-use std::fs::{self, File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use crate::crypto::{SecureKey, encrypt, decrypt};
-use crate::vault::Category;
+use crate::crypto::{
+    self, KdfParams, SecureKey, NonceSequence, SigningKey, VERIFY_KEY_LEN,
+    encrypt_counted, decrypt, write_atomic, verify_signature,
+};
+use crate::vault::{AccessMode, Category, VaultEntry};
+
+/// What `AuditLog::query` picks out; unset criteria match everything
+#[derive(Debug, Clone)]
+pub struct AuditFilter {
+    pub since: Option<DateTime<Utc>>,
+    /// Any of these (all, if empty)
+    pub event_types: Vec<AuditEventType>,
+    pub agent_id: Option<String>,
+    pub category: Option<Category>,
+    pub limit: usize,
+}
+
+impl AuditFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        (self.event_types.is_empty() || self.event_types.contains(&entry.event_type))
+            && self.agent_id.as_ref().is_none_or(|agent| entry.agent_id.as_ref() == Some(agent))
+            && self.category.is_none_or(|cat| entry.category == Some(cat))
+    }
+}
 
 /// Type of audit event
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum AuditEventType {
     VaultUnlock,
     VaultLock,
+    KeyRotation,
+    PassphraseChange,
+    /// The master key was re-derived under new Argon2 parameters
+    KdfUpgrade,
+    /// A hardware security key became required to unlock
+    HardwareKeyEnroll,
     CategoryUnlock,
     EntryAccess,
     EntryCreate,
@@ -35,6 +69,11 @@ pub enum AuditEventType {
     AuthUse,
     AnomalyDetected,
     AccessDenied,
+    VaultExport,
+    /// Old entries were purged (`AuditLog::purge_older_than`)
+    AuditPurge,
+    /// Every vault was locked at once on a suspected compromise
+    EmergencyLock,
 }
 
 /// A single audit log entry
@@ -62,6 +101,14 @@ pub struct AuditEntry {
     
     // For auth operations
     pub target_domain: Option<String>,
+
+    // For unlocks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_mode: Option<AccessMode>,
+
+    // What came back from an auth request, e.g. "HTTP 200"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub outcome: Option<String>,
     
     // Hash chain
     pub previous_hash: String,
@@ -84,6 +131,8 @@ impl AuditEntry {
             granted: true,
             denial_reason: None,
             target_domain: None,
+            access_mode: None,
+            outcome: None,
             previous_hash: previous_hash.to_string(),
             entry_hash: String::new(),
         };
@@ -111,6 +160,16 @@ impl AuditEntry {
             self.previous_hash,
         );
         
+        // Appended only when set so entries written before it still verify
+        let hash_input = match self.access_mode {
+            Some(mode) => format!("{}|{:?}", hash_input, mode),
+            None => hash_input,
+        };
+        let hash_input = match &self.outcome {
+            Some(outcome) => format!("{}|{}", hash_input, outcome),
+            None => hash_input,
+        };
+        
         let hash = blake3::hash(hash_input.as_bytes());
         self.entry_hash = hash.to_hex().to_string();
     }
@@ -167,13 +226,386 @@ impl AuditEntry {
         self.compute_hash();
         self
     }
+
+    pub fn with_access_mode(mut self, mode: AccessMode) -> Self {
+        self.access_mode = Some(mode);
+        self.compute_hash();
+        self
+    }
+
+    pub fn with_outcome(mut self, outcome: impl Into<String>) -> Self {
+        self.outcome = Some(outcome.into());
+        self.compute_hash();
+        self
+    }
+}
+
+/// Why a chain entry failed verification
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChainError {
+    /// `previous_hash` doesn't match the preceding entry (removed/reordered)
+    PreviousHashMismatch,
+    /// The entry's own contents don't match its hash (modified)
+    EntryHashMismatch,
+}
+
+/// Result of a detailed chain verification
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum ChainVerification {
+    Valid,
+    Broken {
+        index: usize,
+        entry_id: Uuid,
+        reason: ChainError,
+    },
+    /// The chain is shorter than a signed checkpoint vouches for
+    Truncated { checkpoint: u64, entries: u64 },
+    /// A checkpoint's signature doesn't verify, or the chain doesn't end
+    /// in its hash at its count
+    BadCheckpoint { count: u64 },
+    /// The record of purged entries the chain starts from isn't signed
+    /// by the verify key
+    BadAnchor,
+}
+
+/// Entries appended between signed checkpoints
+pub const CHECKPOINT_INTERVAL: u64 = 100;
+
+/// Signed statement of how long the chain was and which hash it ended in
+///
+/// Stored in plain text beside the log, so it can be checked, or copied
+/// somewhere the log's holder can't reach, with only the verify key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditCheckpoint {
+    /// Entries in the chain so far, archives included
+    pub count: u64,
+    /// `entry_hash` of the last of them
+    pub last_hash: String,
+    pub timestamp: DateTime<Utc>,
+    /// Base64 Ed25519 signature over the fields above
+    pub signature: String,
+}
+
+impl AuditCheckpoint {
+    fn message(count: u64, last_hash: &str, timestamp: &DateTime<Utc>) -> Vec<u8> {
+        format!("audit-checkpoint|{}|{}|{}", count, last_hash, timestamp.to_rfc3339()).into_bytes()
+    }
+
+    fn sign(count: u64, last_hash: &str, key: &SigningKey) -> Self {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+        let timestamp = Utc::now();
+        let signature = key.sign(&Self::message(count, last_hash, &timestamp));
+        Self { count, last_hash: last_hash.to_string(), timestamp, signature: STANDARD.encode(signature) }
+    }
+
+    /// Whether the signature is `verify_key`'s
+    pub fn verify(&self, verify_key: &[u8; VERIFY_KEY_LEN]) -> bool {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+        let Ok(signature) = STANDARD.decode(&self.signature) else {
+            return false;
+        };
+        verify_signature(verify_key, &Self::message(self.count, &self.last_hash, &self.timestamp), &signature)
+    }
+}
+
+/// Signed record of the entries purged from the start of the chain
+///
+/// Takes the place of the genesis hash: the oldest entry kept links to
+/// `last_hash`, so the rest of the chain still verifies. Stored in plain
+/// text in `<stem>.anchor`, like checkpoints.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditAnchor {
+    /// Entries purged so far, over every purge
+    pub purged: u64,
+    /// Entries older than this went in the latest purge
+    pub cutoff: DateTime<Utc>,
+    /// `entry_hash` of the last entry purged
+    pub last_hash: String,
+    pub timestamp: DateTime<Utc>,
+    /// Base64 Ed25519 signature over the fields above; empty if the log
+    /// had no signing key
+    pub signature: String,
+}
+
+impl AuditAnchor {
+    fn message(&self) -> Vec<u8> {
+        format!(
+            "audit-anchor|{}|{}|{}|{}",
+            self.purged, self.cutoff.to_rfc3339(), self.last_hash, self.timestamp.to_rfc3339()
+        ).into_bytes()
+    }
+
+    fn new(purged: u64, cutoff: DateTime<Utc>, last_hash: &str, key: Option<&SigningKey>) -> Self {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+        let mut anchor = Self {
+            purged,
+            cutoff,
+            last_hash: last_hash.to_string(),
+            timestamp: Utc::now(),
+            signature: String::new(),
+        };
+        if let Some(key) = key {
+            anchor.signature = STANDARD.encode(key.sign(&anchor.message()));
+        }
+        anchor
+    }
+
+    /// Whether the signature is `verify_key`'s
+    pub fn verify(&self, verify_key: &[u8; VERIFY_KEY_LEN]) -> bool {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+
+        let Ok(signature) = STANDARD.decode(&self.signature) else {
+            return false;
+        };
+        verify_signature(verify_key, &self.message(), &signature)
+    }
+}
+
+/// Overwrite a file with zeros, then remove it
+///
+/// Best effort: copy-on-write filesystems and SSDs may keep the old blocks.
+fn shred(path: &Path) -> Result<()> {
+    use std::io::Write;
+
+    let len = fs::metadata(path)?.len();
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    file.write_all(&vec![0u8; len as usize])?;
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Clear `stale`, a link to `file`'s old bytes an interrupted purge left
+///
+/// Shredded if it's the old bytes alone, only unlinked if the crash came
+/// before the rewrite and it is still `file` itself.
+fn remove_stale_link(file: &Path, stale: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(stale_meta) = fs::metadata(stale) else {
+        return Ok(());
+    };
+    match fs::metadata(file) {
+        Ok(meta) if meta.dev() == stale_meta.dev() && meta.ino() == stale_meta.ino() => Ok(fs::remove_file(stale)?),
+        _ => shred(stale),
+    }
+}
+
+/// How many entries at the start of the chain a purge deleted but left on
+/// disk
+///
+/// `purge_older_than` writes its anchor before touching the files, so a
+/// crash part way leaves the chain running up to the anchor's hash and on
+/// past it; those entries are as good as gone.
+fn interrupted_purge<'a>(anchor: Option<&AuditAnchor>, entries: impl IntoIterator<Item = &'a AuditEntry>) -> usize {
+    let Some(anchor) = anchor else {
+        return 0;
+    };
+    let mut entries = entries.into_iter().peekable();
+    if entries.peek().is_none_or(|first| first.previous_hash == anchor.last_hash) {
+        return 0;
+    }
+    entries.position(|e| e.entry_hash == anchor.last_hash).map_or(0, |last| last + 1)
+}
+
+/// Limits used by anomaly detection
+#[derive(Debug, Clone)]
+pub struct AnomalyThresholds {
+    /// How far back `AuditLog::record_anomalies` looks on each access
+    pub window: Duration,
+    /// Distinct entries one agent may access within the detection window
+    pub max_distinct_entries: usize,
+    /// Accesses one agent may make within `burst_window`
+    pub max_burst: usize,
+    pub burst_window: Duration,
+    /// Categories where an agent's first-ever access is flagged
+    pub sensitive_categories: Vec<Category>,
+}
+
+impl Default for AnomalyThresholds {
+    fn default() -> Self {
+        Self {
+            window: Duration::hours(1),
+            max_distinct_entries: 20,
+            max_burst: 30,
+            burst_window: Duration::minutes(1),
+            sensitive_categories: vec![Category::Financial, Category::Identity],
+        }
+    }
+}
+
+/// What anomaly detection needs from the log, kept current as entries are
+/// appended so a check doesn't reread the file
+#[derive(Default)]
+struct AccessHistory {
+    /// `EntryAccess` and `AnomalyDetected` entries inside the window,
+    /// oldest first
+    recent: VecDeque<AuditEntry>,
+    /// Every (agent, category) pair ever accessed
+    accessed: HashSet<(Option<String>, Category)>,
+    /// Recent accesses that were the first of their pair
+    first_accesses: HashSet<Uuid>,
+}
+
+impl AccessHistory {
+    fn record(&mut self, entry: &AuditEntry) {
+        match entry.event_type {
+            AuditEventType::EntryAccess => {
+                if let Some(cat) = entry.category {
+                    if self.accessed.insert((entry.agent_id.clone(), cat)) {
+                        self.first_accesses.insert(entry.id);
+                    }
+                }
+            }
+            AuditEventType::AnomalyDetected => {}
+            _ => return,
+        }
+        self.recent.push_back(entry.clone());
+    }
+
+    /// Drop what falls outside the `window` up to now
+    fn forget_outside(&mut self, window: Duration) {
+        let cutoff = Utc::now().checked_sub_signed(window).unwrap_or(DateTime::<Utc>::MIN_UTC);
+        while let Some(old) = self.recent.pop_front() {
+            if old.timestamp >= cutoff {
+                self.recent.push_front(old);
+                break;
+            }
+            self.first_accesses.remove(&old.id);
+        }
+    }
+}
+
+/// Kind of suspicious access pattern
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    ManyDistinctEntries,
+    NewSensitiveCategory,
+    AccessBurst,
+}
+
+impl AnomalyKind {
+    fn label(&self) -> &'static str {
+        match self {
+            AnomalyKind::ManyDistinctEntries => "many_distinct_entries",
+            AnomalyKind::NewSensitiveCategory => "new_sensitive_category",
+            AnomalyKind::AccessBurst => "access_burst",
+        }
+    }
+}
+
+/// A detected anomaly
+#[derive(Debug, Clone, Serialize)]
+pub struct Anomaly {
+    pub kind: AnomalyKind,
+    pub agent_id: Option<String>,
+    pub category: Option<Category>,
+    pub description: String,
+}
+
+/// Usage in a window of the audit log, from `AuditLog::stats`
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditStats {
+    pub since: DateTime<Utc>,
+    pub events: usize,
+    /// Refused requests, of any kind
+    pub denials: usize,
+    /// Reads and auth uses per entry, most used first
+    pub entries: Vec<EntryUsage>,
+    /// Events per agent, most active first
+    pub agents: Vec<AgentActivity>,
+    /// Reads and auth uses per category, most used first
+    pub categories: Vec<CategoryUsage>,
+    /// Events by hour of day (UTC): `by_hour[0]` is 00:00-00:59
+    pub by_hour: [usize; 24],
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EntryUsage {
+    pub entry_id: Uuid,
+    /// As of the latest use
+    pub entry_name: Option<String>,
+    pub uses: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AgentActivity {
+    /// `None` for requests that named no agent
+    pub agent_id: Option<String>,
+    pub events: usize,
+    pub denials: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CategoryUsage {
+    pub category: Category,
+    pub uses: usize,
+}
+
+/// When the active log is sealed into an archive
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    /// Rotate once the decrypted log reaches this many bytes
+    pub max_bytes: Option<u64>,
+    /// Rotate once the log's first entry is this old
+    pub max_age: Option<Duration>,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: Some(4 * 1024 * 1024),
+            max_age: Some(Duration::days(90)),
+        }
+    }
+}
+
+/// What `AuditLog::compact` did
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CompactionReport {
+    /// Entries in the archives it compressed
+    pub entries_retained: usize,
+    /// How much smaller those archives got
+    pub bytes_reclaimed: u64,
 }
 
+/// gzip's magic number, which no line of JSON starts with: marks a log
+/// file `AuditLog::compact` has compressed
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
 /// Audit log manager
+///
+/// Entries go to the active log (e.g. `audit.enc`). Rotation renames it to
+/// `audit-<timestamp>.enc`; the next entry still links to the archive's
+/// last hash, so the chain runs unbroken from the oldest archive through
+/// the active log.
+///
+/// With a signing key, every `CHECKPOINT_INTERVAL` entries (and on lock)
+/// the count and last hash are signed into `<stem>.checkpoints`.
 pub struct AuditLog {
     path: PathBuf,
     key: SecureKey,
     last_hash: String,
+    notifier: Option<broadcast::Sender<AuditEntry>>,
+    thresholds: AnomalyThresholds,
+    rotation: RotationPolicy,
+    /// The vault's leased nonce counters (see `crypto::encrypt_counted`)
+    nonces: Option<Arc<NonceSequence>>,
+    signer: Option<SigningKey>,
+    verify_key: Option<[u8; VERIFY_KEY_LEN]>,
+    /// Entries in the chain, archives included
+    count: u64,
+    /// `count` at the newest checkpoint
+    checkpointed: u64,
+    /// Built from the log on the first anomaly check, then kept by `append`
+    history: Option<AccessHistory>,
 }
 
 impl AuditLog {
@@ -184,33 +616,384 @@ impl AuditLog {
     pub fn open(path: impl AsRef<Path>, key: SecureKey) -> Result<Self> {
         let path = path.as_ref().to_path_buf();
         
-        let last_hash = if path.exists() {
-            // Read last entry to get its hash
-            Self::read_last_hash(&path, &key)?
-        } else {
-            Self::GENESIS_HASH.to_string()
+        let mut log = Self {
+            path,
+            key,
+            last_hash: Self::GENESIS_HASH.to_string(),
+            notifier: None,
+            thresholds: AnomalyThresholds::default(),
+            rotation: RotationPolicy::default(),
+            nonces: None,
+            signer: None,
+            verify_key: None,
+            count: 0,
+            checkpointed: 0,
+            history: None,
         };
-        
-        Ok(Self { path, key, last_hash })
+
+        let anchor = log.anchor()?;
+        if let Some(anchor) = &anchor {
+            log.count = anchor.purged;
+            log.last_hash = anchor.last_hash.clone();
+        }
+
+        // Right after a rotation the active log is absent; the chain
+        // continues from the newest archive
+        let mut files = log.archives()?;
+        files.push(log.path.clone());
+        let mut entries = Vec::new();
+        for file in &files {
+            entries.extend(Self::read_entries(file, &log.key)?);
+        }
+        log.count += (entries.len() - interrupted_purge(anchor.as_ref(), &entries)) as u64;
+        if let Some(last) = entries.last() {
+            log.last_hash = last.entry_hash.clone();
+        }
+        log.checkpointed = log.checkpoints()?.last().map_or(0, |c| c.count);
+
+        Ok(log)
+    }
+
+    /// Override the anomaly detection thresholds
+    pub fn with_thresholds(mut self, thresholds: AnomalyThresholds) -> Self {
+        self.thresholds = thresholds;
+        self
+    }
+
+    /// Publish every appended entry to a broadcast channel
+    ///
+    /// Publishing never blocks: subscribers that fall behind the channel
+    /// capacity observe a lag error instead of slowing down `append`.
+    pub fn with_notifier(mut self, notifier: broadcast::Sender<AuditEntry>) -> Self {
+        self.notifier = Some(notifier);
+        self
     }
 
-    /// Read the hash of the last entry in the log
-    fn read_last_hash(path: &Path, key: &SecureKey) -> Result<String> {
+    /// Draw nonces from the vault's leased counters; every append
+    /// re-encrypts the whole log under the same key
+    pub fn with_nonces(mut self, nonces: Option<Arc<NonceSequence>>) -> Self {
+        self.nonces = nonces;
+        self
+    }
+
+    /// Sign checkpoints with `signer` and check them against `verify_key`
+    /// in `verify_chain_detailed`
+    pub fn with_checkpoints(
+        mut self,
+        signer: Option<SigningKey>,
+        verify_key: Option<[u8; VERIFY_KEY_LEN]>,
+    ) -> Self {
+        self.signer = signer;
+        self.verify_key = verify_key;
+        self
+    }
+
+    /// Override when the active log is rotated
+    pub fn with_rotation(mut self, rotation: RotationPolicy) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Decrypt and parse one log file (missing or empty means no entries)
+    fn read_entries(path: &Path, key: &SecureKey) -> Result<Vec<AuditEntry>> {
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
         let encrypted = fs::read(path)?;
         if encrypted.is_empty() {
-            return Ok(Self::GENESIS_HASH.to_string());
+            return Ok(Vec::new());
         }
-        
-        let decrypted = decrypt(&encrypted, key)?;
-        let content = String::from_utf8(decrypted)?;
-        
-        // Get last non-empty line
-        if let Some(last_line) = content.lines().filter(|l| !l.is_empty()).last() {
-            let entry: AuditEntry = serde_json::from_str(last_line)?;
-            Ok(entry.entry_hash)
-        } else {
-            Ok(Self::GENESIS_HASH.to_string())
+
+        let decrypted = decrypt(&encrypted, key, b"")?;
+        Self::parse_entries(&Self::decode(&decrypted)?)
+    }
+
+    /// A log file's decrypted content, inflated first if it was compacted
+    fn decode(plaintext: &[u8]) -> Result<String> {
+        if !plaintext.starts_with(&GZIP_MAGIC) {
+            return Ok(std::str::from_utf8(plaintext)?.to_owned());
+        }
+        let mut content = String::new();
+        GzDecoder::new(plaintext).read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    /// Parse decrypted log content, one entry per line; blank lines are skipped
+    fn parse_entries(content: &str) -> Result<Vec<AuditEntry>> {
+        let mut entries = Vec::new();
+        for line in content.lines() {
+            if !line.trim().is_empty() {
+                let entry: AuditEntry = serde_json::from_str(line)?;
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    /// Archived logs, oldest first
+    pub fn archives(&self) -> Result<Vec<PathBuf>> {
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        let prefix = format!("{}-", self.stem());
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut archives: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with(&prefix) && n.ends_with(".enc"))
+            })
+            .collect();
+        // Timestamps are fixed-width, so name order is age order
+        archives.sort();
+        Ok(archives)
+    }
+
+    fn checkpoints_path(&self) -> PathBuf {
+        self.path.with_extension("checkpoints")
+    }
+
+    fn anchor_path(&self) -> PathBuf {
+        self.path.with_extension("anchor")
+    }
+
+    /// Where the chain starts, if entries were ever purged
+    pub fn anchor(&self) -> Result<Option<AuditAnchor>> {
+        let path = self.anchor_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&fs::read_to_string(&path)?)?))
+    }
+
+    /// Signed checkpoints, oldest first
+    pub fn checkpoints(&self) -> Result<Vec<AuditCheckpoint>> {
+        let path = self.checkpoints_path();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        fs::read_to_string(&path)?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    /// Sign the chain as it stands, unless there's no signing key or
+    /// nothing new since the last checkpoint
+    pub fn checkpoint(&mut self) -> Result<Option<AuditCheckpoint>> {
+        let Some(ref signer) = self.signer else {
+            return Ok(None);
+        };
+        if self.count == self.checkpointed {
+            return Ok(None);
+        }
+
+        let checkpoint = AuditCheckpoint::sign(self.count, &self.last_hash, signer);
+        let path = self.checkpoints_path();
+        let mut content = if path.exists() { fs::read_to_string(&path)? } else { String::new() };
+        content.push_str(&(serde_json::to_string(&checkpoint)? + "\n"));
+        write_atomic(&path, content.as_bytes())?;
+
+        self.checkpointed = self.count;
+        Ok(Some(checkpoint))
+    }
+
+    /// The active log's file name without `.enc`
+    fn stem(&self) -> String {
+        self.path.file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "audit".to_string())
+    }
+
+    /// Seal the active log into `<stem>-<timestamp>.enc` and start afresh
+    ///
+    /// Returns the archive's path. The chain carries on: the next entry's
+    /// `previous_hash` is the archive's last hash.
+    pub fn rotate(&mut self) -> Result<PathBuf> {
+        if !self.path.exists() {
+            return Err(anyhow!("Audit log is empty; nothing to rotate"));
+        }
+
+        let stamp = Utc::now().format("%Y%m%dT%H%M%S%.6fZ");
+        let archive = self.path.with_file_name(format!("{}-{}.enc", self.stem(), stamp));
+        if archive.exists() {
+            return Err(anyhow!("Audit archive {:?} already exists", archive));
+        }
+
+        fs::rename(&self.path, &archive)?;
+        if let Some(dir) = self.path.parent() {
+            crypto::sync_dir(dir)?;
+        }
+        Ok(archive)
+    }
+
+    /// Compress the archives rotation left behind, which nothing appends
+    /// to again
+    ///
+    /// The active log is left as it is, since every append rewrites it.
+    /// Refuses unless the whole chain, archives included, verifies first.
+    /// Each compressed archive is decrypted and checked against the
+    /// original before it replaces it, so a bad rewrite never reaches
+    /// disk; archives already compressed, or that wouldn't shrink, are
+    /// skipped.
+    pub fn compact(&mut self) -> Result<CompactionReport> {
+        let verification = self.verify_chain_detailed()?;
+        if verification != ChainVerification::Valid {
+            return Err(anyhow!("Audit chain doesn't verify ({:?}); not compacting", verification));
         }
+
+        let mut report = CompactionReport::default();
+        for archive in self.archives()? {
+            let encrypted = fs::read(&archive)?;
+            let plaintext = decrypt(&encrypted, &self.key, b"")?;
+            if plaintext.starts_with(&GZIP_MAGIC) {
+                continue;
+            }
+
+            let entries = Self::parse_entries(std::str::from_utf8(&plaintext)?)?;
+            let mut gz = GzEncoder::new(Vec::new(), Compression::best());
+            for entry in &entries {
+                gz.write_all((serde_json::to_string(entry)? + "\n").as_bytes())?;
+            }
+            let compacted = encrypt_counted(&gz.finish()?, &self.key, b"", self.nonces.as_deref())?;
+            if compacted.len() >= encrypted.len() {
+                continue;
+            }
+
+            let rewritten = Self::parse_entries(&Self::decode(&decrypt(&compacted, &self.key, b"")?)?)?;
+            let intact = rewritten.len() == entries.len()
+                && rewritten.iter().zip(&entries).all(|(new, old)| {
+                    new.verify_hash() && new.entry_hash == old.entry_hash && new.previous_hash == old.previous_hash
+                });
+            if !intact {
+                return Err(anyhow!("Compacted {} doesn't match the original; left it alone", archive.display()));
+            }
+
+            write_atomic(&archive, &compacted)?;
+            report.entries_retained += entries.len();
+            report.bytes_reclaimed += (encrypted.len() - compacted.len()) as u64;
+        }
+        Ok(report)
+    }
+
+    /// Delete every entry older than `cutoff`, archives included; returns
+    /// how many went
+    ///
+    /// The chain is re-anchored on a signed `AuditAnchor` recording how
+    /// many entries were purged before `cutoff` and the hash the oldest
+    /// remaining entry links to, so what's left still verifies. The purge
+    /// is then logged as an `AuditPurge` entry. Emptied archives are
+    /// shredded, as are the old copies of files that are rewritten.
+    /// Checkpoints over purged entries are dropped.
+    ///
+    /// The anchor is written first: a crash after it leaves purged entries
+    /// on disk, which the chain skips (`interrupted_purge`) and the next
+    /// purge removes. Files are rewritten through a temporary copy, so a
+    /// failed write leaves the old one in place.
+    ///
+    /// Refuses unless the chain verifies first, since purging could hide
+    /// where it was broken.
+    pub fn purge_older_than(&mut self, cutoff: DateTime<Utc>) -> Result<usize> {
+        let verification = self.verify_chain_detailed()?;
+        if verification != ChainVerification::Valid {
+            return Err(anyhow!("Audit chain doesn't verify ({:?}); not purging", verification));
+        }
+
+        let mut files = self.archives()?;
+        files.push(self.path.clone());
+        let mut contents = Vec::with_capacity(files.len());
+        for file in files {
+            let entries = Self::read_entries(&file, &self.key)?;
+            contents.push((file, entries));
+        }
+        let chain: Vec<&AuditEntry> = contents.iter().flat_map(|(_, entries)| entries).collect();
+        let mut anchor = self.anchor()?;
+        let leftover = interrupted_purge(anchor.as_ref(), chain.iter().copied());
+        // Entries are in time order, so the purged ones are a prefix
+        let purged = chain[leftover..].iter().take_while(|e| e.timestamp < cutoff).count();
+        if leftover + purged == 0 {
+            return Ok(0);
+        }
+
+        if purged > 0 {
+            let previous = anchor.as_ref().map_or(0, |a| a.purged);
+            let last_hash = &chain[leftover + purged - 1].entry_hash;
+            let new = AuditAnchor::new(previous + purged as u64, cutoff, last_hash, self.signer.as_ref());
+            write_atomic(&self.anchor_path(), (serde_json::to_string(&new)? + "\n").as_bytes())?;
+            anchor = Some(new);
+        }
+        let purged_total = anchor.as_ref().map_or(0, |a| a.purged);
+
+        let mut remaining = leftover + purged;
+        for (file, entries) in contents {
+            if remaining == 0 {
+                break;
+            }
+            if entries.is_empty() {
+                continue;
+            }
+            let old = remaining.min(entries.len());
+            remaining -= old;
+            if old == entries.len() {
+                shred(&file)?;
+                continue;
+            }
+
+            // Keep a second link to the old bytes, so they can be shredded
+            // once the rewrite has replaced the file (`archives` ignores the
+            // link if a crash leaves it behind)
+            let stale = file.with_extension("enc.purged");
+            remove_stale_link(&file, &stale)?;
+            fs::hard_link(&file, &stale)?;
+            let mut content = String::new();
+            for entry in &entries[old..] {
+                content.push_str(&(serde_json::to_string(entry)? + "\n"));
+            }
+            let encrypted = encrypt_counted(content.as_bytes(), &self.key, b"", self.nonces.as_deref())?;
+            write_atomic(&file, &encrypted)?;
+            shred(&stale)?;
+        }
+        if let Some(dir) = self.path.parent() {
+            crypto::sync_dir(dir)?;
+        }
+
+        let kept: Vec<AuditCheckpoint> = self.checkpoints()?.into_iter()
+            .filter(|c| c.count > purged_total)
+            .collect();
+        let content: String = kept.iter()
+            .map(|c| serde_json::to_string(c).map(|line| line + "\n"))
+            .collect::<Result<_, _>>()?;
+        write_atomic(&self.checkpoints_path(), content.as_bytes())?;
+
+        let mut purpose = format!("{} entries purged before {}", purged, cutoff.to_rfc3339());
+        if leftover > 0 {
+            purpose.push_str(&format!("; {} more left behind by an interrupted purge", leftover));
+        }
+        let entry = AuditEntry::new(AuditEventType::AuditPurge, &self.last_hash).with_purpose(purpose);
+        self.append(entry)?;
+        Ok(purged)
+    }
+
+    /// Whether `content` (the decrypted active log) is due for rotation
+    fn rotation_due(&self, content: &str) -> bool {
+        if content.is_empty() {
+            return false;
+        }
+        if self.rotation.max_bytes.is_some_and(|max| content.len() as u64 >= max) {
+            return true;
+        }
+        let Some(max_age) = self.rotation.max_age else {
+            return false;
+        };
+        content.lines()
+            .next()
+            .and_then(|line| serde_json::from_str::<AuditEntry>(line).ok())
+            .is_some_and(|first| Utc::now() - first.timestamp >= max_age)
     }
 
     /// Append an entry to the log
@@ -229,58 +1012,270 @@ impl AuditLog {
             if encrypted.is_empty() {
                 String::new()
             } else {
-                String::from_utf8(decrypt(&encrypted, &self.key)?)?
+                Self::decode(&decrypt(&encrypted, &self.key, b"")?)?
             }
         } else {
             String::new()
         };
+
+        if self.rotation_due(&content) {
+            self.rotate()?;
+            content.clear();
+        }
         
         content.push_str(&line);
         
         // Re-encrypt and save
-        let encrypted = encrypt(content.as_bytes(), &self.key)?;
-        fs::write(&self.path, &encrypted)?;
+        let encrypted = encrypt_counted(content.as_bytes(), &self.key, b"", self.nonces.as_deref())?;
+        write_atomic(&self.path, &encrypted)?;
         
+        // No subscribers is not an error
+        if let Some(ref notifier) = self.notifier {
+            let _ = notifier.send(entry.clone());
+        }
+
+        if let Some(history) = self.history.as_mut() {
+            history.record(&entry);
+        }
         self.last_hash = entry.entry_hash;
+        self.count += 1;
+
+        // The entry is in; a checkpoint that can't be written is retried
+        // on the next append
+        if self.count - self.checkpointed >= CHECKPOINT_INTERVAL {
+            if let Err(e) = self.checkpoint() {
+                tracing::warn!("Audit checkpoint of {:?} failed: {:#}", self.path, e);
+            }
+        }
         Ok(())
     }
 
     /// Log a vault unlock event
-    pub fn log_unlock(&mut self) -> Result<()> {
-        let entry = AuditEntry::new(AuditEventType::VaultUnlock, &self.last_hash);
+    pub fn log_unlock(&mut self, mode: AccessMode) -> Result<()> {
+        let entry = AuditEntry::new(AuditEventType::VaultUnlock, &self.last_hash)
+            .with_access_mode(mode);
         self.append(entry)
     }
 
-    /// Log a vault lock event
+    /// Log a vault lock event, checkpointing the session's entries
     pub fn log_lock(&mut self) -> Result<()> {
         let entry = AuditEntry::new(AuditEventType::VaultLock, &self.last_hash);
-        self.append(entry)
+        self.append(entry)?;
+        self.checkpoint()?;
+        Ok(())
     }
 
-    /// Log an entry access event
-    pub fn log_access(
-        &mut self,
-        entry_id: Uuid,
-        entry_name: &str,
-        category: Category,
-        agent_id: Option<&str>,
-        purpose: Option<&str>,
-    ) -> Result<()> {
-        let mut entry = AuditEntry::new(AuditEventType::EntryAccess, &self.last_hash)
-            .with_entry(entry_id, entry_name)
-            .with_category(category);
-        
+    /// Log an emergency lock, checkpointing so it's signed before the
+    /// keys go; `trigger` says what asked for it
+    pub fn log_emergency(&mut self, trigger: &str, agent_id: Option<&str>) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditEventType::EmergencyLock, &self.last_hash)
+            .with_purpose(trigger);
         if let Some(agent) = agent_id {
             entry = entry.with_agent(agent);
         }
-        if let Some(p) = purpose {
-            entry = entry.with_purpose(p);
-        }
-        
+        self.append(entry)?;
+        self.checkpoint()?;
+        Ok(())
+    }
+
+    /// Log a data encryption key rotation
+    pub fn log_key_rotation(&mut self) -> Result<()> {
+        let entry = AuditEntry::new(AuditEventType::KeyRotation, &self.last_hash);
         self.append(entry)
     }
 
-    /// Log an access denial
+    /// Log a change of the vault passphrase
+    pub fn log_passphrase_change(&mut self) -> Result<()> {
+        let entry = AuditEntry::new(AuditEventType::PassphraseChange, &self.last_hash);
+        self.append(entry)
+    }
+
+    /// Log the enrollment of a hardware security key
+    pub fn log_hardware_key_enroll(&mut self) -> Result<()> {
+        let entry = AuditEntry::new(AuditEventType::HardwareKeyEnroll, &self.last_hash);
+        self.append(entry)
+    }
+
+    /// Log a re-derivation of the master key under `kdf`
+    pub fn log_kdf_upgrade(&mut self, kdf: &KdfParams) -> Result<()> {
+        let entry = AuditEntry::new(AuditEventType::KdfUpgrade, &self.last_hash).with_purpose(format!(
+            "argon2id m={} KiB t={} p={}",
+            kdf.memory_kib, kdf.iterations, kdf.parallelism,
+        ));
+        self.append(entry)
+    }
+
+    /// Log that every entry was exported in one blob
+    pub fn log_export(&mut self, agent_id: Option<&str>) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditEventType::VaultExport, &self.last_hash)
+            .with_purpose("full export");
+        if let Some(agent) = agent_id {
+            entry = entry.with_agent(agent);
+        }
+        self.append(entry)
+    }
+
+    /// Log an entry access event
+    pub fn log_access(
+        &mut self,
+        entry_id: Uuid,
+        entry_name: &str,
+        category: Category,
+        agent_id: Option<&str>,
+        purpose: Option<&str>,
+        origin_chain: Option<Vec<String>>,
+    ) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditEventType::EntryAccess, &self.last_hash)
+            .with_entry(entry_id, entry_name)
+            .with_category(category);
+        
+        if let Some(agent) = agent_id {
+            entry = entry.with_agent(agent);
+        }
+        if let Some(p) = purpose {
+            entry = entry.with_purpose(p);
+        }
+        if let Some(chain) = origin_chain {
+            entry = entry.with_origin_chain(chain);
+        }
+        
+        self.append(entry)
+    }
+
+    /// Log an entry creation
+    pub fn log_create(
+        &mut self,
+        entry_id: Uuid,
+        entry_name: &str,
+        category: Category,
+        agent_id: Option<&str>,
+        origin_chain: Option<Vec<String>>,
+    ) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditEventType::EntryCreate, &self.last_hash)
+            .with_entry(entry_id, entry_name)
+            .with_category(category);
+
+        if let Some(agent) = agent_id {
+            entry = entry.with_agent(agent);
+        }
+        if let Some(chain) = origin_chain {
+            entry = entry.with_origin_chain(chain);
+        }
+
+        self.append(entry)
+    }
+
+    /// Log an entry modification
+    pub fn log_update(
+        &mut self,
+        entry_id: Uuid,
+        entry_name: &str,
+        category: Category,
+        agent_id: Option<&str>,
+    ) -> Result<()> {
+        self.log_mutation(AuditEventType::EntryUpdate, entry_id, entry_name, category, agent_id)
+    }
+
+    /// Log a credential rotation: an update whose new value was generated
+    pub fn log_rotation(
+        &mut self,
+        entry_id: Uuid,
+        entry_name: &str,
+        category: Category,
+        agent_id: Option<&str>,
+    ) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditEventType::EntryUpdate, &self.last_hash)
+            .with_entry(entry_id, entry_name)
+            .with_category(category)
+            .with_purpose("rotation");
+        if let Some(agent) = agent_id {
+            entry = entry.with_agent(agent);
+        }
+        self.append(entry)
+    }
+
+    /// Log an entry deletion
+    pub fn log_delete(
+        &mut self,
+        entry_id: Uuid,
+        entry_name: &str,
+        category: Category,
+        agent_id: Option<&str>,
+    ) -> Result<()> {
+        self.log_mutation(AuditEventType::EntryDelete, entry_id, entry_name, category, agent_id)
+    }
+
+    /// Log a one-time entry deleted because its value was read
+    pub fn log_consume(
+        &mut self,
+        entry_id: Uuid,
+        entry_name: &str,
+        category: Category,
+        agent_id: Option<&str>,
+    ) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditEventType::EntryDelete, &self.last_hash)
+            .with_entry(entry_id, entry_name)
+            .with_category(category)
+            .with_purpose("consumed");
+
+        if let Some(agent) = agent_id {
+            entry = entry.with_agent(agent);
+        }
+
+        self.append(entry)
+    }
+
+    fn log_mutation(
+        &mut self,
+        event_type: AuditEventType,
+        entry_id: Uuid,
+        entry_name: &str,
+        category: Category,
+        agent_id: Option<&str>,
+    ) -> Result<()> {
+        let mut entry = AuditEntry::new(event_type, &self.last_hash)
+            .with_entry(entry_id, entry_name)
+            .with_category(category);
+
+        if let Some(agent) = agent_id {
+            entry = entry.with_agent(agent);
+        }
+
+        self.append(entry)
+    }
+
+    /// Log a credential use against a target domain
+    ///
+    /// `sent` is `Ok` with how the target answered once the credential
+    /// went out, or `Err` with why it was never sent.
+    pub fn log_auth_use(
+        &mut self,
+        vault_entry: &VaultEntry,
+        agent_id: &str,
+        purpose: &str,
+        target_domain: &str,
+        sent: std::result::Result<&str, &str>,
+        origin_chain: Option<Vec<String>>,
+    ) -> Result<()> {
+        let mut entry = AuditEntry::new(AuditEventType::AuthUse, &self.last_hash)
+            .with_entry(vault_entry.id, &vault_entry.name)
+            .with_category(vault_entry.category)
+            .with_agent(agent_id)
+            .with_purpose(purpose)
+            .with_target_domain(target_domain);
+
+        entry = match sent {
+            Ok(outcome) => entry.with_outcome(outcome),
+            Err(reason) => entry.denied(reason),
+        };
+        if let Some(chain) = origin_chain {
+            entry = entry.with_origin_chain(chain);
+        }
+
+        self.append(entry)
+    }
+
+    /// Log an access denial
     pub fn log_denial(
         &mut self,
         reason: &str,
@@ -300,62 +1295,342 @@ impl AuditLog {
         self.append(entry)
     }
 
-    /// Read all entries
+    /// Read all entries in the active log
     pub fn read_all(&self) -> Result<Vec<AuditEntry>> {
-        if !self.path.exists() {
-            return Ok(Vec::new());
-        }
-        
-        let encrypted = fs::read(&self.path)?;
-        if encrypted.is_empty() {
-            return Ok(Vec::new());
-        }
-        
-        let decrypted = decrypt(&encrypted, &self.key)?;
-        let content = String::from_utf8(decrypted)?;
-        
-        let mut entries = Vec::new();
-        for line in content.lines() {
-            if !line.is_empty() {
-                let entry: AuditEntry = serde_json::from_str(line)?;
-                entries.push(entry);
-            }
-        }
-        
-        Ok(entries)
+        Self::read_entries(&self.path, &self.key)
     }
 
-    /// Verify the entire chain integrity
+    /// Whether the whole chain verifies; the daemon wants to know why
+    /// not, from `verify_chain_detailed`
+    #[cfg(test)]
     pub fn verify_chain(&self) -> Result<bool> {
-        let entries = self.read_all()?;
-        
-        let mut expected_prev = Self::GENESIS_HASH.to_string();
+        Ok(self.verify_chain_detailed()? == ChainVerification::Valid)
+    }
+
+    /// Verify the chain, reporting the first entry that breaks it
+    ///
+    /// Walks every archive, oldest first, then the active log; `index`
+    /// counts entries across all of them. A missing archive shows up as a
+    /// broken link at the first entry after it. With a verify key, every
+    /// checkpoint must then be signed and match the chain, which catches
+    /// entries cut off the end even if the hashes were recomputed.
+    pub fn verify_chain_detailed(&self) -> Result<ChainVerification> {
+        let mut files = self.archives()?;
+        files.push(self.path.clone());
+        let mut entries = Vec::new();
+        for file in &files {
+            entries.extend(Self::read_entries(file, &self.key)?);
+        }
+
+        let anchor = self.anchor()?;
+        let mut expected_prev = anchor.as_ref()
+            .map_or_else(|| Self::GENESIS_HASH.to_string(), |a| a.last_hash.clone());
+        let leftover = interrupted_purge(anchor.as_ref(), &entries);
+        entries.drain(..leftover);
         
-        for entry in entries {
+        for (index, entry) in entries.iter().enumerate() {
             // Check previous hash matches
             if entry.previous_hash != expected_prev {
-                return Ok(false);
+                return Ok(ChainVerification::Broken {
+                    index,
+                    entry_id: entry.id,
+                    reason: ChainError::PreviousHashMismatch,
+                });
             }
             
             // Verify entry's own hash
             if !entry.verify_hash() {
-                return Ok(false);
+                return Ok(ChainVerification::Broken {
+                    index,
+                    entry_id: entry.id,
+                    reason: ChainError::EntryHashMismatch,
+                });
             }
             
-            expected_prev = entry.entry_hash;
+            expected_prev = entry.entry_hash.clone();
+        }
+
+        let Some(verify_key) = self.verify_key else {
+            return Ok(ChainVerification::Valid);
+        };
+        if anchor.as_ref().is_some_and(|a| !a.verify(&verify_key)) {
+            return Ok(ChainVerification::BadAnchor);
+        }
+        // Checkpoints count purged entries too
+        let purged = anchor.map_or(0, |a| a.purged);
+        for checkpoint in self.checkpoints()? {
+            if !checkpoint.verify(&verify_key) {
+                return Ok(ChainVerification::BadCheckpoint { count: checkpoint.count });
+            }
+            // Over purged entries: left by a purge that didn't finish
+            let Some(last) = checkpoint.count.checked_sub(purged + 1) else {
+                continue;
+            };
+            let last = last as usize;
+            match entries.get(last) {
+                None => return Ok(ChainVerification::Truncated {
+                    checkpoint: checkpoint.count,
+                    entries: purged + entries.len() as u64,
+                }),
+                Some(entry) if entry.entry_hash != checkpoint.last_hash => {
+                    return Ok(ChainVerification::BadCheckpoint { count: checkpoint.count });
+                }
+                Some(_) => {}
+            }
         }
         
-        Ok(true)
+        Ok(ChainVerification::Valid)
     }
 
-    /// Get entries from last N hours
-    pub fn recent_entries(&self, hours: i64) -> Result<Vec<AuditEntry>> {
-        let cutoff = Utc::now() - chrono::Duration::hours(hours);
-        let entries = self.read_all()?;
-        
-        Ok(entries.into_iter()
-            .filter(|e| e.timestamp >= cutoff)
-            .collect())
+    /// The access history of the whole log, archives included
+    fn load_history(&self) -> Result<AccessHistory> {
+        let mut history = AccessHistory::default();
+        let mut files = self.archives()?;
+        files.push(self.path.clone());
+        for file in &files {
+            for entry in Self::read_entries(file, &self.key)? {
+                history.record(&entry);
+            }
+        }
+        Ok(history)
+    }
+
+    /// Bring `history` up to the thresholds' window, reading the log only
+    /// the first time
+    fn refresh_history(&mut self) -> Result<()> {
+        if self.history.is_none() {
+            self.history = Some(self.load_history()?);
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.forget_outside(self.thresholds.window);
+        }
+        Ok(())
+    }
+
+    /// Examine `EntryAccess` events within `window` for suspicious patterns
+    ///
+    /// Flags, per agent:
+    /// - more than `max_distinct_entries` distinct entries accessed
+    /// - a first-ever access to a sensitive category
+    /// - more than `max_burst` accesses inside any `burst_window`
+    ///
+    /// Reads the whole log, archives included, on every call, so nothing
+    /// from before a daemon restart is missed; the check on each access
+    /// goes through `record_anomalies` instead, which keeps the history
+    /// in memory.
+    pub fn detect_anomalies(&self, window: Duration) -> Result<Vec<Anomaly>> {
+        let mut history = self.load_history()?;
+        history.forget_outside(window);
+        Ok(self.anomalies_in(&history))
+    }
+
+    fn anomalies_in(&self, history: &AccessHistory) -> Vec<Anomaly> {
+        let accesses: Vec<&AuditEntry> = history.recent.iter()
+            .filter(|e| e.event_type == AuditEventType::EntryAccess)
+            .collect();
+
+        let mut anomalies = Vec::new();
+
+        // (b) First touch of a sensitive category, as far back as the log goes
+        for e in &accesses {
+            let Some(cat) = e.category else { continue };
+            if history.first_accesses.contains(&e.id) && self.thresholds.sensitive_categories.contains(&cat) {
+                anomalies.push(Anomaly {
+                    kind: AnomalyKind::NewSensitiveCategory,
+                    agent_id: e.agent_id.clone(),
+                    category: Some(cat),
+                    description: format!(
+                        "first access to {:?} by agent {}",
+                        cat,
+                        e.agent_id.as_deref().unwrap_or("unknown"),
+                    ),
+                });
+            }
+        }
+
+        // Group in-window accesses per agent (log order is chronological)
+        let mut per_agent: HashMap<Option<String>, Vec<&AuditEntry>> = HashMap::new();
+        for e in accesses {
+            per_agent.entry(e.agent_id.clone()).or_default().push(e);
+        }
+
+        for (agent, events) in per_agent {
+            let agent_name = agent.as_deref().unwrap_or("unknown");
+
+            // (a) Breadth of access
+            let distinct: HashSet<Uuid> = events.iter().filter_map(|e| e.entry_id).collect();
+            if distinct.len() > self.thresholds.max_distinct_entries {
+                anomalies.push(Anomaly {
+                    kind: AnomalyKind::ManyDistinctEntries,
+                    agent_id: agent.clone(),
+                    category: None,
+                    description: format!(
+                        "agent {} accessed {} distinct entries",
+                        agent_name,
+                        distinct.len(),
+                    ),
+                });
+            }
+
+            // (c) Densest burst via a sliding window over timestamps
+            let mut start = 0;
+            let mut densest = 0;
+            for end in 0..events.len() {
+                while events[end].timestamp - events[start].timestamp > self.thresholds.burst_window {
+                    start += 1;
+                }
+                densest = densest.max(end - start + 1);
+            }
+            if densest > self.thresholds.max_burst {
+                anomalies.push(Anomaly {
+                    kind: AnomalyKind::AccessBurst,
+                    agent_id: agent.clone(),
+                    category: None,
+                    description: format!(
+                        "agent {} made {} accesses within {}s",
+                        agent_name,
+                        densest,
+                        self.thresholds.burst_window.num_seconds(),
+                    ),
+                });
+            }
+        }
+
+        anomalies
+    }
+
+    /// Detect anomalies and append an `AnomalyDetected` entry for each one
+    /// not already recorded within the window
+    ///
+    /// Looks back over the thresholds' `window`, through a history read
+    /// from the log once and kept current as entries are appended. Returns
+    /// only the newly recorded anomalies.
+    pub fn record_anomalies(&mut self) -> Result<Vec<Anomaly>> {
+        self.refresh_history()?;
+        let anomalies = self.anomalies_in(self.history.as_ref().expect("refreshed above"));
+        if anomalies.is_empty() {
+            return Ok(anomalies);
+        }
+
+        // The kind label leads the purpose field, so a recorded anomaly
+        // can be matched without re-parsing its description
+        let recorded: Vec<AuditEntry> = self.history.iter()
+            .flat_map(|h| &h.recent)
+            .filter(|e| e.event_type == AuditEventType::AnomalyDetected)
+            .cloned()
+            .collect();
+
+        let mut fresh = Vec::new();
+        for anomaly in anomalies {
+            let already = recorded.iter().any(|e| {
+                e.agent_id == anomaly.agent_id
+                    && e.category == anomaly.category
+                    && e.purpose.as_deref()
+                        .is_some_and(|p| p.starts_with(anomaly.kind.label()))
+            });
+            if already {
+                continue;
+            }
+
+            let mut entry = AuditEntry::new(AuditEventType::AnomalyDetected, &self.last_hash)
+                .with_purpose(format!("{}: {}", anomaly.kind.label(), anomaly.description));
+            if let Some(ref agent) = anomaly.agent_id {
+                entry = entry.with_agent(agent.clone());
+            }
+            if let Some(cat) = anomaly.category {
+                entry = entry.with_category(cat);
+            }
+            self.append(entry)?;
+            fresh.push(anomaly);
+        }
+
+        Ok(fresh)
+    }
+
+    /// Entries from `cutoff` on, reaching into archives as far as needed
+    fn entries_since(&self, cutoff: DateTime<Utc>) -> Result<Vec<AuditEntry>> {
+        let mut entries = self.read_all()?;
+        for archive in self.archives()?.iter().rev() {
+            if entries.first().is_some_and(|e| e.timestamp < cutoff) {
+                break;
+            }
+            let mut older = Self::read_entries(archive, &self.key)?;
+            older.append(&mut entries);
+            entries = older;
+        }
+        entries.retain(|e| e.timestamp >= cutoff);
+        Ok(entries)
+    }
+
+    /// Entries matching `filter`, oldest first, and how many matched in
+    /// all; past `filter.limit` only the most recent are kept
+    pub fn query(&self, filter: &AuditFilter) -> Result<(Vec<AuditEntry>, usize)> {
+        let mut entries = self.entries_since(filter.since.unwrap_or(DateTime::<Utc>::MIN_UTC))?;
+        entries.retain(|e| filter.matches(e));
+        let matched = entries.len();
+        entries.drain(..matched.saturating_sub(filter.limit));
+        Ok((entries, matched))
+    }
+
+    /// Usage over the last `window`: who used what, how often, and when
+    pub fn stats(&self, window: Duration) -> Result<AuditStats> {
+        let since = Utc::now().checked_sub_signed(window).unwrap_or(DateTime::<Utc>::MIN_UTC);
+        let entries = self.entries_since(since)?;
+
+        let mut uses: HashMap<Uuid, EntryUsage> = HashMap::new();
+        let mut agents: HashMap<Option<String>, AgentActivity> = HashMap::new();
+        let mut categories: HashMap<Category, usize> = HashMap::new();
+        let mut by_hour = [0; 24];
+        let mut denials = 0;
+
+        for e in &entries {
+            let denied = !e.granted || e.event_type == AuditEventType::AccessDenied;
+            by_hour[e.timestamp.hour() as usize] += 1;
+            let agent = agents.entry(e.agent_id.clone()).or_insert_with(|| AgentActivity {
+                agent_id: e.agent_id.clone(),
+                events: 0,
+                denials: 0,
+            });
+            agent.events += 1;
+            if denied {
+                agent.denials += 1;
+                denials += 1;
+                continue;
+            }
+
+            if !matches!(e.event_type, AuditEventType::EntryAccess | AuditEventType::AuthUse) {
+                continue;
+            }
+            if let Some(id) = e.entry_id {
+                let usage = uses.entry(id).or_insert(EntryUsage { entry_id: id, entry_name: None, uses: 0 });
+                usage.uses += 1;
+                if e.entry_name.is_some() {
+                    usage.entry_name = e.entry_name.clone();
+                }
+            }
+            if let Some(cat) = e.category {
+                *categories.entry(cat).or_default() += 1;
+            }
+        }
+
+        let mut entry_usage: Vec<EntryUsage> = uses.into_values().collect();
+        entry_usage.sort_by(|a, b| b.uses.cmp(&a.uses).then(a.entry_name.cmp(&b.entry_name)));
+        let mut agents: Vec<AgentActivity> = agents.into_values().collect();
+        agents.sort_by(|a, b| b.events.cmp(&a.events).then(a.agent_id.cmp(&b.agent_id)));
+        let mut categories: Vec<CategoryUsage> = categories.into_iter()
+            .map(|(category, uses)| CategoryUsage { category, uses })
+            .collect();
+        categories.sort_by(|a, b| b.uses.cmp(&a.uses).then(a.category.filename().cmp(b.category.filename())));
+
+        Ok(AuditStats {
+            since,
+            events: entries.len(),
+            denials,
+            entries: entry_usage,
+            agents,
+            categories,
+            by_hour,
+        })
     }
 }
 
@@ -373,18 +1648,21 @@ mod tests {
         
         let mut log = AuditLog::open(&path, key.clone()).unwrap();
         
-        log.log_unlock().unwrap();
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
         log.log_access(
             Uuid::new_v4(),
             "Gmail",
             Category::Authentication,
             Some("email-agent"),
             Some("send email"),
+            Some(vec!["user".into(), "assistant".into()]),
         ).unwrap();
         log.log_lock().unwrap();
         
         let entries = log.read_all().unwrap();
         assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].origin_chain, Some(vec!["user".to_string(), "assistant".to_string()]));
+        assert!(log.verify_chain().unwrap());
     }
 
     #[test]
@@ -395,12 +1673,470 @@ mod tests {
         
         let mut log = AuditLog::open(&path, key.clone()).unwrap();
         
-        log.log_unlock().unwrap();
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
         log.log_lock().unwrap();
         
         assert!(log.verify_chain().unwrap());
     }
 
+    /// Rewrite the encrypted log with one entry modified
+    fn tamper(path: &Path, key: &SecureKey, f: impl FnOnce(&mut Vec<AuditEntry>)) {
+        let log = AuditLog::open(path, key.clone()).unwrap();
+        let mut entries = log.read_all().unwrap();
+        f(&mut entries);
+
+        let content: String = entries.iter()
+            .map(|e| serde_json::to_string(e).unwrap() + "\n")
+            .collect();
+        fs::write(path, crypto::encrypt(content.as_bytes(), key, b"").unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_verify_chain_pinpoints_break() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("audit.enc");
+        let key = SecureKey::generate();
+
+        let mut log = AuditLog::open(&path, key.clone()).unwrap();
+        for _ in 0..4 {
+            log.log_unlock(AccessMode::ReadWrite).unwrap();
+        }
+        assert_eq!(log.verify_chain_detailed().unwrap(), ChainVerification::Valid);
+
+        // Modifying an entry breaks its own hash
+        tamper(&path, &key, |entries| entries[2].granted = false);
+        let id = log.read_all().unwrap()[2].id;
+        assert_eq!(
+            log.verify_chain_detailed().unwrap(),
+            ChainVerification::Broken { index: 2, entry_id: id, reason: ChainError::EntryHashMismatch },
+        );
+
+        // Removing an entry breaks the next entry's link
+        tamper(&path, &key, |entries| {
+            entries.remove(2);
+        });
+        let id = log.read_all().unwrap()[2].id;
+        assert_eq!(
+            log.verify_chain_detailed().unwrap(),
+            ChainVerification::Broken { index: 2, entry_id: id, reason: ChainError::PreviousHashMismatch },
+        );
+        assert!(!log.verify_chain().unwrap());
+    }
+
+    #[test]
+    fn test_checkpoints_detect_truncation() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("audit.enc");
+        let key = SecureKey::generate();
+        let (signer, verify_key) = SigningKey::generate().unwrap();
+        let open = || AuditLog::open(&path, key.clone()).unwrap()
+            .with_checkpoints(Some(signer.clone()), Some(verify_key));
+
+        // Every CHECKPOINT_INTERVAL entries, and on lock
+        let mut log = open();
+        for _ in 0..CHECKPOINT_INTERVAL {
+            log.log_unlock(AccessMode::ReadWrite).unwrap();
+        }
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
+        log.log_lock().unwrap();
+        let checkpoints = log.checkpoints().unwrap();
+        assert_eq!(checkpoints.iter().map(|c| c.count).collect::<Vec<_>>(), [CHECKPOINT_INTERVAL, CHECKPOINT_INTERVAL + 2]);
+        assert_eq!(checkpoints[1].last_hash, log.read_all().unwrap().last().unwrap().entry_hash);
+        assert_eq!(log.verify_chain_detailed().unwrap(), ChainVerification::Valid);
+
+        // Counting resumes on reopen; nothing new means no checkpoint
+        let mut log = open();
+        assert!(log.checkpoint().unwrap().is_none());
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
+        assert_eq!(log.checkpoint().unwrap().unwrap().count, CHECKPOINT_INTERVAL + 3);
+
+        // Cutting entries off the end leaves a valid chain, but too short
+        tamper(&path, &key, |entries| entries.truncate(entries.len() - 2));
+        assert_eq!(
+            log.verify_chain_detailed().unwrap(),
+            ChainVerification::Truncated { checkpoint: CHECKPOINT_INTERVAL + 2, entries: CHECKPOINT_INTERVAL + 1 },
+        );
+
+        // Rewriting history with recomputed hashes no longer matches
+        tamper(&path, &key, |entries| {
+            entries[0].granted = false;
+            let mut previous = AuditLog::GENESIS_HASH.to_string();
+            for entry in entries.iter_mut() {
+                entry.previous_hash = previous;
+                entry.compute_hash();
+                previous = entry.entry_hash.clone();
+            }
+        });
+        assert!(AuditLog::open(&path, key.clone()).unwrap().verify_chain().unwrap());
+        assert_eq!(log.verify_chain_detailed().unwrap(), ChainVerification::BadCheckpoint { count: CHECKPOINT_INTERVAL });
+
+        // Nor can checkpoints be forged without the signing key
+        let checkpoints_path = tmp.path().join("audit.checkpoints");
+        let forged = fs::read_to_string(&checkpoints_path).unwrap()
+            .replacen(&format!("\"count\":{}", CHECKPOINT_INTERVAL), "\"count\":1", 1);
+        fs::write(&checkpoints_path, forged).unwrap();
+        assert_eq!(log.verify_chain_detailed().unwrap(), ChainVerification::BadCheckpoint { count: 1 });
+
+        // Without a verify key only the chain itself is checked
+        let unchecked = AuditLog::open(&path, key.clone()).unwrap();
+        assert_eq!(unchecked.verify_chain_detailed().unwrap(), ChainVerification::Valid);
+    }
+
+    #[test]
+    fn test_purge_reanchors_chain() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("audit.enc");
+        let key = SecureKey::generate();
+        let (signer, verify_key) = SigningKey::generate().unwrap();
+        let open = || AuditLog::open(&path, key.clone()).unwrap()
+            .with_checkpoints(Some(signer.clone()), Some(verify_key));
+
+        let mut log = open();
+        for _ in 0..3 {
+            log.log_unlock(AccessMode::ReadWrite).unwrap();
+        }
+        log.rotate().unwrap();
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
+        let cutoff = Utc::now();
+        log.log_lock().unwrap();
+        log.log_unlock(AccessMode::ReadOnly).unwrap();
+        assert_eq!(log.purge_older_than(cutoff - Duration::days(1)).unwrap(), 0);
+
+        // The whole archive and part of the active log go
+        assert_eq!(log.purge_older_than(cutoff).unwrap(), 5);
+        assert!(log.archives().unwrap().is_empty());
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 3);
+        let entries = log.read_all().unwrap();
+        let events: Vec<AuditEventType> = entries.iter().map(|e| e.event_type).collect();
+        assert_eq!(events, [AuditEventType::VaultLock, AuditEventType::VaultUnlock, AuditEventType::AuditPurge]);
+        assert_eq!(entries[2].purpose, Some(format!("5 entries purged before {}", cutoff.to_rfc3339())));
+        let anchor = log.anchor().unwrap().unwrap();
+        assert_eq!((anchor.purged, &anchor.last_hash), (5, &entries[0].previous_hash));
+
+        // What's left verifies, its checkpoint included, and counting goes on
+        assert_eq!(log.verify_chain_detailed().unwrap(), ChainVerification::Valid);
+        assert_eq!(log.checkpoints().unwrap().iter().map(|c| c.count).collect::<Vec<_>>(), [6]);
+        let mut log = open();
+        assert_eq!(log.checkpoint().unwrap().unwrap().count, 8);
+        assert_eq!(log.verify_chain_detailed().unwrap(), ChainVerification::Valid);
+
+        // A purge record can't be altered without the signing key
+        let anchor_path = tmp.path().join("audit.anchor");
+        let forged = fs::read_to_string(&anchor_path).unwrap().replacen("\"purged\":5", "\"purged\":4", 1);
+        fs::write(&anchor_path, forged).unwrap();
+        assert_eq!(log.verify_chain_detailed().unwrap(), ChainVerification::BadAnchor);
+        assert!(log.purge_older_than(Utc::now()).is_err());
+    }
+
+    #[test]
+    fn test_purge_interrupted_after_anchor() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("audit.enc");
+        let key = SecureKey::generate();
+        let (signer, verify_key) = SigningKey::generate().unwrap();
+        let open = || AuditLog::open(&path, key.clone()).unwrap()
+            .with_checkpoints(Some(signer.clone()), Some(verify_key));
+
+        let mut log = open();
+        for _ in 0..3 {
+            log.log_unlock(AccessMode::ReadWrite).unwrap();
+        }
+        log.rotate().unwrap();
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
+        let cutoff = Utc::now();
+        log.log_lock().unwrap();
+        log.log_unlock(AccessMode::ReadOnly).unwrap();
+        let before: Vec<(PathBuf, Vec<u8>)> = fs::read_dir(tmp.path()).unwrap()
+            .map(|e| e.unwrap().path())
+            .map(|p| (p.clone(), fs::read(&p).unwrap()))
+            .collect();
+
+        // Crash right after the anchor is written: the files are as they were
+        assert_eq!(log.purge_older_than(cutoff).unwrap(), 5);
+        let anchor = fs::read(tmp.path().join("audit.anchor")).unwrap();
+        for file in fs::read_dir(tmp.path()).unwrap() {
+            fs::remove_file(file.unwrap().path()).unwrap();
+        }
+        for (file, bytes) in &before {
+            fs::write(file, bytes).unwrap();
+        }
+        fs::write(tmp.path().join("audit.anchor"), anchor).unwrap();
+
+        let mut log = open();
+        assert_eq!(log.verify_chain_detailed().unwrap(), ChainVerification::Valid);
+        assert_eq!(log.count, 7);
+
+        // The next purge finishes the job
+        assert_eq!(log.purge_older_than(cutoff).unwrap(), 0);
+        assert!(log.archives().unwrap().is_empty());
+        let entries = log.read_all().unwrap();
+        let events: Vec<AuditEventType> = entries.iter().map(|e| e.event_type).collect();
+        assert_eq!(events, [AuditEventType::VaultLock, AuditEventType::VaultUnlock, AuditEventType::AuditPurge]);
+        assert!(entries[2].purpose.as_deref().unwrap().ends_with("5 more left behind by an interrupted purge"));
+        assert_eq!(log.anchor().unwrap().unwrap().purged, 5);
+        assert_eq!(log.verify_chain_detailed().unwrap(), ChainVerification::Valid);
+        assert_eq!(open().count, 8);
+    }
+
+    #[test]
+    fn test_rotation_keeps_chain_across_archives() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("audit.enc");
+        let key = SecureKey::generate();
+
+        let mut log = AuditLog::open(&path, key.clone()).unwrap();
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
+        log.log_lock().unwrap();
+        let archive = log.rotate().unwrap();
+        assert!(!path.exists());
+
+        // Reopening right after rotation still links to the archive
+        let mut log = AuditLog::open(&path, key.clone()).unwrap();
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
+        let archived = AuditLog::read_entries(&archive, &key).unwrap();
+        assert_eq!(log.read_all().unwrap()[0].previous_hash, archived[1].entry_hash);
+        assert!(log.verify_chain().unwrap());
+
+        // Size-triggered rotation happens on append
+        let mut log = log.with_rotation(RotationPolicy { max_bytes: Some(1), max_age: None });
+        log.log_lock().unwrap();
+        assert_eq!(log.archives().unwrap().len(), 2);
+        assert_eq!(log.read_all().unwrap().len(), 1);
+        assert!(log.verify_chain().unwrap());
+
+        // Dropping an archive breaks the link into the next file
+        fs::remove_file(&archive).unwrap();
+        assert!(matches!(
+            log.verify_chain_detailed().unwrap(),
+            ChainVerification::Broken { index: 0, reason: ChainError::PreviousHashMismatch, .. },
+        ));
+    }
+
+    #[test]
+    fn test_compaction() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("audit.enc");
+        let key = SecureKey::generate();
+
+        let mut log = AuditLog::open(&path, key.clone()).unwrap();
+        for _ in 0..50 {
+            log.log_access(Uuid::new_v4(), "Bank", Category::Financial, Some("agent-a"), None, None).unwrap();
+        }
+        let archive = log.rotate().unwrap();
+        log.log_lock().unwrap();
+        let archived_before = fs::metadata(&archive).unwrap().len();
+        let active_before = fs::read(&path).unwrap();
+
+        let report = log.compact().unwrap();
+        assert_eq!(report.entries_retained, 50);
+        let archived_after = fs::metadata(&archive).unwrap().len();
+        assert_eq!(report.bytes_reclaimed, archived_before - archived_after);
+        assert!(archived_after < archived_before / 2);
+        assert_eq!(fs::read(&path).unwrap(), active_before);
+
+        // Still readable and chained, and appending carries on from it
+        assert_eq!(AuditLog::read_entries(&archive, &key).unwrap().len(), 50);
+        assert!(log.verify_chain().unwrap());
+        let mut log = AuditLog::open(&path, key.clone()).unwrap();
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
+        assert!(log.verify_chain().unwrap());
+        assert_eq!(log.compact().unwrap(), CompactionReport::default());
+
+        // A broken chain is left exactly as it is
+        log.rotate().unwrap();
+        log.log_lock().unwrap();
+        tamper(&path, &key, |entries| entries[0].granted = false);
+        let before: Vec<_> = log.archives().unwrap().iter().map(|a| fs::read(a).unwrap()).collect();
+        assert!(log.compact().is_err());
+        let after: Vec<_> = log.archives().unwrap().iter().map(|a| fs::read(a).unwrap()).collect();
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn test_stats() {
+        let tmp = TempDir::new().unwrap();
+        let mut log = AuditLog::open(tmp.path().join("audit.enc"), SecureKey::generate()).unwrap();
+        let (bank, mail) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let mut old = AuditEntry::new(AuditEventType::EntryAccess, "")
+            .with_entry(mail, "Mail")
+            .with_category(Category::Personal);
+        old.timestamp = Utc::now() - Duration::days(3);
+        log.append(old).unwrap();
+        log.log_access(bank, "Bank", Category::Financial, Some("agent-a"), None, None).unwrap();
+        // Earlier entries in an archive still count
+        log.rotate().unwrap();
+        log.log_access(bank, "Bank", Category::Financial, Some("agent-a"), None, None).unwrap();
+        log.log_access(mail, "Mail", Category::Personal, Some("agent-b"), None, None).unwrap();
+        log.log_denial("policy", Some("agent-b"), Some(Category::Financial)).unwrap();
+
+        let stats = log.stats(Duration::days(1)).unwrap();
+        assert_eq!((stats.events, stats.denials), (4, 1));
+        assert_eq!(stats.by_hour.iter().sum::<usize>(), 4);
+        assert_eq!((stats.entries[0].entry_id, stats.entries[0].uses), (bank, 2));
+        assert_eq!(stats.entries[0].entry_name.as_deref(), Some("Bank"));
+        assert_eq!(stats.entries[1].uses, 1);
+        assert_eq!((stats.categories[0].category, stats.categories[0].uses), (Category::Financial, 2));
+        let b = stats.agents.iter().find(|a| a.agent_id.as_deref() == Some("agent-b")).unwrap();
+        assert_eq!((b.events, b.denials), (2, 1));
+
+        assert_eq!(log.stats(Duration::days(7)).unwrap().entries[1].uses, 2);
+    }
+
+    #[test]
+    fn test_query() {
+        let tmp = TempDir::new().unwrap();
+        let mut log = AuditLog::open(tmp.path().join("audit.enc"), SecureKey::generate()).unwrap();
+        let (bank, mail) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let mut old = AuditEntry::new(AuditEventType::EntryAccess, "")
+            .with_entry(mail, "Mail")
+            .with_agent("agent-a");
+        old.timestamp = Utc::now() - Duration::days(3);
+        log.append(old).unwrap();
+        log.rotate().unwrap();
+        log.log_access(bank, "Bank", Category::Financial, Some("agent-a"), None, None).unwrap();
+        log.log_access(mail, "Mail", Category::Personal, Some("agent-b"), None, None).unwrap();
+        log.log_denial("policy", Some("agent-a"), Some(Category::Financial)).unwrap();
+
+        let all = AuditFilter { since: None, event_types: Vec::new(), agent_id: None, category: None, limit: 100 };
+        assert_eq!(log.query(&all).unwrap().1, 4);
+
+        // Criteria combine; the archive is only read when the window reaches it
+        let recent_a = AuditFilter {
+            since: Some(Utc::now() - Duration::days(1)),
+            agent_id: Some("agent-a".to_string()),
+            ..all.clone()
+        };
+        let (entries, matched) = log.query(&recent_a).unwrap();
+        assert_eq!(matched, 2);
+        assert_eq!(entries[0].entry_id, Some(bank));
+
+        let denials = AuditFilter { event_types: vec![AuditEventType::AccessDenied], ..all.clone() };
+        assert_eq!(log.query(&denials).unwrap().0[0].denial_reason.as_deref(), Some("policy"));
+        let financial = AuditFilter { category: Some(Category::Financial), ..all.clone() };
+        assert_eq!(log.query(&financial).unwrap().1, 2);
+
+        // Over the limit, the most recent are kept
+        let (entries, matched) = log.query(&AuditFilter { limit: 1, ..all }).unwrap();
+        assert_eq!((entries.len(), matched), (1, 4));
+        assert_eq!(entries[0].event_type, AuditEventType::AccessDenied);
+    }
+
+    #[test]
+    fn test_append_publishes_to_subscribers() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("audit.enc");
+        let (tx, mut rx) = broadcast::channel(4);
+
+        let mut log = AuditLog::open(&path, SecureKey::generate())
+            .unwrap()
+            .with_notifier(tx);
+
+        log.log_unlock(AccessMode::ReadWrite).unwrap();
+        log.log_lock().unwrap();
+
+        let first = rx.try_recv().unwrap();
+        assert!(matches!(first.event_type, AuditEventType::VaultUnlock));
+        let second = rx.try_recv().unwrap();
+        assert!(matches!(second.event_type, AuditEventType::VaultLock));
+        assert_eq!(second.previous_hash, first.entry_hash);
+    }
+
+    #[test]
+    fn test_lagging_subscriber_does_not_block_append() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("audit.enc");
+        let (tx, mut rx) = broadcast::channel(2);
+
+        let mut log = AuditLog::open(&path, SecureKey::generate())
+            .unwrap()
+            .with_notifier(tx);
+
+        for _ in 0..5 {
+            log.log_unlock(AccessMode::ReadWrite).unwrap();
+        }
+
+        assert!(matches!(rx.try_recv(), Err(broadcast::error::TryRecvError::Lagged(3))));
+        assert_eq!(log.read_all().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_detect_anomalies() {
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("audit.enc");
+
+        let thresholds = AnomalyThresholds {
+            max_distinct_entries: 3,
+            max_burst: 5,
+            ..Default::default()
+        };
+        let key = SecureKey::generate();
+        let open = || AuditLog::open(&path, SecureKey::new(*key.expose()))
+            .unwrap()
+            .with_thresholds(thresholds.clone());
+        let mut log = open();
+
+        // A well-behaved agent stays under every threshold
+        log.log_access(Uuid::new_v4(), "Gmail", Category::Authentication, Some("mail"), None, None).unwrap();
+        assert!(log.detect_anomalies(Duration::hours(1)).unwrap().is_empty());
+
+        // First time the mail agent touches Financial
+        log.log_access(Uuid::new_v4(), "Bank", Category::Financial, Some("mail"), None, None).unwrap();
+
+        // A scraping agent: many entries in a burst
+        for i in 0..6 {
+            log.log_access(Uuid::new_v4(), &format!("e{}", i), Category::Personal, Some("scraper"), None, None)
+                .unwrap();
+        }
+
+        let anomalies = log.detect_anomalies(Duration::hours(1)).unwrap();
+        let kinds = |agent: &str| -> Vec<AnomalyKind> {
+            anomalies.iter()
+                .filter(|a| a.agent_id.as_deref() == Some(agent))
+                .map(|a| a.kind)
+                .collect()
+        };
+
+        assert_eq!(kinds("mail"), vec![AnomalyKind::NewSensitiveCategory]);
+        let scraper = kinds("scraper");
+        assert!(scraper.contains(&AnomalyKind::ManyDistinctEntries));
+        assert!(scraper.contains(&AnomalyKind::AccessBurst));
+
+        // Recording is idempotent within the window
+        assert_eq!(log.record_anomalies().unwrap().len(), 3);
+        assert!(log.record_anomalies().unwrap().is_empty());
+        let recorded = log.read_all().unwrap().iter()
+            .filter(|e| e.event_type == AuditEventType::AnomalyDetected)
+            .count();
+        assert_eq!(recorded, 3);
+        assert!(log.verify_chain().unwrap());
+
+        // Detection only looks inside the window it's given
+        assert!(log.detect_anomalies(Duration::zero()).unwrap().is_empty());
+
+        // Reopened, as after a restart, the history is read back, recorded
+        // anomalies with it
+        let mut reopened = open();
+        assert_eq!(reopened.detect_anomalies(Duration::hours(1)).unwrap().len(), 3);
+        assert!(reopened.record_anomalies().unwrap().is_empty());
+
+        // Detection rereads the log every time; the check on each access
+        // doesn't read it again at all
+        fs::write(&path, b"not a log").unwrap();
+        assert!(log.detect_anomalies(Duration::hours(1)).is_err());
+        assert!(log.record_anomalies().unwrap().is_empty());
+
+        // Nor does that history outlive the thresholds' window
+        let mut brief = AuditLog::open(tmp.path().join("brief.enc"), SecureKey::generate())
+            .unwrap()
+            .with_thresholds(AnomalyThresholds { window: Duration::zero(), ..thresholds });
+        brief.log_access(Uuid::new_v4(), "Bank", Category::Financial, Some("mail"), None, None).unwrap();
+        assert!(brief.record_anomalies().unwrap().is_empty());
+    }
+
     #[test]
     fn test_entry_hash_verification() {
         let entry = AuditEntry::new(AuditEventType::VaultUnlock, "genesis");
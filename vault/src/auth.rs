@@ -0,0 +1,636 @@
+//! Credential use without exposure
+//!
+//! The daemon performs authenticated HTTP requests on behalf of agents,
+//! so the secret value never leaves this process:
+//! - Target host must match the entry's stored URL (see `DomainPolicy`)
+//! - Credential injected per entry type (Bearer / Basic)
+//! - Optional leaf certificate pinning (SHA-256)
+//! - Only response status and headers are returned
+//! - OAuth tokens are refreshed in place; neither token is returned
+
+use anyhow::{anyhow, Result};
+use reqwest::header::ACCEPT;
+use reqwest::redirect::Policy;
+use reqwest::{Client, RequestBuilder};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+use zeroize::Zeroizing;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::vault::{EntryType, VaultEntry};
+
+/// Upper bound on a single outbound auth request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Custom fields holding an `OAuthToken` entry's refresh grant; the
+/// entry's value is the access token
+pub const REFRESH_TOKEN_FIELD: &str = "refresh_token";
+pub const TOKEN_ENDPOINT_FIELD: &str = "token_endpoint";
+pub const CLIENT_ID_FIELD: &str = "client_id";
+/// Only confidential clients have one
+pub const CLIENT_SECRET_FIELD: &str = "client_secret";
+
+/// Largest token endpoint response read
+const MAX_TOKEN_RESPONSE_BYTES: usize = 64 * 1024;
+
+/// Result of an auth request (never contains the credential)
+#[derive(Debug, Serialize)]
+pub struct AuthOutcome {
+    pub auth_performed: bool,
+    pub target: String,
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+}
+
+/// The server's leaf certificate did not match the entry's pin
+///
+/// Raised during the TLS handshake, so no request bytes (and thus no
+/// credential) were ever written to the connection.
+#[derive(Debug, thiserror::Error)]
+#[error("certificate pin mismatch")]
+pub struct PinMismatch;
+
+/// A prepared outbound request with the credential attached
+pub struct AuthRequest {
+    request: RequestBuilder,
+    pin_mismatch: Arc<AtomicBool>,
+}
+
+/// Parse a URL, assuming https:// when the scheme is missing
+///
+/// Stored entry URLs are user-entered and often just "github.com".
+pub fn parse_url(raw: &str) -> Result<Url> {
+    let url = match Url::parse(raw) {
+        Ok(url) => url,
+        Err(url::ParseError::RelativeUrlWithoutBase) => Url::parse(&format!("https://{}", raw))?,
+        Err(e) => return Err(anyhow!("Invalid URL '{}': {}", raw, e)),
+    };
+
+    match url.scheme() {
+        "https" | "http" => {}
+        other => return Err(anyhow!("Unsupported URL scheme: {}", other)),
+    }
+
+    if url.host_str().is_none() {
+        return Err(anyhow!("URL has no host: {}", raw));
+    }
+
+    Ok(url)
+}
+
+/// How closely a target URL must match an entry's URL to use its
+/// credential
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DomainPolicy {
+    /// `login.example.com` may use an entry for `example.com`
+    pub allow_subdomains: bool,
+    /// Ports must be equal: `example.com:8443` doesn't match
+    /// `https://example.com`, though default ports match across schemes
+    pub match_port: bool,
+    /// An entry stored with an https URL is never sent over plain http
+    pub require_https: bool,
+}
+
+impl Default for DomainPolicy {
+    fn default() -> Self {
+        Self { allow_subdomains: false, match_port: true, require_https: true }
+    }
+}
+
+/// Why a target URL may not use an entry's credential
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum UrlMismatch {
+    #[error("Target uses http but the entry's URL is https")]
+    Downgrade,
+    #[error("Target host {target} does not match entry host {entry}")]
+    Host { target: String, entry: String },
+    #[error("Target host {target} is a subdomain of entry host {entry}, which isn't allowed")]
+    Subdomain { target: String, entry: String },
+    #[error("Target port {target} does not match entry port {entry}")]
+    Port { target: u16, entry: u16 },
+}
+
+/// How `target` fails to match `entry_url` under `policy`, if it does
+///
+/// Hosts compare exactly (`Url` lowercases them), except that a domain
+/// may be a subdomain of the entry's when the policy allows it. IP
+/// addresses only ever match themselves.
+pub fn url_mismatch(target: &Url, entry_url: &Url, policy: &DomainPolicy) -> Option<UrlMismatch> {
+    if policy.require_https && entry_url.scheme() == "https" && target.scheme() != "https" {
+        return Some(UrlMismatch::Downgrade);
+    }
+
+    // A fully qualified "example.com." is the same host
+    let target_host = target.host_str().unwrap_or_default().trim_end_matches('.');
+    let entry_host = entry_url.host_str().unwrap_or_default().trim_end_matches('.');
+    if target_host != entry_host {
+        let both_domains = matches!(
+            (target.host(), entry_url.host()),
+            (Some(url::Host::Domain(_)), Some(url::Host::Domain(_))),
+        );
+        let subdomain = both_domains && target_host
+            .strip_suffix(entry_host)
+            .is_some_and(|prefix| prefix.len() > 1 && prefix.ends_with('.'));
+        let (target, entry) = (target_host.to_string(), entry_host.to_string());
+        return match subdomain {
+            true if policy.allow_subdomains => None,
+            true => Some(UrlMismatch::Subdomain { target, entry }),
+            false => Some(UrlMismatch::Host { target, entry }),
+        };
+    }
+
+    // `port` is `None` for the scheme's default, so an upgrade from http
+    // to https on default ports still matches
+    if policy.match_port && target.port() != entry_url.port() {
+        return Some(UrlMismatch::Port {
+            target: target.port_or_known_default().unwrap_or_default(),
+            entry: entry_url.port_or_known_default().unwrap_or_default(),
+        });
+    }
+    None
+}
+
+/// Verify the target matches the entry's stored URL under `policy`
+pub fn check_target(entry: &VaultEntry, target: &Url, policy: &DomainPolicy) -> Result<()> {
+    let entry_url = entry.url.as_deref()
+        .ok_or_else(|| anyhow!("Entry has no associated URL"))?;
+    let entry_url = parse_url(entry_url)?;
+
+    match url_mismatch(target, &entry_url, policy) {
+        Some(mismatch) => Err(mismatch.into()),
+        None => Ok(()),
+    }
+}
+
+/// Parse a pinned certificate fingerprint
+///
+/// Accepts 64 hex digits, optionally colon-separated (as printed by
+/// `openssl x509 -fingerprint -sha256`).
+pub fn parse_pin(pin: &str) -> Result<[u8; 32]> {
+    let hex: String = pin.chars().filter(|c| *c != ':').collect();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(anyhow!("Certificate pin must be a SHA-256 fingerprint (64 hex digits)"));
+    }
+
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| anyhow!("Certificate pin contains non-hex characters"))?;
+    }
+    Ok(out)
+}
+
+/// Build the outbound request with the credential injected
+///
+/// - `OAuthToken` / `ApiKey` -> `Authorization: Bearer <value>`
+/// - `Password` -> HTTP Basic with the entry's username
+pub fn build_request(entry: &VaultEntry, target: &Url) -> Result<AuthRequest> {
+    if entry.protected {
+        return Err(anyhow!("Protected entries can't be used for auth"));
+    }
+    let secret = std::str::from_utf8(&entry.value)
+        .map_err(|_| anyhow!("Credential value is not valid UTF-8"))?;
+
+    // Redirects are not followed: the caller sees the 3xx and decides,
+    // rather than the credential being replayed to wherever it points
+    let mut builder = Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(Policy::none());
+
+    let pin_mismatch = Arc::new(AtomicBool::new(false));
+    if let Some(ref pin) = entry.pinned_cert_sha256 {
+        if target.scheme() != "https" {
+            return Err(anyhow!("Certificate pin requires an https target"));
+        }
+        let config = pinned_tls_config(parse_pin(pin)?, Arc::clone(&pin_mismatch))?;
+        builder = builder.use_preconfigured_tls(config);
+    }
+    let client = builder.build()?;
+
+    let request = client.get(target.clone());
+    let request = match entry.entry_type {
+        EntryType::OAuthToken | EntryType::ApiKey => request.bearer_auth(secret),
+        EntryType::Password => {
+            let username = entry.username.as_deref()
+                .ok_or_else(|| anyhow!("Password entry has no username for basic auth"))?;
+            request.basic_auth(username, Some(secret))
+        }
+        other => return Err(anyhow!("Entry type {:?} cannot be used for auth", other)),
+    };
+
+    Ok(AuthRequest { request, pin_mismatch })
+}
+
+/// Send the request, returning only status and headers
+///
+/// Fails with [`PinMismatch`] if the pinned certificate check rejected
+/// the server during the handshake.
+pub async fn execute(request: AuthRequest, target: &Url) -> Result<AuthOutcome> {
+    let response = match request.request.send().await {
+        Ok(r) => r,
+        Err(_) if request.pin_mismatch.load(Ordering::SeqCst) => return Err(PinMismatch.into()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut headers: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in response.headers() {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        headers.entry(name.as_str().to_string())
+            .and_modify(|existing| {
+                existing.push_str(", ");
+                existing.push_str(&value);
+            })
+            .or_insert_with(|| value.into_owned());
+    }
+
+    Ok(AuthOutcome {
+        auth_performed: true,
+        target: target.to_string(),
+        status: response.status().as_u16(),
+        headers,
+    })
+}
+
+/// Tokens a refresh grant returned
+pub struct RefreshedToken {
+    pub access_token: Zeroizing<String>,
+    /// Set when the server rotates refresh tokens
+    pub refresh_token: Option<Zeroizing<String>>,
+    /// Seconds the access token is valid for, if the server said
+    pub expires_in: Option<i64>,
+}
+
+/// A non-empty custom field of `entry`
+fn field<'a>(entry: &'a VaultEntry, name: &str) -> Option<&'a str> {
+    entry.custom_fields.iter()
+        .find(|f| f.name == name)
+        .map(|f| f.value.as_str())
+        .filter(|v| !v.is_empty())
+}
+
+/// Build the refresh grant (RFC 6749 section 6) for an `OAuthToken`
+/// entry, returning it with the token endpoint
+///
+/// The endpoint must be https since the grant carries the refresh token.
+/// It needn't share the entry's host, and the entry's certificate pin
+/// (which is for that host) isn't applied.
+pub fn build_refresh(entry: &VaultEntry) -> Result<(RequestBuilder, Url)> {
+    if entry.protected {
+        return Err(anyhow!("Protected entries can't be refreshed"));
+    }
+    if entry.entry_type != EntryType::OAuthToken {
+        return Err(anyhow!("Entry type {:?} has no refresh grant", entry.entry_type));
+    }
+    let required = |name: &str| field(entry, name).ok_or_else(|| anyhow!("Entry has no {} field", name));
+    let refresh_token = required(REFRESH_TOKEN_FIELD)?;
+    let client_id = required(CLIENT_ID_FIELD)?;
+    let endpoint = parse_url(required(TOKEN_ENDPOINT_FIELD)?)?;
+    if endpoint.scheme() != "https" {
+        return Err(anyhow!("Token endpoint must be https"));
+    }
+
+    let mut form = vec![
+        ("grant_type", "refresh_token"),
+        ("refresh_token", refresh_token),
+        ("client_id", client_id),
+    ];
+    if let Some(secret) = field(entry, CLIENT_SECRET_FIELD) {
+        form.push(("client_secret", secret));
+    }
+
+    let client = Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(Policy::none())
+        .build()?;
+    let request = client.post(endpoint.clone())
+        .header(ACCEPT, "application/json")
+        .form(&form);
+    Ok((request, endpoint))
+}
+
+/// Send a refresh grant and read the tokens it returns
+pub async fn refresh(request: RequestBuilder) -> Result<RefreshedToken> {
+    let response = request.send().await?;
+    let status = response.status().as_u16();
+    if response.content_length().is_some_and(|n| n > MAX_TOKEN_RESPONSE_BYTES as u64) {
+        return Err(anyhow!("Token endpoint response is too large"));
+    }
+    let body = Zeroizing::new(response.bytes().await?.to_vec());
+    if body.len() > MAX_TOKEN_RESPONSE_BYTES {
+        return Err(anyhow!("Token endpoint response is too large"));
+    }
+    parse_token_response(status, &body)
+}
+
+/// Read a token endpoint's answer (RFC 6749 sections 5.1 and 5.2)
+fn parse_token_response(status: u16, body: &[u8]) -> Result<RefreshedToken> {
+    #[derive(Deserialize)]
+    struct TokenResponse {
+        access_token: Option<String>,
+        refresh_token: Option<String>,
+        expires_in: Option<i64>,
+        error: Option<String>,
+    }
+
+    let parsed: TokenResponse = serde_json::from_slice(body)
+        .map_err(|_| anyhow!("Token endpoint answered {} without a token response", status))?;
+    let access_token = match parsed.access_token {
+        Some(token) if (200..300).contains(&status) && !token.is_empty() => Zeroizing::new(token),
+        // The error code (e.g. "invalid_grant") is safe to pass on
+        _ => return Err(anyhow!(
+            "Token endpoint refused the refresh ({}{})",
+            status,
+            parsed.error.map(|e| format!(": {}", e)).unwrap_or_default(),
+        )),
+    };
+
+    Ok(RefreshedToken {
+        access_token,
+        refresh_token: parsed.refresh_token.filter(|t| !t.is_empty()).map(Zeroizing::new),
+        expires_in: parsed.expires_in,
+    })
+}
+
+/// TLS config that validates the chain normally and also pins the leaf
+fn pinned_tls_config(pin: [u8; 32], mismatch: Arc<AtomicBool>) -> Result<ClientConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let verifier = PinnedCertVerifier::new(pin, mismatch, Arc::clone(&provider))?;
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+
+    Ok(config)
+}
+
+/// Certificate verifier that requires the leaf to match a SHA-256 pin
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pin: [u8; 32],
+    mismatch: Arc<AtomicBool>,
+}
+
+impl PinnedCertVerifier {
+    fn new(pin: [u8; 32], mismatch: Arc<AtomicBool>, provider: Arc<CryptoProvider>) -> Result<Self> {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider)
+            .build()?;
+
+        Ok(Self { inner, pin, mismatch })
+    }
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = Sha256::digest(end_entity.as_ref());
+        if fingerprint.as_slice() != self.pin {
+            self.mismatch.store(true, Ordering::SeqCst);
+            return Err(rustls::Error::General(PinMismatch.to_string()));
+        }
+
+        self.inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vault::Category;
+
+    fn entry_with_url(url: &str) -> VaultEntry {
+        VaultEntry::new(
+            Category::Authentication,
+            EntryType::ApiKey,
+            "GitHub",
+            b"ghp_xxxxxxxxxxxx".to_vec(),
+        ).with_url(url)
+    }
+
+    #[test]
+    fn test_parse_url_defaults_to_https() {
+        let url = parse_url("github.com").unwrap();
+        assert_eq!(url.scheme(), "https");
+        assert_eq!(url.host_str(), Some("github.com"));
+
+        assert!(parse_url("ftp://github.com").is_err());
+    }
+
+    #[test]
+    fn test_check_target_host() {
+        let entry = entry_with_url("https://api.github.com/user");
+
+        let policy = DomainPolicy::default();
+        let same = parse_url("https://API.github.com/repos").unwrap();
+        assert!(check_target(&entry, &same, &policy).is_ok());
+
+        let other = parse_url("https://evil.example.com/").unwrap();
+        assert!(check_target(&entry, &other, &policy).is_err());
+
+        let no_url = VaultEntry::new(
+            Category::Authentication,
+            EntryType::ApiKey,
+            "Orphan",
+            b"key".to_vec(),
+        );
+        assert!(check_target(&no_url, &same, &policy).is_err());
+    }
+
+    #[test]
+    fn test_url_mismatch() {
+        let check = |target: &str, entry: &str, policy: DomainPolicy| {
+            url_mismatch(&parse_url(target).unwrap(), &parse_url(entry).unwrap(), &policy)
+        };
+        let strict = DomainPolicy::default();
+        let lax = DomainPolicy { allow_subdomains: true, match_port: false, require_https: false };
+
+        assert_eq!(check("https://example.com./login", "example.com", strict), None);
+        assert_eq!(check("https://example.com:443/", "https://example.com", strict), None);
+
+        // Subdomains only by policy, and never the other way round
+        assert_eq!(
+            check("https://login.example.com/", "example.com", strict),
+            Some(UrlMismatch::Subdomain { target: "login.example.com".into(), entry: "example.com".into() }),
+        );
+        assert_eq!(check("https://login.example.com/", "example.com", lax), None);
+        assert!(matches!(check("https://example.com/", "login.example.com", lax), Some(UrlMismatch::Host { .. })));
+        assert!(matches!(check("https://badexample.com/", "example.com", lax), Some(UrlMismatch::Host { .. })));
+
+        assert_eq!(
+            check("https://example.com:8443/", "https://example.com", strict),
+            Some(UrlMismatch::Port { target: 8443, entry: 443 }),
+        );
+        assert_eq!(check("https://example.com:8443/", "https://example.com", lax), None);
+
+        // Upgrading is fine; downgrading only if allowed
+        assert_eq!(check("https://example.com/", "http://example.com", strict), None);
+        assert_eq!(check("http://example.com/", "https://example.com", strict), Some(UrlMismatch::Downgrade));
+        assert_eq!(check("http://example.com/", "https://example.com", lax), None);
+    }
+
+    #[test]
+    fn test_build_request_by_entry_type() {
+        let target = parse_url("https://api.github.com/user").unwrap();
+
+        let bearer = build_request(&entry_with_url("api.github.com"), &target)
+            .unwrap().request.build().unwrap();
+        assert_eq!(
+            bearer.headers()["authorization"],
+            "Bearer ghp_xxxxxxxxxxxx",
+        );
+
+        let password = VaultEntry::new(
+            Category::Authentication,
+            EntryType::Password,
+            "GitHub",
+            b"hunter2".to_vec(),
+        );
+        assert!(build_request(&password, &target).is_err());
+
+        let basic = build_request(&password.with_username("adam"), &target)
+            .unwrap().request.build().unwrap();
+        assert!(basic.headers()["authorization"].to_str().unwrap().starts_with("Basic "));
+
+        let card = VaultEntry::new(
+            Category::Financial,
+            EntryType::Card,
+            "Visa",
+            b"4111111111111111".to_vec(),
+        );
+        assert!(build_request(&card, &target).is_err());
+    }
+
+    #[test]
+    fn test_build_refresh_grant() {
+        let token = |fields: &[(&str, &str)]| fields.iter().fold(
+            VaultEntry::new(Category::Authentication, EntryType::OAuthToken, "Google", b"ya29.old".to_vec()),
+            |entry, (name, value)| entry.with_custom_field(*name, *value, true),
+        );
+        let grant = [
+            (REFRESH_TOKEN_FIELD, "1//refresh"),
+            (TOKEN_ENDPOINT_FIELD, "https://oauth2.googleapis.com/token"),
+            (CLIENT_ID_FIELD, "client-1"),
+        ];
+
+        let (request, endpoint) = build_refresh(&token(&grant)).unwrap();
+        assert_eq!(endpoint.host_str(), Some("oauth2.googleapis.com"));
+        let request = request.build().unwrap();
+        assert_eq!(request.method(), "POST");
+        assert_eq!(request.headers()["accept"], "application/json");
+        assert!(request.headers().get("authorization").is_none());
+        let body = std::str::from_utf8(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(body, "grant_type=refresh_token&refresh_token=1%2F%2Frefresh&client_id=client-1");
+
+        let confidential = token(&[grant[0], grant[1], grant[2], (CLIENT_SECRET_FIELD, "s3cret")]);
+        let request = build_refresh(&confidential).unwrap().0.build().unwrap();
+        let body = std::str::from_utf8(request.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert!(body.ends_with("&client_secret=s3cret"));
+
+        assert!(build_refresh(&token(&grant[..2])).is_err());
+        assert!(build_refresh(&token(&[grant[0], (TOKEN_ENDPOINT_FIELD, "http://example.com/token"), grant[2]]))
+            .is_err());
+        let mut api_key = token(&grant);
+        api_key.entry_type = EntryType::ApiKey;
+        assert!(build_refresh(&api_key).is_err());
+    }
+
+    #[test]
+    fn test_parse_token_response() {
+        let ok = br#"{"access_token": "ya29.new", "expires_in": 3599, "refresh_token": "1//next", "token_type": "Bearer"}"#;
+        let refreshed = parse_token_response(200, ok).unwrap();
+        assert_eq!(refreshed.access_token.as_str(), "ya29.new");
+        assert_eq!(refreshed.refresh_token.as_deref().map(String::as_str), Some("1//next"));
+        assert_eq!(refreshed.expires_in, Some(3599));
+
+        let plain = parse_token_response(200, br#"{"access_token": "ya29.new"}"#).unwrap();
+        assert!(plain.refresh_token.is_none() && plain.expires_in.is_none());
+
+        let refused = parse_token_response(400, br#"{"error": "invalid_grant"}"#).err().unwrap();
+        assert_eq!(refused.to_string(), "Token endpoint refused the refresh (400: invalid_grant)");
+        assert!(parse_token_response(502, b"<html>Bad Gateway</html>").is_err());
+        assert!(parse_token_response(200, br#"{"access_token": ""}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_pin() {
+        let hex = "ab".repeat(32);
+        assert_eq!(parse_pin(&hex).unwrap(), [0xab; 32]);
+
+        let colons = vec!["AB"; 32].join(":");
+        assert_eq!(parse_pin(&colons).unwrap(), [0xab; 32]);
+
+        assert!(parse_pin("abcd").is_err());
+        assert!(parse_pin(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_pinned_entry_requires_https() {
+        let entry = entry_with_url("api.github.com").with_pinned_cert("ab".repeat(32));
+
+        let http = parse_url("http://api.github.com/user").unwrap();
+        assert!(build_request(&entry, &http).is_err());
+
+        let https = parse_url("https://api.github.com/user").unwrap();
+        assert!(build_request(&entry, &https).is_ok());
+    }
+
+    #[test]
+    fn test_pin_mismatch_rejects_handshake() {
+        let pinned = CertificateDer::from(b"pinned leaf".to_vec());
+        let mut pin = [0u8; 32];
+        pin.copy_from_slice(&Sha256::digest(pinned.as_ref()));
+
+        let mismatch = Arc::new(AtomicBool::new(false));
+        let provider = Arc::new(ring::default_provider());
+        let verifier = PinnedCertVerifier::new(pin, Arc::clone(&mismatch), provider).unwrap();
+
+        let attacker = CertificateDer::from(b"attacker leaf".to_vec());
+        let name = ServerName::try_from("api.github.com").unwrap();
+        let result = verifier.verify_server_cert(&attacker, &[], &name, &[], UnixTime::now());
+
+        assert!(result.is_err());
+        assert!(mismatch.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,642 @@
+//! Offline commands that work on a vault directory directly
+//!
+//! For scripting and recovery when no daemon is running: each command
+//! unlocks the vault itself, does its one thing and locks it again, with
+//! the audit log recording it as agent `cli`. A vault a running daemon has
+//! unlocked is in use, and refused.
+//!
+//! The passphrase is prompted for on the terminal or, when stdin is piped,
+//! read from its first line. `get` of a protected entry reads its extra
+//! passphrase the same way, and `create` the new value: a second prompt,
+//! or the rest of stdin. `recover` reads its shares and the new
+//! passphrase a line each in place of the old passphrase.
+
+use anyhow::{anyhow, Context, Result};
+use clap::Subcommand;
+use serde::de::DeserializeOwned;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use crate::api::AUDIT_LOG_FILE;
+#[cfg(any(feature = "import-1pif", feature = "import-kdbx"))]
+use crate::api::{NewEntryRequest, Response};
+use crate::audit::AuditLog;
+use crate::crypto::read_pepper;
+use crate::recovery::RecoveryShare;
+use crate::validate;
+use crate::vault::{AccessMode, Category, EntryMetadata, EntryType, MergeStrategy, Vault, VaultEntry};
+
+/// How the audit log names these commands
+const CLI_AGENT: &str = "cli";
+
+/// Longest passphrase line read from stdin
+const MAX_PASSPHRASE_BYTES: u64 = 4096;
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Print entry metadata (never values) as JSON
+    List {
+        /// Only this category [default: all]
+        #[arg(long, value_parser = parse_name::<Category>)]
+        category: Option<Category>,
+    },
+    /// Write an entry's value to stdout
+    Get {
+        id: Uuid,
+        /// Print the entry's metadata as JSON instead
+        #[arg(long)]
+        metadata: bool,
+    },
+    /// Add an entry, printing its id
+    Create {
+        #[arg(long, value_parser = parse_name::<Category>)]
+        category: Category,
+        /// e.g. password, api_key, secure_note
+        #[arg(long = "type", value_parser = parse_name::<EntryType>)]
+        entry_type: EntryType,
+        #[arg(long)]
+        name: String,
+        #[arg(long)]
+        username: Option<String>,
+        #[arg(long)]
+        url: Option<String>,
+    },
+    /// Rebuild a vault at --vault, which must not exist yet, from an
+    /// encrypted export (the daemon's `export`, base64-decoded)
+    ///
+    /// The export's passphrase is read and becomes the new vault's.
+    Restore {
+        export: std::path::PathBuf,
+    },
+    /// Split a new recovery key into shares, printing one per line
+    ///
+    /// Any --threshold of them can later reset a forgotten passphrase with
+    /// `recover`. Shares printed by an earlier run stop working.
+    #[command(name = "enable-recovery")]
+    EnableRecovery {
+        #[arg(long)]
+        shares: u8,
+        #[arg(long)]
+        threshold: u8,
+    },
+    /// Set a new passphrase for a vault whose passphrase is forgotten
+    ///
+    /// Reads recovery shares, one per line, until the first share's
+    /// threshold is met, then the new passphrase. Recovery is turned off
+    /// afterwards; run `enable-recovery` again for a fresh set.
+    Recover,
+    /// Merge another copy of the vault (e.g. from another device) into
+    /// this one, printing the added, updated and conflicting ids as JSON
+    ///
+    /// The other vault's passphrase is read after this one's; only this
+    /// vault is written to.
+    Merge {
+        other: std::path::PathBuf,
+        /// How to settle an entry both sides changed: latest_wins,
+        /// theirs_wins, or manual (keep ours and report it)
+        #[arg(long, value_parser = parse_name::<MergeStrategy>, default_value = "latest_wins")]
+        strategy: MergeStrategy,
+    },
+    /// Import a 1Password 1PIF export, printing the new entries' ids
+    #[cfg(feature = "import-1pif")]
+    #[command(name = "import-1pif")]
+    Import1pif {
+        /// The `.1pif` directory, or the `data.1pif` inside it
+        path: std::path::PathBuf,
+    },
+    /// Import a KeePass KDBX database, printing the new entries' ids
+    ///
+    /// Its master password is read after the vault's passphrase.
+    #[cfg(feature = "import-kdbx")]
+    #[command(name = "import-kdbx")]
+    ImportKdbx {
+        path: std::path::PathBuf,
+    },
+}
+
+/// Parse a category or entry type by its protocol name
+fn parse_name<T: DeserializeOwned>(name: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(name.to_string()))
+        .map_err(|_| format!("unknown name '{}'", name))
+}
+
+/// Run `command` against the vault at `path`, printing its output
+pub fn run(command: Command, path: &Path, pepper_file: Option<&Path>) -> Result<()> {
+    crate::crypto::init()?;
+    let output = run_with(command, path, pepper_file, &mut Input::stdin())?;
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&output)?;
+    // Only for reading on screen; a pipe gets the exact bytes
+    if stdout.is_terminal() && !output.ends_with(b"\n") {
+        writeln!(stdout)?;
+    }
+    Ok(())
+}
+
+fn run_with(
+    command: Command,
+    path: &Path,
+    pepper_file: Option<&Path>,
+    input: &mut Input,
+) -> Result<Zeroizing<Vec<u8>>> {
+    // Builds the vault rather than unlocking one
+    if let Command::Restore { ref export } = command {
+        return restore(export, path, input);
+    }
+    // Shares stand in for the passphrase
+    if let Command::Recover = command {
+        return recover(path, input);
+    }
+    let mut vault = Vault::open(path).with_context(|| format!("Can't open the vault at {:?}", path))?;
+    if vault.requires_pepper() {
+        let pepper_file = pepper_file.ok_or_else(|| anyhow!("This vault needs --pepper-file"))?;
+        vault = vault.with_pepper(Some(read_pepper(pepper_file)?));
+    }
+    let mode = match command {
+        Command::Create { .. } | Command::EnableRecovery { .. } | Command::Merge { .. } => AccessMode::ReadWrite,
+        #[cfg(feature = "import-1pif")]
+        Command::Import1pif { .. } => AccessMode::ReadWrite,
+        #[cfg(feature = "import-kdbx")]
+        Command::ImportKdbx { .. } => AccessMode::ReadWrite,
+        Command::List { .. } | Command::Get { .. } => AccessMode::ReadOnly,
+        Command::Restore { .. } => unreachable!("restored above"),
+        Command::Recover => unreachable!("recovered above"),
+    };
+
+    let passphrase = input.secret("Passphrase: ")?;
+    vault.unlock(&passphrase, mode).context("Unlock failed")?;
+    drop(passphrase);
+
+    let mut audit = match open_audit_log(&vault, path) {
+        Ok(audit) => audit,
+        Err(e) => {
+            eprintln!("warning: not audited: {:#}", e);
+            None
+        }
+    };
+    if let Some(audit) = audit.as_mut() {
+        audit.log_unlock(mode)?;
+    }
+
+    let result = execute(command, &mut vault, audit.as_mut(), input);
+
+    if let Some(audit) = audit.as_mut() {
+        audit.log_lock()?;
+    }
+    vault.lock();
+    result
+}
+
+/// Create the vault at `path` from the export in the file `export`
+fn restore(export: &Path, path: &Path, input: &mut Input) -> Result<Zeroizing<Vec<u8>>> {
+    let bytes = std::fs::read(export).with_context(|| format!("Can't read {:?}", export))?;
+    let passphrase = input.secret("Export passphrase: ")?;
+    let mut vault = Vault::import(path, &bytes, &passphrase).context("Restore failed")?;
+    vault.lock();
+    Ok(Zeroizing::new(Vec::new()))
+}
+
+/// Reset the passphrase of the vault at `path` from recovery shares
+fn recover(path: &Path, input: &mut Input) -> Result<Zeroizing<Vec<u8>>> {
+    let mut shares: Vec<RecoveryShare> = Vec::new();
+    while shares.first().is_none_or(|first| shares.len() < first.threshold as usize) {
+        let line = input.secret(&format!("Recovery share {}: ", shares.len() + 1))?;
+        if line.trim().is_empty() {
+            return Err(anyhow!("Too few recovery shares"));
+        }
+        shares.push(line.parse()?);
+    }
+    let passphrase = input.secret("New passphrase: ")?;
+    if passphrase.is_empty() {
+        return Err(anyhow!("New passphrase must not be empty"));
+    }
+
+    let mut vault = Vault::recover_passphrase(path, &shares, &passphrase).context("Recovery failed")?;
+    drop(passphrase);
+    match open_audit_log(&vault, path) {
+        Ok(Some(mut audit)) => audit.log_passphrase_change()?,
+        Ok(None) => {}
+        Err(e) => eprintln!("warning: not audited: {:#}", e),
+    }
+    vault.lock();
+    Ok(Zeroizing::new(Vec::new()))
+}
+
+/// The vault's audit log, if the daemon has set one up
+fn open_audit_log(vault: &Vault, path: &Path) -> Result<Option<AuditLog>> {
+    let Some(key) = vault.audit_key()? else {
+        return Ok(None);
+    };
+    let log = AuditLog::open(path.join(AUDIT_LOG_FILE), key)?
+        .with_nonces(vault.nonce_sequence())
+        .with_checkpoints(vault.audit_signing_key()?, vault.audit_verify_key());
+    Ok(Some(log))
+}
+
+fn execute(
+    command: Command,
+    vault: &mut Vault,
+    audit: Option<&mut AuditLog>,
+    input: &mut Input,
+) -> Result<Zeroizing<Vec<u8>>> {
+    let output = match command {
+        Command::List { category } => {
+            let categories = category.map_or_else(|| Category::all().to_vec(), |c| vec![c]);
+            let mut entries = Vec::new();
+            for cat in categories {
+                entries.extend(vault.list_entries(cat)?);
+            }
+            serde_json::to_string_pretty(&entries)? + "\n"
+        }
+        Command::Get { id, metadata } => {
+            let mut entry = vault.get_entry(&id)?.ok_or_else(|| anyhow!("No entry {}", id))?;
+            if metadata {
+                serde_json::to_string_pretty(&EntryMetadata::from(&entry))? + "\n"
+            } else {
+                // Opened read-only, so it couldn't be deleted as it's read
+                if entry.consume_on_read {
+                    return Err(anyhow!("Entry {} is deleted once read; get it through the daemon", id));
+                }
+                if entry.protected {
+                    let extra = input.secret("Entry passphrase: ")?;
+                    entry = vault.get_protected_entry(&id, &extra)?
+                        .ok_or_else(|| anyhow!("No entry {}", id))?;
+                }
+                if let Some(audit) = audit {
+                    audit.log_access(entry.id, &entry.name, entry.category, Some(CLI_AGENT), None, None)?;
+                }
+                return Ok(entry.value.clone());
+            }
+        }
+        Command::Create { category, entry_type, name, username, url } => {
+            validate::check_name(&name)
+                .and_then(|()| validate::check_category(entry_type, category))
+                .and_then(|()| url.as_deref().map_or(Ok(()), validate::check_url))
+                .map_err(|reason| anyhow!(reason))?;
+            let value = input.value("Value: ")?;
+            validate::check_value(entry_type, &value).map_err(|reason| anyhow!(reason))?;
+
+            let mut entry = VaultEntry::new(category, entry_type, name.clone(), value.to_vec());
+            entry.username = username;
+            entry.url = url;
+            let id = vault.add_entry(entry)?;
+            if let Some(audit) = audit {
+                audit.log_create(id, &name, category, Some(CLI_AGENT), None)?;
+            }
+            format!("{}\n", id)
+        }
+        Command::EnableRecovery { shares, threshold } => {
+            let shares = vault.enable_recovery(shares, threshold)?;
+            // As secret as a value, so never in an unzeroized buffer
+            let mut output = Zeroizing::new(String::new());
+            for share in &shares {
+                writeln!(output, "{}", share)?;
+            }
+            return Ok(Zeroizing::new(output.as_bytes().to_vec()));
+        }
+        Command::Merge { other, strategy } => {
+            let mut theirs = Vault::open(&other).with_context(|| format!("Can't open the vault at {:?}", other))?;
+            if theirs.requires_pepper() {
+                return Err(anyhow!("The vault at {:?} needs a pepper file, which merge can't take", other));
+            }
+            let passphrase = input.secret("Other vault's passphrase: ")?;
+            theirs.unlock(&passphrase, AccessMode::ReadOnly).context("Unlock of the other vault failed")?;
+            drop(passphrase);
+
+            let report = vault.merge(&mut theirs, strategy);
+            theirs.lock();
+            let report = report?;
+            if let Some(audit) = audit {
+                for entry in report.added.iter().filter_map(|id| vault.get_entry(id).ok().flatten()) {
+                    audit.log_create(entry.id, &entry.name, entry.category, Some(CLI_AGENT), None)?;
+                }
+                for entry in report.updated.iter().filter_map(|id| vault.get_entry(id).ok().flatten()) {
+                    audit.log_update(entry.id, &entry.name, entry.category, Some(CLI_AGENT))?;
+                }
+            }
+            serde_json::to_string_pretty(&report)? + "\n"
+        }
+        #[cfg(feature = "import-1pif")]
+        Command::Import1pif { path } => {
+            let requests = crate::import::import_1pif(&path)?;
+            import(vault, audit, requests)?
+        }
+        #[cfg(feature = "import-kdbx")]
+        Command::ImportKdbx { path } => {
+            let password = input.secret("KeePass password: ")?;
+            let requests = crate::import::import_kdbx(&path, &password)?;
+            import(vault, audit, requests)?
+        }
+        Command::Restore { .. } => unreachable!("restored before unlocking"),
+        Command::Recover => unreachable!("recovered before unlocking"),
+    };
+    Ok(Zeroizing::new(output.into_bytes()))
+}
+
+/// Add `requests` in one write, returning their ids as JSON
+#[cfg(any(feature = "import-1pif", feature = "import-kdbx"))]
+fn import(vault: &mut Vault, audit: Option<&mut AuditLog>, requests: Vec<NewEntryRequest>) -> Result<String> {
+    let entries = NewEntryRequest::into_entries(requests).map_err(|response| match response {
+        Response::Error { message, .. } => anyhow!(message),
+        Response::Ok { .. } => anyhow!("Import failed"),
+    })?;
+    let summaries: Vec<(String, Category)> = entries.iter()
+        .map(|e| (e.name.clone(), e.category))
+        .collect();
+    let ids = vault.import_entries(entries)?;
+    if let Some(audit) = audit {
+        for (id, (name, category)) in ids.iter().zip(&summaries) {
+            audit.log_create(*id, name, *category, Some(CLI_AGENT), None)?;
+        }
+    }
+    Ok(serde_json::to_string_pretty(&ids)? + "\n")
+}
+
+/// Secrets from the terminal (not echoed) or, when piped, from stdin
+struct Input {
+    reader: Box<dyn BufRead>,
+    interactive: bool,
+}
+
+impl Input {
+    fn stdin() -> Self {
+        let stdin = io::stdin();
+        let interactive = stdin.is_terminal();
+        Self { reader: Box::new(stdin.lock()), interactive }
+    }
+
+    #[cfg(test)]
+    fn piped(bytes: &[u8]) -> Self {
+        Self { reader: Box::new(io::Cursor::new(bytes.to_vec())), interactive: false }
+    }
+
+    /// One line: a prompt, or the next line of stdin
+    fn secret(&mut self, prompt: &str) -> Result<Zeroizing<String>> {
+        if self.interactive {
+            return read_hidden(prompt);
+        }
+        let mut line = Zeroizing::new(String::new());
+        (&mut self.reader).take(MAX_PASSPHRASE_BYTES + 1).read_line(&mut line)?;
+        if line.len() as u64 > MAX_PASSPHRASE_BYTES {
+            return Err(anyhow!("Passphrase longer than {} bytes", MAX_PASSPHRASE_BYTES));
+        }
+        Ok(trim_newline(line))
+    }
+
+    /// A prompt, or everything left on stdin less one trailing newline
+    fn value(&mut self, prompt: &str) -> Result<Zeroizing<Vec<u8>>> {
+        if self.interactive {
+            let line = read_hidden(prompt)?;
+            return Ok(Zeroizing::new(line.as_bytes().to_vec()));
+        }
+        let mut bytes = Zeroizing::new(Vec::new());
+        (&mut self.reader).take(validate::MAX_VALUE_BYTES as u64 + 2).read_to_end(&mut bytes)?;
+        if bytes.ends_with(b"\n") {
+            bytes.pop();
+            if bytes.ends_with(b"\r") {
+                bytes.pop();
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+fn trim_newline(mut line: Zeroizing<String>) -> Zeroizing<String> {
+    while line.ends_with(['\n', '\r']) {
+        line.pop();
+    }
+    line
+}
+
+/// Prompt on the controlling terminal and read a line without echoing it
+fn read_hidden(prompt: &str) -> Result<Zeroizing<String>> {
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")
+        .context("No terminal to prompt on; pipe the input to stdin")?;
+    tty.write_all(prompt.as_bytes())?;
+
+    let fd = tty.as_raw_fd();
+    let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut saved) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let mut hidden = saved;
+    hidden.c_lflag &= !libc::ECHO;
+    hidden.c_lflag |= libc::ECHONL;
+    if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &hidden) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    let line = read_line_unbuffered(&tty);
+    unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &saved) };
+
+    let line = line?;
+    std::str::from_utf8(&line)
+        .map(|text| trim_newline(Zeroizing::new(text.to_string())))
+        .map_err(|_| anyhow!("Input is not UTF-8"))
+}
+
+/// Read up to a newline a byte at a time, so no buffer keeps a copy
+fn read_line_unbuffered(mut tty: &File) -> Result<Zeroizing<Vec<u8>>> {
+    let mut line = Zeroizing::new(Vec::with_capacity(MAX_PASSPHRASE_BYTES as usize));
+    let mut byte = [0u8; 1];
+    while tty.read(&mut byte)? == 1 && byte[0] != b'\n' {
+        if line.len() as u64 == MAX_PASSPHRASE_BYTES {
+            return Err(anyhow!("Input longer than {} bytes", MAX_PASSPHRASE_BYTES));
+        }
+        line.push(byte[0]);
+    }
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::AuditEventType;
+    use crate::crypto::SecureKey;
+    use tempfile::TempDir;
+
+    fn run_piped(command: Command, path: &Path, stdin: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        run_with(command, path, None, &mut Input::piped(stdin))
+    }
+
+    #[cfg(feature = "import-1pif")]
+    #[test]
+    fn test_import_1pif() {
+        sodiumoxide::init().unwrap();
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("vault");
+        Vault::create(&path, "pass").unwrap();
+        let export = tmp.path().join("data.1pif");
+        std::fs::write(&export, concat!(
+            r#"{"typeName":"webforms.WebForm","title":"GitHub","location":"https://github.com","#,
+            r#""secureContents":{"fields":[{"designation":"password","value":"hunter2"}]}}"#,
+            "\n***5642bee8-a5ff-11dc-8314-0800200c9a66***\n",
+        )).unwrap();
+
+        let ids = run_piped(Command::Import1pif { path: export.clone() }, &path, b"pass\n").unwrap();
+        let ids: Vec<Uuid> = serde_json::from_slice(&ids).unwrap();
+        let value = run_piped(Command::Get { id: ids[0], metadata: false }, &path, b"pass\n").unwrap();
+        assert_eq!(value.as_slice(), b"hunter2");
+        assert!(run_piped(Command::Import1pif { path: export }, &path, b"wrong\n").is_err());
+    }
+
+    #[cfg(feature = "import-kdbx")]
+    #[test]
+    fn test_import_kdbx() {
+        sodiumoxide::init().unwrap();
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("vault");
+        Vault::create(&path, "pass").unwrap();
+        let database = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/keepass4.kdbx");
+
+        let import = || Command::ImportKdbx { path: database.clone() };
+        assert!(run_piped(import(), &path, b"pass\nwrong\n").is_err());
+        let ids = run_piped(import(), &path, b"pass\ncorrect horse\n").unwrap();
+        let ids: Vec<Uuid> = serde_json::from_slice(&ids).unwrap();
+        assert_eq!(ids.len(), 4);
+        let value = run_piped(Command::Get { id: ids[1], metadata: false }, &path, b"pass\n").unwrap();
+        assert_eq!(value.as_slice(), b"hunter2");
+    }
+
+    #[test]
+    fn test_restore() {
+        sodiumoxide::init().unwrap();
+        let tmp = TempDir::new().unwrap();
+        let mut vault = Vault::create(tmp.path().join("laptop"), "pass").unwrap();
+        let id = vault.add_entry(VaultEntry::new(
+            Category::Financial, EntryType::Password, "Bank", b"hunter2".to_vec(),
+        )).unwrap();
+        let export = tmp.path().join("backup.json");
+        std::fs::write(&export, vault.export("backup pass").unwrap()).unwrap();
+
+        let path = tmp.path().join("restored");
+        let restore = || Command::Restore { export: export.clone() };
+        assert!(run_piped(restore(), &path, b"wrong\n").is_err());
+        assert!(!path.exists());
+        run_piped(restore(), &path, b"backup pass\n").unwrap();
+        let value = run_piped(Command::Get { id, metadata: false }, &path, b"backup pass\n").unwrap();
+        assert_eq!(value.as_slice(), b"hunter2");
+
+        // Never over an existing vault
+        assert!(run_piped(restore(), &path, b"backup pass\n").is_err());
+    }
+
+    #[test]
+    fn test_recover() {
+        sodiumoxide::init().unwrap();
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("vault");
+        let mut vault = Vault::create(&path, "forgotten").unwrap();
+        let audit_key = SecureKey::generate();
+        vault.store_audit_key(&audit_key).unwrap();
+        let id = vault.add_entry(VaultEntry::new(
+            Category::Financial, EntryType::Password, "Bank", b"hunter2".to_vec(),
+        )).unwrap();
+        drop(vault);
+
+        let enable = Command::EnableRecovery { shares: 3, threshold: 2 };
+        let printed = run_piped(enable, &path, b"forgotten\n").unwrap();
+        let shares: Vec<&str> = std::str::from_utf8(&printed).unwrap().lines().collect();
+        assert_eq!(shares.len(), 3);
+
+        let stdin = |shares: &[&str], passphrase: &str| format!("{}\n{}\n", shares.join("\n"), passphrase).into_bytes();
+        assert!(run_piped(Command::Recover, &path, &stdin(&shares[..1], "")).is_err());
+        assert!(run_piped(Command::Recover, &path, &stdin(&shares[..2], "")).is_err());
+        run_piped(Command::Recover, &path, &stdin(&shares[1..], "remembered")).unwrap();
+
+        assert!(run_piped(Command::Get { id, metadata: false }, &path, b"forgotten\n").is_err());
+        let value = run_piped(Command::Get { id, metadata: false }, &path, b"remembered\n").unwrap();
+        assert_eq!(value.as_slice(), b"hunter2");
+        // Spent once the passphrase changed
+        assert!(run_piped(Command::Recover, &path, &stdin(&shares[..2], "again")).is_err());
+
+        let log = AuditLog::open(path.join(AUDIT_LOG_FILE), audit_key).unwrap();
+        assert!(log.read_all().unwrap().iter().any(|e| matches!(e.event_type, AuditEventType::PassphraseChange)));
+    }
+
+    #[test]
+    fn test_merge() {
+        sodiumoxide::init().unwrap();
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("laptop");
+        let laptop = Vault::create(&path, "pass").unwrap();
+        let audit_key = SecureKey::generate();
+        laptop.store_audit_key(&audit_key).unwrap();
+        drop(laptop);
+        let other = tmp.path().join("desktop");
+        let mut desktop = Vault::create(&other, "other pass").unwrap();
+        let id = desktop.add_entry(VaultEntry::new(
+            Category::Authentication, EntryType::Password, "GitHub", b"ghp_1".to_vec(),
+        )).unwrap();
+        drop(desktop);
+
+        let merge = || Command::Merge { other: other.clone(), strategy: MergeStrategy::LatestWins };
+        assert!(run_piped(merge(), &path, b"pass\nwrong\n").is_err());
+        let report = run_piped(merge(), &path, b"pass\nother pass\n").unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&report).unwrap();
+        assert_eq!(report["added"], serde_json::json!([id]));
+        let value = run_piped(Command::Get { id, metadata: false }, &path, b"pass\n").unwrap();
+        assert_eq!(value.as_slice(), b"ghp_1");
+
+        let log = AuditLog::open(path.join(AUDIT_LOG_FILE), audit_key).unwrap();
+        assert!(log.read_all().unwrap().iter().any(|e| matches!(e.event_type, AuditEventType::EntryCreate)
+            && e.entry_id == Some(id)));
+    }
+
+    #[test]
+    fn test_offline_commands() {
+        sodiumoxide::init().unwrap();
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("vault");
+        let mut vault = Vault::create(&path, "pass").unwrap();
+        let audit_key = SecureKey::generate();
+        vault.store_audit_key(&audit_key).unwrap();
+        let github = vault.add_entry(VaultEntry::new(
+            Category::Authentication, EntryType::Password, "GitHub", b"ghp_1".to_vec(),
+        )).unwrap();
+        vault.lock();
+        drop(vault);
+
+        let listed = run_piped(Command::List { category: None }, &path, b"pass\n").unwrap();
+        let listed: Vec<EntryMetadata> = serde_json::from_slice(&listed).unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "GitHub");
+
+        let value = run_piped(Command::Get { id: github, metadata: false }, &path, b"pass\n").unwrap();
+        assert_eq!(value.as_slice(), b"ghp_1");
+        let meta = run_piped(Command::Get { id: github, metadata: true }, &path, b"pass\n").unwrap();
+        assert!(!String::from_utf8_lossy(&meta).contains("ghp_1"));
+
+        let create = || Command::Create {
+            category: Category::Personal,
+            entry_type: EntryType::ApiKey,
+            name: "CI".to_string(),
+            username: None,
+            url: None,
+        };
+        let id = run_piped(create(), &path, b"pass\nsk-123\n").unwrap();
+        let id: Uuid = std::str::from_utf8(&id).unwrap().trim().parse().unwrap();
+        let value = run_piped(Command::Get { id, metadata: false }, &path, b"pass\n").unwrap();
+        assert_eq!(value.as_slice(), b"sk-123");
+
+        // Nothing is read or written without the passphrase
+        assert!(run_piped(Command::List { category: None }, &path, b"wrong\n").is_err());
+        assert!(run_piped(create(), &path, b"wrong\nsk-456\n").is_err());
+        assert!(run_piped(Command::Get { id: Uuid::new_v4(), metadata: false }, &path, b"pass\n").is_err());
+
+        let log = AuditLog::open(path.join(AUDIT_LOG_FILE), audit_key).unwrap();
+        let entries = log.read_all().unwrap();
+        let reads: Vec<_> = entries.iter()
+            .filter(|e| matches!(e.event_type, AuditEventType::EntryAccess))
+            .collect();
+        assert_eq!(reads.len(), 2);
+        assert!(reads.iter().all(|e| e.agent_id.as_deref() == Some(CLI_AGENT)));
+        assert!(entries.iter().any(|e| matches!(e.event_type, AuditEventType::EntryCreate)
+            && e.entry_id == Some(id)));
+    }
+}
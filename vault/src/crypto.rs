@@ -3,7 +3,8 @@
 //! Implements:
 //! - Argon2id key derivation (256 MiB memory-hard)
 //! - HKDF-SHA256 for subkey derivation
-//! - XChaCha20-Poly1305 AEAD encryption
+//! - XChaCha20-Poly1305 AEAD encryption, one-shot or streamed in frames
+//! - Ed25519 signatures
 //! - Secure memory handling
 
 use anyhow::{anyhow, Result};
@@ -12,38 +13,104 @@ use hkdf::Hkdf;
 use secrecy::{ExposeSecret, Secret};
 use sha2::Sha256;
 use sodiumoxide::crypto::aead::xchacha20poly1305_ietf::{
-    self, Key, Nonce, KEYBYTES, NONCEBYTES,
+    self, Key, Nonce, TAGBYTES, NONCEBYTES,
 };
+use sodiumoxide::crypto::sign;
 use sodiumoxide::randombytes::randombytes;
-use zeroize::Zeroize;
+use zeroize::{Zeroize, Zeroizing};
 
-use std::fs::File;
-use std::io::{Read, Write};
+use std::fs::{self, File};
+use std::io::{BufWriter, ErrorKind, Read, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
 
 // Argon2id parameters (fixed baseline per spec)
 pub const ARGON2_MEMORY_KIB: u32 = 262_144; // 256 MiB
 pub const ARGON2_ITERATIONS: u32 = 4;
 pub const ARGON2_PARALLELISM: u32 = 4;
 
+/// Argon2id cost parameters
+///
+/// Stored with whatever they protect (vault metadata, export envelopes),
+/// so a key derives the same whichever build reads it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl KdfParams {
+    /// The fixed baseline per spec
+    pub const BASELINE: Self = Self {
+        memory_kib: ARGON2_MEMORY_KIB,
+        iterations: ARGON2_ITERATIONS,
+        parallelism: ARGON2_PARALLELISM,
+    };
+
+    /// Cheap enough for a test suite to derive hundreds of keys; never
+    /// for real secrets
+    pub const FAST: Self = Self { memory_kib: 8 * 1024, iterations: 1, parallelism: 1 };
+
+    /// What new keys are derived with: the baseline, or `FAST` in tests
+    /// and builds with the `fast-kdf` feature
+    pub fn for_new_keys() -> Self {
+        if cfg!(any(test, feature = "fast-kdf")) {
+            Self::FAST
+        } else {
+            Self::BASELINE
+        }
+    }
+}
+
 // Key/nonce sizes
 pub const SALT_LEN: usize = 32;
 pub const KEY_LEN: usize = 32;
 pub const NONCE_LEN: usize = NONCEBYTES; // 24 bytes for XChaCha20
 
+/// Plaintext bytes per frame of `encrypt_stream`
+pub const STREAM_FRAME_LEN: usize = 64 * 1024;
+
+/// Leads every `encrypt_stream` output, telling it apart from `encrypt`'s
+pub const STREAM_MAGIC: &[u8; 8] = b"PVSTREAM";
+
+/// Random bytes starting each frame nonce; the rest is the frame counter
+const STREAM_PREFIX_LEN: usize = NONCE_LEN - 8;
+
+/// Counters a vault leases at a time for `encrypt_counted` nonces
+pub const NONCE_LEASE: u64 = 1 << 32;
+
+pub const VERIFY_KEY_LEN: usize = sign::PUBLICKEYBYTES;
+
 /// Secure key wrapper with auto-zeroing
-#[derive(Clone)]
+///
+/// The key lives in its own heap allocation so its address is stable and
+/// can be `mlock`ed against being paged out to swap.
 pub struct SecureKey {
-    inner: Secret<[u8; KEY_LEN]>,
+    inner: Secret<Box<[u8]>>,
+    locked: bool,
 }
 
 impl SecureKey {
-    pub fn new(key: [u8; KEY_LEN]) -> Self {
-        Self { inner: Secret::new(key) }
+    pub fn new(mut key: [u8; KEY_LEN]) -> Self {
+        let boxed: Box<[u8]> = Box::new(key);
+        key.zeroize();
+
+        let locked = memlock::lock(boxed.as_ptr(), KEY_LEN);
+        Self { inner: Secret::new(boxed), locked }
     }
 
     pub fn expose(&self) -> &[u8; KEY_LEN] {
-        self.inner.expose_secret()
+        (**self.inner.expose_secret())
+            .try_into()
+            .expect("SecureKey always holds KEY_LEN bytes")
+    }
+
+    /// Whether the key's memory is pinned in RAM (false if `mlock` failed,
+    /// typically because of `RLIMIT_MEMLOCK`)
+    pub fn is_memory_locked(&self) -> bool {
+        self.locked
     }
 
     /// Generate random key
@@ -55,9 +122,101 @@ impl SecureKey {
     }
 }
 
+impl Clone for SecureKey {
+    fn clone(&self) -> Self {
+        // Goes through `new` so the copy gets its own locked allocation
+        Self::new(*self.expose())
+    }
+}
+
+/// Constant-time, so a comparison doesn't leak how many bytes matched
+impl PartialEq for SecureKey {
+    fn eq(&self, other: &Self) -> bool {
+        ct_eq(self.expose(), other.expose())
+    }
+}
+
+impl Eq for SecureKey {}
+
 impl Drop for SecureKey {
     fn drop(&mut self) {
-        // secrecy handles zeroing, but we're explicit
+        // secrecy zeroes the bytes once this returns; the page stays
+        // resident until every key sharing it is gone
+        if self.locked {
+            memlock::unlock(self.inner.expose_secret().as_ptr(), KEY_LEN);
+        }
+    }
+}
+
+/// Page-granular `mlock` bookkeeping
+///
+/// Linux locks don't nest: one `munlock` releases a page no matter how many
+/// keys sit on it. Keys are small and share pages, so count per page and
+/// only unlock when the last one is dropped.
+mod memlock {
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Mutex, OnceLock};
+
+    static LOCKED_PAGES: OnceLock<Mutex<HashMap<usize, usize>>> = OnceLock::new();
+    static WARNED: AtomicBool = AtomicBool::new(false);
+
+    fn page_size() -> usize {
+        // SAFETY: sysconf has no preconditions
+        let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        if size > 0 { size as usize } else { 4096 }
+    }
+
+    fn pages(ptr: *const u8, len: usize) -> impl Iterator<Item = usize> {
+        let size = page_size();
+        let start = ptr as usize / size * size;
+        let end = ptr as usize + len;
+        (start..end).step_by(size)
+    }
+
+    pub fn lock(ptr: *const u8, len: usize) -> bool {
+        let mut locked = LOCKED_PAGES.get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        // SAFETY: the range is a live allocation owned by the caller
+        if unsafe { libc::mlock(ptr as *const libc::c_void, len) } != 0 {
+            if !WARNED.swap(true, Ordering::Relaxed) {
+                tracing::warn!(
+                    "mlock failed ({}); key material may be swapped to disk",
+                    std::io::Error::last_os_error()
+                );
+            }
+            return false;
+        }
+
+        for page in pages(ptr, len) {
+            *locked.entry(page).or_insert(0) += 1;
+        }
+        true
+    }
+
+    pub fn unlock(ptr: *const u8, len: usize) {
+        let mut locked = LOCKED_PAGES.get_or_init(Default::default)
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        for page in pages(ptr, len) {
+            if let Some(count) = locked.get_mut(&page) {
+                *count -= 1;
+                if *count == 0 {
+                    locked.remove(&page);
+                    // SAFETY: unlocking a page we locked; failure is harmless
+                    unsafe { libc::munlock(page as *const libc::c_void, page_size()) };
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn lock_count(ptr: *const u8) -> usize {
+        let locked = LOCKED_PAGES.get_or_init(Default::default).lock().unwrap();
+        pages(ptr, 1).next().and_then(|p| locked.get(&p).copied()).unwrap_or(0)
     }
 }
 
@@ -69,6 +228,19 @@ pub fn generate_salt() -> [u8; SALT_LEN] {
     salt
 }
 
+/// Initialize libsodium, once per process
+///
+/// `sodiumoxide::init` is idempotent but takes a lock and does work on
+/// every call; after the first this is a single load, so the hot paths
+/// (each category save and load, each audit append) can call it freely.
+pub fn init() -> Result<()> {
+    static INITIALIZED: OnceLock<bool> = OnceLock::new();
+    match *INITIALIZED.get_or_init(|| sodiumoxide::init().is_ok()) {
+        true => Ok(()),
+        false => Err(anyhow!("Failed to initialize sodiumoxide")),
+    }
+}
+
 /// Generate random nonce for XChaCha20
 pub fn generate_nonce() -> [u8; NONCE_LEN] {
     let bytes = randombytes(NONCE_LEN);
@@ -77,16 +249,23 @@ pub fn generate_nonce() -> [u8; NONCE_LEN] {
     nonce
 }
 
+/// Compare secrets (keys, tokens) in time independent of their contents
+///
+/// Only the lengths may show; slices of different lengths are unequal.
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    sodiumoxide::utils::memcmp(a, b)
+}
+
 /// Derive master key from passphrase using Argon2id
 /// 
 /// This is the expensive operation (~1 second on baseline hardware)
 /// that protects against brute-force attacks.
-pub fn derive_master_key(passphrase: &str, salt: &[u8; SALT_LEN]) -> Result<SecureKey> {
-    // Build Argon2id with our parameters
+pub fn derive_master_key(passphrase: &str, salt: &[u8; SALT_LEN], kdf: &KdfParams) -> Result<SecureKey> {
+    // Build Argon2id with the given parameters
     let params = Params::new(
-        ARGON2_MEMORY_KIB,
-        ARGON2_ITERATIONS,
-        ARGON2_PARALLELISM,
+        kdf.memory_kib,
+        kdf.iterations,
+        kdf.parallelism,
         Some(KEY_LEN),
     ).map_err(|e| anyhow!("Invalid Argon2 params: {}", e))?;
 
@@ -115,14 +294,112 @@ pub fn derive_subkey(master: &SecureKey, context: &str) -> SecureKey {
     SecureKey::new(okm)
 }
 
+/// Like `derive_subkey`, with `salt` as the HKDF salt
+///
+/// Used to mix a second factor into a key: the result depends on both
+/// `master` and `salt`, and neither alone reveals it.
+pub fn derive_subkey_salted(master: &SecureKey, salt: &[u8], context: &str) -> SecureKey {
+    let hk = Hkdf::<Sha256>::new(Some(salt), master.expose());
+    let mut okm = [0u8; KEY_LEN];
+    hk.expand(context.as_bytes(), &mut okm)
+        .expect("HKDF expand should never fail with 32-byte output");
+    SecureKey::new(okm)
+}
+
+/// Shortest pepper file accepted, in bytes
+pub const MIN_PEPPER_LEN: usize = 16;
+
+/// A required pepper couldn't be read; distinct from a wrong passphrase
+#[derive(Debug)]
+pub struct PepperUnavailable(pub String);
+
+impl std::fmt::Display for PepperUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pepper required: {}", self.0)
+    }
+}
+
+impl std::error::Error for PepperUnavailable {}
+
+/// Read a pepper: a secret kept outside the vault directory (e.g. on a
+/// USB key) and mixed into the KEK
+///
+/// Any bytes will do, such as `head -c 32 /dev/urandom`; they are hashed
+/// down to a key, so only their length (`MIN_PEPPER_LEN`) is checked.
+pub fn read_pepper(path: &Path) -> Result<SecureKey> {
+    let bytes = Zeroizing::new(fs::read(path)
+        .map_err(|e| PepperUnavailable(format!("can't read {}: {}", path.display(), e)))?);
+    if bytes.len() < MIN_PEPPER_LEN {
+        return Err(PepperUnavailable(format!(
+            "{} holds fewer than {} bytes",
+            path.display(),
+            MIN_PEPPER_LEN
+        )).into());
+    }
+
+    let hk = Hkdf::<Sha256>::new(None, &bytes);
+    let mut okm = [0u8; KEY_LEN];
+    hk.expand(b"pepper", &mut okm)
+        .expect("HKDF expand should never fail with 32-byte output");
+    Ok(SecureKey::new(okm))
+}
+
 /// Encrypt plaintext using XChaCha20-Poly1305
 /// 
+/// `aad` is authenticated but not stored; the same bytes must be passed
+/// to `decrypt`. An empty slice is equivalent to no associated data.
+/// 
 /// Returns: nonce (24 bytes) || ciphertext || tag (16 bytes)
-pub fn encrypt(plaintext: &[u8], key: &SecureKey) -> Result<Vec<u8>> {
-    // Initialize sodiumoxide (safe to call multiple times)
-    sodiumoxide::init().map_err(|_| anyhow!("Failed to initialize sodiumoxide"))?;
+pub fn encrypt(plaintext: &[u8], key: &SecureKey, aad: &[u8]) -> Result<Vec<u8>> {
+    seal_with_nonce(plaintext, key, aad, generate_nonce())
+}
+
+/// A block of nonce counters leased from the vault metadata
+///
+/// See `VaultMeta::nonce_counter`; shared by everything a vault writes.
+#[derive(Debug)]
+pub struct NonceSequence {
+    next: AtomicU64,
+    end: u64,
+}
+
+impl NonceSequence {
+    /// Counters `start` up to (not including) `end`
+    pub fn new(start: u64, end: u64) -> Self {
+        Self { next: AtomicU64::new(start), end }
+    }
+
+    /// The next unused counter, or `None` once the block is used up
+    fn take(&self) -> Option<u64> {
+        self.next.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| (n < self.end).then_some(n + 1))
+            .ok()
+    }
+}
+
+/// `encrypt` for keys that seal over and over (category files, the audit
+/// log): the nonce is a counter from `nonces` followed by random bytes
+///
+/// Counters never repeat within a vault, so nonces stay unique however
+/// poor the RNG is. If the counter is ever rolled back (a restored
+/// `vault.meta`, two writers), 128 random bits still keep collisions
+/// out of reach. With no sequence, or one used up, the nonce is fully
+/// random like `encrypt`'s. `decrypt` reads either kind.
+pub fn encrypt_counted(
+    plaintext: &[u8],
+    key: &SecureKey,
+    aad: &[u8],
+    nonces: Option<&NonceSequence>,
+) -> Result<Vec<u8>> {
+    let mut nonce = generate_nonce();
+    if let Some(counter) = nonces.and_then(NonceSequence::take) {
+        nonce[..8].copy_from_slice(&counter.to_be_bytes());
+    }
+    seal_with_nonce(plaintext, key, aad, nonce)
+}
+
+fn seal_with_nonce(plaintext: &[u8], key: &SecureKey, aad: &[u8], nonce_bytes: [u8; NONCE_LEN]) -> Result<Vec<u8>> {
+    init()?;
 
-    let nonce_bytes = generate_nonce();
     let nonce = Nonce::from_slice(&nonce_bytes)
         .ok_or_else(|| anyhow!("Invalid nonce"))?;
     
@@ -130,7 +407,7 @@ pub fn encrypt(plaintext: &[u8], key: &SecureKey) -> Result<Vec<u8>> {
         .ok_or_else(|| anyhow!("Invalid key"))?;
 
     // Seal: encrypt and authenticate
-    let ciphertext = xchacha20poly1305_ietf::seal(plaintext, None, &nonce, &key);
+    let ciphertext = xchacha20poly1305_ietf::seal(plaintext, Some(aad), &nonce, &key);
 
     // Prepend nonce to ciphertext
     let mut output = Vec::with_capacity(NONCE_LEN + ciphertext.len());
@@ -143,9 +420,10 @@ pub fn encrypt(plaintext: &[u8], key: &SecureKey) -> Result<Vec<u8>> {
 /// Decrypt ciphertext using XChaCha20-Poly1305
 /// 
 /// Input format: nonce (24 bytes) || ciphertext || tag (16 bytes)
-pub fn decrypt(ciphertext: &[u8], key: &SecureKey) -> Result<Vec<u8>> {
-    // Initialize sodiumoxide
-    sodiumoxide::init().map_err(|_| anyhow!("Failed to initialize sodiumoxide"))?;
+///
+/// The plaintext is wiped when the returned buffer is dropped.
+pub fn decrypt(ciphertext: &[u8], key: &SecureKey, aad: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    init()?;
 
     // Minimum size: nonce + tag
     if ciphertext.len() < NONCE_LEN + 16 {
@@ -159,25 +437,242 @@ pub fn decrypt(ciphertext: &[u8], key: &SecureKey) -> Result<Vec<u8>> {
         .ok_or_else(|| anyhow!("Invalid key"))?;
 
     // Open: decrypt and verify
-    xchacha20poly1305_ietf::open(&ciphertext[NONCE_LEN..], None, &nonce, &key)
-        .map_err(|_| anyhow!("Decryption failed: invalid key or tampered data"))
+    xchacha20poly1305_ietf::open(&ciphertext[NONCE_LEN..], Some(aad), &nonce, &key)
+        .map(Zeroizing::new)
+        .map_err(|_| DecryptionFailed.into())
+}
+
+/// Authentication failed: wrong key, wrong AAD, or tampered ciphertext
+#[derive(Debug)]
+pub struct DecryptionFailed;
+
+impl std::fmt::Display for DecryptionFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Decryption failed: invalid key or tampered data")
+    }
+}
+
+impl std::error::Error for DecryptionFailed {}
+
+/// Ed25519 secret key (sodiumoxide wipes it on drop)
+#[derive(Clone)]
+pub struct SigningKey(sign::SecretKey);
+
+impl SigningKey {
+    /// A fresh key pair, as the secret key and its public verify key
+    pub fn generate() -> Result<(Self, [u8; VERIFY_KEY_LEN])> {
+        init()?;
+        let (public, secret) = sign::gen_keypair();
+        Ok((Self(secret), public.0))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        sign::SecretKey::from_slice(bytes)
+            .map(Self)
+            .ok_or_else(|| anyhow!("Signing key has wrong length {}", bytes.len()))
+    }
+
+    pub fn expose(&self) -> &[u8] {
+        &self.0.0
+    }
+
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        sign::sign_detached(message, &self.0).to_bytes().to_vec()
+    }
+}
+
+/// Whether `signature` is `verify_key`'s signature over `message`
+pub fn verify_signature(verify_key: &[u8; VERIFY_KEY_LEN], message: &[u8], signature: &[u8]) -> bool {
+    match sign::Signature::from_bytes(signature) {
+        Ok(signature) => sign::verify_detached(&signature, message, &sign::PublicKey(*verify_key)),
+        Err(_) => false,
+    }
+}
+
+/// Encrypt everything `reader` yields into `writer`, a frame at a time
+///
+/// For data too large to hold in memory at once; `encrypt` stays the
+/// format for small blobs. Output: `STREAM_MAGIC` || nonce prefix, then
+/// frames of length (u32 BE) || ciphertext || tag, each holding up to
+/// `STREAM_FRAME_LEN` bytes. Frame nonces are the prefix and a counter,
+/// so frames can't be reordered, and an empty frame flagged as final
+/// (in its associated data) ends the stream, so cutting it short is
+/// detected. Returns the number of plaintext bytes.
+pub fn encrypt_stream(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    key: &SecureKey,
+    aad: &[u8],
+) -> Result<u64> {
+    init()?;
+    let key = Key::from_slice(key.expose())
+        .ok_or_else(|| anyhow!("Invalid key"))?;
+
+    let prefix = randombytes(STREAM_PREFIX_LEN);
+    writer.write_all(STREAM_MAGIC)?;
+    writer.write_all(&prefix)?;
+
+    let mut plaintext = Zeroizing::new(vec![0u8; STREAM_FRAME_LEN]);
+    let mut total = 0u64;
+    for counter in 0u64.. {
+        let len = read_full(&mut reader, &mut plaintext)?;
+        let last = len == 0;
+        let nonce = frame_nonce(&prefix, counter);
+        let sealed = xchacha20poly1305_ietf::seal(&plaintext[..len], Some(&frame_aad(aad, last)), &nonce, &key);
+        writer.write_all(&(sealed.len() as u32).to_be_bytes())?;
+        writer.write_all(&sealed)?;
+        total += len as u64;
+        if last {
+            break;
+        }
+    }
+    writer.flush()?;
+    Ok(total)
+}
+
+/// Decrypt an `encrypt_stream` output from `reader` into `writer`
+///
+/// Frames are written out as they verify, so after an error `writer`
+/// holds a prefix of the plaintext that must be thrown away. Missing
+/// the final frame, or anything after it, is a `DecryptionFailed`.
+/// Returns the number of plaintext bytes.
+pub fn decrypt_stream(
+    mut reader: impl Read,
+    mut writer: impl Write,
+    key: &SecureKey,
+    aad: &[u8],
+) -> Result<u64> {
+    init()?;
+    let key = Key::from_slice(key.expose())
+        .ok_or_else(|| anyhow!("Invalid key"))?;
+
+    let mut header = [0u8; STREAM_MAGIC.len() + STREAM_PREFIX_LEN];
+    if read_full(&mut reader, &mut header)? < header.len() || header[..STREAM_MAGIC.len()] != STREAM_MAGIC[..] {
+        return Err(anyhow!("Not an encrypted stream"));
+    }
+    let prefix = &header[STREAM_MAGIC.len()..];
+
+    let mut sealed = vec![0u8; STREAM_FRAME_LEN + TAGBYTES];
+    let mut total = 0u64;
+    for counter in 0u64.. {
+        let mut len = [0u8; 4];
+        if read_full(&mut reader, &mut len)? < len.len() {
+            // Truncated
+            return Err(DecryptionFailed.into());
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if !(TAGBYTES..=sealed.len()).contains(&len) || read_full(&mut reader, &mut sealed[..len])? < len {
+            return Err(DecryptionFailed.into());
+        }
+
+        // Only the final frame is empty
+        let last = len == TAGBYTES;
+        let nonce = frame_nonce(prefix, counter);
+        let plaintext = xchacha20poly1305_ietf::open(&sealed[..len], Some(&frame_aad(aad, last)), &nonce, &key)
+            .map(Zeroizing::new)
+            .map_err(|_| DecryptionFailed)?;
+        if last {
+            break;
+        }
+        writer.write_all(&plaintext)?;
+        total += plaintext.len() as u64;
+    }
+
+    if read_full(&mut reader, &mut [0u8; 1])? > 0 {
+        return Err(DecryptionFailed.into());
+    }
+    writer.flush()?;
+    Ok(total)
+}
+
+/// Nonce of frame `counter`: the stream's random prefix, then the counter
+fn frame_nonce(prefix: &[u8], counter: u64) -> Nonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce[..STREAM_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[STREAM_PREFIX_LEN..].copy_from_slice(&counter.to_be_bytes());
+    Nonce(nonce)
+}
+
+/// A frame's associated data: the caller's, then whether it ends the stream
+fn frame_aad(aad: &[u8], last: bool) -> Vec<u8> {
+    let mut frame_aad = aad.to_vec();
+    frame_aad.push(last as u8);
+    frame_aad
+}
+
+/// Fill `buf` unless `reader` ends first; returns the bytes read
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
 }
 
 /// Save data encrypted to file
-pub fn save_encrypted(path: &Path, data: &[u8], key: &SecureKey) -> Result<()> {
-    let encrypted = encrypt(data, key)?;
-    let mut file = File::create(path)?;
-    file.write_all(&encrypted)?;
+pub fn save_encrypted(path: &Path, data: &[u8], key: &SecureKey, aad: &[u8]) -> Result<()> {
+    let encrypted = encrypt(data, key, aad)?;
+    write_atomic(path, &encrypted)
+}
+
+/// Replace `path` with `data` so readers see the old or new file, never a
+/// truncated one
+///
+/// Writes `<path>.tmp`, fsyncs it, renames it over `path` (atomic within a
+/// filesystem), then fsyncs the directory so the rename survives a crash.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    write_atomic_with(path, |file| Ok(file.write_all(data)?))
+}
+
+/// `write_atomic` with the contents written by `fill`
+///
+/// If `fill` fails, the temporary file is removed and `path` is untouched.
+fn write_atomic_with<T>(path: &Path, fill: impl FnOnce(&mut File) -> Result<T>) -> Result<T> {
+    let mut tmp_name = path.file_name()
+        .ok_or_else(|| anyhow!("Not a file path: {:?}", path))?
+        .to_os_string();
+    tmp_name.push(".tmp");
+    let tmp = path.with_file_name(tmp_name);
+
+    let mut file = File::create(&tmp)?;
+    let out = match fill(&mut file) {
+        Ok(out) => out,
+        Err(e) => {
+            drop(file);
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+    };
     file.sync_all()?;
+    drop(file);
+
+    fs::rename(&tmp, path)?;
+    sync_dir(path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")))?;
+    Ok(out)
+}
+
+/// Stream `reader` encrypted (`encrypt_stream`) into a file, atomically
+pub fn save_encrypted_stream(path: &Path, reader: impl Read, key: &SecureKey, aad: &[u8]) -> Result<u64> {
+    write_atomic_with(path, |file| encrypt_stream(reader, BufWriter::new(file), key, aad))
+}
+
+/// fsync a directory so renames and creations inside it are durable
+pub fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()?;
     Ok(())
 }
 
 /// Load and decrypt data from file
-pub fn load_encrypted(path: &Path, key: &SecureKey) -> Result<Vec<u8>> {
+pub fn load_encrypted(path: &Path, key: &SecureKey, aad: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
     let mut file = File::open(path)?;
     let mut ciphertext = Vec::new();
     file.read_to_end(&mut ciphertext)?;
-    decrypt(&ciphertext, key)
+    decrypt(&ciphertext, key, aad)
 }
 
 #[cfg(test)]
@@ -187,9 +682,9 @@ mod tests {
     #[test]
     fn test_key_derivation() {
         let salt = generate_salt();
-        let key1 = derive_master_key("test passphrase", &salt).unwrap();
-        let key2 = derive_master_key("test passphrase", &salt).unwrap();
-        let key3 = derive_master_key("different passphrase", &salt).unwrap();
+        let key1 = derive_master_key("test passphrase", &salt, &KdfParams::for_new_keys()).unwrap();
+        let key2 = derive_master_key("test passphrase", &salt, &KdfParams::for_new_keys()).unwrap();
+        let key3 = derive_master_key("different passphrase", &salt, &KdfParams::for_new_keys()).unwrap();
 
         // Same passphrase + salt = same key
         assert_eq!(key1.expose(), key2.expose());
@@ -197,10 +692,23 @@ mod tests {
         assert_ne!(key1.expose(), key3.expose());
     }
 
+    #[test]
+    fn test_constant_time_comparison() {
+        assert!(ct_eq(b"token", b"token"));
+        assert!(!ct_eq(b"token", b"tokem"));
+        assert!(!ct_eq(b"token", b"token2"));
+        assert!(ct_eq(b"", b""));
+
+        let key = SecureKey::generate();
+        assert!(key == key.clone());
+        assert!(key != SecureKey::generate());
+        assert!(SecureKey::new([7; KEY_LEN]) == SecureKey::new([7; KEY_LEN]));
+    }
+
     #[test]
     fn test_subkey_derivation() {
         let salt = generate_salt();
-        let master = derive_master_key("test", &salt).unwrap();
+        let master = derive_master_key("test", &salt, &KdfParams::for_new_keys()).unwrap();
 
         let kek = derive_subkey(&master, "kek");
         let meta = derive_subkey(&master, "meta");
@@ -212,13 +720,32 @@ mod tests {
         assert_eq!(kek.expose(), kek2.expose());
     }
 
+    #[test]
+    fn test_key_memory_lock_is_refcounted() {
+        let a = SecureKey::generate();
+        if !a.is_memory_locked() {
+            // RLIMIT_MEMLOCK too low in this environment; nothing to check
+            return;
+        }
+        let ptr = a.expose().as_ptr();
+
+        let b = a.clone();
+        assert!(b.is_memory_locked());
+        assert_eq!(b.expose(), a.expose());
+        assert_ne!(b.expose().as_ptr(), ptr);
+
+        // Dropping a neighbour must not unlock the page under `a`
+        drop(b);
+        assert!(memlock::lock_count(ptr) > 0);
+    }
+
     #[test]
     fn test_encrypt_decrypt_roundtrip() {
         let key = SecureKey::generate();
         let plaintext = b"Hello, Prosperity!";
 
-        let ciphertext = encrypt(plaintext, &key).unwrap();
-        let decrypted = decrypt(&ciphertext, &key).unwrap();
+        let ciphertext = encrypt(plaintext, &key, b"").unwrap();
+        let decrypted = decrypt(&ciphertext, &key, b"").unwrap();
 
         assert_eq!(plaintext.as_slice(), decrypted.as_slice());
     }
@@ -229,8 +756,8 @@ mod tests {
         let key2 = SecureKey::generate();
         let plaintext = b"secret data";
 
-        let ciphertext = encrypt(plaintext, &key1).unwrap();
-        let result = decrypt(&ciphertext, &key2);
+        let ciphertext = encrypt(plaintext, &key1, b"").unwrap();
+        let result = decrypt(&ciphertext, &key2, b"");
 
         assert!(result.is_err());
     }
@@ -240,13 +767,136 @@ mod tests {
         let key = SecureKey::generate();
         let plaintext = b"secret data";
 
-        let mut ciphertext = encrypt(plaintext, &key).unwrap();
+        let mut ciphertext = encrypt(plaintext, &key, b"").unwrap();
         // Tamper with the data
         if let Some(byte) = ciphertext.last_mut() {
             *byte ^= 0xFF;
         }
 
-        let result = decrypt(&ciphertext, &key);
+        let result = decrypt(&ciphertext, &key, b"");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_write_atomic_replaces_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("data.enc");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+
+        // Nothing left behind next to the target
+        let names: Vec<_> = fs::read_dir(dir.path()).unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec![std::ffi::OsString::from("data.enc")]);
+    }
+
+    #[test]
+    fn test_mismatched_aad_fails() {
+        let key = SecureKey::generate();
+        let plaintext = b"secret data";
+
+        let ciphertext = encrypt(plaintext, &key, b"financial.enc").unwrap();
+        assert!(decrypt(&ciphertext, &key, b"health.enc").is_err());
+        assert!(decrypt(&ciphertext, &key, b"").is_err());
+        assert_eq!(
+            *decrypt(&ciphertext, &key, b"financial.enc").unwrap(),
+            plaintext.as_slice()
+        );
+
+        // Empty AAD matches data sealed before AAD was threaded through
+        let legacy = encrypt(plaintext, &key, b"").unwrap();
+        let nonce = Nonce::from_slice(&legacy[..NONCE_LEN]).unwrap();
+        let k = Key::from_slice(key.expose()).unwrap();
+        assert!(xchacha20poly1305_ietf::open(&legacy[NONCE_LEN..], None, &nonce, &k).is_ok());
+    }
+
+    #[test]
+    fn test_counted_nonces() {
+        let key = SecureKey::generate();
+        let nonces = NonceSequence::new(7, 9);
+        let counter = |sealed: &[u8]| u64::from_be_bytes(sealed[..8].try_into().unwrap());
+
+        let first = encrypt_counted(b"a", &key, b"", Some(&nonces)).unwrap();
+        let second = encrypt_counted(b"a", &key, b"", Some(&nonces)).unwrap();
+        assert_eq!((counter(&first), counter(&second)), (7, 8));
+        assert_ne!(first[8..NONCE_LEN], second[8..NONCE_LEN]);
+        assert_eq!(*decrypt(&second, &key, b"").unwrap(), b"a");
+
+        // Used up: random, like `encrypt`
+        let third = encrypt_counted(b"a", &key, b"", Some(&nonces)).unwrap();
+        assert_eq!(nonces.take(), None);
+        assert_eq!(*decrypt(&third, &key, b"").unwrap(), b"a");
+    }
+
+    #[test]
+    fn test_signatures() {
+        let (key, verify_key) = SigningKey::generate().unwrap();
+        let signature = key.sign(b"checkpoint");
+        assert!(verify_signature(&verify_key, b"checkpoint", &signature));
+        assert!(!verify_signature(&verify_key, b"checkpoinT", &signature));
+        assert!(!verify_signature(&verify_key, b"checkpoint", &signature[1..]));
+
+        let restored = SigningKey::from_bytes(key.expose()).unwrap();
+        assert!(verify_signature(&verify_key, b"again", &restored.sign(b"again")));
+        let (_, other) = SigningKey::generate().unwrap();
+        assert!(!verify_signature(&other, b"checkpoint", &signature));
+        assert!(SigningKey::from_bytes(&[0u8; 3]).is_err());
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let key = SecureKey::generate();
+        for len in [0, 1, STREAM_FRAME_LEN, STREAM_FRAME_LEN + 1, 3 * STREAM_FRAME_LEN + 17] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let mut sealed = Vec::new();
+            assert_eq!(encrypt_stream(&plaintext[..], &mut sealed, &key, b"att").unwrap(), len as u64);
+            assert!(sealed.starts_with(STREAM_MAGIC));
+
+            let mut opened = Vec::new();
+            assert_eq!(decrypt_stream(&sealed[..], &mut opened, &key, b"att").unwrap(), len as u64);
+            assert_eq!(opened, plaintext);
+            assert!(decrypt_stream(&sealed[..], Vec::new(), &key, b"other").is_err());
+            assert!(decrypt_stream(&sealed[..], Vec::new(), &SecureKey::generate(), b"att").is_err());
+        }
+    }
+
+    #[test]
+    fn test_stream_tampering_detected() {
+        let key = SecureKey::generate();
+        let plaintext = vec![7u8; 2 * STREAM_FRAME_LEN + 5];
+        let mut sealed = Vec::new();
+        encrypt_stream(&plaintext[..], &mut sealed, &key, b"").unwrap();
+        let open = |bytes: &[u8]| decrypt_stream(bytes, Vec::new(), &key, b"");
+        let failed = |bytes: &[u8]| open(bytes).unwrap_err().downcast_ref::<DecryptionFailed>().is_some();
+
+        // Dropping the final frame, or cutting mid-frame
+        let final_frame = 4 + TAGBYTES;
+        assert!(failed(&sealed[..sealed.len() - final_frame]));
+        assert!(failed(&sealed[..sealed.len() - final_frame - 3]));
+        // Trailing data, and a flipped bit
+        let mut longer = sealed.clone();
+        longer.push(0);
+        assert!(failed(&longer));
+        let mut flipped = sealed.clone();
+        flipped[STREAM_MAGIC.len() + STREAM_PREFIX_LEN + 10] ^= 1;
+        assert!(failed(&flipped));
+
+        // Swapping the first two (full-size) frames
+        let header = STREAM_MAGIC.len() + STREAM_PREFIX_LEN;
+        let frame = 4 + STREAM_FRAME_LEN + TAGBYTES;
+        let mut swapped = sealed[..header].to_vec();
+        swapped.extend_from_slice(&sealed[header + frame..header + 2 * frame]);
+        swapped.extend_from_slice(&sealed[header..header + frame]);
+        swapped.extend_from_slice(&sealed[header + 2 * frame..]);
+        assert!(failed(&swapped));
+
+        // An empty plaintext still has its final frame to check
+        let mut empty = Vec::new();
+        encrypt_stream(&b""[..], &mut empty, &key, b"").unwrap();
+        assert!(failed(&empty[..empty.len() - final_frame]));
+        assert!(open(&empty).is_ok());
+    }
 }
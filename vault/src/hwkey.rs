@@ -0,0 +1,363 @@
+//! Hardware security key second factor (FIDO2 `hmac-secret`)
+//!
+//! An enrolled authenticator holds a credential created with the
+//! `hmac-secret` extension. At unlock the vault sends it a stored salt and
+//! gets back a 32-byte secret that only that key can produce; the secret is
+//! mixed into the KEK, so the passphrase alone no longer opens the DEK.
+//!
+//! The authenticator is driven through libfido2, which handles the CTAP2
+//! transport and key agreement for us. The library is loaded by its soname
+//! (`libfido2.so.1`, `libfido2.1.dylib` on macOS) the first time a key is
+//! needed, so it's a runtime dependency of hardware keys alone: package
+//! `libfido2-1` on Debian and Ubuntu, `libfido2` on Fedora and Homebrew.
+//! Without it, enrolling and unlocking an enrolled vault fail with
+//! `HardwareKeyUnavailable`. Keys with a PIN set aren't supported; the
+//! daemon has no one to ask for it.
+
+use anyhow::{anyhow, Result};
+use sodiumoxide::randombytes::randombytes;
+use zeroize::Zeroizing;
+
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::sync::OnceLock;
+
+use crate::crypto::{SecureKey, KEY_LEN, SALT_LEN};
+
+/// Relying party the vault's credentials are scoped to
+pub const RP_ID: &str = "prosperity-vault";
+
+/// The authenticator was missing or refused; distinct from a wrong passphrase
+#[derive(Debug)]
+pub struct HardwareKeyUnavailable(pub String);
+
+impl std::fmt::Display for HardwareKeyUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Hardware key required: {}", self.0)
+    }
+}
+
+impl std::error::Error for HardwareKeyUnavailable {}
+
+/// An authenticator supporting the `hmac-secret` extension
+pub trait HmacSecretDevice: Send + Sync {
+    /// Create a new credential with `hmac-secret` enabled, returning its id
+    fn make_credential(&self) -> Result<Vec<u8>>;
+
+    /// The credential's secret for `salt` (requires a touch)
+    fn hmac_secret(&self, credential_id: &[u8], salt: &[u8; SALT_LEN]) -> Result<SecureKey>;
+}
+
+#[cfg(target_os = "macos")]
+const LIBFIDO2: &CStr = c"libfido2.1.dylib";
+#[cfg(not(target_os = "macos"))]
+const LIBFIDO2: &CStr = c"libfido2.so.1";
+
+/// `FIDO_OK`
+const FIDO_OK: c_int = 0;
+/// `COSE_ES256`, the credential algorithm every authenticator supports
+const COSE_ES256: c_int = -7;
+/// `FIDO_EXT_HMAC_SECRET`
+const FIDO_EXT_HMAC_SECRET: c_int = 0x01;
+/// Authenticators looked for; the first one found is used
+const MAX_DEVICES: usize = 64;
+
+type Handle = *mut c_void;
+
+/// The parts of libfido2's API the vault uses, resolved from the library
+struct Fido2Lib {
+    strerr: unsafe extern "C" fn(c_int) -> *const c_char,
+    dev_info_new: unsafe extern "C" fn(usize) -> Handle,
+    dev_info_free: unsafe extern "C" fn(*mut Handle, usize),
+    dev_info_manifest: unsafe extern "C" fn(Handle, usize, *mut usize) -> c_int,
+    dev_info_ptr: unsafe extern "C" fn(Handle, usize) -> Handle,
+    dev_info_path: unsafe extern "C" fn(Handle) -> *const c_char,
+    dev_new: unsafe extern "C" fn() -> Handle,
+    dev_open: unsafe extern "C" fn(Handle, *const c_char) -> c_int,
+    dev_close: unsafe extern "C" fn(Handle) -> c_int,
+    dev_free: unsafe extern "C" fn(*mut Handle),
+    dev_make_cred: unsafe extern "C" fn(Handle, Handle, *const c_char) -> c_int,
+    dev_get_assert: unsafe extern "C" fn(Handle, Handle, *const c_char) -> c_int,
+    cred_new: unsafe extern "C" fn() -> Handle,
+    cred_free: unsafe extern "C" fn(*mut Handle),
+    cred_set_type: unsafe extern "C" fn(Handle, c_int) -> c_int,
+    cred_set_clientdata_hash: unsafe extern "C" fn(Handle, *const u8, usize) -> c_int,
+    cred_set_rp: unsafe extern "C" fn(Handle, *const c_char, *const c_char) -> c_int,
+    cred_set_user: unsafe extern "C" fn(
+        Handle, *const u8, usize, *const c_char, *const c_char, *const c_char,
+    ) -> c_int,
+    cred_set_extensions: unsafe extern "C" fn(Handle, c_int) -> c_int,
+    cred_id_ptr: unsafe extern "C" fn(Handle) -> *const u8,
+    cred_id_len: unsafe extern "C" fn(Handle) -> usize,
+    assert_new: unsafe extern "C" fn() -> Handle,
+    assert_free: unsafe extern "C" fn(*mut Handle),
+    assert_set_clientdata_hash: unsafe extern "C" fn(Handle, *const u8, usize) -> c_int,
+    assert_set_rp: unsafe extern "C" fn(Handle, *const c_char) -> c_int,
+    assert_allow_cred: unsafe extern "C" fn(Handle, *const u8, usize) -> c_int,
+    assert_set_extensions: unsafe extern "C" fn(Handle, c_int) -> c_int,
+    assert_set_hmac_salt: unsafe extern "C" fn(Handle, *const u8, usize) -> c_int,
+    assert_count: unsafe extern "C" fn(Handle) -> usize,
+    assert_hmac_secret_ptr: unsafe extern "C" fn(Handle, usize) -> *const u8,
+    assert_hmac_secret_len: unsafe extern "C" fn(Handle, usize) -> usize,
+}
+
+impl Fido2Lib {
+    /// Load `name` and resolve every function, or say why not
+    fn load(name: &CStr) -> std::result::Result<Self, String> {
+        // Never unloaded: the functions are used for the process's lifetime
+        let handle = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            return Err(format!("cannot load {}; is libfido2 installed?", name.to_string_lossy()));
+        }
+        let lib = Fido2Lib {
+            strerr: symbol(handle, c"fido_strerr")?,
+            dev_info_new: symbol(handle, c"fido_dev_info_new")?,
+            dev_info_free: symbol(handle, c"fido_dev_info_free")?,
+            dev_info_manifest: symbol(handle, c"fido_dev_info_manifest")?,
+            dev_info_ptr: symbol(handle, c"fido_dev_info_ptr")?,
+            dev_info_path: symbol(handle, c"fido_dev_info_path")?,
+            dev_new: symbol(handle, c"fido_dev_new")?,
+            dev_open: symbol(handle, c"fido_dev_open")?,
+            dev_close: symbol(handle, c"fido_dev_close")?,
+            dev_free: symbol(handle, c"fido_dev_free")?,
+            dev_make_cred: symbol(handle, c"fido_dev_make_cred")?,
+            dev_get_assert: symbol(handle, c"fido_dev_get_assert")?,
+            cred_new: symbol(handle, c"fido_cred_new")?,
+            cred_free: symbol(handle, c"fido_cred_free")?,
+            cred_set_type: symbol(handle, c"fido_cred_set_type")?,
+            cred_set_clientdata_hash: symbol(handle, c"fido_cred_set_clientdata_hash")?,
+            cred_set_rp: symbol(handle, c"fido_cred_set_rp")?,
+            cred_set_user: symbol(handle, c"fido_cred_set_user")?,
+            cred_set_extensions: symbol(handle, c"fido_cred_set_extensions")?,
+            cred_id_ptr: symbol(handle, c"fido_cred_id_ptr")?,
+            cred_id_len: symbol(handle, c"fido_cred_id_len")?,
+            assert_new: symbol(handle, c"fido_assert_new")?,
+            assert_free: symbol(handle, c"fido_assert_free")?,
+            assert_set_clientdata_hash: symbol(handle, c"fido_assert_set_clientdata_hash")?,
+            assert_set_rp: symbol(handle, c"fido_assert_set_rp")?,
+            assert_allow_cred: symbol(handle, c"fido_assert_allow_cred")?,
+            assert_set_extensions: symbol(handle, c"fido_assert_set_extensions")?,
+            assert_set_hmac_salt: symbol(handle, c"fido_assert_set_hmac_salt")?,
+            assert_count: symbol(handle, c"fido_assert_count")?,
+            assert_hmac_secret_ptr: symbol(handle, c"fido_assert_hmac_secret_ptr")?,
+            assert_hmac_secret_len: symbol(handle, c"fido_assert_hmac_secret_len")?,
+        };
+        let init: unsafe extern "C" fn(c_int) = symbol(handle, c"fido_init")?;
+        unsafe { init(0) };
+        Ok(lib)
+    }
+
+    /// The process-wide library, loaded on first use
+    fn get() -> Result<&'static Self> {
+        static LIBRARY: OnceLock<std::result::Result<Fido2Lib, String>> = OnceLock::new();
+        LIBRARY.get_or_init(|| Self::load(LIBFIDO2))
+            .as_ref()
+            .map_err(|e| HardwareKeyUnavailable(e.clone()).into())
+    }
+
+    /// `status` of a call to `what` as a result; the authenticator's
+    /// refusals (no touch, unknown credential, ...) are all unavailability
+    fn check(&self, status: c_int, what: &str) -> Result<()> {
+        if status == FIDO_OK {
+            return Ok(());
+        }
+        let reason = unsafe { CStr::from_ptr((self.strerr)(status)) }.to_string_lossy();
+        Err(HardwareKeyUnavailable(format!("{} failed: {}", what, reason)).into())
+    }
+
+    /// Paths of the attached authenticators
+    fn device_paths(&self) -> Result<Vec<CString>> {
+        let mut list = unsafe { (self.dev_info_new)(MAX_DEVICES) };
+        if list.is_null() {
+            return Err(anyhow!("fido_dev_info_new failed"));
+        }
+        let mut found = 0;
+        let status = unsafe { (self.dev_info_manifest)(list, MAX_DEVICES, &mut found) };
+        let paths = (0..found)
+            .map(|i| unsafe { CStr::from_ptr((self.dev_info_path)((self.dev_info_ptr)(list, i))) }.to_owned())
+            .collect();
+        unsafe { (self.dev_info_free)(&mut list, MAX_DEVICES) };
+        self.check(status, "listing security keys")?;
+        Ok(paths)
+    }
+
+    /// The first attached authenticator, opened
+    fn open_device(&self) -> Result<Owned> {
+        let path = self.device_paths()?.into_iter().next()
+            .ok_or_else(|| HardwareKeyUnavailable("no security key found; insert it and retry".into()))?;
+        let device = Owned::new(unsafe { (self.dev_new)() }, self.dev_free, Some(self.dev_close))?;
+        self.check(unsafe { (self.dev_open)(device.handle, path.as_ptr()) }, "opening the security key")?;
+        Ok(device)
+    }
+}
+
+/// `name` from the library `handle`, as the function type `T`
+fn symbol<T: Copy>(handle: *mut c_void, name: &CStr) -> std::result::Result<T, String> {
+    assert_eq!(std::mem::size_of::<T>(), std::mem::size_of::<*mut c_void>());
+    let address = unsafe { libc::dlsym(handle, name.as_ptr()) };
+    if address.is_null() {
+        return Err(format!("libfido2 has no {}; it's too old", name.to_string_lossy()));
+    }
+    Ok(unsafe { std::mem::transmute_copy(&address) })
+}
+
+/// A libfido2 object, freed (and first closed, for a device) on drop
+struct Owned {
+    handle: Handle,
+    free: unsafe extern "C" fn(*mut Handle),
+    close: Option<unsafe extern "C" fn(Handle) -> c_int>,
+}
+
+impl Owned {
+    fn new(
+        handle: Handle,
+        free: unsafe extern "C" fn(*mut Handle),
+        close: Option<unsafe extern "C" fn(Handle) -> c_int>,
+    ) -> Result<Self> {
+        if handle.is_null() {
+            return Err(anyhow!("libfido2 is out of memory"));
+        }
+        Ok(Self { handle, free, close })
+    }
+}
+
+impl Drop for Owned {
+    fn drop(&mut self) {
+        if let Some(close) = self.close {
+            unsafe { close(self.handle) };
+        }
+        unsafe { (self.free)(&mut self.handle) };
+    }
+}
+
+/// The first authenticator libfido2 can see
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LibFido2;
+
+impl HmacSecretDevice for LibFido2 {
+    fn make_credential(&self) -> Result<Vec<u8>> {
+        let lib = Fido2Lib::get()?;
+        let device = lib.open_device()?;
+        let cred = Owned::new(unsafe { (lib.cred_new)() }, lib.cred_free, None)?;
+
+        // Client data and user id only matter for attestation, which isn't used
+        let client_data = randombytes(32);
+        let user_id = randombytes(32);
+        let rp = CString::new(RP_ID)?;
+        unsafe {
+            lib.check((lib.cred_set_type)(cred.handle, COSE_ES256), "fido_cred_set_type")?;
+            lib.check((lib.cred_set_clientdata_hash)(cred.handle, client_data.as_ptr(), client_data.len()), "fido_cred_set_clientdata_hash")?;
+            lib.check((lib.cred_set_rp)(cred.handle, rp.as_ptr(), rp.as_ptr()), "fido_cred_set_rp")?;
+            lib.check(
+                (lib.cred_set_user)(cred.handle, user_id.as_ptr(), user_id.len(), c"vault".as_ptr(), std::ptr::null(), std::ptr::null()),
+                "fido_cred_set_user",
+            )?;
+            lib.check((lib.cred_set_extensions)(cred.handle, FIDO_EXT_HMAC_SECRET), "fido_cred_set_extensions")?;
+            lib.check((lib.dev_make_cred)(device.handle, cred.handle, std::ptr::null()), "creating the credential")?;
+
+            let (id, len) = ((lib.cred_id_ptr)(cred.handle), (lib.cred_id_len)(cred.handle));
+            if id.is_null() || len == 0 {
+                return Err(anyhow!("The security key returned no credential id"));
+            }
+            Ok(std::slice::from_raw_parts(id, len).to_vec())
+        }
+    }
+
+    fn hmac_secret(&self, credential_id: &[u8], salt: &[u8; SALT_LEN]) -> Result<SecureKey> {
+        let lib = Fido2Lib::get()?;
+        let device = lib.open_device()?;
+        let assert = Owned::new(unsafe { (lib.assert_new)() }, lib.assert_free, None)?;
+
+        let client_data = randombytes(32);
+        let rp = CString::new(RP_ID)?;
+        unsafe {
+            lib.check((lib.assert_set_clientdata_hash)(assert.handle, client_data.as_ptr(), client_data.len()), "fido_assert_set_clientdata_hash")?;
+            lib.check((lib.assert_set_rp)(assert.handle, rp.as_ptr()), "fido_assert_set_rp")?;
+            lib.check((lib.assert_allow_cred)(assert.handle, credential_id.as_ptr(), credential_id.len()), "fido_assert_allow_cred")?;
+            lib.check((lib.assert_set_extensions)(assert.handle, FIDO_EXT_HMAC_SECRET), "fido_assert_set_extensions")?;
+            lib.check((lib.assert_set_hmac_salt)(assert.handle, salt.as_ptr(), salt.len()), "fido_assert_set_hmac_salt")?;
+            lib.check((lib.dev_get_assert)(device.handle, assert.handle, std::ptr::null()), "getting the assertion")?;
+
+            if (lib.assert_count)(assert.handle) != 1 {
+                return Err(anyhow!("The security key returned no single assertion"));
+            }
+            let (secret, len) = ((lib.assert_hmac_secret_ptr)(assert.handle, 0), (lib.assert_hmac_secret_len)(assert.handle, 0));
+            if secret.is_null() || len != KEY_LEN {
+                return Err(anyhow!("The security key's hmac-secret has the wrong length"));
+            }
+            let mut bytes = Zeroizing::new([0u8; KEY_LEN]);
+            bytes.copy_from_slice(std::slice::from_raw_parts(secret, len));
+            Ok(SecureKey::new(*bytes))
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Software stand-in for an authenticator; unplug it via `present`
+    #[derive(Clone)]
+    pub struct SoftKey {
+        secret: [u8; 32],
+        pub present: Arc<AtomicBool>,
+    }
+
+    impl SoftKey {
+        pub fn new() -> Self {
+            Self {
+                secret: randombytes(32).try_into().unwrap(),
+                present: Arc::new(AtomicBool::new(true)),
+            }
+        }
+    }
+
+    impl HmacSecretDevice for SoftKey {
+        fn make_credential(&self) -> Result<Vec<u8>> {
+            Ok(randombytes(16))
+        }
+
+        fn hmac_secret(&self, credential_id: &[u8], salt: &[u8; SALT_LEN]) -> Result<SecureKey> {
+            if !self.present.load(Ordering::SeqCst) {
+                return Err(HardwareKeyUnavailable("no security key found".into()).into());
+            }
+            let mut mac = blake3::Hasher::new_keyed(&self.secret);
+            mac.update(credential_id);
+            mac.update(salt);
+            Ok(SecureKey::new(*mac.finalize().as_bytes()))
+        }
+    }
+
+    #[test]
+    fn test_soft_key_is_deterministic_per_salt() {
+        let key = SoftKey::new();
+        let cred = key.make_credential().unwrap();
+        let a = key.hmac_secret(&cred, &[1u8; SALT_LEN]).unwrap();
+        let b = key.hmac_secret(&cred, &[1u8; SALT_LEN]).unwrap();
+        let c = key.hmac_secret(&cred, &[2u8; SALT_LEN]).unwrap();
+        assert_eq!(a.expose(), b.expose());
+        assert_ne!(a.expose(), c.expose());
+    }
+
+    #[test]
+    fn test_missing_library_is_unavailable() {
+        let err = Fido2Lib::load(c"libfido2-absent.so.0").err().unwrap();
+        assert!(err.contains("cannot load"));
+    }
+
+    #[test]
+    fn test_libfido2_lists_devices() {
+        // Resolves every function against the real library, where it's installed
+        let Ok(lib) = Fido2Lib::load(LIBFIDO2) else { return };
+        // Whatever is plugged in; only listed, never opened or touched
+        for path in lib.device_paths().unwrap() {
+            assert!(!path.as_bytes().is_empty());
+        }
+        assert!(lib.check(FIDO_OK, "nothing").is_ok());
+        let err = lib.check(-1, "something").unwrap_err();
+        assert!(err.downcast_ref::<HardwareKeyUnavailable>().is_some());
+        assert!(err.to_string().starts_with("Hardware key required: something failed: "));
+    }
+}
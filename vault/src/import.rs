@@ -0,0 +1,54 @@
+//! Importers for other password managers' exports (features `import-1pif`
+//! and `import-kdbx`)
+//!
+//! Each reads an export into `NewEntryRequest`s, the same shape clients
+//! send with `import_entries`, so an import goes through the same
+//! validation and lands in one all-or-nothing write.
+
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use zeroize::Zeroizing;
+
+use crate::api::{NewEntryRequest, Secret};
+use crate::validate;
+use crate::vault::{Category, CustomField, EntryType};
+
+#[cfg(feature = "import-kdbx")]
+mod kdbx;
+#[cfg(feature = "import-1pif")]
+mod onepif;
+
+#[cfg(feature = "import-kdbx")]
+pub use kdbx::import_kdbx;
+#[cfg(feature = "import-1pif")]
+pub use onepif::import_1pif;
+
+/// A request with only the required fields set
+fn request(category: Category, entry_type: EntryType, name: String, value: &[u8]) -> NewEntryRequest {
+    NewEntryRequest {
+        category,
+        entry_type,
+        name,
+        value: Secret::from(STANDARD.encode(value).as_str()),
+        username: None,
+        url: None,
+        pinned_cert_sha256: None,
+        expires: None,
+        custom_fields: Vec::new(),
+        max_access_per_minute: None,
+        consume_on_read: false,
+        notes: None,
+        folder: None,
+    }
+}
+
+/// `url` if it would validate; otherwise it's kept, just not as the
+/// entry's URL, as a custom field
+fn checked_url(url: Option<String>, custom_fields: &mut Vec<CustomField>) -> Option<String> {
+    match url {
+        Some(url) if validate::check_url(&url).is_err() => {
+            custom_fields.push(CustomField { name: "URL".into(), value: Zeroizing::new(url), sensitive: false });
+            None
+        }
+        url => url,
+    }
+}
@@ -0,0 +1,883 @@
+//! KeePass KDBX databases, versions 3.1 and 4
+//!
+//! The database is opened with its master password alone; key files and
+//! challenge-response keys aren't supported, nor is the Twofish cipher.
+//! Entries with a password become passwords in Authentication, and ones
+//! with only notes secure notes in Personal; the group path below the root
+//! is kept as the folder. Other string fields become custom fields
+//! (protected ones sensitive), and an `otp` field its own TOTP entry.
+//! Entry history, attachments and the recycle bin are left out.
+
+use aes::Aes256;
+use aes::cipher::{BlockDecryptMut, BlockEncrypt, KeyInit, KeyIvInit, StreamCipher};
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::generic_array::GenericArray;
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use chacha20::ChaCha20;
+use flate2::read::GzDecoder;
+use hmac::{Hmac, Mac};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use salsa20::Salsa20;
+use sha2::{Digest, Sha256, Sha512};
+use zeroize::Zeroizing;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use super::{checked_url, request};
+use crate::api::{NewEntryRequest, Secret};
+use crate::crypto::ct_eq;
+use crate::validate;
+use crate::vault::{Category, CustomField, EntryType, MAX_KDF_MEMORY_KIB};
+
+/// The first eight bytes of every KDBX file
+const SIGNATURE: [u8; 8] = [0x03, 0xd9, 0xa2, 0x9a, 0x67, 0xfb, 0x4b, 0xb5];
+
+const CIPHER_AES256: [u8; 16] = [
+    0x31, 0xc1, 0xf2, 0xe6, 0xbf, 0x71, 0x43, 0x50, 0xbe, 0x58, 0x05, 0x21, 0x6a, 0xfc, 0x5a, 0xff,
+];
+const CIPHER_CHACHA20: [u8; 16] = [
+    0xd6, 0x03, 0x8a, 0x2b, 0x8b, 0x6f, 0x4c, 0xb5, 0xa5, 0x24, 0x33, 0x9a, 0x31, 0xdb, 0xb5, 0x9a,
+];
+const KDF_AES: [u8; 16] = [
+    0xc9, 0xd9, 0xf3, 0x9a, 0x62, 0x8a, 0x44, 0x60, 0xbf, 0x74, 0x0d, 0x08, 0xc1, 0x8a, 0x4f, 0xea,
+];
+const KDF_ARGON2D: [u8; 16] = [
+    0xef, 0x63, 0x6d, 0xdf, 0x8c, 0x29, 0x44, 0x4b, 0x91, 0xf7, 0xa9, 0xa4, 0x03, 0xe3, 0x0a, 0x0c,
+];
+const KDF_ARGON2ID: [u8; 16] = [
+    0x9e, 0x29, 0x8b, 0x19, 0x56, 0xdb, 0x47, 0x73, 0xb2, 0x3d, 0xfc, 0x3e, 0xc6, 0xf0, 0xa1, 0xe6,
+];
+
+/// Inner stream ids: how protected values in the XML are encrypted
+const INNER_SALSA20: u32 = 2;
+const INNER_CHACHA20: u32 = 3;
+
+/// The fixed Salsa20 nonce of KDBX 3.1's inner stream
+const SALSA20_NONCE: [u8; 8] = [0xe8, 0x30, 0x09, 0x4b, 0x97, 0x20, 0x5d, 0x2a];
+
+/// Largest database read, on disk or decompressed, so a small file can't
+/// inflate without bound
+const MAX_PAYLOAD_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Most AES-KDF rounds a database may ask for. KeePass's one-second
+/// calibration lands in the tens of millions; this is minutes at worst
+/// rather than the years a crafted header could ask for.
+const MAX_AES_KDF_ROUNDS: u64 = 1 << 30;
+
+/// Most Argon2 work a database may ask for, as memory times passes in
+/// KiB: some thirty times KeePassXC's default of 64 MiB calibrated to a
+/// second
+const MAX_ARGON2_WORK_KIB: u64 = 64 * 1024 * 1024;
+
+/// Most Argon2 lanes a database may ask for; KeePassXC offers up to the
+/// machine's core count
+const MAX_ARGON2_PARALLELISM: u32 = 256;
+
+/// String fields holding a one-time password: KeePassXC's `otpauth://`
+/// URI and KeePass's base32 secret
+const OTP_FIELDS: &[&str] = &["otp", "TimeOtp-Secret-Base32"];
+
+const WRONG_PASSWORD: &str = "Wrong password, or the database is corrupted";
+
+/// Read the KeePass database at `path`, unlocked with `password`
+///
+/// Entries with nothing to store are left out.
+pub fn import_kdbx(path: &Path, password: &str) -> Result<Vec<NewEntryRequest>> {
+    let size = fs::metadata(path).with_context(|| format!("Can't read {:?}", path))?.len();
+    if size > MAX_PAYLOAD_BYTES {
+        bail!("{:?} is over {} MiB; that's no password database", path, MAX_PAYLOAD_BYTES / (1024 * 1024));
+    }
+    let file = fs::read(path).with_context(|| format!("Can't read {:?}", path))?;
+    let entries = read_kdbx(&file, password)?;
+    Ok(entries.into_iter().flat_map(convert_entry).collect())
+}
+
+/// An entry's current string fields and where it sits
+#[derive(Debug, Default)]
+struct KdbxEntry {
+    /// Group names from below the root down to the entry's group
+    groups: Vec<String>,
+    fields: Vec<Field>,
+}
+
+#[derive(Debug)]
+struct Field {
+    key: String,
+    value: Zeroizing<String>,
+    protected: bool,
+}
+
+/// The entries for one KeePass entry: the entry itself, then a TOTP entry
+/// for its one-time password
+fn convert_entry(entry: KdbxEntry) -> Vec<NewEntryRequest> {
+    let folder = validate::clean_folder(&entry.groups.join("/")).ok().flatten();
+    let (mut title, mut password, mut username, mut url, mut notes) = (None, None, None, None, None);
+    let mut otp = Vec::new();
+    let mut custom_fields = Vec::new();
+    for field in entry.fields.into_iter().filter(|f| !f.value.trim().is_empty()) {
+        match field.key.as_str() {
+            "Title" => title = Some(field.value.trim().to_string()),
+            "Password" => password = Some(field.value),
+            "UserName" => username = Some(field.value.to_string()),
+            "URL" => url = Some(field.value.trim().to_string()),
+            "Notes" => notes = Some(field.value),
+            key if OTP_FIELDS.contains(&key) => otp.push(field),
+            _ => custom_fields.push(CustomField { name: field.key, value: field.value, sensitive: field.protected }),
+        }
+    }
+    let name = title.unwrap_or_else(|| "Untitled".to_string());
+
+    // A seed that wouldn't validate is kept, just not as a TOTP entry
+    let mut totp = Vec::new();
+    for field in otp {
+        match validate::check_value(EntryType::TotpSeed, field.value.as_bytes()) {
+            Ok(()) => {
+                let mut entry = request(Category::Authentication, EntryType::TotpSeed, format!("{} (TOTP)", name), field.value.as_bytes());
+                entry.url = url.clone().filter(|u| validate::check_url(u).is_ok());
+                entry.folder = folder.clone();
+                totp.push(entry);
+            }
+            Err(_) => custom_fields.push(CustomField { name: field.key, value: field.value, sensitive: true }),
+        }
+    }
+
+    let (category, entry_type, value) = match password {
+        Some(password) => (Category::Authentication, EntryType::Password, password),
+        None => match notes.take() {
+            Some(text) => (Category::Personal, EntryType::SecureNote, text),
+            None => return totp,
+        },
+    };
+    let url = checked_url(url, &mut custom_fields);
+
+    let mut entry = request(category, entry_type, name, value.as_bytes());
+    entry.username = username;
+    entry.url = url;
+    entry.notes = notes.as_deref().map(|n| Secret::from(n.as_str()));
+    entry.custom_fields = custom_fields;
+    entry.folder = folder;
+
+    let mut requests = vec![entry];
+    requests.extend(totp);
+    requests
+}
+
+/// The live entries of a KDBX 3.1 or 4 database
+fn read_kdbx(file: &[u8], password: &str) -> Result<Vec<KdbxEntry>> {
+    let mut input = Cursor::new(file);
+    if input.take(SIGNATURE.len()).ok() != Some(&SIGNATURE[..]) {
+        bail!("Not a KeePass database");
+    }
+    let minor = input.u16()?;
+    let major = input.u16()?;
+    if !(major == 4 || (major == 3 && minor >= 1)) {
+        bail!("KDBX {}.{} isn't supported; save the database as KDBX 4", major, minor);
+    }
+    let header = Header::read(&mut input, major)?;
+    let header_bytes = &file[..input.pos];
+    let transformed = transform_key(password, &header.kdf)?;
+    let key: Zeroizing<[u8; 32]> = Zeroizing::new(Sha256::new()
+        .chain_update(&header.master_seed)
+        .chain_update(*transformed)
+        .finalize()
+        .into());
+
+    let payload;
+    let (stream, xml) = match major {
+        4 => {
+            payload = read_blocks_v4(&mut input, header_bytes, &header, &transformed)
+                .and_then(|ciphertext| decrypt(&header, &key, ciphertext))
+                .and_then(|plaintext| decompress(plaintext, header.compressed))?;
+            read_inner_header(&payload)?
+        }
+        _ => {
+            let plaintext = decrypt(&header, &key, Zeroizing::new(input.rest().to_vec()))?;
+            let start = header.stream_start.as_deref().ok_or_else(|| anyhow!("The header has no stream start bytes"))?;
+            if plaintext.len() < start.len() || !ct_eq(&plaintext[..start.len()], start) {
+                bail!(WRONG_PASSWORD);
+            }
+            payload = read_blocks_v3(&plaintext[start.len()..])
+                .and_then(|blocks| decompress(blocks, header.compressed))?;
+            let key = header.protected_key.as_deref().ok_or_else(|| anyhow!("The header has no protected value key"))?;
+            (InnerStream::new(header.inner_stream, key)?, &payload[..])
+        }
+    };
+    EntryReader::new(stream).read(xml)
+}
+
+/// How the master key is stretched
+enum Kdf {
+    Aes { seed: Vec<u8>, rounds: u64 },
+    Argon2 {
+        algorithm: Algorithm,
+        salt: Vec<u8>,
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+        version: Version,
+        secret: Option<Vec<u8>>,
+    },
+}
+
+/// The outer header's fields; the inner stream ones are only here in 3.1
+struct Header {
+    cipher: Vec<u8>,
+    compressed: bool,
+    master_seed: Vec<u8>,
+    iv: Vec<u8>,
+    kdf: Kdf,
+    protected_key: Option<Vec<u8>>,
+    stream_start: Option<Vec<u8>>,
+    inner_stream: u32,
+}
+
+impl Header {
+    fn read(input: &mut Cursor, major: u16) -> Result<Self> {
+        let (mut cipher, mut compressed, mut master_seed, mut iv, mut kdf) = (None, false, None, None, None);
+        let (mut protected_key, mut stream_start, mut inner_stream) = (None, None, 0);
+        let (mut transform_seed, mut transform_rounds) = (None, None);
+        loop {
+            let id = input.u8()?;
+            let len = match major {
+                4 => input.u32()? as usize,
+                _ => input.u16()? as usize,
+            };
+            let data = input.take(len)?;
+            match id {
+                0 => break,
+                2 => cipher = Some(data.to_vec()),
+                3 => compressed = match le_u32(data)? {
+                    0 => false,
+                    1 => true,
+                    other => bail!("Unknown compression {}", other),
+                },
+                4 => master_seed = Some(data.to_vec()),
+                5 => transform_seed = Some(data.to_vec()),
+                6 => transform_rounds = Some(le_u64(data)?),
+                7 => iv = Some(data.to_vec()),
+                8 => protected_key = Some(data.to_vec()),
+                9 => stream_start = Some(data.to_vec()),
+                10 => inner_stream = le_u32(data)?,
+                11 => kdf = Some(read_kdf_parameters(data)?),
+                // Comments and plugin data
+                _ => {}
+            }
+        }
+        if let (None, Some(seed), Some(rounds)) = (&kdf, transform_seed, transform_rounds) {
+            kdf = Some(aes_kdf(seed, rounds)?);
+        }
+        let missing = |field: &str| anyhow!("The header has no {}", field);
+        Ok(Header {
+            cipher: cipher.ok_or_else(|| missing("cipher"))?,
+            compressed,
+            master_seed: master_seed.ok_or_else(|| missing("master seed"))?,
+            iv: iv.ok_or_else(|| missing("IV"))?,
+            kdf: kdf.ok_or_else(|| missing("key derivation parameters"))?,
+            protected_key,
+            stream_start,
+            inner_stream,
+        })
+    }
+}
+
+/// KDBX 4's KDF parameters, a typed name-value dictionary
+fn read_kdf_parameters(data: &[u8]) -> Result<Kdf> {
+    let mut input = Cursor::new(data);
+    if input.u16()? >> 8 != 1 {
+        bail!("Unsupported KDF parameters version");
+    }
+    let mut params = HashMap::new();
+    loop {
+        let kind = input.u8()?;
+        if kind == 0 {
+            break;
+        }
+        let name_len = input.u32()? as usize;
+        let name = String::from_utf8_lossy(input.take(name_len)?).into_owned();
+        let value_len = input.u32()? as usize;
+        params.insert(name, input.take(value_len)?);
+    }
+    let param = |name: &str| params.get(name).copied().ok_or_else(|| anyhow!("KDF parameter {} is missing", name));
+
+    let uuid = param("$UUID")?;
+    if uuid == KDF_AES {
+        return aes_kdf(param("S")?.to_vec(), le_u64(param("R")?)?);
+    }
+    let algorithm = match uuid {
+        _ if uuid == KDF_ARGON2D => Algorithm::Argon2d,
+        _ if uuid == KDF_ARGON2ID => Algorithm::Argon2id,
+        _ => bail!("Unsupported key derivation function"),
+    };
+    if params.get("A").is_some_and(|data| !data.is_empty()) {
+        bail!("Argon2 associated data isn't supported");
+    }
+    let memory_kib = le_u64(param("M")?)? / 1024;
+    if memory_kib > MAX_KDF_MEMORY_KIB as u64 {
+        bail!("KDF memory must be at most {} KiB", MAX_KDF_MEMORY_KIB);
+    }
+    let iterations = le_u64(param("I")?)?;
+    if iterations.saturating_mul(memory_kib.max(1)) > MAX_ARGON2_WORK_KIB {
+        bail!("Too many KDF iterations; at most {} KiB of Argon2 work is allowed", MAX_ARGON2_WORK_KIB);
+    }
+    let parallelism = le_u32(param("P")?)?;
+    if parallelism > MAX_ARGON2_PARALLELISM {
+        bail!("KDF parallelism must be at most {}", MAX_ARGON2_PARALLELISM);
+    }
+    Ok(Kdf::Argon2 {
+        algorithm,
+        salt: param("S")?.to_vec(),
+        memory_kib: memory_kib as u32,
+        iterations: iterations as u32,
+        parallelism,
+        version: match le_u32(param("V")?)? {
+            0x10 => Version::V0x10,
+            0x13 => Version::V0x13,
+            other => bail!("Unknown Argon2 version {:#x}", other),
+        },
+        secret: params.get("K").map(|k| k.to_vec()),
+    })
+}
+
+/// AES-KDF with `rounds` rounds, if that many would finish
+fn aes_kdf(seed: Vec<u8>, rounds: u64) -> Result<Kdf> {
+    if rounds > MAX_AES_KDF_ROUNDS {
+        bail!("Too many KDF rounds; at most {} are allowed", MAX_AES_KDF_ROUNDS);
+    }
+    Ok(Kdf::Aes { seed, rounds })
+}
+
+/// The password's composite key, run through the KDF
+fn transform_key(password: &str, kdf: &Kdf) -> Result<Zeroizing<[u8; 32]>> {
+    let composite: Zeroizing<[u8; 32]> = Zeroizing::new(Sha256::digest(Sha256::digest(password.as_bytes())).into());
+    let mut transformed = Zeroizing::new([0u8; 32]);
+    match kdf {
+        Kdf::Aes { seed, rounds } => {
+            let cipher = Aes256::new_from_slice(seed).map_err(|_| anyhow!("The AES-KDF seed isn't 32 bytes"))?;
+            let mut blocks = [GenericArray::clone_from_slice(&composite[..16]), GenericArray::clone_from_slice(&composite[16..])];
+            for _ in 0..*rounds {
+                cipher.encrypt_blocks(&mut blocks);
+            }
+            let hash = Sha256::new().chain_update(blocks[0]).chain_update(blocks[1]).finalize();
+            transformed.copy_from_slice(&hash);
+            blocks.iter_mut().for_each(|b| b.fill(0));
+        }
+        Kdf::Argon2 { algorithm, salt, memory_kib, iterations, parallelism, version, secret } => {
+            let params = Params::new(*memory_kib, *iterations, *parallelism, Some(transformed.len()))
+                .map_err(|e| anyhow!("Invalid Argon2 parameters: {}", e))?;
+            let argon2 = match secret {
+                Some(secret) => Argon2::new_with_secret(secret, *algorithm, *version, params)
+                    .map_err(|e| anyhow!("Invalid Argon2 secret: {}", e))?,
+                None => Argon2::new(*algorithm, *version, params),
+            };
+            argon2.hash_password_into(&*composite, salt, &mut *transformed)
+                .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        }
+    }
+    Ok(transformed)
+}
+
+/// KDBX 4's ciphertext, checking the header and every block's HMAC
+fn read_blocks_v4(input: &mut Cursor, header_bytes: &[u8], header: &Header, transformed: &[u8; 32]) -> Result<Zeroizing<Vec<u8>>> {
+    let hash = input.take(32)?;
+    if Sha256::digest(header_bytes)[..] != *hash {
+        bail!("The database header is corrupted");
+    }
+    let hmac_base: Zeroizing<[u8; 64]> = Zeroizing::new(Sha512::new()
+        .chain_update(&header.master_seed)
+        .chain_update(transformed)
+        .chain_update([1])
+        .finalize()
+        .into());
+    block_hmac(&hmac_base, u64::MAX)
+        .chain_update(header_bytes)
+        .verify_slice(input.take(32)?)
+        .map_err(|_| anyhow!(WRONG_PASSWORD))?;
+
+    let mut ciphertext = Zeroizing::new(Vec::new());
+    for index in 0u64.. {
+        let mac = input.take(32)?;
+        let size = input.take(4)?;
+        let block = input.take(le_u32(size)? as usize)?;
+        block_hmac(&hmac_base, index)
+            .chain_update(index.to_le_bytes())
+            .chain_update(size)
+            .chain_update(block)
+            .verify_slice(mac)
+            .map_err(|_| anyhow!("Block {} of the database is corrupted", index))?;
+        if block.is_empty() {
+            break;
+        }
+        ciphertext.extend_from_slice(block);
+    }
+    Ok(ciphertext)
+}
+
+/// The HMAC for block `index`; the header's is `u64::MAX`
+fn block_hmac(hmac_base: &[u8; 64], index: u64) -> Hmac<Sha256> {
+    let key: Zeroizing<[u8; 64]> = Zeroizing::new(Sha512::new()
+        .chain_update(index.to_le_bytes())
+        .chain_update(hmac_base)
+        .finalize()
+        .into());
+    <Hmac<Sha256> as Mac>::new_from_slice(&*key).expect("HMAC takes keys of any length")
+}
+
+/// KDBX 3.1's payload, checking every block's hash
+fn read_blocks_v3(data: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let mut input = Cursor::new(data);
+    let mut payload = Zeroizing::new(Vec::new());
+    loop {
+        let index = input.u32()?;
+        let hash = input.take(32)?;
+        let size = input.u32()? as usize;
+        if size == 0 {
+            return Ok(payload);
+        }
+        let block = input.take(size)?;
+        if Sha256::digest(block)[..] != *hash {
+            bail!("Block {} of the database is corrupted", index);
+        }
+        payload.extend_from_slice(block);
+    }
+}
+
+fn decrypt(header: &Header, key: &[u8; 32], mut data: Zeroizing<Vec<u8>>) -> Result<Zeroizing<Vec<u8>>> {
+    if header.cipher == CIPHER_AES256 {
+        let len = cbc::Decryptor::<Aes256>::new_from_slices(key, &header.iv)
+            .map_err(|_| anyhow!("The IV isn't 16 bytes"))?
+            .decrypt_padded_mut::<Pkcs7>(&mut data)
+            .map_err(|_| anyhow!(WRONG_PASSWORD))?
+            .len();
+        data.truncate(len);
+    } else if header.cipher == CIPHER_CHACHA20 {
+        ChaCha20::new_from_slices(key, &header.iv)
+            .map_err(|_| anyhow!("The IV isn't 12 bytes"))?
+            .apply_keystream(&mut data);
+    } else {
+        bail!("Unsupported cipher; only AES-256 and ChaCha20 are");
+    }
+    Ok(data)
+}
+
+fn decompress(data: Zeroizing<Vec<u8>>, compressed: bool) -> Result<Zeroizing<Vec<u8>>> {
+    if !compressed {
+        return Ok(data);
+    }
+    gunzip(&data, MAX_PAYLOAD_BYTES)
+}
+
+/// `data` inflated, refused once it passes `limit` bytes
+fn gunzip(data: &[u8], limit: u64) -> Result<Zeroizing<Vec<u8>>> {
+    let mut payload = Zeroizing::new(Vec::new());
+    GzDecoder::new(data)
+        .take(limit + 1)
+        .read_to_end(&mut payload)
+        .map_err(|e| anyhow!("Can't decompress the database: {}", e))?;
+    if payload.len() as u64 > limit {
+        bail!("The database is over {} MiB decompressed", limit / (1024 * 1024));
+    }
+    Ok(payload)
+}
+
+/// KDBX 4's inner header, ahead of the XML: the protected values' stream
+fn read_inner_header(payload: &[u8]) -> Result<(InnerStream, &[u8])> {
+    let mut input = Cursor::new(payload);
+    let (mut id, mut key) = (None, None);
+    loop {
+        let field = input.u8()?;
+        let len = input.u32()? as usize;
+        let data = input.take(len)?;
+        match field {
+            0 => break,
+            1 => id = Some(le_u32(data)?),
+            2 => key = Some(data),
+            // Attachments
+            _ => {}
+        }
+    }
+    let stream = match (id, key) {
+        (Some(id), Some(key)) => InnerStream::new(id, key)?,
+        _ => bail!("The inner header has no protected value stream"),
+    };
+    Ok((stream, input.rest()))
+}
+
+/// The keystream protected values are XORed with, in document order
+enum InnerStream {
+    Salsa20(Salsa20),
+    ChaCha20(ChaCha20),
+}
+
+impl InnerStream {
+    fn new(id: u32, key: &[u8]) -> Result<Self> {
+        match id {
+            INNER_SALSA20 => {
+                let key = Zeroizing::new(<[u8; 32]>::from(Sha256::digest(key)));
+                Ok(InnerStream::Salsa20(Salsa20::new(&(*key).into(), &SALSA20_NONCE.into())))
+            }
+            INNER_CHACHA20 => {
+                let hash = Zeroizing::new(<[u8; 64]>::from(Sha512::digest(key)));
+                Ok(InnerStream::ChaCha20(ChaCha20::new_from_slices(&hash[..32], &hash[32..44])
+                    .expect("SHA-512 is long enough for a key and nonce")))
+            }
+            other => bail!("Unsupported protected value cipher {}", other),
+        }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        match self {
+            InnerStream::Salsa20(cipher) => cipher.apply_keystream(data),
+            InnerStream::ChaCha20(cipher) => cipher.apply_keystream(data),
+        }
+    }
+}
+
+/// Reads entries out of the XML as it streams past
+///
+/// Every protected value is decrypted, history's and the recycle bin's
+/// included, since each one moves the inner stream on.
+struct EntryReader {
+    stream: InnerStream,
+    /// Element names from the document root down to the current one
+    path: Vec<String>,
+    text: Zeroizing<String>,
+    protected: bool,
+    recycle_bin_enabled: bool,
+    recycle_bin: Option<String>,
+    /// Open groups' names, and whether each is (in) the recycle bin
+    groups: Vec<(String, bool)>,
+    entry: KdbxEntry,
+    key: String,
+    value: Option<(Zeroizing<String>, bool)>,
+    entries: Vec<KdbxEntry>,
+}
+
+impl EntryReader {
+    fn new(stream: InnerStream) -> Self {
+        Self {
+            stream,
+            path: Vec::new(),
+            text: Zeroizing::new(String::new()),
+            protected: false,
+            recycle_bin_enabled: false,
+            recycle_bin: None,
+            groups: Vec::new(),
+            entry: KdbxEntry::default(),
+            key: String::new(),
+            value: None,
+            entries: Vec::new(),
+        }
+    }
+
+    fn read(mut self, xml: &[u8]) -> Result<Vec<KdbxEntry>> {
+        let xml = std::str::from_utf8(xml).map_err(|_| anyhow!("The database XML isn't UTF-8"))?;
+        let mut reader = Reader::from_str(xml.trim_start_matches('\u{feff}'));
+        loop {
+            match reader.read_event().context("The database XML is malformed")? {
+                Event::Start(e) => self.start(&e)?,
+                Event::Empty(e) => {
+                    self.start(&e)?;
+                    self.end()?;
+                }
+                Event::End(_) => self.end()?,
+                Event::Text(e) => self.text.push_str(&e.unescape()?),
+                Event::CData(e) => self.text.push_str(std::str::from_utf8(&e)?),
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+        Ok(self.entries)
+    }
+
+    fn start(&mut self, element: &BytesStart) -> Result<()> {
+        let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+        self.protected = element.try_get_attribute("Protected")?
+            .is_some_and(|a| a.value.eq_ignore_ascii_case(b"True"));
+        self.text.clear();
+        match (self.path.last().map(String::as_str), name.as_str()) {
+            (_, "Group") => {
+                let recycled = self.groups.last().is_some_and(|&(_, recycled)| recycled);
+                self.groups.push((String::new(), recycled));
+            }
+            (Some("Group"), "Entry") => self.entry = KdbxEntry::default(),
+            _ => {}
+        }
+        self.path.push(name);
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<()> {
+        let name = self.path.pop().unwrap_or_default();
+        let mut text = Zeroizing::new(std::mem::take(&mut *self.text));
+        let protected = std::mem::take(&mut self.protected);
+        if protected {
+            let mut bytes = Zeroizing::new(STANDARD.decode(text.trim()).map_err(|_| anyhow!("A protected value isn't base64"))?);
+            self.stream.apply(&mut bytes);
+            text = Zeroizing::new(String::from_utf8(bytes.to_vec()).map_err(|_| anyhow!(WRONG_PASSWORD))?);
+        }
+        let len = self.path.len();
+        let grandparent = len.checked_sub(2).map(|i| self.path[i].as_str());
+        match (grandparent, self.path.last().map(String::as_str), name.as_str()) {
+            (_, Some("Meta"), "RecycleBinEnabled") => self.recycle_bin_enabled = text.trim() == "True",
+            (_, Some("Meta"), "RecycleBinUUID") => self.recycle_bin = Some(text.trim().to_string()),
+            (_, Some("Group"), "Name") => if let Some(group) = self.groups.last_mut() {
+                group.0 = text.trim().to_string();
+            },
+            (_, Some("Group"), "UUID") => {
+                let is_bin = self.recycle_bin_enabled && self.recycle_bin.as_deref() == Some(text.trim());
+                if let Some(group) = self.groups.last_mut().filter(|_| is_bin) {
+                    group.1 = true;
+                }
+            }
+            (_, Some("String"), "Key") => self.key = text.to_string(),
+            (_, Some("String"), "Value") => self.value = Some((text, protected)),
+            // Only the entry's own fields, not its history's
+            (Some("Group"), Some("Entry"), "String") => {
+                if let Some((value, protected)) = self.value.take() {
+                    self.entry.fields.push(Field { key: std::mem::take(&mut self.key), value, protected });
+                }
+            }
+            (_, Some("Group"), "Entry") if self.groups.last().is_some_and(|&(_, recycled)| !recycled) => {
+                let mut entry = std::mem::take(&mut self.entry);
+                entry.groups = self.groups.iter().skip(1).map(|(name, _)| name.clone()).collect();
+                self.entries.push(entry);
+            }
+            (_, _, "Group") => {
+                self.groups.pop();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Little-endian reads over a byte slice, failing past its end
+struct Cursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(len).filter(|&end| end <= self.data.len())
+            .ok_or_else(|| anyhow!("The database is truncated"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let bytes = &self.data[self.pos..];
+        self.pos = self.data.len();
+        bytes
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        le_u32(self.take(4)?)
+    }
+}
+
+fn le_u32(data: &[u8]) -> Result<u32> {
+    Ok(u32::from_le_bytes(data.try_into().map_err(|_| anyhow!("Expected a 4-byte number"))?))
+}
+
+fn le_u64(data: &[u8]) -> Result<u64> {
+    Ok(u64::from_le_bytes(data.try_into().map_err(|_| anyhow!("Expected an 8-byte number"))?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Written by `testdata/make_kdbx.py`, with this password
+    const PASSWORD: &str = "correct horse";
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join(name)
+    }
+
+    #[test]
+    fn test_import_kdbx4() {
+        // ChaCha20, Argon2id, protected values in a ChaCha20 stream
+        let requests = import_kdbx(&fixture("keepass4.kdbx"), PASSWORD).unwrap();
+        let names: Vec<&str> = requests.iter().map(|r| r.name.as_str()).collect();
+        // Not the recycle bin's "Old"
+        assert_eq!(names, ["Router", "GitHub", "GitHub (TOTP)", "Wifi"]);
+
+        let entries = NewEntryRequest::into_entries(requests).unwrap();
+        let [router, github, totp, wifi] = &entries[..] else { unreachable!() };
+        assert_eq!((github.category, github.entry_type), (Category::Authentication, EntryType::Password));
+        // The current password, not the one in its history
+        assert_eq!((github.value.as_slice(), github.username.as_deref()), (&b"hunter2"[..], Some("octocat")));
+        assert_eq!(github.url.as_deref(), Some("https://github.com/login"));
+        assert_eq!(github.notes.as_deref(), Some("Work account & 2FA"));
+        assert_eq!(github.folder.as_deref(), Some("Work"));
+        let fields: Vec<(&str, &str, bool)> = github.custom_fields.iter()
+            .map(|f| (f.name.as_str(), f.value.as_str(), f.sensitive))
+            .collect();
+        assert_eq!(fields, [("Recovery code", "1234-5678", true)]);
+
+        assert_eq!((totp.entry_type, totp.folder.as_deref()), (EntryType::TotpSeed, Some("Work")));
+        assert!(totp.value.starts_with(b"otpauth://totp/GitHub"));
+        assert_eq!(totp.url, github.url);
+
+        // In the root group; not an absolute URL, so kept as a field instead
+        assert_eq!((router.value.as_slice(), router.folder.as_deref(), router.url.as_deref()), (&b"admin"[..], None, None));
+        assert_eq!(router.custom_fields[0].value.as_str(), "192.168.1.1");
+
+        assert_eq!((wifi.category, wifi.entry_type, wifi.notes.as_deref()), (Category::Personal, EntryType::SecureNote, None));
+        assert_eq!(wifi.value.as_slice(), b"Network: home\nKey: s3cret");
+        assert_eq!(wifi.folder.as_deref(), Some("Personal/Home"));
+    }
+
+    #[test]
+    fn test_import_kdbx3() {
+        // AES-256, AES-KDF, protected values in a Salsa20 stream
+        let requests = import_kdbx(&fixture("keepass3.kdbx"), PASSWORD).unwrap();
+        let entries = NewEntryRequest::into_entries(requests).unwrap();
+        let values: Vec<(&str, &[u8])> = entries.iter().map(|e| (e.name.as_str(), e.value.as_slice())).collect();
+        assert_eq!(values[..2], [("Router", &b"admin"[..]), ("GitHub", b"hunter2")]);
+        assert_eq!(entries[1].custom_fields[0].value.as_str(), "1234-5678");
+        assert_eq!(entries[3].folder.as_deref(), Some("Personal/Home"));
+    }
+
+    #[test]
+    fn test_kdbx_rejected() {
+        for name in ["keepass4.kdbx", "keepass3.kdbx"] {
+            let err = import_kdbx(&fixture(name), "wrong").unwrap_err();
+            assert_eq!(err.to_string(), WRONG_PASSWORD);
+        }
+
+        let file = fs::read(fixture("keepass4.kdbx")).unwrap();
+        let mut tampered = file.clone();
+        let last = tampered.len() - 40;
+        tampered[last] ^= 1;
+        assert!(read_kdbx(&tampered, PASSWORD).unwrap_err().to_string().contains("corrupted"));
+        assert!(read_kdbx(&file[..file.len() - 10], PASSWORD).is_err());
+        assert_eq!(read_kdbx(b"PK\x03\x04", PASSWORD).unwrap_err().to_string(), "Not a KeePass database");
+    }
+
+    /// A KDBX header of `major` version with `fields`, ending where the
+    /// KDF would start work
+    fn header(major: u16, fields: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut out = SIGNATURE.to_vec();
+        out.extend([1u16, major].iter().flat_map(|v| v.to_le_bytes()));
+        for (id, data) in fields.iter().chain([&(0, b"\r\n\r\n".to_vec())]) {
+            out.push(*id);
+            match major {
+                4 => out.extend((data.len() as u32).to_le_bytes()),
+                _ => out.extend((data.len() as u16).to_le_bytes()),
+            }
+            out.extend(data);
+        }
+        out
+    }
+
+    /// A KDBX 4 header with the KDF parameters `kdf`, as (type, name, value)
+    fn header4(kdf: &[(u8, &str, Vec<u8>)]) -> Vec<u8> {
+        let mut dictionary = 0x0100u16.to_le_bytes().to_vec();
+        for (kind, name, value) in kdf {
+            dictionary.push(*kind);
+            dictionary.extend((name.len() as u32).to_le_bytes());
+            dictionary.extend(name.as_bytes());
+            dictionary.extend((value.len() as u32).to_le_bytes());
+            dictionary.extend(value);
+        }
+        dictionary.push(0);
+        header(4, &[
+            (2, CIPHER_CHACHA20.to_vec()),
+            (3, 1u32.to_le_bytes().to_vec()),
+            (4, vec![0; 32]),
+            (7, vec![0; 12]),
+            (11, dictionary),
+        ])
+    }
+
+    fn argon2(memory: u64, iterations: u64, parallelism: u32) -> Vec<u8> {
+        header4(&[
+            (0x42, "$UUID", KDF_ARGON2ID.to_vec()),
+            (0x42, "S", vec![0; 32]),
+            (0x05, "M", memory.to_le_bytes().to_vec()),
+            (0x05, "I", iterations.to_le_bytes().to_vec()),
+            (0x04, "P", parallelism.to_le_bytes().to_vec()),
+            (0x04, "V", 0x13u32.to_le_bytes().to_vec()),
+        ])
+    }
+
+    fn error(file: &[u8]) -> String {
+        read_kdbx(file, PASSWORD).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_hostile_headers() {
+        // Work that would never finish is refused before any is done
+        let aes4 = |rounds: u64| header4(&[
+            (0x42, "$UUID", KDF_AES.to_vec()),
+            (0x42, "S", vec![0; 32]),
+            (0x05, "R", rounds.to_le_bytes().to_vec()),
+        ]);
+        assert!(error(&aes4(u64::MAX)).contains("Too many KDF rounds"));
+        let aes3 = |rounds: u64| header(3, &[
+            (2, CIPHER_AES256.to_vec()),
+            (4, vec![0; 32]),
+            (5, vec![0; 32]),
+            (6, rounds.to_le_bytes().to_vec()),
+            (7, vec![0; 16]),
+        ]);
+        assert!(error(&aes3(MAX_AES_KDF_ROUNDS + 1)).contains("Too many KDF rounds"));
+        assert!(error(&argon2(1024 * 1024, u64::MAX, 1)).contains("Too many KDF iterations"));
+        assert!(error(&argon2(1024, 1 << 40, 1)).contains("Too many KDF iterations"));
+        assert!(error(&argon2(1024 * 1024, 2, u32::MAX)).contains("parallelism"));
+        assert!(error(&argon2(u64::MAX, 2, 1)).contains("memory"));
+        // Within bounds the KDF runs, and the missing body is noticed
+        assert_eq!(error(&aes3(1000)), WRONG_PASSWORD);
+        assert!(error(&argon2(1024 * 1024, 2, 1)).contains("truncated"));
+
+        // Lengths running past the end of the file
+        let mut huge_field = header4(&[]);
+        huge_field.truncate(12);
+        huge_field.extend([2, 0xff, 0xff, 0xff, 0xff]);
+        assert!(error(&huge_field).contains("truncated"));
+        let mut dictionary = 0x0100u16.to_le_bytes().to_vec();
+        dictionary.extend([0x42, 0xff, 0xff, 0xff, 0x7f]);
+        assert!(error(&header(4, &[(11, dictionary)])).contains("truncated"));
+
+        // Missing or unknown pieces
+        assert!(error(&header(4, &[(2, CIPHER_CHACHA20.to_vec())])).contains("has no"));
+        assert!(error(&header4(&[(0x42, "$UUID", vec![7; 16])])).contains("Unsupported key derivation"));
+        assert!(error(&header(3, &[(3, 9u32.to_le_bytes().to_vec())])).contains("Unknown compression"));
+        assert!(error(&header(2, &[])).contains("isn't supported"));
+    }
+
+    #[test]
+    fn test_gzip_bomb() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let mut bomb = GzEncoder::new(Vec::new(), Compression::best());
+        bomb.write_all(&vec![0; 4 * 1024 * 1024]).unwrap();
+        let bomb = bomb.finish().unwrap();
+        assert!(bomb.len() < 64 * 1024);
+
+        assert!(gunzip(&bomb, 1024 * 1024).unwrap_err().to_string().contains("decompressed"));
+        assert_eq!(gunzip(&bomb, 4 * 1024 * 1024).unwrap().len(), 4 * 1024 * 1024);
+    }
+}
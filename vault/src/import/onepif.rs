@@ -0,0 +1,254 @@
+//! 1Password 1PIF exports
+//!
+//! A 1PIF is one JSON item per line, separated by `***<uuid>***`
+//! lines. Logins and passwords become passwords in Authentication, cards
+//! and bank accounts go to Financial, and anything else is a secure note
+//! in Personal. Section fields become custom fields (concealed ones
+//! sensitive), and each one-time password field its own TOTP entry.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use zeroize::Zeroizing;
+
+use std::fs;
+use std::path::Path;
+
+use super::{checked_url, request};
+use crate::api::{NewEntryRequest, Secret};
+use crate::validate;
+use crate::vault::{Category, CustomField, EntryType};
+
+/// Name of the items file inside a `.1pif` export directory
+const ITEMS_FILE: &str = "data.1pif";
+
+/// Item types that aren't secrets: folders, saved searches, deletions
+const SKIPPED_TYPES: &[&str] = &["system.folder.Regular", "system.folder.SavedSearch", "system.Tombstone"];
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct Item {
+    type_name: String,
+    title: String,
+    location: String,
+    trashed: bool,
+    secure_contents: Contents,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Contents {
+    /// A login's form fields, picked out by `designation`
+    fields: Vec<FormField>,
+    #[serde(rename = "URLs")]
+    urls: Vec<ItemUrl>,
+    #[serde(rename = "notesPlain")]
+    notes: String,
+    /// The value of a `passwords.Password` item
+    password: Option<String>,
+    ccnum: Option<String>,
+    #[serde(rename = "accountNo")]
+    account_no: Option<String>,
+    sections: Vec<Section>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FormField {
+    designation: String,
+    value: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ItemUrl {
+    url: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Section {
+    fields: Vec<SectionField>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct SectionField {
+    /// Kind: `string`, `concealed`, `date`, ...
+    k: String,
+    /// Internal name; one-time passwords are `TOTP_<id>`
+    n: String,
+    /// Label shown to the user
+    t: String,
+    /// The value, not always a string (dates are numbers)
+    v: serde_json::Value,
+}
+
+/// Read a 1Password 1PIF export: the `.1pif` directory or its `data.1pif`
+///
+/// Trashed items, folders and items with nothing to store are left out.
+pub fn import_1pif(path: &Path) -> Result<Vec<NewEntryRequest>> {
+    let file = match path.is_dir() {
+        true => path.join(ITEMS_FILE),
+        false => path.to_path_buf(),
+    };
+    let text = Zeroizing::new(fs::read_to_string(&file).with_context(|| format!("Can't read {:?}", file))?);
+    parse_1pif(&text)
+}
+
+fn parse_1pif(text: &str) -> Result<Vec<NewEntryRequest>> {
+    let mut requests = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (line.starts_with("***") && line.ends_with("***")) {
+            continue;
+        }
+        let item: Item = serde_json::from_str(line)
+            .map_err(|e| anyhow!("Line {}: not a 1PIF item: {}", number + 1, e))?;
+        if item.trashed || SKIPPED_TYPES.contains(&item.type_name.as_str()) {
+            continue;
+        }
+        requests.extend(convert_item(item));
+    }
+    Ok(requests)
+}
+
+/// The entries for one item: the item itself, then a TOTP entry for each
+/// one-time password it holds
+fn convert_item(item: Item) -> Vec<NewEntryRequest> {
+    let contents = item.secure_contents;
+    let name = match item.title.trim() {
+        "" => "Untitled".to_string(),
+        title => title.to_string(),
+    };
+    let url = Some(item.location)
+        .filter(|l| !l.is_empty())
+        .or_else(|| contents.urls.into_iter().map(|u| u.url).find(|u| !u.is_empty()));
+    let designated = |designation: &str| contents.fields.iter()
+        .find(|f| f.designation == designation && !f.value.is_empty())
+        .map(|f| f.value.clone());
+
+    let (category, entry_type, value) = match item.type_name.as_str() {
+        "webforms.WebForm" => (Category::Authentication, EntryType::Password, designated("password")),
+        "passwords.Password" => (Category::Authentication, EntryType::Password, contents.password.clone()),
+        "wallet.financial.CreditCard" => (Category::Financial, EntryType::Card, contents.ccnum.clone()),
+        "wallet.financial.BankAccountUS" => (Category::Financial, EntryType::BankAccount, contents.account_no.clone()),
+        _ => (Category::Personal, EntryType::SecureNote, None),
+    };
+    let mut notes = Some(contents.notes).filter(|n| !n.is_empty());
+    // Anything without a value of its own (notes included) is kept as a
+    // note, if it has notes to keep
+    let (category, entry_type, value) = match value.filter(|v| !v.is_empty()) {
+        Some(value) => (category, entry_type, value),
+        None => match notes.take() {
+            Some(text) => (Category::Personal, EntryType::SecureNote, text),
+            None => return totp_entries(&name, &url, &contents.sections),
+        },
+    };
+
+    let mut custom_fields: Vec<CustomField> = contents.sections.iter()
+        .flat_map(|s| &s.fields)
+        .filter(|f| !is_totp(f))
+        .filter_map(|f| Some(CustomField {
+            name: if f.t.is_empty() { f.n.clone() } else { f.t.clone() },
+            value: Zeroizing::new(field_text(&f.v)?),
+            sensitive: f.k == "concealed",
+        }))
+        .filter(|f| !f.name.is_empty() && !f.value.is_empty())
+        .collect();
+    let url = checked_url(url, &mut custom_fields);
+
+    let mut entry = request(category, entry_type, name.clone(), value.as_bytes());
+    entry.username = designated("username");
+    entry.url = url.clone();
+    entry.notes = notes.as_deref().map(Secret::from);
+    entry.custom_fields = custom_fields;
+
+    let mut requests = vec![entry];
+    requests.extend(totp_entries(&name, &url, &contents.sections));
+    requests
+}
+
+/// A TOTP entry for each one-time password field in `sections`
+fn totp_entries(name: &str, url: &Option<String>, sections: &[Section]) -> Vec<NewEntryRequest> {
+    sections.iter()
+        .flat_map(|s| &s.fields)
+        .filter(|f| is_totp(f))
+        .filter_map(|f| field_text(&f.v))
+        .map(|seed| {
+            let mut entry = request(Category::Authentication, EntryType::TotpSeed, format!("{} (TOTP)", name), seed.as_bytes());
+            entry.url = url.clone().filter(|u| validate::check_url(u).is_ok());
+            entry
+        })
+        .collect()
+}
+
+fn is_totp(field: &SectionField) -> bool {
+    field.n.starts_with("TOTP_")
+}
+
+/// A field's value as text, if it has one
+fn field_text(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::String(s) if !s.is_empty() => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORT: &str = r#"{"uuid":"1","typeName":"webforms.WebForm","title":"GitHub","location":"https://github.com/login","secureContents":{"fields":[{"designation":"username","value":"octocat"},{"designation":"password","value":"hunter2"}],"notesPlain":"Work account","sections":[{"fields":[{"k":"concealed","n":"TOTP_ABC","t":"one-time password","v":"otpauth://totp/GitHub:octocat?secret=JBSWY3DPEHPK3PXP&issuer=GitHub"},{"k":"string","n":"recovery","t":"Recovery email","v":"cat@example.com"}]}]}}
+***5642bee8-a5ff-11dc-8314-0800200c9a66***
+{"uuid":"2","typeName":"securenotes.SecureNote","title":"Wifi","secureContents":{"notesPlain":"Network: home\nKey: s3cret"}}
+***5642bee8-a5ff-11dc-8314-0800200c9a66***
+{"uuid":"3","typeName":"wallet.financial.CreditCard","title":"Visa","secureContents":{"ccnum":"4111 1111 1111 1111","sections":[{"fields":[{"k":"concealed","n":"cvv","t":"verification number","v":"123"},{"k":"monthYear","n":"expiry","t":"expiry date","v":202812}]}]}}
+***5642bee8-a5ff-11dc-8314-0800200c9a66***
+{"uuid":"4","typeName":"webforms.WebForm","title":"Old","trashed":true,"secureContents":{"fields":[{"designation":"password","value":"x"}]}}
+***5642bee8-a5ff-11dc-8314-0800200c9a66***
+{"uuid":"5","typeName":"system.folder.Regular","title":"Work"}
+***5642bee8-a5ff-11dc-8314-0800200c9a66***
+{"uuid":"6","typeName":"passwords.Password","title":"Router","location":"192.168.1.1","secureContents":{"password":"admin"}}
+***5642bee8-a5ff-11dc-8314-0800200c9a66***
+"#;
+
+    #[test]
+    fn test_import_1pif() {
+        let tmp = tempfile::TempDir::new().unwrap();
+        fs::write(tmp.path().join(ITEMS_FILE), EXPORT).unwrap();
+        let requests = import_1pif(tmp.path()).unwrap();
+        let names: Vec<&str> = requests.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["GitHub", "GitHub (TOTP)", "Wifi", "Visa", "Router"]);
+
+        let entries = NewEntryRequest::into_entries(requests).unwrap();
+        let [github, totp, wifi, visa, router] = &entries[..] else { unreachable!() };
+        assert_eq!((github.category, github.entry_type), (Category::Authentication, EntryType::Password));
+        assert_eq!((github.value.as_slice(), github.username.as_deref()), (&b"hunter2"[..], Some("octocat")));
+        assert_eq!(github.url.as_deref(), Some("https://github.com/login"));
+        assert_eq!(github.notes.as_deref(), Some("Work account"));
+        assert_eq!(github.custom_fields.len(), 1);
+        assert_eq!((github.custom_fields[0].name.as_str(), github.custom_fields[0].sensitive), ("Recovery email", false));
+
+        assert_eq!(totp.entry_type, EntryType::TotpSeed);
+        assert!(totp.value.starts_with(b"otpauth://totp/GitHub"));
+        assert_eq!(totp.url, github.url);
+
+        assert_eq!((wifi.category, wifi.entry_type, wifi.notes.as_deref()), (Category::Personal, EntryType::SecureNote, None));
+        assert_eq!(wifi.value.as_slice(), b"Network: home\nKey: s3cret");
+
+        assert_eq!((visa.category, visa.entry_type), (Category::Financial, EntryType::Card));
+        let fields: Vec<(&str, &str, bool)> = visa.custom_fields.iter()
+            .map(|f| (f.name.as_str(), f.value.as_str(), f.sensitive))
+            .collect();
+        assert_eq!(fields, [("verification number", "123", true), ("expiry date", "202812", false)]);
+
+        // Not an absolute URL, so kept as a field instead
+        assert_eq!(router.url, None);
+        assert_eq!(router.custom_fields[0].value.as_str(), "192.168.1.1");
+
+        assert!(parse_1pif("{not json").is_err());
+        assert!(parse_1pif("").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,25 @@
+//! Prosperity Vault: the vault, its audit log and the daemon serving them
+//!
+//! The `prosperity-vault` binary is a thin command line over `api` and
+//! `cli`; the library is what its benchmarks link against.
+
+pub mod api;
+pub mod audit;
+pub mod auth;
+pub mod cli;
+pub mod crypto;
+pub mod schema;
+pub mod strength;
+pub mod vault;
+#[cfg(feature = "websocket")]
+pub mod ws;
+
+mod generate;
+mod hwkey;
+#[cfg(any(feature = "import-1pif", feature = "import-kdbx"))]
+mod import;
+mod metrics;
+mod policy;
+mod recovery;
+mod totp;
+mod validate;
@@ -7,77 +7,442 @@
 //!   prosperity-vault                    # Run daemon
 //!   prosperity-vault --socket PATH      # Custom socket path
 //!   prosperity-vault --vault PATH       # Custom vault path
+//!   prosperity-vault --named-vault NAME=PATH  # Extra vault (repeatable)
+//!   prosperity-vault --allow-uid UID    # Allow a local user (repeatable)
+//!   prosperity-vault --lock-timeout SEC # Auto-lock after idle seconds
+//!   prosperity-vault --unlock-from-fd N # Unlock at startup, passphrase on fd N
+//!   prosperity-vault --unlock-from-env VAR
+//!                                       # Unlock at startup, passphrase in $VAR
+//!   prosperity-vault --emergency-remove-socket
+//!                                       # SIGUSR1 locks everything, then removes the socket
+//!   prosperity-vault --metrics-listen ADDR
+//!                                       # Serve Prometheus metrics on /metrics
+//!   prosperity-vault --ws-listen ADDR --ws-token TOKEN
+//!                                       # Also serve WebSocket (feature `websocket`)
+//!   prosperity-vault --dump-schema      # Print the protocol's JSON Schema
+//!   prosperity-vault list|get|create|merge|restore|enable-recovery|recover
+//!                                       # Work on the vault directly, no daemon
+//!   prosperity-vault --help             # Full usage
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use clap::Parser;
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use zeroize::Zeroizing;
 
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::io::{FromRawFd, RawFd};
 use std::path::PathBuf;
+use std::time::Duration;
 
-mod crypto;
-mod vault;
-mod audit;
-mod api;
+#[cfg(feature = "websocket")]
+use prosperity_vault::ws;
+use prosperity_vault::{api, audit, auth, cli, crypto, schema, strength};
 
 const DEFAULT_SOCKET_PATH: &str = "/run/prosperity/vault.sock";
 const DEFAULT_VAULT_PATH: &str = ".prosperity/vault";
 
+/// Longest startup passphrase read from an fd
+const MAX_PASSPHRASE_BYTES: u64 = 4096;
+
+/// Secure credential vault daemon for Prosperity AI
+///
+/// Runs the daemon unless given a command.
+#[derive(Debug, Parser)]
+#[command(name = "prosperity-vault", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<cli::Command>,
+
+    /// Unix socket to listen on
+    #[arg(long, value_name = "PATH", default_value = DEFAULT_SOCKET_PATH)]
+    socket: PathBuf,
+
+    /// Vault directory [default: ~/.prosperity/vault]
+    #[arg(long, value_name = "PATH", global = true)]
+    vault: Option<PathBuf>,
+
+    /// Extra vault clients can select by name (repeatable)
+    #[arg(long = "named-vault", value_name = "NAME=PATH", value_parser = parse_named_vault)]
+    named_vault: Vec<(String, PathBuf)>,
+
+    /// Local uid allowed to connect (repeatable; default: the daemon's own uid)
+    #[arg(long = "allow-uid", value_name = "UID")]
+    allow_uid: Vec<u32>,
+
+    /// Lock the vault after this many seconds without a request
+    #[arg(long, value_name = "SECONDS")]
+    lock_timeout: Option<u64>,
+
+    /// Compact unlocked vaults' audit logs every this many seconds (they
+    /// are always compacted on lock)
+    #[arg(long, value_name = "SECONDS", value_parser = clap::value_parser!(u64).range(1..))]
+    audit_compact_interval: Option<u64>,
+
+    /// Keep at most this many MiB of decrypted categories in memory per
+    /// vault; the least recently used are dropped and re-read on demand
+    #[arg(long, value_name = "MIB")]
+    cache_budget_mib: Option<u64>,
+
+    /// Strength score (0-4) a passphrase needs to create a vault, unless
+    /// the unlock request sets "force"
+    #[arg(long, value_name = "SCORE", default_value_t = strength::DEFAULT_MIN_SCORE,
+          value_parser = clap::value_parser!(u8).range(0..=4))]
+    min_passphrase_score: u8,
+
+    /// Refuse to unlock a vault whose audit log can't be opened, instead of
+    /// running it unaudited
+    #[arg(long)]
+    require_audit: bool,
+
+    /// Purge audit entries older than this many days whenever a vault is
+    /// unlocked; the log keeps a signed record of what was purged
+    #[arg(long, value_name = "DAYS", value_parser = clap::value_parser!(u64).range(1..))]
+    audit_retention_days: Option<u64>,
+
+    /// Archive a vault's active audit log once it reaches this many MiB
+    /// [default: 4]
+    #[arg(long, value_name = "MIB", value_parser = clap::value_parser!(u64).range(1..))]
+    audit_rotate_mib: Option<u64>,
+
+    /// Archive a vault's active audit log once its first entry is this many
+    /// days old [default: 90]
+    #[arg(long, value_name = "DAYS", value_parser = clap::value_parser!(u32).range(1..))]
+    audit_rotate_days: Option<u32>,
+
+    /// Let use-for-auth send an entry's credential to subdomains of its
+    /// URL's host (login.example.com for example.com)
+    #[arg(long)]
+    auth_allow_subdomains: bool,
+
+    /// Let use-for-auth ignore ports when matching targets to entry URLs
+    #[arg(long)]
+    auth_any_port: bool,
+
+    /// Let use-for-auth send an entry stored with an https URL over http
+    #[arg(long)]
+    auth_allow_http: bool,
+
+    /// File holding a secret (at least 16 bytes) kept outside the vault,
+    /// e.g. on a USB key; new vaults mix it into their key and can't be
+    /// unlocked without it
+    #[arg(long, value_name = "PATH", global = true)]
+    pepper_file: Option<PathBuf>,
+
+    /// Close connections sending a request line (or WebSocket message)
+    /// longer than this many MiB
+    #[arg(long, value_name = "MIB", default_value_t = api::DEFAULT_MAX_REQUEST_BYTES >> 20,
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..=1024))]
+    max_request_mib: usize,
+
+    /// Give up on a request after this many seconds, so one stuck request
+    /// can't block every client; unlocks and other key derivations get at
+    /// least two minutes
+    #[arg(long, value_name = "SECS", default_value_t = api::DEFAULT_REQUEST_TIMEOUT.as_secs(),
+          value_parser = clap::value_parser!(u64).range(1..))]
+    request_timeout: u64,
+
+    /// Unlock the default vault at startup with a passphrase read from this
+    /// file descriptor (e.g. a pipe from a supervisor), which is then closed
+    #[arg(long, value_name = "FD", conflicts_with = "unlock_from_env")]
+    unlock_from_fd: Option<RawFd>,
+
+    /// Unlock the default vault at startup with the passphrase in this
+    /// environment variable, which is then removed
+    #[arg(long, value_name = "VAR")]
+    unlock_from_env: Option<String>,
+
+    /// On an emergency lock (the "emergency" request or SIGUSR1) also
+    /// remove the socket, so nothing can connect until a restart
+    #[arg(long)]
+    emergency_remove_socket: bool,
+
+    /// Serve Prometheus metrics on GET /metrics at PORT (localhost) or
+    /// IP:PORT; they carry request counts and timings, never vault data
+    #[arg(long, value_name = "ADDR", value_parser = api::parse_listen_addr)]
+    metrics_listen: Option<std::net::SocketAddr>,
+
+    /// Also serve the protocol over WebSocket on PORT (localhost) or IP:PORT
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "ADDR", value_parser = api::parse_listen_addr, requires = "ws_token")]
+    ws_listen: Option<std::net::SocketAddr>,
+
+    /// Bearer token WebSocket clients must present
+    #[cfg(feature = "websocket")]
+    #[arg(long, value_name = "TOKEN", requires = "ws_listen")]
+    ws_token: Option<String>,
+
+    /// Stay attached to the terminal (the daemon never forks; accepted so
+    /// service files can be explicit)
+    #[arg(long)]
+    foreground: bool,
+
+    /// Print the JSON Schema of the socket protocol and exit
+    #[arg(long)]
+    dump_schema: bool,
+}
+
+/// Parse a `NAME=PATH` pair for `--named-vault`
+fn parse_named_vault(arg: &str) -> Result<(String, PathBuf), String> {
+    match arg.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            if name == api::DEFAULT_VAULT {
+                return Err(format!("'{}' is reserved; use --vault", name));
+            }
+            Ok((name.to_string(), PathBuf::from(path)))
+        }
+        _ => Err(format!("expected NAME=PATH, got '{}'", arg)),
+    }
+}
+
+/// The audit rotation `--audit-rotate-mib` and `--audit-rotate-days` ask
+/// for, each falling back to the default
+fn audit_rotation(mib: Option<u64>, days: Option<u32>) -> audit::RotationPolicy {
+    let defaults = audit::RotationPolicy::default();
+    audit::RotationPolicy {
+        max_bytes: mib.map(|mib| mib.saturating_mul(1 << 20)).or(defaults.max_bytes),
+        max_age: days.map(|days| chrono::Duration::days(days.into())).or(defaults.max_age),
+    }
+}
+
+/// The passphrase `--unlock-from-fd` or `--unlock-from-env` points at, if any
+fn startup_passphrase(cli: &Cli) -> Result<Option<Zeroizing<String>>> {
+    let passphrase = if let Some(fd) = cli.unlock_from_fd {
+        read_passphrase_fd(fd)?
+    } else if let Some(ref var) = cli.unlock_from_env {
+        let value = Zeroizing::new(
+            std::env::var(var).map_err(|e| anyhow!("--unlock-from-env {}: {}", var, e))?,
+        );
+        // Nothing started later should inherit it
+        std::env::remove_var(var);
+        Zeroizing::new(value.trim_end_matches(['\r', '\n']).to_string())
+    } else {
+        return Ok(None);
+    };
+
+    if passphrase.is_empty() {
+        return Err(anyhow!("Startup passphrase is empty"));
+    }
+    Ok(Some(passphrase))
+}
+
+/// Read a passphrase from `fd` up to EOF, then close it
+///
+/// A trailing newline (as `echo` or a here-string adds) is dropped.
+fn read_passphrase_fd(fd: RawFd) -> Result<Zeroizing<String>> {
+    // Taking ownership of a closed fd would trip IO-safety checks on drop
+    if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+        return Err(anyhow!("--unlock-from-fd {}: {}", fd, std::io::Error::last_os_error()));
+    }
+    // The fd was handed over for this alone, so closing it here is fine
+    let file = unsafe { File::from_raw_fd(fd) };
+
+    // Sized up front so the buffer never reallocates, leaving stray copies
+    let mut bytes = Zeroizing::new(Vec::with_capacity(MAX_PASSPHRASE_BYTES as usize + 1));
+    file.take(MAX_PASSPHRASE_BYTES + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_PASSPHRASE_BYTES {
+        return Err(anyhow!("--unlock-from-fd {}: passphrase longer than {} bytes", fd, MAX_PASSPHRASE_BYTES));
+    }
+    while matches!(bytes.last(), Some(b'\n' | b'\r')) {
+        bytes.pop();
+    }
+
+    let text = String::from_utf8(std::mem::take(&mut *bytes))
+        .map_err(|_| anyhow!("--unlock-from-fd {}: passphrase is not UTF-8", fd))?;
+    Ok(Zeroizing::new(text))
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if cli.dump_schema {
+        println!("{}", serde_json::to_string_pretty(&schema::protocol_schema())?);
+        return Ok(());
+    }
+    let vault_path = cli.vault.clone().unwrap_or_else(|| {
+        dirs::home_dir()
+            .expect("Could not find home directory")
+            .join(DEFAULT_VAULT_PATH)
+    });
+    if let Some(command) = cli.command {
+        return cli::run(command, &vault_path, cli.pepper_file.as_deref());
+    }
+    // Before anything else runs, so the secret is in as few places as possible
+    let unlock_passphrase = startup_passphrase(&cli)?;
+
     // Initialize logging
     tracing_subscriber::registry()
         .with(fmt::layer())
         .with(EnvFilter::from_default_env().add_directive("prosperity_vault=info".parse()?))
         .init();
 
-    // Parse arguments (simple for now)
-    let args: Vec<String> = std::env::args().collect();
-    
-    let socket_path = get_arg(&args, "--socket")
-        .map(PathBuf::from)
-        .unwrap_or_else(|| PathBuf::from(DEFAULT_SOCKET_PATH));
-    
-    let vault_path = get_arg(&args, "--vault")
-        .map(PathBuf::from)
-        .unwrap_or_else(|| {
-            dirs::home_dir()
-                .expect("Could not find home directory")
-                .join(DEFAULT_VAULT_PATH)
-        });
+    let config = api::DaemonConfig {
+        peer_policy: api::PeerPolicy::new(cli.allow_uid),
+        lock_timeout: cli.lock_timeout.map(Duration::from_secs),
+        audit_compact_interval: cli.audit_compact_interval.map(Duration::from_secs),
+        named_vaults: cli.named_vault,
+        unlock_passphrase,
+        cache_budget: cli.cache_budget_mib.map(|mib| mib.saturating_mul(1 << 20)),
+        min_passphrase_score: cli.min_passphrase_score,
+        require_audit: cli.require_audit,
+        audit_retention: cli.audit_retention_days.map(|days| Duration::from_secs(days.saturating_mul(24 * 60 * 60))),
+        audit_rotation: audit_rotation(cli.audit_rotate_mib, cli.audit_rotate_days),
+        domain_policy: auth::DomainPolicy {
+            allow_subdomains: cli.auth_allow_subdomains,
+            match_port: !cli.auth_any_port,
+            require_https: !cli.auth_allow_http,
+        },
+        pepper_file: cli.pepper_file,
+        max_request_bytes: cli.max_request_mib << 20,
+        request_timeout: Duration::from_secs(cli.request_timeout),
+        emergency_remove_socket: cli.emergency_remove_socket,
+        metrics_listen: cli.metrics_listen,
+        #[cfg(feature = "websocket")]
+        websocket: cli.ws_listen.zip(cli.ws_token)
+            .map(|(listen, token)| ws::WsConfig { listen, token }),
+    };
 
     tracing::info!("Prosperity Vault Daemon starting...");
-    tracing::info!("Socket: {:?}", socket_path);
+    tracing::info!("Socket: {:?}", cli.socket);
     tracing::info!("Vault: {:?}", vault_path);
+    for (name, path) in &config.named_vaults {
+        tracing::info!("Vault '{}': {:?}", name, path);
+    }
+    if let Some(timeout) = config.lock_timeout {
+        tracing::info!("Idle lock timeout: {:?}", timeout);
+    }
+    if let Some(budget) = config.cache_budget {
+        tracing::info!("Category cache budget: {} bytes per vault", budget);
+    }
 
     // Initialize sodiumoxide
-    sodiumoxide::init().expect("Failed to initialize sodiumoxide");
+    crypto::init().expect("Failed to initialize sodiumoxide");
+
+    if cfg!(feature = "fast-kdf") {
+        tracing::warn!("Built with fast-kdf: new vaults get test-strength key derivation");
+    }
+
+    // Probe once so a missing mlock shows up before any real key exists
+    if !crypto::SecureKey::generate().is_memory_locked() {
+        tracing::warn!("Keys are not pinned in RAM; raise RLIMIT_MEMLOCK (ulimit -l) or disable swap");
+    }
     
     // Run daemon
-    api::run_daemon(socket_path, vault_path).await
-}
-
-fn get_arg(args: &[String], flag: &str) -> Option<String> {
-    args.iter()
-        .position(|a| a == flag)
-        .and_then(|i| args.get(i + 1))
-        .cloned()
+    api::run_daemon(cli.socket, vault_path, config).await
 }
 
 #[cfg(test)]
 mod integration_tests {
     use super::*;
+    use prosperity_vault::vault;
     use tempfile::TempDir;
     
+    #[test]
+    fn test_cli_parsing() {
+        let cli = Cli::try_parse_from([
+            "prosperity-vault",
+            "--socket", "/tmp/v.sock",
+            "--allow-uid", "1000",
+            "--allow-uid", "1001",
+            "--lock-timeout", "300",
+            "--audit-compact-interval", "3600",
+            "--audit-retention-days", "365",
+            "--audit-rotate-mib", "16",
+            "--named-vault", "work=/srv/vaults/work",
+            "--metrics-listen", "9464",
+            "--emergency-remove-socket",
+            "--auth-allow-subdomains",
+            "--foreground",
+        ]).unwrap();
+        assert_eq!(cli.socket, PathBuf::from("/tmp/v.sock"));
+        assert_eq!(cli.allow_uid, vec![1000, 1001]);
+        assert_eq!(cli.lock_timeout, Some(300));
+        assert_eq!(cli.audit_compact_interval, Some(3600));
+        assert_eq!(cli.audit_retention_days, Some(365));
+        let rotation = audit_rotation(cli.audit_rotate_mib, cli.audit_rotate_days);
+        assert_eq!(rotation.max_bytes, Some(16 << 20));
+        assert_eq!(rotation.max_age, audit::RotationPolicy::default().max_age);
+        assert_eq!(cli.metrics_listen, Some("127.0.0.1:9464".parse().unwrap()));
+        assert!(cli.emergency_remove_socket);
+        assert!(cli.auth_allow_subdomains && !cli.auth_any_port && !cli.auth_allow_http);
+        assert_eq!(cli.named_vault, vec![("work".to_string(), PathBuf::from("/srv/vaults/work"))]);
+        assert!(cli.foreground);
+
+        let defaults = Cli::try_parse_from(["prosperity-vault"]).unwrap();
+        assert_eq!(defaults.socket, PathBuf::from(DEFAULT_SOCKET_PATH));
+        assert!(defaults.vault.is_none());
+
+        // Typos and malformed values are errors, not silently ignored
+        assert!(Cli::try_parse_from(["prosperity-vault", "--sockett", "/foo"]).is_err());
+        assert!(Cli::try_parse_from(["prosperity-vault", "--allow-uid", "adam"]).is_err());
+        assert!(Cli::try_parse_from(["prosperity-vault", "--audit-compact-interval", "0"]).is_err());
+        assert!(Cli::try_parse_from(["prosperity-vault", "--named-vault", "work"]).is_err());
+        assert!(Cli::try_parse_from(["prosperity-vault", "--named-vault", "default=/x"]).is_err());
+
+        // Commands take the vault flags on either side
+        let cli = Cli::try_parse_from(["prosperity-vault", "list", "--vault", "/x"]).unwrap();
+        assert!(matches!(cli.command, Some(cli::Command::List { category: None })));
+        assert_eq!(cli.vault, Some(PathBuf::from("/x")));
+        assert!(Cli::try_parse_from([
+            "prosperity-vault", "create", "--category", "authentication", "--type", "pasword", "--name", "x",
+        ]).is_err());
+        let cli = Cli::try_parse_from(["prosperity-vault", "merge", "/other", "--strategy", "manual"]).unwrap();
+        assert!(matches!(cli.command, Some(cli::Command::Merge { strategy: vault::MergeStrategy::Manual, .. })));
+        assert!(Cli::try_parse_from(["prosperity-vault", "merge", "/other", "--strategy", "ours"]).is_err());
+        let cli = Cli::try_parse_from(["prosperity-vault", "enable-recovery", "--shares", "5", "--threshold", "3"]).unwrap();
+        assert!(matches!(cli.command, Some(cli::Command::EnableRecovery { shares: 5, threshold: 3 })));
+        assert!(Cli::try_parse_from(["prosperity-vault", "enable-recovery", "--shares", "256", "--threshold", "3"]).is_err());
+        assert!(defaults.command.is_none());
+    }
+
+    #[test]
+    fn test_startup_passphrase() {
+        use std::io::Write;
+        use std::os::unix::io::IntoRawFd;
+
+        let tmp = TempDir::new().unwrap();
+        let path = tmp.path().join("pass");
+        File::create(&path).unwrap().write_all(b"correct horse\n").unwrap();
+        let fd = File::open(&path).unwrap().into_raw_fd();
+
+        let cli = Cli::try_parse_from(["prosperity-vault", "--unlock-from-fd", &fd.to_string()]).unwrap();
+        assert_eq!(startup_passphrase(&cli).unwrap().unwrap().as_str(), "correct horse");
+
+        std::env::set_var("PROSPERITY_TEST_UNLOCK", "battery staple");
+        let cli = Cli::try_parse_from(["prosperity-vault", "--unlock-from-env", "PROSPERITY_TEST_UNLOCK"]).unwrap();
+        assert_eq!(startup_passphrase(&cli).unwrap().unwrap().as_str(), "battery staple");
+        assert!(std::env::var("PROSPERITY_TEST_UNLOCK").is_err());
+        assert!(startup_passphrase(&cli).is_err());
+
+        assert!(startup_passphrase(&Cli::try_parse_from(["prosperity-vault"]).unwrap()).unwrap().is_none());
+        assert!(Cli::try_parse_from([
+            "prosperity-vault", "--unlock-from-fd", "3", "--unlock-from-env", "X",
+        ]).is_err());
+    }
+
+    #[cfg(feature = "websocket")]
+    #[test]
+    fn test_ws_flags_go_together() {
+        let cli = Cli::try_parse_from([
+            "prosperity-vault", "--ws-listen", "8765", "--ws-token", "t",
+        ]).unwrap();
+        assert_eq!(cli.ws_listen, Some("127.0.0.1:8765".parse().unwrap()));
+
+        assert!(Cli::try_parse_from(["prosperity-vault", "--ws-listen", "8765"]).is_err());
+        assert!(Cli::try_parse_from(["prosperity-vault", "--ws-token", "t"]).is_err());
+    }
+
     #[test]
     fn test_crypto_roundtrip() {
         sodiumoxide::init().unwrap();
         
         let salt = crypto::generate_salt();
-        let master = crypto::derive_master_key("test passphrase", &salt).unwrap();
+        let master = crypto::derive_master_key("test passphrase", &salt, &crypto::KdfParams::for_new_keys()).unwrap();
         let subkey = crypto::derive_subkey(&master, "test-context");
         
         let plaintext = b"Hello, Prosperity!";
-        let ciphertext = crypto::encrypt(plaintext, &subkey).unwrap();
-        let decrypted = crypto::decrypt(&ciphertext, &subkey).unwrap();
+        let ciphertext = crypto::encrypt(plaintext, &subkey, b"").unwrap();
+        let decrypted = crypto::decrypt(&ciphertext, &subkey, b"").unwrap();
         
         assert_eq!(plaintext.as_slice(), decrypted.as_slice());
     }
@@ -107,11 +472,11 @@ mod integration_tests {
         drop(v);
         
         let mut v2 = vault::Vault::open(&vault_path).unwrap();
-        v2.unlock("secure passphrase").unwrap();
+        v2.unlock("secure passphrase", vault::AccessMode::ReadWrite).unwrap();
         
         // Retrieve entry
         let retrieved = v2.get_entry(&id).unwrap().unwrap();
         assert_eq!(retrieved.name, "GitHub");
-        assert_eq!(retrieved.value, b"ghp_xxxxxxxxxxxx");
+        assert_eq!(*retrieved.value, b"ghp_xxxxxxxxxxxx");
     }
 }
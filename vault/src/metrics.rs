@@ -0,0 +1,263 @@
+//! Prometheus metrics for the vault daemon
+//!
+//! Counts requests per command, how many failed and how long they took,
+//! plus unlocks, locks and connections, and serves them as Prometheus text
+//! on `GET /metrics` when `--metrics-listen` is given. Only the `cmd` tag
+//! and vault names are ever used as labels: nothing a request carries, and
+//! nothing derived from a secret, reaches the scrape output.
+
+use anyhow::Result;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::api::{Response, VaultDaemon};
+
+/// Upper bounds, in seconds, of the request latency histogram buckets
+///
+/// Unlocks spend around a second in Argon2, so the top buckets are wide.
+const LATENCY_BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 30.0];
+
+/// Longest request head a scrape may send
+const MAX_HEAD_BYTES: u64 = 8 * 1024;
+
+/// How long a scraper gets to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What's been recorded for one command
+#[derive(Debug, Default, Clone)]
+struct CommandStats {
+    requests: u64,
+    errors: u64,
+    /// Requests per latency bucket (not cumulative); the last is `+Inf`
+    buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    seconds: f64,
+}
+
+/// Daemon-wide counters, shared by every connection
+#[derive(Debug, Default)]
+pub struct Metrics {
+    commands: Mutex<BTreeMap<&'static str, CommandStats>>,
+    invalid_requests: AtomicU64,
+    connections: AtomicU64,
+    rejected_connections: AtomicU64,
+    unlocks: AtomicU64,
+    locks: AtomicU64,
+}
+
+impl Metrics {
+    /// Count a handled request, under its `Request::command`
+    pub fn record_request(&self, command: &'static str, response: &Response, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS.iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+
+        let mut commands = self.commands.lock().unwrap_or_else(PoisonError::into_inner);
+        let stats = commands.entry(command).or_default();
+        stats.requests += 1;
+        stats.errors += u64::from(matches!(response, Response::Error { .. }));
+        stats.buckets[bucket] += 1;
+        stats.seconds += seconds;
+    }
+
+    /// Count a request line that didn't parse
+    pub fn record_invalid_request(&self) {
+        self.invalid_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count an accepted connection, or one refused by the peer policy
+    pub fn record_connection(&self, allowed: bool) {
+        let counter = if allowed { &self.connections } else { &self.rejected_connections };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_unlock(&self) {
+        self.unlocks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_lock(&self) {
+        self.locks.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Everything recorded, in the Prometheus text format, with a lock
+    /// state gauge for each `(vault, unlocked)` in `vaults`
+    pub fn render(&self, vaults: &[(String, bool)]) -> String {
+        let commands = self.commands.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        let mut out = String::new();
+
+        header(&mut out, "vault_requests_total", "counter", "Requests handled, by command");
+        for (cmd, stats) in &commands {
+            let _ = writeln!(out, "vault_requests_total{{cmd=\"{}\"}} {}", cmd, stats.requests);
+        }
+        header(&mut out, "vault_request_errors_total", "counter", "Requests answered with an error, by command");
+        for (cmd, stats) in &commands {
+            let _ = writeln!(out, "vault_request_errors_total{{cmd=\"{}\"}} {}", cmd, stats.errors);
+        }
+        header(&mut out, "vault_request_duration_seconds", "histogram", "Time taken to answer requests, by command");
+        for (cmd, stats) in &commands {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&stats.buckets) {
+                cumulative += count;
+                let _ = writeln!(out, "vault_request_duration_seconds_bucket{{cmd=\"{}\",le=\"{}\"}} {}", cmd, bound, cumulative);
+            }
+            let _ = writeln!(out, "vault_request_duration_seconds_bucket{{cmd=\"{}\",le=\"+Inf\"}} {}", cmd, stats.requests);
+            let _ = writeln!(out, "vault_request_duration_seconds_sum{{cmd=\"{}\"}} {}", cmd, stats.seconds);
+            let _ = writeln!(out, "vault_request_duration_seconds_count{{cmd=\"{}\"}} {}", cmd, stats.requests);
+        }
+
+        let counters = [
+            ("vault_invalid_requests_total", "Request lines that didn't parse", &self.invalid_requests),
+            ("vault_connections_total", "Connections accepted", &self.connections),
+            ("vault_rejected_connections_total", "Connections refused by the peer policy", &self.rejected_connections),
+            ("vault_unlocks_total", "Successful unlocks", &self.unlocks),
+            ("vault_locks_total", "Locks, including idle locks", &self.locks),
+        ];
+        for (name, help, counter) in counters {
+            header(&mut out, name, "counter", help);
+            let _ = writeln!(out, "{} {}", name, counter.load(Ordering::Relaxed));
+        }
+
+        header(&mut out, "vault_unlocked", "gauge", "Whether the vault is unlocked (1) or locked (0)");
+        for (name, unlocked) in vaults {
+            let _ = writeln!(out, "vault_unlocked{{vault=\"{}\"}} {}", escape_label(name), u8::from(*unlocked));
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escape a label value as the text format requires
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Bind the listener up front so a bad address fails daemon startup
+pub async fn bind(addr: SocketAddr) -> Result<TcpListener> {
+    if !addr.ip().is_loopback() {
+        tracing::warn!("Metrics listener on non-loopback address {}", addr);
+    }
+    let listener = TcpListener::bind(addr).await?;
+    tracing::info!("Metrics listening on {}", listener.local_addr()?);
+    Ok(listener)
+}
+
+/// Answer scrapes until the listener fails
+pub async fn serve(listener: TcpListener, daemon: Arc<RwLock<VaultDaemon>>) -> Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let daemon = Arc::clone(&daemon);
+        tokio::spawn(async move {
+            if let Err(e) = handle_scrape(stream, &daemon).await {
+                tracing::debug!("Metrics connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Answer one HTTP request, then close the connection
+async fn handle_scrape(stream: TcpStream, daemon: &RwLock<VaultDaemon>) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader).take(MAX_HEAD_BYTES);
+
+    let request_line = tokio::time::timeout(READ_TIMEOUT, async {
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await?;
+        // Headers are read and ignored, up to the blank line ending them
+        let mut header = String::new();
+        loop {
+            header.clear();
+            if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+        Ok::<_, std::io::Error>(request_line)
+    }).await??;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let daemon = daemon.read().await;
+            ("200 OK", daemon.metrics().render(&daemon.lock_states()))
+        }
+        (Some("GET"), _) => ("404 Not Found", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Only GET is supported\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, body.len(), body,
+    );
+    writer.write_all(response.as_bytes()).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ErrorCode;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record_request("get", &Response::ok(), Duration::from_millis(3));
+        metrics.record_request("get", &Response::error(ErrorCode::NotFound, "gone"), Duration::from_secs(60));
+        metrics.record_request("list", &Response::ok(), Duration::ZERO);
+        metrics.record_unlock();
+        metrics.record_connection(false);
+
+        let text = metrics.render(&[("default".into(), true), ("odd \"name\"".into(), false)]);
+        for line in [
+            "vault_requests_total{cmd=\"get\"} 2",
+            "vault_request_errors_total{cmd=\"get\"} 1",
+            "vault_request_errors_total{cmd=\"list\"} 0",
+            "vault_request_duration_seconds_bucket{cmd=\"get\",le=\"0.0025\"} 0",
+            "vault_request_duration_seconds_bucket{cmd=\"get\",le=\"0.005\"} 1",
+            "vault_request_duration_seconds_bucket{cmd=\"get\",le=\"30\"} 1",
+            "vault_request_duration_seconds_bucket{cmd=\"get\",le=\"+Inf\"} 2",
+            "vault_request_duration_seconds_count{cmd=\"get\"} 2",
+            "vault_unlocks_total 1",
+            "vault_locks_total 0",
+            "vault_rejected_connections_total 1",
+            "vault_unlocked{vault=\"default\"} 1",
+            "vault_unlocked{vault=\"odd \\\"name\\\"\"} 0",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?} in\n{}", line, text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve() {
+        let tmp = TempDir::new().unwrap();
+        let daemon = Arc::new(RwLock::new(VaultDaemon::new(tmp.path().join("vault"))));
+        let listener = bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, daemon));
+
+        let get = |path: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\nvault_unlocked{vault=\"default\"} 0\n"));
+        assert!(get("/").await.starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,57 @@
+//! Per-agent category allowlist
+//!
+//! Maps each agent id to the categories it may read. A vault without a
+//! policy allows everything (the behaviour before policies existed); once
+//! one is set, agents not listed and requests that don't name an agent
+//! are refused.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+use crate::vault::Category;
+
+/// Which categories each agent may read
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct AccessPolicy {
+    pub agents: BTreeMap<String, Vec<Category>>,
+}
+
+impl AccessPolicy {
+    /// Allow `agent_id` to read `categories` (builder; the daemon takes
+    /// whole policies from `set_policy`)
+    #[cfg(test)]
+    pub fn allow(mut self, agent_id: impl Into<String>, categories: &[Category]) -> Self {
+        self.agents.entry(agent_id.into()).or_default().extend_from_slice(categories);
+        self
+    }
+
+    /// Why `agent_id` may not read `category`, if it may not
+    pub fn check(&self, agent_id: Option<&str>, category: Category) -> Result<(), String> {
+        let Some(agent_id) = agent_id else {
+            return Err("policy requires an agent_id".to_string());
+        };
+        match self.agents.get(agent_id) {
+            Some(allowed) if allowed.contains(&category) => Ok(()),
+            Some(_) => Err(format!("agent '{}' may not access {:?}", agent_id, category)),
+            None => Err(format!("agent '{}' is not in the access policy", agent_id)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_check() {
+        let policy = AccessPolicy::default()
+            .allow("email-agent", &[Category::Authentication]);
+
+        assert!(policy.check(Some("email-agent"), Category::Authentication).is_ok());
+        assert!(policy.check(Some("email-agent"), Category::Financial).is_err());
+        assert!(policy.check(Some("stranger"), Category::Authentication).is_err());
+        assert!(policy.check(None, Category::Authentication).is_err());
+    }
+}
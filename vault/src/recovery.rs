@@ -0,0 +1,261 @@
+//! Shamir's Secret Sharing for vault recovery
+//!
+//! A random recovery key wraps the master key on disk; the recovery key
+//! itself is split into N shares over GF(256), any T of which rebuild it.
+//! Each byte of the secret gets its own random polynomial of degree T-1
+//! whose constant term is that byte; share `x` holds the evaluations at `x`.
+//!
+//! Shares print as Base58Check so they can be written down and typos are
+//! caught by the checksum before any combining is attempted.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sodiumoxide::randombytes::randombytes;
+use zeroize::Zeroize;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Version byte leading every encoded share
+const SHARE_FORMAT_VERSION: u8 = 1;
+
+/// One share of a split secret
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecoveryShare {
+    /// Evaluation point (1..=255, never 0: that is the secret itself)
+    pub index: u8,
+    /// Number of shares needed to recombine
+    pub threshold: u8,
+    /// Polynomial evaluations, one per secret byte
+    data: Vec<u8>,
+}
+
+impl RecoveryShare {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(3 + self.data.len());
+        out.push(SHARE_FORMAT_VERSION);
+        out.push(self.threshold);
+        out.push(self.index);
+        out.extend_from_slice(&self.data);
+        out
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            [SHARE_FORMAT_VERSION, threshold, index, data @ ..] if !data.is_empty() => {
+                if *index == 0 || *threshold == 0 {
+                    return Err(anyhow!("Malformed recovery share"));
+                }
+                Ok(Self { index: *index, threshold: *threshold, data: data.to_vec() })
+            }
+            [version, ..] if *version != SHARE_FORMAT_VERSION => {
+                Err(anyhow!("Unsupported recovery share version {}", version))
+            }
+            _ => Err(anyhow!("Malformed recovery share")),
+        }
+    }
+}
+
+impl Drop for RecoveryShare {
+    fn drop(&mut self) {
+        self.data.zeroize();
+    }
+}
+
+impl fmt::Display for RecoveryShare {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&bs58::encode(self.to_bytes()).with_check().into_string())
+    }
+}
+
+impl FromStr for RecoveryShare {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        // Tolerate the whitespace people add when copying shares by hand
+        let compact: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        let bytes = bs58::decode(compact)
+            .with_check(None)
+            .into_vec()
+            .map_err(|e| anyhow!("Invalid recovery share: {}", e))?;
+        Self::from_bytes(&bytes)
+    }
+}
+
+impl Serialize for RecoveryShare {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RecoveryShare {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Split `secret` into `shares` shares, any `threshold` of which recombine
+pub fn split(secret: &[u8], shares: u8, threshold: u8) -> Result<Vec<RecoveryShare>> {
+    if secret.is_empty() {
+        return Err(anyhow!("Cannot split an empty secret"));
+    }
+    if threshold == 0 || threshold > shares {
+        return Err(anyhow!(
+            "Threshold must be between 1 and the number of shares ({}), got {}",
+            shares, threshold
+        ));
+    }
+
+    let mut out: Vec<RecoveryShare> = (1..=shares)
+        .map(|index| RecoveryShare { index, threshold, data: Vec::with_capacity(secret.len()) })
+        .collect();
+
+    // coeffs[0] is the secret byte, the rest are random
+    let mut coeffs = vec![0u8; threshold as usize];
+    for &byte in secret {
+        coeffs[0] = byte;
+        coeffs[1..].copy_from_slice(&randombytes(threshold as usize - 1));
+        for share in out.iter_mut() {
+            share.data.push(eval_poly(&coeffs, share.index));
+        }
+    }
+    coeffs.zeroize();
+
+    Ok(out)
+}
+
+/// Rebuild the secret from at least `threshold` distinct shares
+pub fn combine(shares: &[RecoveryShare]) -> Result<Vec<u8>> {
+    let first = shares.first().ok_or_else(|| anyhow!("No recovery shares given"))?;
+    let threshold = first.threshold;
+    let len = first.data.len();
+
+    if shares.iter().any(|s| s.threshold != threshold || s.data.len() != len) {
+        return Err(anyhow!("Recovery shares come from different splits"));
+    }
+    for (i, s) in shares.iter().enumerate() {
+        if shares[..i].iter().any(|o| o.index == s.index) {
+            return Err(anyhow!("Duplicate recovery share #{}", s.index));
+        }
+    }
+    if shares.len() < threshold as usize {
+        return Err(anyhow!(
+            "Need {} recovery shares, got {}",
+            threshold, shares.len()
+        ));
+    }
+
+    // Any `threshold` shares determine the polynomial; extras are ignored
+    let used = &shares[..threshold as usize];
+
+    // Lagrange basis values at x = 0 (subtraction is XOR in GF(256))
+    let basis: Vec<u8> = used
+        .iter()
+        .map(|si| {
+            used.iter()
+                .filter(|sj| sj.index != si.index)
+                .fold(1u8, |acc, sj| {
+                    gf_mul(acc, gf_div(sj.index, sj.index ^ si.index))
+                })
+        })
+        .collect();
+
+    let secret = (0..len)
+        .map(|b| {
+            used.iter()
+                .zip(&basis)
+                .fold(0u8, |acc, (s, l)| acc ^ gf_mul(s.data[b], *l))
+        })
+        .collect();
+
+    Ok(secret)
+}
+
+/// Evaluate a polynomial at `x` (Horner's rule)
+fn eval_poly(coeffs: &[u8], x: u8) -> u8 {
+    coeffs.iter().rev().fold(0u8, |acc, &c| gf_mul(acc, x) ^ c)
+}
+
+/// Multiply in GF(2^8) modulo the AES polynomial x^8 + x^4 + x^3 + x + 1
+///
+/// Branch-free on the operands so timing doesn't depend on secret bytes.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & (b & 1).wrapping_neg();
+        let carry = (a >> 7).wrapping_neg();
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// Divide in GF(2^8); `b` must be non-zero
+fn gf_div(a: u8, b: u8) -> u8 {
+    // b^254 == b^-1 since the multiplicative group has order 255
+    let mut inv = 1u8;
+    let mut base = b;
+    let mut exp = 254u8;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            inv = gf_mul(inv, base);
+        }
+        base = gf_mul(base, base);
+        exp >>= 1;
+    }
+    gf_mul(a, inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gf_arithmetic() {
+        // Known AES field product
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+        for x in 1..=255u8 {
+            assert_eq!(gf_mul(gf_div(1, x), x), 1);
+        }
+    }
+
+    #[test]
+    fn test_split_combine_any_subset() {
+        let secret = b"0123456789abcdef0123456789abcdef";
+        let shares = split(secret, 5, 3).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        for subset in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let picked: Vec<_> = subset.iter().map(|&i| shares[i].clone()).collect();
+            assert_eq!(combine(&picked).unwrap(), secret);
+        }
+
+        // Below threshold is refused rather than returning garbage
+        assert!(combine(&shares[..2]).is_err());
+        // Duplicates don't count twice
+        let dup = vec![shares[0].clone(), shares[0].clone(), shares[1].clone()];
+        assert!(combine(&dup).is_err());
+        assert!(split(secret, 3, 4).is_err());
+        assert!(split(secret, 3, 0).is_err());
+    }
+
+    #[test]
+    fn test_printable_roundtrip() {
+        let shares = split(&[7u8; 32], 3, 2).unwrap();
+        let text = shares[1].to_string();
+        assert!(text.chars().all(|c| c.is_ascii_alphanumeric()));
+
+        let parsed: RecoveryShare = text.parse().unwrap();
+        assert_eq!(parsed, shares[1]);
+
+        // Spaces from hand-copying are fine; a typo is caught by the checksum
+        let spaced = format!("{} {}", &text[..10], &text[10..]);
+        assert_eq!(spaced.parse::<RecoveryShare>().unwrap(), shares[1]);
+        let typo: String = text.chars().rev().collect();
+        assert!(typo.parse::<RecoveryShare>().is_err());
+
+        let json = serde_json::to_string(&shares[0]).unwrap();
+        assert_eq!(serde_json::from_str::<RecoveryShare>(&json).unwrap(), shares[0]);
+    }
+}
@@ -0,0 +1,68 @@
+//! JSON Schema for the daemon protocol (`--dump-schema`)
+//!
+//! Generated from the types serde reads and writes on the socket, so it
+//! can't drift from what the daemon actually accepts. Clients in other
+//! languages can generate bindings from it instead of reading `api.rs`.
+
+use schemars::gen::SchemaSettings;
+use serde_json::{json, Value};
+
+use crate::api::{Greeting, Request, Response, PROTOCOL_VERSION};
+
+/// Schema for every line that crosses the socket
+///
+/// The root accepts any one message; `definitions` holds `Greeting`
+/// (sent once on connect), `Request` (tagged by `cmd`) and `Response`
+/// (tagged by `status`), plus the types they use.
+pub fn protocol_schema() -> Value {
+    let mut gen = SchemaSettings::draft07().into_generator();
+    let messages = [
+        gen.subschema_for::<Greeting>(),
+        gen.subschema_for::<Request>(),
+        gen.subschema_for::<Response>(),
+    ];
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "Prosperity vault protocol",
+        "description": format!(
+            "Protocol version {}. Newline-delimited JSON: the daemon sends a Greeting, \
+             then one Response per Request.",
+            PROTOCOL_VERSION
+        ),
+        "oneOf": messages,
+        "definitions": gen.definitions(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tag values of a `oneOf` of internally tagged variants
+    fn tags(schema: &Value, tag: &str) -> Vec<String> {
+        schema["oneOf"].as_array().unwrap().iter()
+            .map(|variant| variant["properties"][tag]["enum"][0].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_schema_covers_protocol() {
+        let schema = protocol_schema();
+        let defs = &schema["definitions"];
+
+        let commands = tags(&defs["Request"], "cmd");
+        for cmd in ["unlock", "update_passphrase", "get", "use_for_auth", "batch", "hello"] {
+            assert!(commands.iter().any(|c| c == cmd), "missing {}", cmd);
+        }
+        assert_eq!(tags(&defs["Response"], "status"), ["ok", "error"]);
+        assert!(defs["ErrorCode"]["enum"].as_array().unwrap().contains(&json!("rate_limited")));
+
+        // Every reference resolves
+        let text = schema.to_string();
+        for reference in text.split("\"$ref\":\"#/definitions/").skip(1) {
+            let name = &reference[..reference.find('"').unwrap()];
+            assert!(defs.get(name).is_some(), "dangling $ref {}", name);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Rough passphrase strength estimate for new vaults
+//!
+//! Argon2 only slows guessing down; a passphrase from the top of a
+//! cracking dictionary still falls quickly. This estimates guessing
+//! entropy the way zxcvbn does in spirit (length, character variety,
+//! repeats and sequences, common passwords) without its dictionaries.
+
+/// Score new vaults need by default (0-4)
+pub const DEFAULT_MIN_SCORE: u8 = 3;
+
+/// Entropy (bits) needed for scores 1 through 4
+const SCORE_BITS: [f64; 4] = [28.0, 36.0, 50.0, 64.0];
+
+/// Length below which a passphrase gets a "make it longer" hint
+const RECOMMENDED_LENGTH: usize = 12;
+
+/// A repeated character or one continuing a sequence ("aaa", "abc",
+/// "321") counts as this fraction of a character
+const PATTERN_WEIGHT: f64 = 0.25;
+
+/// Passwords at the top of every breach list, lowercase
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456", "123456789", "12345678", "12345", "1234567", "1234567890", "111111", "000000",
+    "123123", "654321", "666666", "121212", "112233", "987654321", "password", "passw0rd",
+    "passphrase", "qwerty", "qwertyuiop", "asdfgh", "asdfghjkl", "zxcvbnm", "1q2w3e4r",
+    "1qaz2wsx", "qazwsx", "abc123", "letmein", "welcome", "admin", "administrator", "root",
+    "login", "master", "monkey", "dragon", "football", "baseball", "basketball", "soccer",
+    "hockey", "princess", "sunshine", "iloveyou", "trustno1", "shadow", "superman", "batman",
+    "michael", "jennifer", "jordan", "hunter", "ranger", "buster", "tigger", "charlie",
+    "freedom", "whatever", "secret", "starwars", "pokemon", "computer", "internet", "matrix",
+    "mustang", "harley", "access", "flower", "hello", "hello123", "cheese", "killer", "ninja",
+    "azerty", "solo", "default", "changeme", "guest", "test", "test123", "summer", "winter",
+    "spring", "autumn", "google", "facebook", "linkedin", "chocolate", "butterfly", "lovely",
+    "loveme", "money", "pepper", "ginger", "jessica", "ashley", "daniel", "thomas", "robert",
+    "vault", "prosperity",
+];
+
+/// How guessable a passphrase is
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrengthReport {
+    /// 0 (trivially guessed) to 4 (strong)
+    pub score: u8,
+    /// What would make it stronger; empty for a strong passphrase
+    pub feedback: Vec<String>,
+}
+
+/// Estimate how hard `passphrase` is to guess
+pub fn estimate_strength(passphrase: &str) -> StrengthReport {
+    let chars: Vec<char> = passphrase.chars().collect();
+    let mut feedback = Vec::new();
+
+    if is_common(passphrase) {
+        feedback.push("This is one of the most common passwords; decorating it with digits, \
+                       symbols or l33t spelling doesn't help".to_string());
+        return StrengthReport { score: 0, feedback };
+    }
+
+    let bits = effective_length(&chars) * (pool_size(&chars) as f64).log2();
+    let score = SCORE_BITS.iter().filter(|&&needed| bits >= needed).count() as u8;
+
+    if chars.len() < RECOMMENDED_LENGTH {
+        feedback.push(format!(
+            "Use at least {} characters; a few unrelated words are easy to remember",
+            RECOMMENDED_LENGTH
+        ));
+    }
+    if score < 4 && character_classes(&chars) == 1 {
+        feedback.push("Mix in other kinds of characters, or add more words".to_string());
+    }
+    if score < 4 && effective_length(&chars) < chars.len() as f64 {
+        feedback.push("Avoid repeated characters and sequences like \"abc\" or \"4321\"".to_string());
+    }
+
+    StrengthReport { score, feedback }
+}
+
+/// Whether `passphrase` is a common password, ignoring case, l33t
+/// spelling and digits or symbols tacked on either end
+fn is_common(passphrase: &str) -> bool {
+    let lower = passphrase.trim().to_lowercase();
+    let unleet: String = lower.chars()
+        .map(|c| match c {
+            '0' => 'o',
+            '1' | '!' => 'i',
+            '3' => 'e',
+            '4' | '@' => 'a',
+            '5' | '$' => 's',
+            '7' => 't',
+            c => c,
+        })
+        .collect();
+    let stripped = lower.trim_matches(|c: char| !c.is_alphabetic());
+
+    [lower.as_str(), unleet.as_str(), stripped].iter()
+        .any(|candidate| COMMON_PASSWORDS.contains(candidate))
+}
+
+/// Length with repeats and sequences discounted
+fn effective_length(chars: &[char]) -> f64 {
+    chars.iter()
+        .enumerate()
+        .map(|(i, &c)| {
+            let Some(prev) = i.checked_sub(1).map(|p| chars[p]) else {
+                return 1.0;
+            };
+            match (c as i64 - prev as i64).abs() {
+                0 | 1 => PATTERN_WEIGHT,
+                _ => 1.0,
+            }
+        })
+        .sum()
+}
+
+/// Kinds of characters used: lowercase, uppercase, digits, ASCII
+/// symbols (including space) and anything else
+fn character_classes(chars: &[char]) -> usize {
+    classes(chars).iter().filter(|(used, _)| *used).count()
+}
+
+/// Characters an attacker would have to try per position
+fn pool_size(chars: &[char]) -> u32 {
+    classes(chars).iter()
+        .filter(|(used, _)| *used)
+        .map(|(_, size)| size)
+        .sum::<u32>()
+        .max(1)
+}
+
+fn classes(chars: &[char]) -> [(bool, u32); 5] {
+    [
+        (chars.iter().any(|c| c.is_ascii_lowercase()), 26),
+        (chars.iter().any(|c| c.is_ascii_uppercase()), 26),
+        (chars.iter().any(|c| c.is_ascii_digit()), 10),
+        (chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' '), 33),
+        (chars.iter().any(|c| !c.is_ascii()), 100),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_common_passwords_score_zero() {
+        for weak in ["password", "P@ssw0rd", "password123!", "Qwerty", "letmein2024"] {
+            let report = estimate_strength(weak);
+            assert_eq!(report.score, 0, "{}", weak);
+            assert!(!report.feedback.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_strength_grows_with_length_and_variety() {
+        assert_eq!(estimate_strength("").score, 0);
+        assert_eq!(estimate_strength("zebra").score, 0);
+        // Sequences and repeats barely count
+        assert!(estimate_strength("abcdefghijkl").score < estimate_strength("qmzvtrwkxbnp").score);
+        assert!(estimate_strength("aaaaaaaaaaaaaaaaaaaa").score <= 1);
+
+        let strong = estimate_strength("correct horse battery staple");
+        assert_eq!(strong.score, 4);
+        assert!(strong.feedback.is_empty());
+        assert!(estimate_strength("Gq7#kP2v!x").score >= 3);
+    }
+}
@@ -0,0 +1,214 @@
+//! TOTP enrollment from `otpauth://` URIs
+//!
+//! Authenticator QR codes carry a URI like
+//! `otpauth://totp/Issuer:alice@example.com?secret=JBSWY3DP...&issuer=Issuer&digits=6&period=30`.
+//! Parsing it yields the secret plus the parameters codes must be made
+//! with; they are kept on the `TotpSeed` entry as custom fields, so codes
+//! generated from it don't fall back to the RFC 6238 defaults.
+
+use anyhow::{anyhow, Result};
+use percent_encoding::percent_decode_str;
+use url::Url;
+use zeroize::Zeroizing;
+
+use crate::validate::decode_base32;
+use crate::vault::{Category, CustomField, EntryType, VaultEntry};
+
+/// Code length when the URI doesn't give one
+const DEFAULT_DIGITS: u32 = 6;
+
+/// Seconds per code when the URI doesn't give one
+const DEFAULT_PERIOD: u32 = 30;
+
+/// HMAC hash the codes are made with
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TotpAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl TotpAlgorithm {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "SHA1" => Some(Self::Sha1),
+            "SHA256" => Some(Self::Sha256),
+            "SHA512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Sha1 => "SHA1",
+            Self::Sha256 => "SHA256",
+            Self::Sha512 => "SHA512",
+        }
+    }
+}
+
+/// Everything an `otpauth://totp/` URI says about a TOTP secret
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TotpParams {
+    /// Base32, upper case, without spaces or padding
+    pub secret: Zeroizing<String>,
+    pub issuer: Option<String>,
+    /// The account the codes are for, from the URI's label
+    pub account: String,
+    pub digits: u32,
+    pub period: u32,
+    pub algorithm: TotpAlgorithm,
+}
+
+impl TotpParams {
+    /// Name for the entry: "Issuer (account)", or just the account
+    pub fn display_name(&self) -> String {
+        match &self.issuer {
+            Some(issuer) => format!("{} ({})", issuer, self.account),
+            None => self.account.clone(),
+        }
+    }
+
+    /// A `TotpSeed` entry in Authentication holding the secret, with the
+    /// account as its username and the other parameters as custom fields
+    pub fn into_entry(self, name: impl Into<String>) -> VaultEntry {
+        let field = |name: &str, value: String| CustomField {
+            name: name.to_string(),
+            value: Zeroizing::new(value),
+            sensitive: false,
+        };
+        let mut custom_fields = vec![
+            field("digits", self.digits.to_string()),
+            field("period", self.period.to_string()),
+            field("algorithm", self.algorithm.name().to_string()),
+        ];
+        if let Some(issuer) = self.issuer {
+            custom_fields.push(field("issuer", issuer));
+        }
+
+        let mut entry = VaultEntry::new(
+            Category::Authentication,
+            EntryType::TotpSeed,
+            name,
+            self.secret.as_bytes().to_vec(),
+        ).with_username(self.account);
+        entry.custom_fields = custom_fields;
+        entry
+    }
+}
+
+/// Parse an `otpauth://totp/` URI, filling in the defaults it leaves out
+///
+/// HOTP URIs are refused, as are secrets that aren't base32 and digits or
+/// periods no authenticator uses. An `issuer` parameter wins over the
+/// label's "Issuer:" prefix.
+pub fn parse_otpauth_uri(uri: &str) -> Result<TotpParams> {
+    let url = Url::parse(uri.trim()).map_err(|e| anyhow!("Invalid otpauth URI: {}", e))?;
+    if url.scheme() != "otpauth" {
+        return Err(anyhow!("Not an otpauth URI"));
+    }
+    match url.host_str() {
+        Some("totp") => {}
+        Some("hotp") => return Err(anyhow!("HOTP (counter-based) secrets aren't supported")),
+        _ => return Err(anyhow!("Invalid otpauth URI: expected otpauth://totp/")),
+    }
+
+    let label = percent_decode_str(url.path().trim_start_matches('/'))
+        .decode_utf8()
+        .map_err(|_| anyhow!("Invalid otpauth URI: label isn't UTF-8"))?;
+    let (label_issuer, account) = match label.split_once(':') {
+        Some((issuer, account)) => (Some(issuer.trim().to_string()), account.trim().to_string()),
+        None => (None, label.trim().to_string()),
+    };
+    if account.is_empty() {
+        return Err(anyhow!("Invalid otpauth URI: no account in the label"));
+    }
+
+    let mut params = TotpParams {
+        secret: Zeroizing::new(String::new()),
+        issuer: label_issuer.filter(|i| !i.is_empty()),
+        account,
+        digits: DEFAULT_DIGITS,
+        period: DEFAULT_PERIOD,
+        algorithm: TotpAlgorithm::default(),
+    };
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "secret" => {
+                let secret: String = value.chars()
+                    .filter(|c| !c.is_whitespace() && *c != '=')
+                    .map(|c| c.to_ascii_uppercase())
+                    .collect();
+                if decode_base32(&secret).is_none_or(|bytes| bytes.is_empty()) {
+                    return Err(anyhow!("Invalid otpauth URI: secret isn't base32"));
+                }
+                params.secret = Zeroizing::new(secret);
+            }
+            "issuer" if !value.trim().is_empty() => params.issuer = Some(value.trim().to_string()),
+            "digits" => {
+                params.digits = value.parse().ok()
+                    .filter(|d| (6..=8).contains(d))
+                    .ok_or_else(|| anyhow!("Invalid otpauth URI: digits must be 6 to 8"))?;
+            }
+            "period" => {
+                params.period = value.parse().ok()
+                    .filter(|p| (1..=3600).contains(p))
+                    .ok_or_else(|| anyhow!("Invalid otpauth URI: period must be 1 to 3600 seconds"))?;
+            }
+            "algorithm" => {
+                params.algorithm = TotpAlgorithm::parse(&value)
+                    .ok_or_else(|| anyhow!("Invalid otpauth URI: unknown algorithm {:?}", value))?;
+            }
+            _ => {}
+        }
+    }
+    if params.secret.is_empty() {
+        return Err(anyhow!("Invalid otpauth URI: no secret parameter"));
+    }
+    Ok(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_otpauth_uri() {
+        let params = parse_otpauth_uri(
+            "otpauth://totp/ACME%20Co:john.doe%40email.com?secret=hxdm vjec jjws rb3h wizr 4ifu gftm xboz\
+             &issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60",
+        ).unwrap();
+        assert_eq!(params.secret.as_str(), "HXDMVJECJJWSRB3HWIZR4IFUGFTMXBOZ");
+        assert_eq!(params.issuer.as_deref(), Some("ACME Co"));
+        assert_eq!(params.account, "john.doe@email.com");
+        assert_eq!((params.digits, params.period, params.algorithm), (8, 60, TotpAlgorithm::Sha256));
+        assert_eq!(params.display_name(), "ACME Co (john.doe@email.com)");
+
+        // Defaults fill in what's left out
+        let params = parse_otpauth_uri("otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP").unwrap();
+        assert_eq!((params.issuer, params.account.as_str()), (None, "alice"));
+        assert_eq!((params.digits, params.period, params.algorithm), (6, 30, TotpAlgorithm::Sha1));
+
+        let entry = parse_otpauth_uri("otpauth://totp/GitHub:octocat?secret=JBSWY3DPEHPK3PXP&period=45")
+            .unwrap()
+            .into_entry("GitHub");
+        assert_eq!((entry.category, entry.entry_type), (Category::Authentication, EntryType::TotpSeed));
+        assert_eq!((entry.value.as_slice(), entry.username.as_deref()), (b"JBSWY3DPEHPK3PXP".as_slice(), Some("octocat")));
+        let fields: Vec<(&str, &str)> = entry.custom_fields.iter().map(|f| (f.name.as_str(), f.value.as_str())).collect();
+        assert_eq!(fields, [("digits", "6"), ("period", "45"), ("algorithm", "SHA1"), ("issuer", "GitHub")]);
+
+        for bad in [
+            "https://example.com/?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://hotp/alice?secret=JBSWY3DPEHPK3PXP&counter=1",
+            "otpauth://totp/alice?issuer=x",
+            "otpauth://totp/alice?secret=not-base32",
+            "otpauth://totp/?secret=JBSWY3DPEHPK3PXP",
+            "otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP&digits=4",
+            "otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP&period=0",
+            "otpauth://totp/alice?secret=JBSWY3DPEHPK3PXP&algorithm=MD5",
+        ] {
+            assert!(parse_otpauth_uri(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! Checks on new entries at creation time
+//!
+//! Only entry types with a well-defined format are checked; free-form types
+//! (passwords, notes, ...) accept anything up to `MAX_VALUE_BYTES`. The point is to catch typos
+//! when the value is entered rather than when it is first used.
+//!
+//! Names, URLs and the category an entry type goes in are checked too, so
+//! junk doesn't end up in the vault.
+
+use url::Url;
+
+use crate::totp;
+use crate::vault::{Category, EntryType};
+
+/// Shortest TOTP secret accepted (RFC 4226 requires at least 128 bits,
+/// but 80-bit secrets are still common in the wild)
+const MIN_TOTP_SECRET_BYTES: usize = 10;
+
+/// Largest entry value accepted, in bytes; files belong in attachments
+pub const MAX_VALUE_BYTES: usize = 1024 * 1024;
+
+/// Largest entry notes accepted, in bytes
+pub const MAX_NOTES_BYTES: usize = 16 * 1024;
+
+/// `notes` with any NUL bytes removed, or why they're too long
+///
+/// Notes are markdown for clients to render; JSON already guarantees
+/// they're UTF-8.
+pub fn clean_notes(notes: &str) -> Result<String, String> {
+    let cleaned = notes.replace('\0', "");
+    if cleaned.len() > MAX_NOTES_BYTES {
+        return Err(format!("Notes are {} bytes; the limit is {}", cleaned.len(), MAX_NOTES_BYTES));
+    }
+    Ok(cleaned)
+}
+
+/// Longest entry name accepted, in characters
+pub const MAX_NAME_CHARS: usize = 256;
+
+/// Why `name` isn't acceptable, if it isn't
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("Name is empty".to_string());
+    }
+    let chars = name.chars().count();
+    if chars > MAX_NAME_CHARS {
+        return Err(format!("Name is {} characters; the limit is {}", chars, MAX_NAME_CHARS));
+    }
+    Ok(())
+}
+
+/// `folder` as a canonical path (`None` for the top level), or why it
+/// isn't acceptable
+///
+/// Surrounding slashes and spaces around each segment are dropped, so
+/// " /work/ aws/" is "work/aws"; an empty segment in the middle is refused.
+pub fn clean_folder(folder: &str) -> Result<Option<String>, String> {
+    let trimmed = folder.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+    let segments: Vec<&str> = trimmed.split('/').map(str::trim).collect();
+    if segments.iter().any(|s| s.is_empty()) {
+        return Err(format!("Folder '{}' has an empty segment", folder));
+    }
+    let path = segments.join("/");
+    let chars = path.chars().count();
+    if chars > MAX_NAME_CHARS {
+        return Err(format!("Folder is {} characters; the limit is {}", chars, MAX_NAME_CHARS));
+    }
+    Ok(Some(path))
+}
+
+/// Why `url` isn't an absolute URL, if it isn't
+pub fn check_url(url: &str) -> Result<(), String> {
+    Url::parse(url).map(drop).map_err(|e| format!("Invalid URL: {}", e))
+}
+
+/// Why `entry_type` doesn't belong in `category`, if it doesn't
+///
+/// Only types with an obvious home are tied to it: cards and bank accounts
+/// are financial, and the pattern types are patterns. Everything else
+/// (passwords, notes, ...) goes anywhere.
+pub fn check_category(entry_type: EntryType, category: Category) -> Result<(), String> {
+    let home = match entry_type {
+        EntryType::Card | EntryType::BankAccount => Category::Financial,
+        EntryType::Command | EntryType::Preference | EntryType::Schedule => Category::Patterns,
+        _ => return Ok(()),
+    };
+    if category != home {
+        return Err(format!("{:?} entries belong in {:?}, not {:?}", entry_type, home, category));
+    }
+    Ok(())
+}
+
+/// Why `value` isn't acceptable for `entry_type`, if it isn't
+pub fn check_value(entry_type: EntryType, value: &[u8]) -> Result<(), String> {
+    if value.len() > MAX_VALUE_BYTES {
+        return Err(format!("Value is {} bytes; the limit is {}", value.len(), MAX_VALUE_BYTES));
+    }
+    match entry_type {
+        EntryType::TotpSeed => check_totp_seed(value),
+        EntryType::Card => check_card(value),
+        _ => Ok(()),
+    }
+}
+
+/// A base32 secret (spaces allowed) or an `otpauth://` URI carrying one
+fn check_totp_seed(value: &[u8]) -> Result<(), String> {
+    let text = std::str::from_utf8(value)
+        .map_err(|_| "Invalid TOTP seed: not text".to_string())?
+        .trim();
+
+    let secret = if text.starts_with("otpauth://") {
+        let params = totp::parse_otpauth_uri(text).map_err(|e| e.to_string())?;
+        params.secret.to_string()
+    } else {
+        text.to_string()
+    };
+
+    let bytes = decode_base32(&secret)
+        .ok_or_else(|| "Invalid TOTP seed: not base32".to_string())?;
+    if bytes.len() < MIN_TOTP_SECRET_BYTES {
+        return Err(format!(
+            "Invalid TOTP seed: {} bytes, expected at least {}",
+            bytes.len(),
+            MIN_TOTP_SECRET_BYTES
+        ));
+    }
+    Ok(())
+}
+
+/// Values that look like a bare card number must pass the Luhn check;
+/// anything else (e.g. structured card details) is left alone
+fn check_card(value: &[u8]) -> Result<(), String> {
+    let Ok(text) = std::str::from_utf8(value) else {
+        return Ok(());
+    };
+    let text = text.trim();
+    if !text.chars().all(|c| c.is_ascii_digit() || c == ' ' || c == '-') {
+        return Ok(());
+    }
+
+    let digits: Vec<u32> = text.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(12..=19).contains(&digits.len()) {
+        return Ok(());
+    }
+    if !luhn_valid(&digits) {
+        return Err("Invalid card number: checksum does not match".to_string());
+    }
+    Ok(())
+}
+
+/// RFC 4648 base32, case-insensitive, ignoring spaces and `=` padding
+pub fn decode_base32(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for c in text.chars().filter(|c| !c.is_whitespace()) {
+        if c == '=' {
+            break;
+        }
+        let v = match c.to_ascii_uppercase() {
+            c @ 'A'..='Z' => c as u32 - 'A' as u32,
+            c @ '2'..='7' => c as u32 - '2' as u32 + 26,
+            _ => return None,
+        };
+        buffer = (buffer << 5) | v;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Luhn mod-10 checksum over `digits`, check digit last
+fn luhn_valid(digits: &[u32]) -> bool {
+    let sum: u32 = digits.iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match i % 2 {
+            0 => d,
+            _ if d * 2 > 9 => d * 2 - 9,
+            _ => d * 2,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_folder() {
+        assert_eq!(clean_folder(" /work/ aws /prod/").unwrap().as_deref(), Some("work/aws/prod"));
+        assert_eq!(clean_folder("/").unwrap(), None);
+        assert_eq!(clean_folder("").unwrap(), None);
+        assert!(clean_folder("work//aws").is_err());
+        assert!(clean_folder(&"x".repeat(MAX_NAME_CHARS + 1)).is_err());
+    }
+
+    #[test]
+    fn test_totp_seed_validation() {
+        assert_eq!(decode_base32("MZXW6YTBOI").unwrap(), b"foobar");
+        assert!(check_value(EntryType::TotpSeed, b"JBSW Y3DP EHPK 3PXP").is_ok());
+        assert!(check_value(EntryType::TotpSeed, b"jbswy3dpehpk3pxp").is_ok());
+        assert!(check_value(
+            EntryType::TotpSeed,
+            b"otpauth://totp/Example:alice?secret=JBSWY3DPEHPK3PXP&issuer=Example",
+        ).is_ok());
+
+        assert!(check_value(EntryType::TotpSeed, b"JBSWY3DP1").is_err());  // '1' isn't base32
+        assert!(check_value(EntryType::TotpSeed, b"JBSWY3DP").is_err());  // 5 bytes
+        assert!(check_value(EntryType::TotpSeed, b"otpauth://totp/x?issuer=y").is_err());
+    }
+
+    #[test]
+    fn test_card_validation() {
+        assert!(check_value(EntryType::Card, b"4111 1111 1111 1111").is_ok());
+        assert!(check_value(EntryType::Card, b"4111-1111-1111-1112").is_err());
+        // Not a bare number: left alone
+        assert!(check_value(EntryType::Card, br#"{"number": "4111"}"#).is_ok());
+        // Free-form types accept anything
+        assert!(check_value(EntryType::Password, b"4111 1111 1111 1112").is_ok());
+    }
+
+    #[test]
+    fn test_field_validation() {
+        assert!(check_name("GitHub").is_ok());
+        assert!(check_name(&"é".repeat(MAX_NAME_CHARS)).is_ok());
+        assert!(check_name(&"x".repeat(MAX_NAME_CHARS + 1)).is_err());
+        assert!(check_name("").is_err());
+        assert!(check_name("  ").is_err());
+
+        assert!(check_url("https://github.com/login").is_ok());
+        assert!(check_url("github.com").is_err());
+
+        assert!(check_category(EntryType::Card, Category::Financial).is_ok());
+        assert!(check_category(EntryType::Card, Category::Personal).is_err());
+        assert!(check_category(EntryType::Schedule, Category::Patterns).is_ok());
+        assert!(check_category(EntryType::Command, Category::Authentication).is_err());
+        assert!(check_category(EntryType::Password, Category::Financial).is_ok());
+
+        assert_eq!(clean_notes("# PIN\0 hint").unwrap(), "# PIN hint");
+        assert!(clean_notes(&"x".repeat(MAX_NOTES_BYTES)).is_ok());
+        assert!(clean_notes(&"x".repeat(MAX_NOTES_BYTES + 1)).is_err());
+    }
+}
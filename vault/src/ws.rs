@@ -0,0 +1,210 @@
+//! WebSocket transport for the vault daemon (feature `websocket`)
+//!
+//! Carries the same JSON `Request`/`Response` protocol as the Unix socket,
+//! one request per line inside text frames, for agents that can't reach a
+//! local socket. There are no peer credentials on TCP, so every connection
+//! must present the bearer token given at startup before the upgrade is
+//! accepted: either as `Authorization: Bearer <token>` or, for browsers
+//! that can't set headers, an `access_token` query parameter.
+
+use anyhow::{anyhow, Result};
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request as HttpRequest, Response as HttpResponse};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::api::{handle_timed, ErrorCode, Greeting, Request, Response, VaultDaemon};
+use crate::crypto::ct_eq;
+
+/// WebSocket listener settings (from the command line)
+#[derive(Clone)]
+pub struct WsConfig {
+    pub listen: SocketAddr,
+    pub token: String,
+}
+
+impl std::fmt::Debug for WsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Never print the token
+        f.debug_struct("WsConfig").field("listen", &self.listen).finish_non_exhaustive()
+    }
+}
+
+/// Bind the listener up front so a bad address fails daemon startup
+pub async fn bind(config: &WsConfig) -> Result<TcpListener> {
+    if config.token.is_empty() {
+        return Err(anyhow!("WebSocket token must not be empty"));
+    }
+    if !config.listen.ip().is_loopback() {
+        tracing::warn!("WebSocket listener on non-loopback address {}", config.listen);
+    }
+
+    let listener = TcpListener::bind(config.listen).await?;
+    tracing::info!("WebSocket listening on {}", listener.local_addr()?);
+    Ok(listener)
+}
+
+/// Accept WebSocket connections forever
+pub async fn serve(
+    listener: TcpListener,
+    token: String,
+    daemon: Arc<RwLock<VaultDaemon>>,
+    max_message: usize,
+    request_timeout: Duration,
+) -> Result<()> {
+    let token: Arc<str> = token.into();
+    loop {
+        let (stream, addr) = listener.accept().await?;
+        let daemon = Arc::clone(&daemon);
+        let token = Arc::clone(&token);
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_ws_connection(stream, &token, daemon, max_message, request_timeout).await {
+                tracing::warn!("WebSocket connection from {} failed: {}", addr, e);
+            }
+        });
+    }
+}
+
+async fn handle_ws_connection(
+    stream: TcpStream,
+    token: &str,
+    daemon: Arc<RwLock<VaultDaemon>>,
+    max_message: usize,
+    request_timeout: Duration,
+) -> Result<()> {
+    // The error type is fixed by tungstenite's handshake callback
+    #[allow(clippy::result_large_err)]
+    let check = |req: &HttpRequest, resp: HttpResponse| -> Result<HttpResponse, ErrorResponse> {
+        if presented_token(req).is_some_and(|t| token_matches(t, token)) {
+            Ok(resp)
+        } else {
+            let mut denied = ErrorResponse::new(Some("Permission denied".to_string()));
+            *denied.status_mut() = StatusCode::UNAUTHORIZED;
+            Err(denied)
+        }
+    };
+    // tungstenite refuses (and closes on) anything bigger
+    let limits = WebSocketConfig::default()
+        .max_message_size(Some(max_message))
+        .max_frame_size(Some(max_message));
+    let ws = tokio_tungstenite::accept_hdr_async_with_config(stream, check, Some(limits)).await?;
+    let metrics = daemon.read().await.metrics();
+    metrics.record_connection(true);
+    let (mut sink, mut source) = ws.split();
+    sink.send(Message::text(serde_json::to_string(&Greeting::current())?)).await?;
+
+    while let Some(msg) = source.next().await {
+        let text = match msg? {
+            Message::Text(text) => text,
+            Message::Close(_) => break,
+            // Pings are answered by tungstenite; binary isn't part of the protocol
+            Message::Binary(_) => {
+                let response = Response::error(ErrorCode::InvalidRequest, "Binary frames are not supported");
+                sink.send(Message::text(serde_json::to_string(&response)?)).await?;
+                continue;
+            }
+            _ => continue,
+        };
+
+        for line in text.as_str().lines().filter(|l| !l.trim().is_empty()) {
+            let mut refused_hello = false;
+            let response = match serde_json::from_str::<Request>(line) {
+                Ok(Request::Subscribe { .. }) => {
+                    Response::error(ErrorCode::InvalidRequest, "Subscribe is only available on the Unix socket")
+                }
+                Ok(req) => {
+                    let hello = matches!(req, Request::Hello { .. });
+                    let command = req.command();
+                    let started = Instant::now();
+                    let response = handle_timed(&daemon, req, request_timeout).await;
+                    metrics.record_request(command, &response, started.elapsed());
+                    refused_hello = hello && matches!(response, Response::Error { .. });
+                    response
+                }
+                Err(e) => {
+                    metrics.record_invalid_request();
+                    Response::error(ErrorCode::InvalidRequest, format!("Invalid request: {}", e))
+                }
+            };
+            sink.send(Message::text(serde_json::to_string(&response)?)).await?;
+            if refused_hello {
+                sink.send(Message::Close(None)).await?;
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Token from the `Authorization` header, else the `access_token` query
+fn presented_token(req: &HttpRequest) -> Option<&str> {
+    let header = req.headers()
+        .get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    header.or_else(|| {
+        req.uri().query()?
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("access_token="))
+    })
+}
+
+/// Compare without leaking how much of the token matched
+fn token_matches(presented: &str, expected: &str) -> bool {
+    ct_eq(presented.as_bytes(), expected.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::parse_listen_addr;
+    use tempfile::TempDir;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    #[tokio::test]
+    async fn test_requires_token_then_speaks_protocol() {
+        let tmp = TempDir::new().unwrap();
+        let daemon = Arc::new(RwLock::new(VaultDaemon::new(tmp.path().join("vault"))));
+        let config = WsConfig { listen: parse_listen_addr("0").unwrap(), token: "s3cret".into() };
+        let listener = bind(&config).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(
+            listener,
+            config.token.clone(),
+            daemon,
+            crate::api::DEFAULT_MAX_REQUEST_BYTES,
+            crate::api::DEFAULT_REQUEST_TIMEOUT,
+        ));
+
+        let connect = |auth: Option<&'static str>| async move {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            let mut req = format!("ws://{}/", addr).into_client_request().unwrap();
+            if let Some(auth) = auth {
+                req.headers_mut().insert("authorization", auth.parse().unwrap());
+            }
+            tokio_tungstenite::client_async(req, stream).await
+        };
+
+        assert!(connect(None).await.is_err());
+        assert!(connect(Some("Bearer wrong")).await.is_err());
+
+        let (mut ws, _) = connect(Some("Bearer s3cret")).await.unwrap();
+        let greeting = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        assert!(greeting.as_str().contains("protocol_version"));
+        ws.send(Message::text("{\"cmd\":\"status\"}\n")).await.unwrap();
+        let reply = ws.next().await.unwrap().unwrap().into_text().unwrap();
+        let reply: serde_json::Value = serde_json::from_str(reply.as_str()).unwrap();
+        assert_eq!(reply["status"], "ok");
+        assert_eq!(reply["data"]["unlocked"], false);
+    }
+}
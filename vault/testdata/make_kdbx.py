@@ -0,0 +1,249 @@
+#!/usr/bin/env python3
+"""Write the KeePass fixtures the `import-kdbx` tests read.
+
+Written from the KDBX format description, independently of the Rust
+reader, so the tests check it against files it didn't produce. Needs
+`cryptography` (44 or later, for Argon2id). Keys, seeds and IVs are fixed
+so the output is reproducible.
+
+    python3 testdata/make_kdbx.py    # from vault/
+"""
+
+import base64
+import gzip
+import hashlib
+import hmac
+import os
+import struct
+
+from cryptography.hazmat.primitives import padding
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+from cryptography.hazmat.primitives.kdf.argon2 import Argon2id
+
+PASSWORD = b"correct horse"
+SIGNATURE = bytes.fromhex("03d9a29a67fb4bb5")
+CIPHER_AES256 = bytes.fromhex("31c1f2e6bf714350be5805216afc5aff")
+CIPHER_CHACHA20 = bytes.fromhex("d6038a2b8b6f4cb5a524339a31dbb59a")
+KDF_ARGON2ID = bytes.fromhex("9e298b1956db4773b23dfc3ec6f0a1e6")
+SALSA20_NONCE = bytes.fromhex("e830094b97205d2a")
+RECYCLE_BIN = bytes(range(16, 32))
+OUT = os.path.dirname(os.path.abspath(__file__))
+
+
+def sha256(data):
+    return hashlib.sha256(data).digest()
+
+
+def sha512(data):
+    return hashlib.sha512(data).digest()
+
+
+def salsa20_stream(key, nonce, length):
+    """Salsa20/20 keystream (not in `cryptography`)"""
+    def rotl(v, c):
+        return ((v << c) & 0xFFFFFFFF) | (v >> (32 - c))
+
+    def quarter(x, a, b, c, d):
+        x[b] ^= rotl((x[a] + x[d]) & 0xFFFFFFFF, 7)
+        x[c] ^= rotl((x[b] + x[a]) & 0xFFFFFFFF, 9)
+        x[d] ^= rotl((x[c] + x[b]) & 0xFFFFFFFF, 13)
+        x[a] ^= rotl((x[d] + x[c]) & 0xFFFFFFFF, 18)
+
+    k = struct.unpack("<8I", key)
+    n = struct.unpack("<2I", nonce)
+    const = struct.unpack("<4I", b"expand 32-byte k")
+    out = b""
+    counter = 0
+    while len(out) < length:
+        state = [const[0], k[0], k[1], k[2], k[3], const[1], n[0], n[1],
+                 counter & 0xFFFFFFFF, counter >> 32, const[2], k[4], k[5], k[6], k[7], const[3]]
+        x = list(state)
+        for _ in range(10):
+            quarter(x, 0, 4, 8, 12)
+            quarter(x, 5, 9, 13, 1)
+            quarter(x, 10, 14, 2, 6)
+            quarter(x, 15, 3, 7, 11)
+            quarter(x, 0, 1, 2, 3)
+            quarter(x, 5, 6, 7, 4)
+            quarter(x, 10, 11, 8, 9)
+            quarter(x, 15, 12, 13, 14)
+        out += struct.pack("<16I", *((a + b) & 0xFFFFFFFF for a, b in zip(x, state)))
+        counter += 1
+    return out[:length]
+
+
+class Protector:
+    """XORs protected values with the inner stream, in document order"""
+
+    def __init__(self, keystream):
+        self.keystream = keystream
+        self.used = 0
+
+    def protect(self, text):
+        data = text.encode()
+        stream = self.keystream(self.used + len(data))[self.used:]
+        self.used += len(data)
+        return base64.b64encode(bytes(a ^ b for a, b in zip(data, stream))).decode()
+
+
+def xml(protector, recycle_bin_entry=True):
+    def string(key, value, protected=False):
+        if protected:
+            return f"<String><Key>{key}</Key><Value Protected=\"True\">{protector.protect(value)}</Value></String>"
+        return f"<String><Key>{key}</Key><Value>{value}</Value></String>"
+
+    def uuid(n):
+        return base64.b64encode(bytes([n]) * 16).decode()
+
+    # Built in document order, the order the inner stream is used in
+    router = (
+        f"<Entry><UUID>{uuid(2)}</UUID>"
+        + string("Title", "Router")
+        + string("Password", "admin", True)
+        + string("URL", "192.168.1.1")
+        + "<Binary><Key>backup.cfg</Key><Value Ref=\"0\"/></Binary>"
+        + "</Entry>"
+    )
+    github = (
+        f"<Entry><UUID>{uuid(1)}</UUID>"
+        + string("Title", "GitHub")
+        + string("UserName", "octocat")
+        + string("Password", "hunter2", True)
+        + string("URL", "https://github.com/login")
+        + string("Notes", "Work account &amp; 2FA")
+        + string("Recovery code", "1234-5678", True)
+        + string("otp", "otpauth://totp/GitHub:octocat?secret=JBSWY3DPEHPK3PXP&issuer=GitHub", True)
+        + "<History><Entry>"
+        + f"<UUID>{uuid(1)}</UUID>"
+        + string("Title", "GitHub")
+        + string("Password", "hunter1", True)
+        + "</Entry></History></Entry>"
+    )
+    wifi = (
+        f"<Entry><UUID>{uuid(3)}</UUID>"
+        + string("Title", "Wifi")
+        + "<String><Key>Password</Key><Value Protected=\"True\"/></String>"
+        + string("Notes", "Network: home\nKey: s3cret")
+        + "</Entry>"
+    )
+    deleted = (
+        f"<Entry><UUID>{uuid(4)}</UUID>"
+        + string("Title", "Old")
+        + string("Password", "x", True)
+        + "</Entry>"
+    )
+    recycle_bin = base64.b64encode(RECYCLE_BIN).decode()
+    return (
+        "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n"
+        "<KeePassFile><Meta><Generator>make_kdbx.py</Generator>"
+        f"<RecycleBinEnabled>True</RecycleBinEnabled><RecycleBinUUID>{recycle_bin}</RecycleBinUUID>"
+        "</Meta><Root>"
+        f"<Group><UUID>{uuid(9)}</UUID><Name>Passwords</Name>"
+        + router
+        + f"<Group><UUID>{uuid(10)}</UUID><Name>Work</Name>{github}</Group>"
+        + f"<Group><UUID>{uuid(11)}</UUID><Name>Personal</Name>"
+        + f"<Group><UUID>{uuid(12)}</UUID><Name>Home</Name>{wifi}</Group></Group>"
+        + (f"<Group><UUID>{recycle_bin}</UUID><Name>Recycle Bin</Name>{deleted}</Group>" if recycle_bin_entry else "")
+        + "</Group><DeletedObjects/></Root></KeePassFile>"
+    ).encode()
+
+
+def variant_dictionary(items):
+    out = struct.pack("<H", 0x0100)
+    for kind, name, value in items:
+        name = name.encode()
+        out += struct.pack("<Bi", kind, len(name)) + name + struct.pack("<i", len(value)) + value
+    return out + b"\x00"
+
+
+def kdbx4(path):
+    """KDBX 4.0: ChaCha20, Argon2id, gzip, ChaCha20 inner stream"""
+    master_seed = bytes(range(32))
+    iv = bytes(range(100, 112))
+    salt = bytes(range(200, 232))
+    inner_key = bytes(range(64))
+    memory, iterations, lanes = 1024 * 1024, 2, 2
+
+    header = SIGNATURE + struct.pack("<HH", 0, 4)
+    kdf = variant_dictionary([
+        (0x42, "$UUID", KDF_ARGON2ID),
+        (0x42, "S", salt),
+        (0x04, "P", struct.pack("<I", lanes)),
+        (0x05, "M", struct.pack("<Q", memory)),
+        (0x05, "I", struct.pack("<Q", iterations)),
+        (0x04, "V", struct.pack("<I", 0x13)),
+    ])
+    for field, data in [(2, CIPHER_CHACHA20), (3, struct.pack("<I", 1)), (4, master_seed), (7, iv), (11, kdf), (0, b"\r\n\r\n")]:
+        header += struct.pack("<BI", field, len(data)) + data
+
+    composite = sha256(sha256(PASSWORD))
+    transformed = Argon2id(salt=salt, length=32, iterations=iterations, lanes=lanes,
+                           memory_cost=memory // 1024).derive(composite)
+    key = sha256(master_seed + transformed)
+    hmac_base = sha512(master_seed + transformed + b"\x01")
+
+    def block_key(index):
+        return sha512(struct.pack("<Q", index) + hmac_base)
+
+    inner_stream = sha512(inner_key)
+    inner_cipher = Cipher(algorithms.ChaCha20(inner_stream[:32], b"\x00" * 4 + inner_stream[32:44]), None)
+    protector = Protector(lambda n: inner_cipher.encryptor().update(b"\x00" * n))
+
+    inner = b""
+    for field, data in [(1, struct.pack("<I", 3)), (2, inner_key), (3, b"\x01backup"), (0, b"")]:
+        inner += struct.pack("<BI", field, len(data)) + data
+    payload = gzip.compress(inner + xml(protector), mtime=0)
+    encrypted = Cipher(algorithms.ChaCha20(key, b"\x00" * 4 + iv), None).encryptor().update(payload)
+
+    out = header + sha256(header) + hmac.new(block_key(0xFFFFFFFFFFFFFFFF), header, "sha256").digest()
+    blocks = [encrypted[i:i + 512] for i in range(0, len(encrypted), 512)] + [b""]
+    for index, block in enumerate(blocks):
+        size = struct.pack("<I", len(block))
+        mac = hmac.new(block_key(index), struct.pack("<Q", index) + size + block, "sha256").digest()
+        out += mac + size + block
+    with open(path, "wb") as f:
+        f.write(out)
+
+
+def kdbx3(path):
+    """KDBX 3.1: AES-256, AES-KDF, gzip, Salsa20 inner stream"""
+    master_seed = bytes(range(50, 82))
+    transform_seed = bytes(range(82, 114))
+    iv = bytes(range(114, 130))
+    inner_key = bytes(range(130, 162))
+    start_bytes = bytes(range(162, 194))
+    rounds = 1000
+
+    header = SIGNATURE + struct.pack("<HH", 1, 3)
+    for field, data in [
+        (2, CIPHER_AES256), (3, struct.pack("<I", 1)), (4, master_seed), (5, transform_seed),
+        (6, struct.pack("<Q", rounds)), (7, iv), (8, inner_key), (9, start_bytes),
+        (10, struct.pack("<I", 2)), (0, b"\r\n\r\n"),
+    ]:
+        header += struct.pack("<BH", field, len(data)) + data
+
+    composite = sha256(sha256(PASSWORD))
+    ecb = Cipher(algorithms.AES(transform_seed), modes.ECB()).encryptor()
+    transformed = composite
+    for _ in range(rounds):
+        transformed = ecb.update(transformed)
+    key = sha256(master_seed + sha256(transformed))
+
+    protector = Protector(lambda n: salsa20_stream(sha256(inner_key), SALSA20_NONCE, n))
+    payload = gzip.compress(xml(protector, recycle_bin_entry=False), mtime=0)
+    blocks = [payload[i:i + 256] for i in range(0, len(payload), 256)]
+    hashed = b""
+    for index, block in enumerate(blocks):
+        hashed += struct.pack("<I", index) + sha256(block) + struct.pack("<I", len(block)) + block
+    hashed += struct.pack("<I", len(blocks)) + b"\x00" * 32 + struct.pack("<I", 0)
+
+    padder = padding.PKCS7(128).padder()
+    plain = padder.update(start_bytes + hashed) + padder.finalize()
+    encrypted = Cipher(algorithms.AES(key), modes.CBC(iv)).encryptor().update(plain)
+    with open(path, "wb") as f:
+        f.write(header + encrypted)
+
+
+if __name__ == "__main__":
+    kdbx4(os.path.join(OUT, "keepass4.kdbx"))
+    kdbx3(os.path.join(OUT, "keepass3.kdbx"))